@@ -21,8 +21,8 @@ pub type ControllerId = usize;
 
 #[derive(Debug, Default, Clone, Copy)]
 pub struct AxisValueType {
-    x: f32,
-    y: f32,
+    pub x: f32,
+    pub y: f32,
 }
 pub type ButtonValueType = bool;
 
@@ -91,7 +91,7 @@ impl Controllers {
     }
 
     pub fn disconnected(&mut self, id: ControllerId, queue: &mut Messages<ControllerMessage>) {
-        if let Some(existing) = self.controllers.remove(&id) {
+        if self.controllers.remove(&id).is_some() {
             queue.send(ControllerMessage::Disconnected(id));
         } else {
             error!(id=%id, "controller not found");
@@ -106,13 +106,13 @@ impl Controllers {
     /// Gets the axis value for a controller
     #[must_use]
     pub fn axis(&self, id: ControllerId, index: usize) -> Option<AxisValueType> {
-        self.controller(id).map(|pad| pad.axis[index as usize])
+        self.controller(id).map(|pad| pad.axis[index])
     }
 
     /// Gets the button value for a controller
     #[must_use]
     pub fn button(&self, id: ControllerId, index: usize) -> Option<ButtonValueType> {
-        self.controller(id).map(|pad| pad.buttons[index as usize])
+        self.controller(id).map(|pad| pad.buttons[index])
     }
 
     pub fn iter_active(&self) -> impl Iterator<Item = &Controller> {
@@ -1,11 +1,10 @@
 use limnus_app::prelude::{App, Plugin};
 use limnus_default_stages::{PreUpdate, Update};
-use limnus_input::Controllers;
 use limnus_input_binding::InputConfig;
 use limnus_local_resource::prelude::LocalResource;
 use limnus_resource::prelude::Resource;
 use limnus_steamworks::SteamworksClient;
-use limnus_system_params::{LoRe, LoReM, Re, ReAll, ReM};
+use limnus_system_params::{LoRe, LoReM, Re, ReAll};
 use seq_map::SeqMap;
 use std::fmt::{Debug, Formatter};
 use steamworks::{ClientManager, Input};
@@ -69,14 +68,9 @@ pub fn get_action_set_for_controller(
 pub fn debug_tick(
     input: LoRe<SteamworksInput>,
     bindings: Re<SteamworksInputBindings>,
-    mut controllers: ReM<Controllers>,
 ) {
     input.manager.run_frame();
     let connected_controllers = input.manager.get_connected_controllers();
-    for controller_id in &connected_controllers {
-        //        info!(?controller_id, "active controller");
-        let bindings = get_action_set_for_controller(*controller_id, &bindings);
-    }
 
     for controller_id in &connected_controllers {
         //        info!(?controller_id, "active controller");
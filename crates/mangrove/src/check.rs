@@ -0,0 +1,108 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+use mangrove_script::SourceMapResource;
+use mangrove_script::check::{check, has_errors};
+use mangrove_script::err::Diagnostic;
+use mangrove_script::manifest::Manifest;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum CheckFormat {
+    /// `file:line:column: severity: message` on stderr
+    #[default]
+    Human,
+    /// One JSON object per diagnostic and line on stdout
+    Json,
+}
+
+impl FromStr for CheckFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "human" => Ok(Self::Human),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown format '{s}', expected human or json")),
+        }
+    }
+}
+
+/// Compiles the scripts in the project and validates the entry points without opening a window.
+/// Prints the diagnostics and returns a failure exit code if there are any errors.
+pub fn run(manifest: &Manifest, format: CheckFormat) -> ExitCode {
+    let diagnostics = match check_project(manifest) {
+        Ok((_, diagnostics)) => diagnostics,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    for diagnostic in &diagnostics {
+        match format {
+            CheckFormat::Human => print_human(diagnostic),
+            CheckFormat::Json => println!("{}", diagnostic.to_json()),
+        }
+    }
+
+    if has_errors(&diagnostics) {
+        ExitCode::FAILURE
+    } else {
+        if format == CheckFormat::Human {
+            eprintln!("{} compiled without errors", manifest.scripts.entry);
+        }
+        ExitCode::SUCCESS
+    }
+}
+
+/// Compiles and checks the scripts, and prints the diagnostics, if any
+///
+/// # Errors
+///
+/// If the scripts can not be mounted, fail to compile or do not have the expected entry points.
+pub fn compile_project(manifest: &Manifest) -> Result<SourceMapResource, String> {
+    let (source_map, diagnostics) = check_project(manifest)?;
+
+    for diagnostic in &diagnostics {
+        print_human(diagnostic);
+    }
+
+    if has_errors(&diagnostics) {
+        return Err(format!(
+            "{} failed to compile",
+            manifest.script_root().display()
+        ));
+    }
+
+    Ok(source_map)
+}
+
+fn check_project(manifest: &Manifest) -> Result<(SourceMapResource, Vec<Diagnostic>), String> {
+    let mut source_map = SourceMapResource::from_manifest(manifest).map_err(|err| {
+        format!(
+            "could not mount the scripts in {}: {err}",
+            manifest.script_root().display()
+        )
+    })?;
+
+    let diagnostics = check(&mut source_map);
+
+    Ok((source_map, diagnostics))
+}
+
+fn print_human(diagnostic: &Diagnostic) {
+    match (&diagnostic.file, &diagnostic.location) {
+        (Some(file), Some(location)) => eprintln!(
+            "{}:{}:{}: {}: {}",
+            file.display(),
+            location.line,
+            location.column,
+            diagnostic.severity,
+            diagnostic.message
+        ),
+        _ => eprintln!("{diagnostic}"),
+    }
+}
@@ -0,0 +1,297 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+use crate::check::CheckFormat;
+use mangrove_script::manifest::WindowMode;
+use std::iter::Peekable;
+use std::path::PathBuf;
+
+pub const USAGE: &str = "usage:
+  mangrove [run] [project-dir] [--headless --ticks N] [--tick-rate HZ] [--time-machine]
+                 [--record <input file> | --replay <input file>]
+                 [--window-mode windowed|on_top|fullscreen] [--scale N] [--no-steam]
+  mangrove check [project-dir] [--format human|json]
+  mangrove new <name>
+  mangrove package [project-dir] [--output <directory>]
+
+every command also takes --log-level LEVEL (e.g. debug, or mangrove_script=trace)";
+
+#[derive(Debug, Default)]
+pub struct RunArgs {
+    pub project: Option<PathBuf>,
+    pub headless: bool,
+    pub ticks: Option<u64>,
+    pub tick_rate: Option<u32>,
+    pub time_machine: bool,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub window_mode: Option<WindowMode>,
+    pub scale: Option<u16>,
+    /// Only used when built with the `steam` feature
+    pub no_steam: bool,
+}
+
+#[derive(Debug)]
+pub enum Command {
+    Run(RunArgs),
+    /// Compiles the scripts and validates the entry points without starting the game
+    Check {
+        project: Option<PathBuf>,
+        format: CheckFormat,
+    },
+    /// Creates a new project directory with a manifest and starter scripts
+    New {
+        name: String,
+    },
+    /// Copies everything needed to run the game into a directory
+    Package {
+        project: Option<PathBuf>,
+        /// Relative to the project directory, `dist` if not set
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Debug)]
+pub struct Cli {
+    pub command: Command,
+    pub log_level: Option<String>,
+}
+
+/// No command (or only flags) is the same as `run`, so `mangrove` still runs the project in the
+/// current directory.
+pub fn parse_args(args: impl Iterator<Item = String>) -> Result<Cli, String> {
+    let mut args = args.peekable();
+    let mut log_level = None;
+
+    let command_name = match args.peek().map(String::as_str) {
+        Some("run" | "check" | "new" | "package") => args.next().unwrap_or_default(),
+        Some(arg) if !arg.starts_with("--") => {
+            return Err(format!("unknown command '{arg}'"));
+        }
+        _ => "run".to_string(),
+    };
+
+    let command = match command_name.as_str() {
+        "check" => {
+            let mut format = CheckFormat::default();
+            let project = parse_project(&mut args, &mut log_level, |arg, args| match arg {
+                "--format" => {
+                    format = args.next().ok_or("--format requires a format")?.parse()?;
+                    Ok(())
+                }
+                _ => Err(format!("unknown argument '{arg}' for check")),
+            })?;
+            Command::Check { project, format }
+        }
+        "new" => {
+            let name = parse_project(&mut args, &mut log_level, |arg, _| {
+                Err(format!("unknown argument '{arg}' for new"))
+            })?
+            .ok_or("new requires a project name")?;
+            Command::New {
+                name: name.to_string_lossy().into_owned(),
+            }
+        }
+        "package" => {
+            let mut output = None;
+            let project = parse_project(&mut args, &mut log_level, |arg, args| match arg {
+                "--output" => {
+                    output = Some(args.next().ok_or("--output requires a directory")?.into());
+                    Ok(())
+                }
+                _ => Err(format!("unknown argument '{arg}' for package")),
+            })?;
+            Command::Package { project, output }
+        }
+        _ => Command::Run(parse_run_args(&mut args, &mut log_level)?),
+    };
+
+    Ok(Cli { command, log_level })
+}
+
+/// Parses the optional project directory and the `--log-level`, the other flags are handled by `flag`
+fn parse_project<I: Iterator<Item = String>>(
+    args: &mut Peekable<I>,
+    log_level: &mut Option<String>,
+    mut flag: impl FnMut(&str, &mut Peekable<I>) -> Result<(), String>,
+) -> Result<Option<PathBuf>, String> {
+    let mut project = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--log-level" => {
+                *log_level = Some(args.next().ok_or("--log-level requires a level")?);
+            }
+            _ if arg.starts_with("--") => flag(&arg, args)?,
+            _ if project.is_none() => project = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{arg}'")),
+        }
+    }
+
+    Ok(project)
+}
+
+fn parse_run_args<I: Iterator<Item = String>>(
+    args: &mut Peekable<I>,
+    log_level: &mut Option<String>,
+) -> Result<RunArgs, String> {
+    let mut parsed = RunArgs::default();
+
+    parsed.project = parse_project(args, log_level, |arg, args| {
+        parse_run_flag(&mut parsed, arg, args)
+    })?;
+
+    if parsed.ticks.is_some() && !parsed.headless {
+        return Err("--ticks can only be used together with --headless".to_string());
+    }
+
+    if parsed.record.is_some() && parsed.replay.is_some() {
+        return Err("--record and --replay can not be used at the same time".to_string());
+    }
+
+    if parsed.time_machine && parsed.headless {
+        return Err("--time-machine can not be used together with --headless".to_string());
+    }
+
+    // Going back in time would make the ticks differ from the input recording
+    if parsed.time_machine && (parsed.record.is_some() || parsed.replay.is_some()) {
+        return Err(
+            "--time-machine can not be used together with --record or --replay".to_string(),
+        );
+    }
+
+    Ok(parsed)
+}
+
+fn parse_run_flag<I: Iterator<Item = String>>(
+    parsed: &mut RunArgs,
+    arg: &str,
+    args: &mut Peekable<I>,
+) -> Result<(), String> {
+    match arg {
+        "--headless" => parsed.headless = true,
+        "--time-machine" => parsed.time_machine = true,
+        "--no-steam" => parsed.no_steam = true,
+        "--ticks" => {
+            let count = args.next().ok_or("--ticks requires a number")?;
+            parsed.ticks = Some(
+                count
+                    .parse()
+                    .map_err(|_| format!("--ticks requires a number, got '{count}'"))?,
+            );
+        }
+        "--tick-rate" => {
+            let rate = args.next().ok_or("--tick-rate requires a number")?;
+            parsed.tick_rate =
+                Some(rate.parse().ok().filter(|&rate| rate > 0).ok_or_else(|| {
+                    format!("--tick-rate requires a positive number, got '{rate}'")
+                })?);
+        }
+        "--scale" => {
+            let scale = args.next().ok_or("--scale requires a number")?;
+            parsed.scale = Some(
+                scale
+                    .parse()
+                    .ok()
+                    .filter(|&scale| scale > 0)
+                    .ok_or_else(|| format!("--scale requires a positive number, got '{scale}'"))?,
+            );
+        }
+        "--window-mode" => {
+            let mode = args.next().ok_or("--window-mode requires a mode")?;
+            parsed.window_mode = Some(mode.parse()?);
+        }
+        "--record" => {
+            parsed.record = Some(args.next().ok_or("--record requires a file name")?.into());
+        }
+        "--replay" => {
+            parsed.replay = Some(args.next().ok_or("--replay requires a file name")?.into());
+        }
+        _ => return Err(format!("unknown argument '{arg}'")),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Cli, String> {
+        parse_args(args.split_whitespace().map(ToString::to_string))
+    }
+
+    fn run_args(args: &str) -> RunArgs {
+        match parse(args).unwrap().command {
+            Command::Run(run_args) => run_args,
+            command => panic!("expected run, got {command:?}"),
+        }
+    }
+
+    fn error(args: &str) -> String {
+        parse(args).unwrap_err()
+    }
+
+    #[test]
+    fn parses_the_run_flags() {
+        let parsed = run_args("run game --headless --ticks 10 --tick-rate 30");
+
+        assert_eq!(parsed.project, Some(PathBuf::from("game")));
+        assert!(parsed.headless);
+        assert_eq!(parsed.ticks, Some(10));
+        assert_eq!(parsed.tick_rate, Some(30));
+        // Only flags is the same as run
+        assert_eq!(run_args("--scale 2").scale, Some(2));
+        assert_eq!(
+            parse("run --log-level debug").unwrap().log_level.as_deref(),
+            Some("debug")
+        );
+    }
+
+    #[test]
+    fn rejects_flag_combinations() {
+        assert!(error("--ticks 10").contains("--headless"));
+        assert!(error("--record a.input --replay b.input").contains("at the same time"));
+        assert!(error("--headless --ticks 1 --time-machine").contains("--headless"));
+        assert!(error("--time-machine --record a.input").contains("--record or --replay"));
+        assert!(error("--time-machine --replay a.input").contains("--record or --replay"));
+    }
+
+    #[test]
+    fn rejects_non_positive_tick_rate_and_scale() {
+        for tick_rate in ["0", "-60", "fast"] {
+            assert_eq!(
+                error(&format!("--tick-rate {tick_rate}")),
+                format!("--tick-rate requires a positive number, got '{tick_rate}'")
+            );
+        }
+        for scale in ["0", "-2", "big"] {
+            assert_eq!(
+                error(&format!("--scale {scale}")),
+                format!("--scale requires a positive number, got '{scale}'")
+            );
+        }
+        assert_eq!(error("--tick-rate"), "--tick-rate requires a number");
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        assert_eq!(error("--fast"), "unknown argument '--fast'");
+        assert_eq!(error("run game --fast"), "unknown argument '--fast'");
+        assert_eq!(
+            error("check --output dist"),
+            "unknown argument '--output' for check"
+        );
+        assert_eq!(
+            error("new game --headless"),
+            "unknown argument '--headless' for new"
+        );
+        assert_eq!(
+            error("package --format json"),
+            "unknown argument '--format' for package"
+        );
+        assert_eq!(error("jump"), "unknown command 'jump'");
+        assert_eq!(error("run one two"), "unexpected argument 'two'");
+    }
+}
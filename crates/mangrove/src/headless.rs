@@ -0,0 +1,78 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+use limnus_asset_registry::AssetRegistryPlugin;
+use limnus_assets_loader::AssetLoaderRegistryPlugin;
+use limnus_audio_sample::AudioSamplePlugin;
+use limnus_clock::ClockPlugin;
+use limnus_default_schedulers::DefaultSchedulersPlugin;
+use limnus_default_stages_plugin::DefaultStagesPlugin;
+use limnus_gamepad::GamepadResourcePlugin;
+use limnus_loader::LoaderPlugin;
+use limnus_log::LogPlugin;
+use mangrove_script::ErrorResource;
+use mangrove_script::ScriptPlugin;
+use mangrove_script::audio::ScriptAudioPlugin;
+use mangrove_script::err::ErrorPlugin;
+use mangrove_script::manifest::Manifest;
+use mangrove_script::save::SaveStore;
+use mangrove_script::script_main::ScriptMainPlugin;
+use mangrove_script::simulation::ScriptSimulationPlugin;
+use mangrove_script::source_map::SourceMapPlugin;
+use mangrove_script::timestep::SimulationTimestep;
+use std::process::ExitCode;
+use swamp::prelude::*;
+
+/// Everything needed to run the simulation, but without a window, wgpu surface or audio device.
+pub struct HeadlessPlugins;
+
+impl Plugin for HeadlessPlugins {
+    fn build(&self, app: &mut App) {
+        app.add_plugins((
+            LogPlugin,
+            DefaultStagesPlugin,
+            ClockPlugin,
+            DefaultSchedulersPlugin,
+        ));
+        app.add_plugins((LoaderPlugin, AssetLoaderRegistryPlugin, AssetRegistryPlugin));
+        app.add_plugins((AudioSamplePlugin, GamepadResourcePlugin));
+
+        app.add_plugins(SourceMapPlugin)
+            .add_plugins(ErrorPlugin)
+            .add_plugins(ScriptPlugin)
+            .add_plugins(ScriptMainPlugin)
+            .add_plugins(ScriptSimulationPlugin);
+
+        let audio = app
+            .get_resource_ref::<Manifest>()
+            .is_none_or(|manifest| manifest.plugins.audio);
+        if audio {
+            app.add_plugins(ScriptAudioPlugin);
+        }
+
+        // Every update is exactly one tick, regardless of how fast the host runs it
+        app.insert_resource(SimulationTimestep::lockstep());
+        // Headless runs must not read or overwrite the saves of the player
+        app.insert_resource(SaveStore::in_memory());
+    }
+}
+
+/// Runs the simulation `ticks` times and exits.
+/// Returns a failure exit code as soon as the scripts fail to compile or report a runtime error.
+pub fn run(app: &mut App, ticks: u64) -> ExitCode {
+    for tick in 0..ticks {
+        app.update();
+
+        let error_resource = app.resource::<ErrorResource>();
+        if error_resource.has_errors() {
+            for diagnostic in &error_resource.diagnostics {
+                eprintln!("{diagnostic}");
+            }
+            eprintln!("headless run failed at tick {tick}");
+            return ExitCode::FAILURE;
+        }
+    }
+
+    ExitCode::SUCCESS
+}
@@ -2,44 +2,213 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
+mod check;
+mod cli;
+mod headless;
+mod new_project;
+mod package;
+
+use cli::{Cli, Command, RunArgs, USAGE, parse_args};
+use headless::HeadlessPlugins;
 #[cfg(feature = "steam")]
 use limnus_steam_input::SteamworksInputPlugin;
 #[cfg(feature = "steam")]
 use limnus_steamworks::SteamworksPlugin;
 use mangrove_save_detector::SaveDetectorPlugin;
+use mangrove_script::asset_reload::AssetReloadPlugin;
+use mangrove_script::audio::ScriptAudioPlugin;
+use mangrove_script::convert::ConvertPlugin;
 use mangrove_script::err::ErrorPlugin;
+use mangrove_script::flow::ScriptFlowPlugin;
 use mangrove_script::input::ScriptInputPlugin;
+use mangrove_script::manifest::{Manifest, WindowMode};
 use mangrove_script::render::ScriptRenderPlugin;
+use mangrove_script::replay::{InputRecordPlugin, InputRecorder, InputReplayPlugin, InputReplayer};
+use mangrove_script::save::SaveStore;
 use mangrove_script::script_main::ScriptMainPlugin;
 use mangrove_script::simulation::ScriptSimulationPlugin;
 use mangrove_script::source_map::SourceMapPlugin;
-//use mangrove_script::flow::ScriptFlowPlugin;
+use mangrove_script::time_machine::TimeMachinePlugin;
+use mangrove_script::timestep::{DEFAULT_MAX_TICKS_PER_UPDATE, SimulationTimestep};
 //use mangrove_script::input::ScriptInputPlugin;
 use mangrove_script::ScriptPlugin;
+use package::DEFAULT_OUTPUT_DIRECTORY;
+use std::env::{current_dir, set_current_dir, set_var};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 use swamp::prelude::SwampDefaultPlugins;
 use swamp::prelude::*;
 
-const OLD_SCHOOL_RESOLUTION_LOW: (u16, u16) = (320, 180);
-#[allow(dead_code)]
-const OLD_SCHOOL_RESOLUTION_HIGH: (u16, u16) = (
-    OLD_SCHOOL_RESOLUTION_LOW.0 * 2,
-    OLD_SCHOOL_RESOLUTION_LOW.1 * 2,
-);
-#[allow(dead_code)]
-const INTERMEDIATE_RESOLUTION: (u16, u16) = (384, 216);
+fn add_input_recording(app: &mut App, args: &RunArgs) -> Result<(), String> {
+    if let Some(path) = &args.record {
+        let recorder = InputRecorder::create(path)
+            .map_err(|err| format!("could not create input recording {}: {err}", path.display()))?;
+        app.insert_local_resource(recorder)
+            .add_plugins(InputRecordPlugin);
+    }
+
+    if let Some(path) = &args.replay {
+        let replayer = InputReplayer::load(path)
+            .map_err(|err| format!("could not load input recording {}: {err}", path.display()))?;
+        app.insert_local_resource(replayer)
+            .add_plugins(InputReplayPlugin);
+    }
+
+    Ok(())
+}
+
+/// Changes the current directory to the project directory, the paths in the arguments are
+/// relative to it
+fn load_manifest(project: Option<&Path>) -> Result<Manifest, String> {
+    if let Some(project) = project {
+        set_current_dir(project)
+            .map_err(|err| format!("could not open project {}: {err}", project.display()))?;
+    }
+
+    let directory =
+        current_dir().map_err(|err| format!("could not find current directory: {err}"))?;
+
+    Manifest::load(&directory).map_err(|err| err.to_string())
+}
+
+/// The asset loader reads from `assets/` in the current directory, so it is changed to the
+/// directory that has the asset root. Done after the files in the arguments have been opened.
+fn enter_asset_loader_directory(directory: &Path) -> Result<(), String> {
+    set_current_dir(directory).map_err(|err| {
+        format!(
+            "could not change to the asset directory {}: {err}",
+            directory.display()
+        )
+    })
+}
+
+/// The saves of the player are kept per project directory name. Recording and replaying keep
+/// them in memory, so the replay starts from the same saves as the recording did.
+fn save_store(manifest: &Manifest, recording: bool) -> SaveStore {
+    if recording {
+        return SaveStore::in_memory();
+    }
+
+    let game_name = manifest.project_directory.file_name().map_or_else(
+        || "game".to_string(),
+        |name| name.to_string_lossy().to_string(),
+    );
+
+    SaveStore::for_game(&game_name)
+}
+
+const fn screen_mode(window_mode: WindowMode) -> ScreenMode {
+    match window_mode {
+        WindowMode::Windowed => ScreenMode::Windowed,
+        WindowMode::OnTop => ScreenMode::WindowedOnTop,
+        WindowMode::Fullscreen => ScreenMode::WindowedFullscreen,
+    }
+}
+
+fn main() -> ExitCode {
+    let Cli { command, log_level } = match parse_args(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(err) => {
+            eprintln!("{err}\n{USAGE}");
+            return ExitCode::from(2);
+        }
+    };
+
+    if let Some(log_level) = log_level {
+        // Safety: No other threads have been started yet
+        unsafe {
+            set_var("RUST_LOG", log_level);
+        }
+    }
+
+    let result = match command {
+        Command::Run(args) => return run(&args),
+        Command::Check { project, format } => {
+            load_manifest(project.as_deref()).map(|manifest| check::run(&manifest, format))
+        }
+        Command::New { name } => new_project::create(Path::new(&name)).map(|()| {
+            eprintln!("created {name}, run it with: mangrove run {name}");
+            ExitCode::SUCCESS
+        }),
+        Command::Package { project, output } => {
+            load_manifest(project.as_deref()).and_then(|manifest| {
+                let output = output.unwrap_or_else(|| PathBuf::from(DEFAULT_OUTPUT_DIRECTORY));
+                package::create(&manifest, &output)?;
+                eprintln!("packaged {} in {}", manifest.title, output.display());
+                Ok(ExitCode::SUCCESS)
+            })
+        }
+    };
 
-const RESOLUTION: (u16, u16) = OLD_SCHOOL_RESOLUTION_LOW;
-const FACTOR: u16 = 5;
+    result.unwrap_or_else(|err| {
+        eprintln!("{err}");
+        ExitCode::FAILURE
+    })
+}
+
+fn run(args: &RunArgs) -> ExitCode {
+    let mut manifest = match load_manifest(args.project.as_deref()) {
+        Ok(manifest) => manifest,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    if args.headless {
+        let Some(ticks) = args.ticks else {
+            eprintln!("--headless requires --ticks N\n{USAGE}");
+            return ExitCode::from(2);
+        };
+
+        let asset_loader_directory = manifest.asset_loader_directory();
+        let mut app = App::new();
+        app.insert_resource(manifest);
+        app.add_plugins(HeadlessPlugins);
+        if let Err(err) = add_input_recording(&mut app, args) {
+            eprintln!("{err}");
+            return ExitCode::from(2);
+        }
+
+        if let Err(err) = enter_asset_loader_directory(&asset_loader_directory) {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+
+        return headless::run(&mut app, ticks);
+    }
+
+    if let Some(scale) = args.scale {
+        manifest.window.scale = scale;
+        manifest.window.size = None;
+    }
+    if let Some(window_mode) = args.window_mode {
+        manifest.window.mode = window_mode;
+    }
+
+    // Going back in time would make the ticks differ from the input recording
+    let recording = args.record.is_some() || args.replay.is_some();
+    if manifest.plugins.time_machine && recording {
+        eprintln!("the time machine is disabled while recording or replaying input");
+    }
+    let time_machine = args.time_machine || (manifest.plugins.time_machine && !recording);
+    let plugins = manifest.plugins.clone();
+    let asset_loader_directory = manifest.asset_loader_directory();
+    let save_store = save_store(&manifest, recording);
+
+    let [width, height] = manifest.window.requested_size();
+    let [virtual_width, virtual_height] = manifest.window.virtual_resolution;
 
-fn main() {
     let mut app = App::new();
 
     app.insert_resource(Window {
-        title: "mangrove".to_string(),
-        requested_surface_size: UVec2::new(RESOLUTION.0 * FACTOR, RESOLUTION.1 * FACTOR),
-        minimal_surface_size: UVec2::new(RESOLUTION.0, RESOLUTION.1),
-        mode: ScreenMode::WindowedOnTop,
+        title: manifest.title.clone(),
+        requested_surface_size: UVec2::new(width, height),
+        minimal_surface_size: UVec2::new(virtual_width, virtual_height),
+        mode: screen_mode(manifest.window.mode),
     })
+    .insert_resource(manifest)
+    .insert_resource(save_store)
     .add_plugins(DefaultPlugins)
     .add_plugins(SwampDefaultPlugins)
     .add_plugins(SourceMapPlugin)
@@ -47,23 +216,50 @@ fn main() {
     .add_plugins(ScriptPlugin)
     .add_plugins(ScriptMainPlugin)
     .add_plugins(ScriptInputPlugin)
-    //.add_plugins(ScriptFlowPlugin)
+    .add_plugins(ScriptFlowPlugin)
     .add_plugins(ScriptSimulationPlugin);
 
-    #[cfg(feature = "time_machine")]
-    {
-        app.add_plugins(ConvertPlugin);
-        app.add_plugins(SerializePlugin);
+    if let Some(tick_rate) = args.tick_rate {
+        app.insert_resource(SimulationTimestep::new(
+            tick_rate,
+            DEFAULT_MAX_TICKS_PER_UPDATE,
+        ));
+    }
+
+    if let Err(err) = add_input_recording(&mut app, args) {
+        eprintln!("{err}");
+        return ExitCode::from(2);
     }
 
-    app.add_plugins(ScriptRenderPlugin)
-        .add_plugins(SaveDetectorPlugin);
+    if let Err(err) = enter_asset_loader_directory(&asset_loader_directory) {
+        eprintln!("{err}");
+        return ExitCode::FAILURE;
+    }
+
+    app.add_plugins(ScriptRenderPlugin);
+
+    if plugins.audio {
+        app.add_plugins(ScriptAudioPlugin);
+    }
+
+    if plugins.hot_reload {
+        app.add_plugins(AssetReloadPlugin);
+        app.add_plugins(SaveDetectorPlugin);
+    }
+
+    app.add_plugins(ConvertPlugin);
+
+    if time_machine {
+        app.add_plugins(TimeMachinePlugin);
+    }
 
     #[cfg(feature = "steam")]
-    {
+    if !args.no_steam {
         app.add_plugins(SteamworksPlugin);
         app.add_plugins(SteamworksInputPlugin);
     }
 
     app.run();
+
+    ExitCode::SUCCESS
 }
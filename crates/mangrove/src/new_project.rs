@@ -0,0 +1,53 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+use mangrove_script::manifest::{MANIFEST_FILE_NAME, Manifest};
+use std::fs;
+use std::path::Path;
+
+/// The starter scripts, relative to the script root
+const SCRIPTS: [(&str, &str); 3] = [
+    ("main.swamp", include_str!("new_project/main.swamp")),
+    ("logic.swamp", include_str!("new_project/logic.swamp")),
+    ("render.swamp", include_str!("new_project/render.swamp")),
+];
+
+/// Creates the project directory with a manifest, an empty asset directory and the starter scripts.
+///
+/// # Errors
+///
+/// If the directory already exists or a file can not be written.
+pub fn create(project_directory: &Path) -> Result<(), String> {
+    if project_directory.exists() {
+        return Err(format!("{} already exists", project_directory.display()));
+    }
+
+    let mut manifest = Manifest::default();
+    if let Some(name) = project_directory.file_name() {
+        manifest.title = name.to_string_lossy().into_owned();
+    }
+
+    let script_root = project_directory.join(&manifest.scripts.root);
+    fs::create_dir_all(&script_root)
+        .map_err(|err| format!("could not create {}: {err}", script_root.display()))?;
+
+    let asset_root = project_directory.join(&manifest.assets.root);
+    fs::create_dir_all(&asset_root)
+        .map_err(|err| format!("could not create {}: {err}", asset_root.display()))?;
+
+    write_file(
+        &project_directory.join(MANIFEST_FILE_NAME),
+        &manifest.to_toml()?,
+    )?;
+
+    for (file_name, contents) in SCRIPTS {
+        write_file(&script_root.join(file_name), contents)?;
+    }
+
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<(), String> {
+    fs::write(path, contents).map_err(|err| format!("could not write {}: {err}", path.display()))
+}
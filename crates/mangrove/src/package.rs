@@ -0,0 +1,118 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+use crate::check::compile_project;
+use mangrove_script::bindings::BINDINGS_FILE_NAME;
+use mangrove_script::manifest::{
+    DEFAULT_ASSET_ROOT, DEFAULT_SCRIPT_ROOT, MANIFEST_FILE_NAME, Manifest,
+};
+use std::collections::BTreeMap;
+use std::env::current_exe;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_OUTPUT_DIRECTORY: &str = "dist";
+
+/// The swamp packages are copied here, so the game does not need a swamp registry
+const PACKAGES_DIRECTORY: &str = "packages";
+const MOUNTS_DIRECTORY: &str = "mounts";
+
+/// Copies the executable, the scripts, the packages they use, the assets and the settings
+/// into the output directory, with a manifest that points to the copies.
+/// Hot reload and the time machine are turned off in the packaged manifest.
+///
+/// # Errors
+///
+/// If the scripts do not compile, the output directory already exists or a file can not be copied.
+pub fn create(manifest: &Manifest, output: &Path) -> Result<(), String> {
+    if output.exists() {
+        return Err(format!(
+            "{} already exists, remove it first",
+            output.display()
+        ));
+    }
+
+    let source_map = compile_project(manifest)?;
+
+    fs::create_dir_all(output)
+        .map_err(|err| format!("could not create {}: {err}", output.display()))?;
+
+    let mut packaged = manifest.clone();
+    packaged.plugins.hot_reload = false;
+    packaged.plugins.time_machine = false;
+
+    copy_directory(&manifest.script_root(), &output.join(DEFAULT_SCRIPT_ROOT))?;
+    packaged.scripts.root = PathBuf::from(DEFAULT_SCRIPT_ROOT);
+
+    let mut mounts = BTreeMap::new();
+    copy_directory(
+        source_map.source_map.base_path("registry"),
+        &output.join(PACKAGES_DIRECTORY),
+    )?;
+    mounts.insert("registry".to_string(), PathBuf::from(PACKAGES_DIRECTORY));
+
+    for (name, path) in manifest.mounts().filter(|(name, _)| *name != "registry") {
+        let relative = Path::new(MOUNTS_DIRECTORY).join(name);
+        copy_directory(&path, &output.join(&relative))?;
+        mounts.insert(name.to_string(), relative);
+    }
+    packaged.scripts.mounts = mounts;
+
+    let asset_root = manifest.asset_root();
+    if asset_root.is_dir() {
+        copy_directory(&asset_root, &output.join(DEFAULT_ASSET_ROOT))?;
+    }
+    packaged.assets.root = PathBuf::from(DEFAULT_ASSET_ROOT);
+
+    let bindings = manifest.project_directory.join(BINDINGS_FILE_NAME);
+    if bindings.is_file() {
+        copy_file(&bindings, &output.join(BINDINGS_FILE_NAME))?;
+    }
+
+    let manifest_path = output.join(MANIFEST_FILE_NAME);
+    fs::write(&manifest_path, packaged.to_toml()?)
+        .map_err(|err| format!("could not write {}: {err}", manifest_path.display()))?;
+
+    let executable =
+        current_exe().map_err(|err| format!("could not find the mangrove executable: {err}"))?;
+    if let Some(file_name) = executable.file_name() {
+        copy_file(&executable, &output.join(file_name))?;
+    }
+
+    Ok(())
+}
+
+/// Follows symbolic links, so the package only contains regular files
+fn copy_directory(source: &Path, target: &Path) -> Result<(), String> {
+    fs::create_dir_all(target)
+        .map_err(|err| format!("could not create {}: {err}", target.display()))?;
+
+    let entries = fs::read_dir(source)
+        .map_err(|err| format!("could not read {}: {err}", source.display()))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|err| format!("could not read {}: {err}", source.display()))?;
+        let path = entry.path();
+        let target_path = target.join(entry.file_name());
+        if path.is_dir() {
+            copy_directory(&path, &target_path)?;
+        } else {
+            copy_file(&path, &target_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn copy_file(source: &Path, target: &Path) -> Result<(), String> {
+    fs::copy(source, target).map_err(|err| {
+        format!(
+            "could not copy {} to {}: {err}",
+            source.display(),
+            target.display()
+        )
+    })?;
+
+    Ok(())
+}
@@ -2,46 +2,104 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
-use limnus_message::prelude::Message;
-use mangrove_script::{ErrorResource, ScriptMessage};
+use mangrove_script::ScriptMessage;
+use mangrove_script::asset_reload::{AssetMessage, RELOADABLE_ASSET_EXTENSIONS};
+use mangrove_script::manifest::Manifest;
 use message_channel::{Channel, Receiver};
-use notify::{RecommendedWatcher, RecursiveMode, Watcher};
-use std::path::Path;
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant};
-use swamp::prelude::{App, LoReM, LocalResource, MsgM, Plugin, ReM, Update};
+use swamp::prelude::{App, LoReM, LocalResource, MsgM, Plugin, Update};
+use tracing::warn;
 
-#[derive(LocalResource, Debug)]
+/// Saving a file often causes several events, e.g. a write and a rename
+const DEBOUNCE_DURATION: Duration = Duration::from_millis(100);
+
+const SCRIPT_EXTENSIONS: &[&str] = &["swamp"];
+
+#[derive(Debug)]
 pub struct FileWatcher {
     pub receiver: Receiver<SaveDetectorMessage>,
     pub watcher: RecommendedWatcher, // keeps watcher alive
+    /// The changed files that have not been sent yet
+    changed_files: Vec<PathBuf>,
+    last_change: Instant,
 }
 
-impl FileWatcher {}
+impl FileWatcher {
+    /// Watches the files with one of the `extensions` in the directory and its subdirectories
+    ///
+    /// # Errors
+    ///
+    /// If the path can not be watched.
+    pub fn new(watch_path: &Path, extensions: &'static [&'static str]) -> notify::Result<Self> {
+        let (watcher, receiver) = start_watch(watch_path, extensions)?;
+        Ok(Self {
+            receiver,
+            watcher,
+            changed_files: Vec::new(),
+            last_change: Instant::now(),
+        })
+    }
+
+    /// The changed files, when no more changes have been detected for a while
+    pub fn poll(&mut self) -> Option<Vec<PathBuf>> {
+        while let Ok(SaveDetectorMessage::Changed(changed_files)) = self.receiver.recv() {
+            for changed_file in changed_files {
+                if !self.changed_files.contains(&changed_file) {
+                    self.changed_files.push(changed_file);
+                }
+            }
+            self.last_change = Instant::now();
+        }
+
+        (!self.changed_files.is_empty() && self.last_change.elapsed() >= DEBOUNCE_DURATION)
+            .then(|| std::mem::take(&mut self.changed_files))
+    }
+}
+
+/// Watches the scripts
+#[derive(LocalResource, Debug)]
+pub struct ScriptWatcher(pub FileWatcher);
+
+/// Watches the assets that can be reloaded in place, see `mangrove_script::asset_reload`
+#[derive(LocalResource, Debug)]
+pub struct AssetWatcher {
+    pub file_watcher: FileWatcher,
+    asset_root: PathBuf,
+}
 
 /// # Errors
 ///
-/// # Panics
-///
-///
+/// If the path can not be watched.
 pub fn start_watch(
     watch_path: &Path,
+    extensions: &'static [&'static str],
 ) -> notify::Result<(RecommendedWatcher, Receiver<SaveDetectorMessage>)> {
     let (sender, receiver) = Channel::create();
 
-    let mut last_event = Instant::now() - Duration::from_secs(1);
-    let debounce_duration = Duration::from_millis(100); // Might need to tweak this
-
-    let mut watcher = notify::recommended_watcher(move |res| match res {
-        Ok(_event) => {
-            let now = Instant::now();
-            if now.duration_since(last_event) >= debounce_duration {
-                sender
-                    .send(SaveDetectorMessage::Changed)
-                    .expect("TODO: panic message");
-                last_event = now;
+    let mut watcher = notify::recommended_watcher(move |res: notify::Result<Event>| match res {
+        Ok(event) => {
+            // Reading the files, e.g. when the scripts are compiled, also causes (access) events
+            if !(event.kind.is_create() || event.kind.is_modify() || event.kind.is_remove()) {
+                return;
+            }
+            let watched_files: Vec<PathBuf> = event
+                .paths
+                .into_iter()
+                .filter(|path| {
+                    path.extension()
+                        .is_some_and(|extension| extensions.iter().any(|ext| extension == *ext))
+                })
+                .collect();
+            // The receiver is only gone when the watcher is shut down
+            if !watched_files.is_empty()
+                && let Err(err) = sender.send(SaveDetectorMessage::Changed(watched_files))
+            {
+                warn!(?err, "could not report the changed files");
             }
         }
-        Err(e) => println!("watch error: {e:?}"),
+        Err(err) => warn!(?err, "watch error"),
     })?;
 
     // Start watching the directory
@@ -50,31 +108,74 @@ pub fn start_watch(
     Ok((watcher, receiver))
 }
 
-pub fn tick(
-    mut detector_message: MsgM<ScriptMessage>,
-    file_watcher: LoReM<FileWatcher>,
-    mut err: ReM<ErrorResource>,
-) {
-    while let Ok(_found) = file_watcher.receiver.recv() {
-        err.has_errors = false;
-        detector_message.send(ScriptMessage::Reload);
+/// Sends a reload with the changed scripts.
+/// The errors are cleared when the reload succeeds, a failed reload keeps the previous program.
+pub fn tick(mut detector_message: MsgM<ScriptMessage>, mut script_watcher: LoReM<ScriptWatcher>) {
+    if let Some(changed_files) = script_watcher.0.poll() {
+        detector_message.send(ScriptMessage::Reload(changed_files));
     }
 }
 
-#[derive(Debug, Message)]
+/// Sends the changed assets, relative to the asset root
+pub fn asset_tick(mut asset_messages: MsgM<AssetMessage>, mut asset_watcher: LoReM<AssetWatcher>) {
+    let Some(changed_files) = asset_watcher.file_watcher.poll() else {
+        return;
+    };
+
+    let changed_assets = changed_files
+        .iter()
+        .filter_map(|changed_file| changed_file.strip_prefix(&asset_watcher.asset_root).ok())
+        .map(Path::to_path_buf)
+        .collect();
+    asset_messages.send(AssetMessage::Changed(changed_assets));
+}
+
+#[derive(Debug)]
 pub enum SaveDetectorMessage {
-    Changed,
+    /// The watched files that were created, changed or removed
+    Changed(Vec<PathBuf>),
 }
 
 pub struct SaveDetectorPlugin;
 
 impl Plugin for SaveDetectorPlugin {
     fn build(&self, app: &mut App) {
-        app.create_message_type::<SaveDetectorMessage>();
-        let (watcher, receiver) = start_watch(Path::new("scripts/")).expect("TODO: panic message");
+        let manifest = app
+            .get_resource_ref::<Manifest>()
+            .cloned()
+            .unwrap_or_default();
+
+        let script_root = manifest.script_root();
+        match FileWatcher::new(&script_root, SCRIPT_EXTENSIONS) {
+            Ok(script_watcher) => {
+                app.insert_local_resource(ScriptWatcher(script_watcher));
+                app.add_system(Update, tick);
+            }
+            // The game still runs, it is just not reloaded when the scripts are saved
+            Err(err) => warn!(
+                ?err,
+                "could not watch the scripts in {}, hot reload is disabled",
+                script_root.display()
+            ),
+        }
 
-        let file_watcher = FileWatcher { receiver, watcher };
-        app.insert_local_resource(file_watcher);
-        app.add_system(Update, tick);
+        // The watcher reports the paths below the watched path as it was given
+        let asset_root = manifest.asset_root();
+        let asset_root = asset_root.canonicalize().unwrap_or(asset_root);
+        match FileWatcher::new(&asset_root, RELOADABLE_ASSET_EXTENSIONS) {
+            Ok(file_watcher) => {
+                app.insert_local_resource(AssetWatcher {
+                    file_watcher,
+                    asset_root,
+                });
+                app.add_system(Update, asset_tick);
+            }
+            // A project without assets can still reload its scripts
+            Err(err) => warn!(
+                ?err,
+                "could not watch the assets in {}",
+                asset_root.display()
+            ),
+        }
     }
 }
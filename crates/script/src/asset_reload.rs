@@ -0,0 +1,128 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Hot reload of the PNG and bitmap font assets.
+//!
+//! A changed asset is converted again into the same asset id, so the `MaterialHandle`,
+//! `FixedAtlasHandle` and `FontAndMaterialHandle` values that the render script holds keep working
+//! and show the new pixels.
+use crate::manifest::resource_asset_root;
+use crate::render::ScriptRender;
+use image::ImageFormat;
+use limnus_assets_loader::WrappedAssetLoaderRegistry;
+use limnus_message::prelude::Message;
+use std::path::{Component, Path, PathBuf};
+use swamp::prelude::{
+    App, Font, LimnusAssets, LoReAll, LocalResourceStorage, Material, Msg, Plugin, PreUpdate,
+    ReAll, ResourceStorage,
+};
+use tracing::{info, warn};
+
+/// The file extensions of the assets that can be reloaded
+pub const RELOADABLE_ASSET_EXTENSIONS: &[&str] = &["png", "fnt"];
+
+#[derive(Message, Debug)]
+pub enum AssetMessage {
+    /// The asset files that were created or changed, relative to the asset root, e.g. `hero.png`
+    Changed(Vec<PathBuf>),
+}
+
+/// The asset name is the path relative to the asset root, always with forward slashes
+fn asset_name(relative_path: &Path) -> Option<String> {
+    relative_path
+        .components()
+        .map(|component| match component {
+            Component::Normal(name) => name.to_str(),
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+        .map(|names| names.join("/"))
+}
+
+/// Converts the file again into every asset that was loaded from it.
+/// The previous asset must be removed first, since an asset id can only be set once.
+fn reload_asset(
+    relative_path: &Path,
+    local_resources: &mut LocalResourceStorage,
+    resources: &mut ResourceStorage,
+) -> Result<usize, String> {
+    let name = asset_name(relative_path).ok_or("not a valid asset name")?;
+
+    let (materials, fonts) = local_resources
+        .get::<ScriptRender>()
+        .map(|script_render| {
+            let loaded_assets = script_render.loaded_assets();
+            (
+                loaded_assets.materials_named(&name),
+                loaded_assets.fonts_named(&name),
+            )
+        })
+        .unwrap_or_default();
+    if materials.is_empty() && fonts.is_empty() {
+        return Ok(0);
+    }
+
+    let octets = std::fs::read(resource_asset_root(resources).join(relative_path))
+        .map_err(|err| format!("could not read the file: {err}"))?;
+
+    // The material converter panics on a broken PNG, e.g. one that is only partly written
+    if !materials.is_empty() {
+        image::load_from_memory_with_format(&octets, ImageFormat::Png)
+            .map_err(|err| format!("not a valid PNG, keeping the previous pixels: {err}"))?;
+    }
+
+    let converters = resources
+        .fetch::<WrappedAssetLoaderRegistry>()
+        .value
+        .clone();
+    let converters = converters
+        .lock()
+        .map_err(|_| "the asset converters are poisoned".to_string())?;
+
+    for material_ref in &materials {
+        resources
+            .fetch_mut::<LimnusAssets<Material>>()
+            .remove(material_ref);
+        converters
+            .convert_and_insert(material_ref.into(), &octets, resources, local_resources)
+            .map_err(|err| format!("could not convert the material: {err:?}"))?;
+    }
+
+    for font_ref in &fonts {
+        resources.fetch_mut::<LimnusAssets<Font>>().remove(font_ref);
+        converters
+            .convert_and_insert(font_ref.into(), &octets, resources, local_resources)
+            .map_err(|err| {
+                format!("could not convert the font, it is missing until it is fixed: {err:?}")
+            })?;
+    }
+
+    Ok(materials.len() + fonts.len())
+}
+
+pub fn reload_tick(
+    asset_messages: Msg<AssetMessage>,
+    mut local_resources: LoReAll,
+    mut resources: ReAll,
+) {
+    for AssetMessage::Changed(changed_assets) in asset_messages.iter_previous() {
+        for changed_asset in changed_assets {
+            match reload_asset(changed_asset, &mut local_resources, &mut resources) {
+                Ok(0) => {}
+                Ok(count) => info!(asset=%changed_asset.display(), count, "reloaded asset"),
+                Err(err) => warn!(asset=%changed_asset.display(), err, "could not reload asset"),
+            }
+        }
+    }
+}
+
+/// Reloads the assets on `AssetMessage::Changed`. The changes are detected by the save detector.
+pub struct AssetReloadPlugin;
+
+impl Plugin for AssetReloadPlugin {
+    fn build(&self, app: &mut App) {
+        app.create_message_type::<AssetMessage>();
+        app.add_system(PreUpdate, reload_tick);
+    }
+}
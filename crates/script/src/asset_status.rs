@@ -0,0 +1,327 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! The load status of the assets that the render script has requested.
+//!
+//! The asset handles are returned before the files are loaded, so the script can ask `Assets` if a
+//! handle is loaded, how far the loading has come and which files that could not be found.
+//! An asset that can not be loaded is also reported as a warning, at the line in the script that
+//! requested it.
+use crate::ErrorResource;
+use crate::SourceMapResource;
+use crate::call_sites::CallSites;
+use crate::err::{Diagnostic, DiagnosticPhase, Severity};
+use crate::manifest::DEFAULT_ASSET_ROOT;
+use crate::render::ScriptRender;
+use std::path::{Path, PathBuf};
+use swamp::prelude::{
+    AssetName, Font, FontRef, LimnusAssets, LoReM, Material, MaterialRef, Re, ReM,
+};
+use swamp_script::prelude::{ExternalFunctionId, Span};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AssetLoadState {
+    Loading,
+    Loaded,
+    /// The file could not be found. It is loaded if the file is added later and hot reload is on.
+    Failed,
+}
+
+#[derive(Debug)]
+struct RequestedAsset<Ref> {
+    asset_ref: Ref,
+    state: AssetLoadState,
+    /// The asset name in the script call that requested the asset
+    requested_by: Option<Span>,
+}
+
+impl<Ref> RequestedAsset<Ref> {
+    fn new(
+        asset_ref: Ref,
+        asset_name: Option<AssetName>,
+        asset_root: &Path,
+        requested_by: Option<Span>,
+    ) -> Self {
+        let state =
+            if asset_name.is_some_and(|asset_name| asset_file_exists(asset_root, &asset_name)) {
+                AssetLoadState::Loading
+            } else {
+                AssetLoadState::Failed
+            };
+
+        Self {
+            asset_ref,
+            state,
+            requested_by,
+        }
+    }
+}
+
+/// The asset loader does not report files that can not be read, so the file is checked when the
+/// asset is requested
+fn asset_file_exists(asset_root: &Path, asset_name: &AssetName) -> bool {
+    asset_root.join(asset_name.value()).is_file()
+}
+
+/// An asset file that is read directly instead of through the asset loader, e.g. an atlas sheet,
+/// and could not be read or understood
+#[derive(Debug)]
+struct FailedFile {
+    asset_name: String,
+    reason: String,
+    requested_by: Option<Span>,
+}
+
+/// An asset that could not be loaded
+#[derive(Debug)]
+struct Failure {
+    asset_name: String,
+    reason: String,
+    requested_by: Option<Span>,
+}
+
+/// The assets that the render script has loaded
+#[derive(Debug)]
+pub struct LoadedAssets {
+    /// Where the asset files are, see `Manifest::asset_root`
+    asset_root: PathBuf,
+    materials: Vec<RequestedAsset<MaterialRef>>,
+    fonts: Vec<RequestedAsset<FontRef>>,
+    failed_files: Vec<FailedFile>,
+    /// The calls to the `Assets` functions in the scripts
+    call_sites: CallSites,
+    /// The call that the assets are requested by, see `set_requested_by`
+    requested_by: Option<Span>,
+}
+
+impl Default for LoadedAssets {
+    fn default() -> Self {
+        Self::new(PathBuf::from(DEFAULT_ASSET_ROOT), CallSites::default())
+    }
+}
+
+impl LoadedAssets {
+    #[must_use]
+    pub const fn new(asset_root: PathBuf, call_sites: CallSites) -> Self {
+        Self {
+            asset_root,
+            materials: Vec::new(),
+            fonts: Vec::new(),
+            failed_files: Vec::new(),
+            call_sites,
+            requested_by: None,
+        }
+    }
+
+    /// Called by an `Assets` function before it loads anything, so the assets that it adds are
+    /// reported at the call, e.g. the tileset images of a map.
+    pub fn set_requested_by(&mut self, function_id: ExternalFunctionId, asset_name: &str) {
+        self.requested_by = self.call_sites.span(function_id, asset_name);
+    }
+
+    /// The directory that the assets are read from
+    #[must_use]
+    pub fn asset_root(&self) -> &Path {
+        &self.asset_root
+    }
+
+    pub fn add_material(&mut self, material_ref: &MaterialRef) {
+        if self.material_state(material_ref).is_none() {
+            self.materials.push(RequestedAsset::new(
+                material_ref.clone(),
+                material_ref.asset_name(),
+                &self.asset_root,
+                self.requested_by.clone(),
+            ));
+        }
+    }
+
+    pub fn add_font(&mut self, font_ref: &FontRef) {
+        if self.font_state(font_ref).is_none() {
+            self.fonts.push(RequestedAsset::new(
+                font_ref.clone(),
+                font_ref.asset_name(),
+                &self.asset_root,
+                self.requested_by.clone(),
+            ));
+        }
+    }
+
+    /// For the files that are read directly, e.g. `hero.json`
+    pub fn add_failed_file(&mut self, asset_name: &str, reason: &str) {
+        if !self
+            .failed_files
+            .iter()
+            .any(|failed_file| failed_file.asset_name == asset_name)
+        {
+            self.failed_files.push(FailedFile {
+                asset_name: asset_name.to_string(),
+                reason: reason.to_string(),
+                requested_by: self.requested_by.clone(),
+            });
+        }
+    }
+
+    /// `None` if the material has not been requested by the script
+    #[must_use]
+    pub fn material_state(&self, material_ref: &MaterialRef) -> Option<AssetLoadState> {
+        self.materials
+            .iter()
+            .find(|requested| requested.asset_ref == *material_ref)
+            .map(|requested| requested.state)
+    }
+
+    /// `None` if the font has not been requested by the script
+    #[must_use]
+    pub fn font_state(&self, font_ref: &FontRef) -> Option<AssetLoadState> {
+        self.fonts
+            .iter()
+            .find(|requested| requested.asset_ref == *font_ref)
+            .map(|requested| requested.state)
+    }
+
+    /// The part of the requested assets that are done, from 0.0 to 1.0.
+    /// Failed assets are counted as done, so a loading screen does not wait for them forever.
+    #[must_use]
+    pub fn load_progress(&self) -> f32 {
+        let states = self.states();
+        let total = states.clone().count();
+        if total == 0 {
+            return 1.0;
+        }
+        let done = states
+            .filter(|state| *state != AssetLoadState::Loading)
+            .count();
+
+        done as f32 / total as f32
+    }
+
+    fn states(&self) -> impl Iterator<Item = AssetLoadState> + Clone {
+        self.materials
+            .iter()
+            .map(|requested| requested.state)
+            .chain(self.fonts.iter().map(|requested| requested.state))
+            .chain(self.failed_files.iter().map(|_| AssetLoadState::Failed))
+    }
+
+    /// The names of the assets that could not be loaded, e.g. `player/ship.png`
+    #[must_use]
+    pub fn failed_assets(&self) -> Vec<String> {
+        self.failures()
+            .into_iter()
+            .map(|failure| failure.asset_name)
+            .collect()
+    }
+
+    /// The assets that could not be loaded, with the reason
+    fn failures(&self) -> Vec<Failure> {
+        let materials = self
+            .materials
+            .iter()
+            .filter(|requested| requested.state == AssetLoadState::Failed)
+            .filter_map(|requested| {
+                Some((requested.asset_ref.asset_name()?, &requested.requested_by))
+            });
+        let fonts = self
+            .fonts
+            .iter()
+            .filter(|requested| requested.state == AssetLoadState::Failed)
+            .filter_map(|requested| {
+                Some((requested.asset_ref.asset_name()?, &requested.requested_by))
+            });
+
+        let mut failures: Vec<Failure> = Vec::new();
+        for (asset_name, requested_by) in materials.chain(fonts) {
+            let name = asset_name.value().to_string();
+            if !failures.iter().any(|failure| failure.asset_name == name) {
+                let reason = format!(
+                    "could not find the asset '{name}' in {}",
+                    self.asset_root.display()
+                );
+                failures.push(Failure {
+                    asset_name: name,
+                    reason,
+                    requested_by: requested_by.clone(),
+                });
+            }
+        }
+        for failed_file in &self.failed_files {
+            failures.push(Failure {
+                asset_name: failed_file.asset_name.clone(),
+                reason: failed_file.reason.clone(),
+                requested_by: failed_file.requested_by.clone(),
+            });
+        }
+        failures
+    }
+
+    /// Marks the assets that have been converted since the last update as loaded
+    pub fn update(&mut self, materials: &LimnusAssets<Material>, fonts: &LimnusAssets<Font>) {
+        for requested in &mut self.materials {
+            if requested.state != AssetLoadState::Loaded && materials.contains(&requested.asset_ref)
+            {
+                requested.state = AssetLoadState::Loaded;
+            }
+        }
+        for requested in &mut self.fonts {
+            if requested.state != AssetLoadState::Loaded && fonts.contains(&requested.asset_ref) {
+                requested.state = AssetLoadState::Loaded;
+            }
+        }
+    }
+
+    pub(crate) fn materials_named(&self, name: &str) -> Vec<MaterialRef> {
+        self.materials
+            .iter()
+            .filter(|requested| has_asset_name(requested.asset_ref.asset_name(), name))
+            .map(|requested| requested.asset_ref.clone())
+            .collect()
+    }
+
+    pub(crate) fn fonts_named(&self, name: &str) -> Vec<FontRef> {
+        self.fonts
+            .iter()
+            .filter(|requested| has_asset_name(requested.asset_ref.asset_name(), name))
+            .map(|requested| requested.asset_ref.clone())
+            .collect()
+    }
+}
+
+fn has_asset_name(asset_name: Option<AssetName>, name: &str) -> bool {
+    asset_name.is_some_and(|asset_name| asset_name.value() == name)
+}
+
+/// Updates the load status and reports the assets that could not be loaded as warnings.
+/// The warnings do not stop the game, a loading screen can show `failed_assets()` instead.
+pub fn asset_status_tick(
+    mut script_render: LoReM<ScriptRender>,
+    materials: Re<LimnusAssets<Material>>,
+    fonts: Re<LimnusAssets<Font>>,
+    mut error: ReM<ErrorResource>,
+    source_map: Re<SourceMapResource>,
+) {
+    let loaded_assets = script_render.loaded_assets_mut();
+    loaded_assets.update(&materials, &fonts);
+
+    for failure in loaded_assets.failures() {
+        if error
+            .diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.message == failure.reason)
+        {
+            continue;
+        }
+
+        let mut diagnostic = Diagnostic::new(
+            DiagnosticPhase::Boot,
+            &failure.reason,
+            failure.requested_by.unwrap_or_default(),
+            &source_map.source_map,
+        );
+        diagnostic.severity = Severity::Warning;
+        warn!("{diagnostic}");
+        error.push(diagnostic);
+    }
+}
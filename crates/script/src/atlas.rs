@@ -0,0 +1,407 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Sprite atlases with named frames of any size, exported from Aseprite or TexturePacker.
+//!
+//! Both tools export a JSON sheet with the frames either as a hash (`{"name": {...}}`) or as an
+//! array (`[{"filename": "name", ...}]`), and the image file in `meta.image`. Aseprite also exports
+//! the frame durations and the animation tags in `meta.frameTags`.
+use serde_json::Value as JsonValue;
+use swamp::prelude::{MaterialRef, URect, UVec2};
+
+/// The order that the frames in a tag are played in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagDirection {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameTag {
+    pub name: String,
+    /// Index of the first frame in the tag
+    pub from: usize,
+    /// Index of the last frame in the tag, inclusive
+    pub to: usize,
+    pub direction: TagDirection,
+}
+
+#[derive(Debug, Clone)]
+pub struct AtlasFrame {
+    pub name: String,
+    /// Where the frame is in the texture, from the upper left corner
+    pub rect: URect,
+    /// How far the trimmed frame is from the lower left corner of the original frame
+    pub offset: UVec2,
+    /// How long the frame is shown, in milliseconds. Only Aseprite exports it.
+    pub duration_ms: Option<u32>,
+}
+
+// URect does not implement PartialEq
+impl PartialEq for AtlasFrame {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.rect.position == other.rect.position
+            && self.rect.size == other.rect.size
+            && self.offset == other.offset
+            && self.duration_ms == other.duration_ms
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AtlasSheet {
+    /// The image file, relative to the sheet
+    pub image: String,
+    pub frames: Vec<AtlasFrame>,
+    pub tags: Vec<FrameTag>,
+}
+
+impl AtlasSheet {
+    /// # Errors
+    ///
+    /// If the text is not a valid Aseprite or TexturePacker JSON sheet.
+    pub fn from_json(text: &str) -> Result<Self, String> {
+        let root: JsonValue = serde_json::from_str(text).map_err(|err| err.to_string())?;
+
+        let frames = match root.get("frames") {
+            Some(JsonValue::Object(members)) => members
+                .iter()
+                .map(|(name, frame)| parse_frame(name, frame))
+                .collect::<Result<Vec<_>, _>>()?,
+            Some(JsonValue::Array(items)) => items
+                .iter()
+                .map(|frame| {
+                    let name = frame
+                        .get("filename")
+                        .and_then(JsonValue::as_str)
+                        .ok_or("a frame is missing its 'filename'")?;
+                    parse_frame(name, frame)
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err("expected 'frames' as an object or an array".to_string()),
+        };
+
+        let meta = root.get("meta").ok_or("missing 'meta'")?;
+        let image = meta
+            .get("image")
+            .and_then(JsonValue::as_str)
+            .ok_or("missing 'meta.image'")?
+            .to_string();
+
+        let tags = meta
+            .get("frameTags")
+            .and_then(JsonValue::as_array)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+            .iter()
+            .map(|tag| parse_tag(tag, frames.len()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Self {
+            image,
+            frames,
+            tags,
+        })
+    }
+}
+
+/// A whole number that is zero or positive and fits in an `u32`
+fn as_u32(value: &JsonValue) -> Option<u32> {
+    value.as_u64().and_then(|number| u32::try_from(number).ok())
+}
+
+fn field_u32(value: &JsonValue, field: &str, owner: &str) -> Result<u32, String> {
+    value
+        .get(field)
+        .and_then(as_u32)
+        .ok_or_else(|| format!("expected '{field}' in {owner} to be a whole number"))
+}
+
+/// The pixel coordinates and sizes in the texture
+fn field_u16(value: &JsonValue, field: &str, owner: &str) -> Result<u16, String> {
+    u16::try_from(field_u32(value, field, owner)?)
+        .map_err(|_| format!("expected '{field}' in {owner} to be at most {}", u16::MAX))
+}
+
+fn parse_rect(value: &JsonValue, owner: &str) -> Result<URect, String> {
+    Ok(URect::new(
+        field_u16(value, "x", owner)?,
+        field_u16(value, "y", owner)?,
+        field_u16(value, "w", owner)?,
+        field_u16(value, "h", owner)?,
+    ))
+}
+
+fn parse_frame(name: &str, frame: &JsonValue) -> Result<AtlasFrame, String> {
+    let owner = format!("frame '{name}'");
+    if frame.get("rotated").and_then(JsonValue::as_bool) == Some(true) {
+        return Err(format!(
+            "{owner} is rotated, turn off rotation in the export settings"
+        ));
+    }
+
+    let rect = parse_rect(
+        frame
+            .get("frame")
+            .ok_or_else(|| format!("{owner} is missing 'frame'"))?,
+        &owner,
+    )?;
+
+    // A trimmed frame is drawn where it was in the original frame. Y goes up when drawing.
+    let offset = match (frame.get("spriteSourceSize"), frame.get("sourceSize")) {
+        (Some(sprite_source), Some(source_size)) => {
+            let trimmed = parse_rect(sprite_source, &owner)?;
+            let source_height = field_u16(source_size, "h", &owner)?;
+            UVec2::new(
+                trimmed.position.x,
+                source_height.saturating_sub(trimmed.position.y + trimmed.size.y),
+            )
+        }
+        _ => UVec2::new(0, 0),
+    };
+
+    Ok(AtlasFrame {
+        name: name.to_string(),
+        rect,
+        offset,
+        duration_ms: frame.get("duration").and_then(as_u32),
+    })
+}
+
+fn parse_tag(tag: &JsonValue, frame_count: usize) -> Result<FrameTag, String> {
+    let name = tag
+        .get("name")
+        .and_then(JsonValue::as_str)
+        .ok_or("a frame tag is missing its 'name'")?;
+    let owner = format!("frame tag '{name}'");
+    let from = field_u32(tag, "from", &owner)? as usize;
+    let to = field_u32(tag, "to", &owner)? as usize;
+    if from > to || to >= frame_count {
+        return Err(format!(
+            "{owner} uses frames {from} to {to}, but there are {frame_count} frames"
+        ));
+    }
+
+    let direction = match tag.get("direction").and_then(JsonValue::as_str) {
+        None | Some("forward") => TagDirection::Forward,
+        Some("reverse") => TagDirection::Reverse,
+        Some("pingpong") => TagDirection::PingPong,
+        Some("pingpong_reverse") => TagDirection::PingPongReverse,
+        Some(other) => return Err(format!("{owner} has an unknown direction '{other}'")),
+    };
+
+    Ok(FrameTag {
+        name: name.to_string(),
+        from,
+        to,
+        direction,
+    })
+}
+
+/// A loaded sheet and the material with its image.
+/// The material is missing if the sheet could not be loaded, then nothing is drawn.
+#[derive(Debug, PartialEq)]
+pub struct NamedAtlas {
+    pub material: Option<MaterialRef>,
+    pub frames: Vec<AtlasFrame>,
+    pub tags: Vec<FrameTag>,
+}
+
+impl NamedAtlas {
+    #[must_use]
+    pub fn frame(&self, name: &str) -> Option<&AtlasFrame> {
+        self.frames.iter().find(|frame| frame.name == name)
+    }
+
+    #[must_use]
+    pub fn tag(&self, name: &str) -> Option<&FrameTag> {
+        self.tags.iter().find(|tag| tag.name == name)
+    }
+
+    /// The frames of the tag, in the order that the tag plays them.
+    /// A ping-pong tag does not repeat the frames at the turns.
+    #[must_use]
+    pub fn tag_frames(&self, tag: &FrameTag) -> Vec<&AtlasFrame> {
+        let forward = &self.frames[tag.from..=tag.to];
+        let mut frames: Vec<&AtlasFrame> = match tag.direction {
+            TagDirection::Forward | TagDirection::PingPong => forward.iter().collect(),
+            TagDirection::Reverse | TagDirection::PingPongReverse => forward.iter().rev().collect(),
+        };
+        if matches!(
+            tag.direction,
+            TagDirection::PingPong | TagDirection::PingPongReverse
+        ) && frames.len() > 2
+        {
+            let back = frames[1..frames.len() - 1]
+                .iter()
+                .rev()
+                .copied()
+                .collect::<Vec<_>>();
+            frames.extend(back);
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH_SHEET: &str = r#"{
+        "frames": {
+            "idle 0.aseprite": {
+                "frame": {"x": 0, "y": 0, "w": 16, "h": 24},
+                "rotated": false,
+                "trimmed": true,
+                "spriteSourceSize": {"x": 2, "y": 4, "w": 16, "h": 24},
+                "sourceSize": {"w": 20, "h": 32},
+                "duration": 100
+            },
+            "idle 1.aseprite": {
+                "frame": {"x": 16, "y": 0, "w": 16, "h": 24},
+                "duration": 150
+            },
+            "idle 2.aseprite": {
+                "frame": {"x": 32, "y": 0, "w": 16, "h": 24},
+                "duration": 100
+            }
+        },
+        "meta": {
+            "image": "hero.png",
+            "frameTags": [
+                {"name": "idle", "from": 0, "to": 2, "direction": "pingpong"},
+                {"name": "blink", "from": 1, "to": 1}
+            ]
+        }
+    }"#;
+
+    const ARRAY_SHEET: &str = r#"{
+        "frames": [
+            {"filename": "coin", "frame": {"x": 8, "y": 16, "w": 8, "h": 8}},
+            {"filename": "gem", "frame": {"x": 0, "y": 0, "w": 12, "h": 10}}
+        ],
+        "meta": {"image": "items.png"}
+    }"#;
+
+    #[test]
+    fn hash_frames() {
+        let sheet = AtlasSheet::from_json(HASH_SHEET).unwrap();
+
+        assert_eq!(sheet.image, "hero.png");
+        assert_eq!(sheet.frames.len(), 3);
+        let first = &sheet.frames[0];
+        assert_eq!(first.name, "idle 0.aseprite");
+        assert_eq!(first.rect.position, UVec2::new(0, 0));
+        assert_eq!(first.rect.size, UVec2::new(16, 24));
+        // 32 - (4 + 24) from the bottom of the original frame
+        assert_eq!(first.offset, UVec2::new(2, 4));
+        assert_eq!(first.duration_ms, Some(100));
+        assert_eq!(sheet.frames[1].offset, UVec2::new(0, 0));
+        assert_eq!(sheet.frames[1].duration_ms, Some(150));
+
+        assert_eq!(
+            sheet.tags,
+            [
+                FrameTag {
+                    name: "idle".to_string(),
+                    from: 0,
+                    to: 2,
+                    direction: TagDirection::PingPong,
+                },
+                FrameTag {
+                    name: "blink".to_string(),
+                    from: 1,
+                    to: 1,
+                    direction: TagDirection::Forward,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn array_frames() {
+        let sheet = AtlasSheet::from_json(ARRAY_SHEET).unwrap();
+
+        assert_eq!(sheet.image, "items.png");
+        let names: Vec<_> = sheet
+            .frames
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .collect();
+        assert_eq!(names, ["coin", "gem"]);
+        assert_eq!(sheet.frames[0].rect.position, UVec2::new(8, 16));
+        assert_eq!(sheet.frames[1].rect.size, UVec2::new(12, 10));
+        assert_eq!(sheet.frames[1].duration_ms, None);
+        assert!(sheet.tags.is_empty());
+    }
+
+    #[test]
+    fn ping_pong_does_not_repeat_the_turns() {
+        let sheet = AtlasSheet::from_json(HASH_SHEET).unwrap();
+        let atlas = NamedAtlas {
+            material: None,
+            frames: sheet.frames,
+            tags: sheet.tags,
+        };
+
+        let tag = atlas.tag("idle").unwrap();
+        let names: Vec<_> = atlas
+            .tag_frames(tag)
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "idle 0.aseprite",
+                "idle 1.aseprite",
+                "idle 2.aseprite",
+                "idle 1.aseprite"
+            ]
+        );
+    }
+
+    #[test]
+    fn hash_frames_keep_the_order_in_the_file() {
+        let sheet = AtlasSheet::from_json(
+            r#"{
+                "frames": {
+                    "walk 1": {"frame": {"x": 0, "y": 0, "w": 1, "h": 1}},
+                    "walk 0": {"frame": {"x": 1, "y": 0, "w": 1, "h": 1}}
+                },
+                "meta": {"image": "walk.png"}
+            }"#,
+        )
+        .unwrap();
+
+        let names: Vec<_> = sheet
+            .frames
+            .iter()
+            .map(|frame| frame.name.as_str())
+            .collect();
+        assert_eq!(names, ["walk 1", "walk 0"]);
+    }
+
+    #[test]
+    fn malformed_sheets() {
+        for text in [
+            "{",
+            r#"{"meta": {"image": "a.png"}}"#,
+            r#"{"frames": {}}"#,
+            r#"{"frames": [{"frame": {"x": 0, "y": 0, "w": 1, "h": 1}}], "meta": {"image": "a.png"}}"#,
+            r#"{"frames": {"a": {"frame": {"x": 0, "y": 0, "w": 1}}}, "meta": {"image": "a.png"}}"#,
+            r#"{"frames": {"a": {"frame": {"x": 0, "y": 0, "w": 70000, "h": 1}}}, "meta": {"image": "a.png"}}"#,
+            r#"{"frames": {"a": {"frame": {"x": 0, "y": 0, "w": 1, "h": 1}, "rotated": true}}, "meta": {"image": "a.png"}}"#,
+            r#"{"frames": {"a": {"frame": {"x": 0, "y": 0, "w": 1, "h": 1}}}, "meta": {"image": "a.png", "frameTags": [{"name": "t", "from": 0, "to": 1}]}}"#,
+            r#"{"frames": {"a": {"frame": {"x": 0, "y": 0, "w": 1, "h": 1}}}, "meta": {"image": "a.png", "frameTags": [{"name": "t", "from": 0, "to": 0, "direction": "up"}]}}"#,
+        ] {
+            assert!(
+                AtlasSheet::from_json(text).is_err(),
+                "'{text}' should not load"
+            );
+        }
+    }
+}
@@ -2,3 +2,1114 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
+use crate::contract::{UPDATE_PARAMETER_COUNTS, required_struct, required_type};
+use crate::err::{DiagnosticPhase, report_mangrove_error, report_runtime_error};
+use crate::script::{MangroveError, create_empty_struct_value, register_print};
+use crate::script_main::ScriptMain;
+use crate::simulation::ScriptSimulation;
+use crate::timestep::SimulationTimestep;
+use crate::util::get_impl_func;
+use crate::{ErrorResource, ScriptMessage, SourceMapResource, external_type_number};
+use cpal::traits::{DeviceTrait, StreamTrait};
+use limnus_audio_device::low_level::Audio;
+use limnus_audio_mixer::{StereoSample, StereoSampleRef};
+use monotonic_time_rs::Millis;
+use oddio::{Cycle, Frames, FramesSignal, Gain, GainControl, Mixed, Mixer, MixerControl, Sample};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use swamp::prelude::{
+    App, Assets, GameAssets, LimnusAssets, LoRe, LoReM, LocalResource, Msg, Plugin, Re, ReAll, ReM,
+    ResourceStorage, Update,
+};
+use swamp_script::prelude::*;
+use tracing::{debug, error, trace, warn};
+
+pub type StereoFrames = Arc<Frames<[Sample; 2]>>;
+pub type VoiceId = u32;
+
+/// The sink that the script audio is played through. Implement it to route audio somewhere else,
+/// e.g. to a file or to nowhere at all when running tests.
+pub trait AudioBackend: Debug {
+    /// Starts playing the frames and returns an id that can be used to control the playback
+    fn play(&mut self, frames: &StereoFrames, looping: bool, volume: f32) -> VoiceId;
+
+    fn stop(&mut self, voice: VoiceId);
+
+    /// Volume is an amplitude ratio, 0.0 is silent and 1.0 is the original volume
+    fn set_volume(&mut self, voice: VoiceId, volume: f32);
+
+    /// Called once every simulation tick, after all the commands for that tick has been issued
+    fn tick(&mut self) {}
+}
+
+/// Keeps track of the voices playing in an oddio mixer
+struct OddioVoices {
+    mixer_control: MixerControl<[Sample; 2]>,
+    voices: HashMap<VoiceId, (Mixed, GainControl)>,
+    next_voice_id: VoiceId,
+}
+
+impl OddioVoices {
+    fn new(mixer_control: MixerControl<[Sample; 2]>) -> Self {
+        Self {
+            mixer_control,
+            voices: HashMap::new(),
+            next_voice_id: 0,
+        }
+    }
+
+    fn play(&mut self, frames: &StereoFrames, looping: bool, volume: f32) -> VoiceId {
+        self.voices.retain(|_, (mixed, _)| !mixed.is_stopped());
+
+        let (gain_control, mixed) = if looping {
+            let (gain_control, mut gain) = Gain::new(Cycle::new(frames.clone()));
+            gain.set_amplitude_ratio(volume);
+            (gain_control, self.mixer_control.play(gain))
+        } else {
+            let (gain_control, mut gain) = Gain::new(FramesSignal::from(frames.clone()));
+            gain.set_amplitude_ratio(volume);
+            (gain_control, self.mixer_control.play(gain))
+        };
+
+        let voice_id = self.next_voice_id;
+        self.next_voice_id = self.next_voice_id.wrapping_add(1);
+        self.voices.insert(voice_id, (mixed, gain_control));
+
+        voice_id
+    }
+
+    fn stop(&mut self, voice: VoiceId) {
+        if let Some((mut mixed, _)) = self.voices.remove(&voice) {
+            mixed.stop();
+        }
+    }
+
+    fn set_volume(&mut self, voice: VoiceId, volume: f32) {
+        if let Some((_, gain_control)) = self.voices.get_mut(&voice) {
+            gain_control.set_amplitude_ratio(volume);
+        }
+    }
+}
+
+/// Plays the audio on the default audio device, using its own output stream.
+pub struct DeviceAudioBackend {
+    voices: OddioVoices,
+    #[allow(dead_code)]
+    stream: cpal::Stream,
+}
+
+impl Debug for DeviceAudioBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "DeviceAudioBackend ({} voices)",
+            self.voices.voices.len()
+        )
+    }
+}
+
+impl DeviceAudioBackend {
+    /// # Errors
+    ///
+    pub fn new(audio: &Audio) -> Result<Self, Box<dyn std::error::Error>> {
+        let (mixer_control, mixer) = Mixer::<[Sample; 2]>::new();
+        let mixer = Arc::new(Mutex::new(mixer));
+        let sample_rate = audio.sample_rate();
+
+        let stream = audio.device().build_output_stream(
+            audio.config(),
+            move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                let out_frames = oddio::frame_stereo(data);
+                oddio::run(&mut *mixer.lock().unwrap(), sample_rate, out_frames);
+            },
+            move |err| {
+                error!(?err, "audio stream error");
+            },
+            None,
+        )?;
+        stream.play()?;
+
+        Ok(Self {
+            voices: OddioVoices::new(mixer_control),
+            stream,
+        })
+    }
+}
+
+impl AudioBackend for DeviceAudioBackend {
+    fn play(&mut self, frames: &StereoFrames, looping: bool, volume: f32) -> VoiceId {
+        self.voices.play(frames, looping, volume)
+    }
+
+    fn stop(&mut self, voice: VoiceId) {
+        self.voices.stop(voice);
+    }
+
+    fn set_volume(&mut self, voice: VoiceId, volume: f32) {
+        self.voices.set_volume(voice, volume);
+    }
+}
+
+/// Accepts all commands, but doesn't output anything. Used when there is no audio device.
+#[derive(Debug, Default)]
+pub struct NullAudioBackend {
+    next_voice_id: VoiceId,
+}
+
+impl AudioBackend for NullAudioBackend {
+    fn play(&mut self, _frames: &StereoFrames, _looping: bool, _volume: f32) -> VoiceId {
+        let voice_id = self.next_voice_id;
+        self.next_voice_id = self.next_voice_id.wrapping_add(1);
+        voice_id
+    }
+
+    fn stop(&mut self, _voice: VoiceId) {}
+
+    fn set_volume(&mut self, _voice: VoiceId, _volume: f32) {}
+}
+
+/// Mixes the audio and writes a fixed number of frames to a wav file every tick.
+/// Since it is driven by the ticks and not a clock, the output is deterministic.
+pub struct WavCaptureAudioBackend {
+    voices: OddioVoices,
+    mixer: Mixer<[Sample; 2]>,
+    sample_rate: u32,
+    buffer: Vec<[Sample; 2]>,
+    writer: Option<hound::WavWriter<BufWriter<File>>>,
+}
+
+impl Debug for WavCaptureAudioBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "WavCaptureAudioBackend ({} Hz, {} frames per tick)",
+            self.sample_rate,
+            self.buffer.len()
+        )
+    }
+}
+
+impl WavCaptureAudioBackend {
+    /// # Errors
+    ///
+    pub fn new(path: &Path, sample_rate: u32, ticks_per_second: u32) -> Result<Self, hound::Error> {
+        let spec = hound::WavSpec {
+            channels: 2,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let writer = hound::WavWriter::create(path, spec)?;
+        let (mixer_control, mixer) = Mixer::<[Sample; 2]>::new();
+        let frames_per_tick = (sample_rate / ticks_per_second.max(1)) as usize;
+
+        Ok(Self {
+            voices: OddioVoices::new(mixer_control),
+            mixer,
+            sample_rate,
+            buffer: vec![[0.0; 2]; frames_per_tick],
+            writer: Some(writer),
+        })
+    }
+
+    /// Writes the wav header. Is also done automatically when dropped.
+    ///
+    /// # Errors
+    ///
+    pub fn finalize(&mut self) -> Result<(), hound::Error> {
+        if let Some(writer) = self.writer.take() {
+            writer.finalize()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WavCaptureAudioBackend {
+    fn drop(&mut self) {
+        if let Err(err) = self.finalize() {
+            error!(?err, "could not finalize captured wav");
+        }
+    }
+}
+
+impl AudioBackend for WavCaptureAudioBackend {
+    fn play(&mut self, frames: &StereoFrames, looping: bool, volume: f32) -> VoiceId {
+        self.voices.play(frames, looping, volume)
+    }
+
+    fn stop(&mut self, voice: VoiceId) {
+        self.voices.stop(voice);
+    }
+
+    fn set_volume(&mut self, voice: VoiceId, volume: f32) {
+        self.voices.set_volume(voice, volume);
+    }
+
+    fn tick(&mut self) {
+        oddio::run(&mut self.mixer, self.sample_rate, &mut self.buffer);
+
+        if let Some(writer) = &mut self.writer {
+            for frame in &self.buffer {
+                for sample in frame {
+                    if let Err(err) = writer.write_sample(*sample) {
+                        error!(?err, "could not write captured audio");
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Holds the backend that all script audio is played through. Insert it before adding the
+/// `ScriptAudioPlugin` to override the default backend.
+#[derive(LocalResource, Debug)]
+pub struct AudioOutput {
+    backend: Box<dyn AudioBackend>,
+}
+
+impl AudioOutput {
+    pub fn new(backend: impl AudioBackend + 'static) -> Self {
+        Self {
+            backend: Box::new(backend),
+        }
+    }
+
+    pub fn backend_mut(&mut self) -> &mut dyn AudioBackend {
+        self.backend.as_mut()
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct SoundWrapper(pub StereoSampleRef);
+
+impl QuickSerialize for SoundWrapper {}
+
+impl Display for SoundWrapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "sound {}", self.0)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct MusicWrapper {
+    pub index: usize,
+    pub sample: StereoSampleRef,
+}
+
+impl QuickSerialize for MusicWrapper {}
+
+impl Display for MusicWrapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "music {} {}", self.index, self.sample)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from_volume: f32,
+    to_volume: f32,
+    ticks_left: u32,
+    total_ticks: u32,
+    stop_when_done: bool,
+}
+
+impl Fade {
+    fn volume(&self) -> f32 {
+        let progress = 1.0 - self.ticks_left as f32 / self.total_ticks as f32;
+        (self.to_volume - self.from_volume).mul_add(progress, self.from_volume)
+    }
+}
+
+#[derive(Debug)]
+pub struct MusicTrack {
+    sample: StereoSampleRef,
+    volume: f32,
+    fade: Option<Fade>,
+    is_playing: bool,
+    voice: Option<VoiceId>,
+}
+
+impl MusicTrack {
+    const fn new(sample: StereoSampleRef) -> Self {
+        Self {
+            sample,
+            volume: 1.0,
+            fade: None,
+            is_playing: false,
+            voice: None,
+        }
+    }
+
+    fn current_volume(&self) -> f32 {
+        self.fade.as_ref().map_or(self.volume, Fade::volume)
+    }
+
+    fn stop(&mut self, backend: &mut dyn AudioBackend) {
+        self.is_playing = false;
+        self.fade = None;
+        if let Some(voice) = self.voice.take() {
+            backend.stop(voice);
+        }
+    }
+}
+
+/// Commands issued by the script during a tick. They are applied to the backend after the script call.
+#[derive(Debug)]
+pub enum AudioCommand {
+    Play(StereoSampleRef),
+    PlayMusic(usize),
+    Stop(usize),
+    SetVolume(usize, f32),
+    Crossfade {
+        current: usize,
+        next: usize,
+        ticks: u32,
+    },
+}
+
+#[derive(Debug)]
+pub struct ScriptAudioContext {
+    pub(crate) audio_assets: Option<AudioAssetsWrapper>,
+    pub(crate) music_tracks: Vec<MusicTrack>,
+    pub(crate) commands: Vec<AudioCommand>,
+}
+
+// Same pointer magic as the `GameAssetsWrapper`, since GameAssets borrows the resource storage.
+#[derive(Debug)]
+pub struct AudioAssetsWrapper {
+    game_assets: *mut GameAssets<'static>,
+
+    struct_types: AudioNamedStructTypes,
+    external_types: AudioExternalTypes,
+}
+
+impl AudioAssetsWrapper {
+    pub fn new(
+        game_assets: &mut GameAssets,
+        struct_types: AudioNamedStructTypes,
+        external_types: AudioExternalTypes,
+    ) -> Self {
+        let ptr = game_assets as *mut GameAssets;
+        Self {
+            game_assets: ptr.cast::<GameAssets<'static>>(),
+            struct_types,
+            external_types,
+        }
+    }
+
+    fn audio_sample_wav(&self, name: &str) -> StereoSampleRef {
+        // Safety: We assume the GameAssets pointer is still valid, since the AudioAssetsWrapper is short-lived (only alive during boot)
+        let assets: &mut GameAssets;
+        unsafe {
+            assets = &mut *self.game_assets;
+        }
+        assets.audio_sample_wav(name)
+    }
+
+    #[must_use]
+    pub fn sound_wav(&self, name: &str) -> Value {
+        let sample = self.audio_sample_wav(name);
+        let sound_ref = Rc::new(RefCell::new(Value::RustValue(
+            self.external_types.sound.clone(),
+            Rc::new(RefCell::new(Box::new(SoundWrapper(sample)))),
+        )));
+
+        Value::NamedStruct(self.struct_types.sound.clone(), [sound_ref].to_vec())
+    }
+
+    #[must_use]
+    pub fn music_wav(&self, name: &str, music_tracks: &mut Vec<MusicTrack>) -> Value {
+        let sample = self.audio_sample_wav(name);
+        let index = music_tracks.len();
+        music_tracks.push(MusicTrack::new(sample.clone()));
+
+        let music_ref = Rc::new(RefCell::new(Value::RustValue(
+            self.external_types.music.clone(),
+            Rc::new(RefCell::new(Box::new(MusicWrapper { index, sample }))),
+        )));
+
+        Value::NamedStruct(self.struct_types.music.clone(), [music_ref].to_vec())
+    }
+}
+
+fn music_index(value: &Value) -> Result<usize, ValueError> {
+    value
+        .downcast_hidden_rust::<MusicWrapper>()
+        .map(|music| music.borrow().index)
+        .ok_or_else(|| ValueError::TypeError("not a music handle".to_string()))
+}
+
+/// The assets are only available while `audio()` creates the audio struct
+fn audio_assets_not_available() -> ValueError {
+    ValueError::TypeError("AudioAssets can only be used in audio()".to_string())
+}
+
+/// # Errors
+///
+/// # Panics
+///
+pub fn register_audio_assets_members(
+    symbol_table: &SymbolTable,
+    associated_impls: &AssociatedImpls,
+    externals: &mut ExternalFunctions<ScriptAudioContext>,
+) -> Result<(), MangroveError> {
+    let audio_assets_struct = required_type(symbol_table, "AudioAssets")?;
+
+    // sound_wav
+    let sound_wav_function_id =
+        associated_impls.api_fetch_external_function_id(audio_assets_struct, "sound_wav");
+    externals.register_external_function(
+        sound_wav_function_id,
+        move |mem_values: &[VariableValue], context| {
+            //let self_value = &params[0]; // AudioAssets is, by design, an empty struct
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to sound wav function");
+            let asset_name = &params[1].expect_string()?;
+
+            let audio_assets = context
+                .audio_assets
+                .as_mut()
+                .ok_or_else(audio_assets_not_available)?;
+
+            Ok(audio_assets.sound_wav(asset_name))
+        },
+    )?;
+
+    // music_wav
+    let music_wav_function_id =
+        associated_impls.api_fetch_external_function_id(audio_assets_struct, "music_wav");
+    externals.register_external_function(
+        music_wav_function_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to music wav function");
+            let asset_name = &params[1].expect_string()?;
+
+            let audio_assets = context
+                .audio_assets
+                .as_mut()
+                .ok_or_else(audio_assets_not_available)?;
+
+            Ok(audio_assets.music_wav(asset_name, &mut context.music_tracks))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// # Errors
+///
+/// # Panics
+///
+pub fn register_audio_members(
+    symbol_table: &SymbolTable,
+    associated_impls: &AssociatedImpls,
+    externals: &mut ExternalFunctions<ScriptAudioContext>,
+) -> Result<(), MangroveError> {
+    let audio_struct = required_type(symbol_table, "Audio")?;
+
+    // play
+    let play_fn_id = associated_impls.api_fetch_external_function_id(audio_struct, "play");
+    externals.register_external_function(
+        play_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            //let _self_value = &params[0]; // the Audio struct is empty by design.
+            let sound = params[1]
+                .downcast_hidden_rust::<SoundWrapper>()
+                .ok_or_else(|| ValueError::TypeError("not a sound handle".to_string()))?;
+
+            context
+                .commands
+                .push(AudioCommand::Play(sound.borrow().0.clone()));
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    // play_music
+    let play_music_fn_id =
+        associated_impls.api_fetch_external_function_id(audio_struct, "play_music");
+    externals.register_external_function(
+        play_music_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let music = music_index(&params[1])?;
+
+            context.commands.push(AudioCommand::PlayMusic(music));
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    // stop
+    let stop_fn_id = associated_impls.api_fetch_external_function_id(audio_struct, "stop");
+    externals.register_external_function(
+        stop_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let music = music_index(&params[1])?;
+
+            context.commands.push(AudioCommand::Stop(music));
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    // set_volume
+    let set_volume_fn_id =
+        associated_impls.api_fetch_external_function_id(audio_struct, "set_volume");
+    externals.register_external_function(
+        set_volume_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let music = music_index(&params[1])?;
+            let volume: f32 = params[2].expect_float()?.into();
+
+            context
+                .commands
+                .push(AudioCommand::SetVolume(music, volume.clamp(0.0, 1.0)));
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    // crossfade
+    let crossfade_fn_id =
+        associated_impls.api_fetch_external_function_id(audio_struct, "crossfade");
+    externals.register_external_function(
+        crossfade_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let current = music_index(&params[1])?;
+            let next = music_index(&params[2])?;
+            let ticks = params[3].expect_int()?;
+
+            context.commands.push(AudioCommand::Crossfade {
+                current,
+                next,
+                ticks: u32::try_from(ticks.max(1)).expect("positive number of ticks"),
+            });
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Debug)]
+pub struct AudioNamedStructTypes {
+    pub sound: NamedStructType,
+    pub music: NamedStructType,
+    pub audio_assets: NamedStructType,
+    pub audio: NamedStructType,
+}
+
+#[derive(Debug)]
+pub struct AudioExternalTypes {
+    pub sound: ExternalType,
+    pub music: ExternalType,
+}
+
+fn create_external_types() -> AudioExternalTypes {
+    let sound = ExternalType {
+        type_name: "SoundHandle".to_string(),
+        number: external_type_number::SOUND_HANDLE,
+    };
+
+    let music = ExternalType {
+        type_name: "MusicHandle".to_string(),
+        number: external_type_number::MUSIC_HANDLE,
+    };
+
+    AudioExternalTypes { sound, music }
+}
+
+fn get_handle_types(
+    mangrove_audio_symbol_table: &SymbolTable,
+) -> Result<AudioNamedStructTypes, MangroveError> {
+    Ok(AudioNamedStructTypes {
+        sound: required_struct(mangrove_audio_symbol_table, "SoundHandle")?,
+        music: required_struct(mangrove_audio_symbol_table, "MusicHandle")?,
+        audio_assets: required_struct(mangrove_audio_symbol_table, "AudioAssets")?,
+        audio: required_struct(mangrove_audio_symbol_table, "Audio")?,
+    })
+}
+
+#[derive(Debug)]
+struct ScriptAudioState {
+    audio_value_ref: ValueRef,
+    update_fn: InternalFunctionDefinitionRef,
+    externals: ExternalFunctions<ScriptAudioContext>,
+    audio_struct_ref: ValueRef,
+}
+
+/// Runs the optional `audio` part of the script. Scripts without an `audio` function in
+/// `main.swamp` are silent.
+#[derive(LocalResource, Debug, Default)]
+pub struct ScriptAudio {
+    state: Option<ScriptAudioState>,
+    music_tracks: Vec<MusicTrack>,
+}
+
+impl ScriptAudio {
+    /// # Errors
+    ///
+    pub fn update(
+        &mut self,
+        script_main: &ScriptMain,
+        simulation_value: &Value,
+    ) -> Result<Vec<AudioCommand>, RuntimeError> {
+        let Some(state) = &self.state else {
+            return Ok(Vec::new());
+        };
+
+        let mut script_context = ScriptAudioContext {
+            audio_assets: None,
+            music_tracks: Vec::new(),
+            commands: Vec::new(),
+        };
+
+        util_execute_function(
+            &state.externals,
+            &script_main.constants,
+            &state.update_fn,
+            [
+                VariableValue::Reference(state.audio_value_ref.clone()),
+                VariableValue::Value(simulation_value.clone()),
+                VariableValue::Reference(state.audio_struct_ref.clone()),
+            ]
+            .as_ref(),
+            &mut script_context,
+            None,
+        )?;
+
+        Ok(script_context.commands)
+    }
+
+    /// The handle comes from the script, so it is not trusted
+    fn music_track(&mut self, index: usize) -> Option<&mut MusicTrack> {
+        let track_count = self.music_tracks.len();
+        let track = self.music_tracks.get_mut(index);
+        if track.is_none() {
+            warn!(
+                index,
+                track_count, "unknown music handle, ignoring the command"
+            );
+        }
+        track
+    }
+
+    pub fn apply(
+        &mut self,
+        commands: Vec<AudioCommand>,
+        backend: &mut dyn AudioBackend,
+        stereo_samples: Option<&LimnusAssets<StereoSample>>,
+    ) {
+        for command in commands {
+            match command {
+                AudioCommand::Play(sample) => {
+                    if let Some(stereo_sample) = stereo_samples.and_then(|s| s.get(&sample)) {
+                        backend.play(stereo_sample.frames(), false, 1.0);
+                    } else {
+                        trace!(%sample, "sound is not loaded yet, skipping it");
+                    }
+                }
+                AudioCommand::PlayMusic(index) => {
+                    if let Some(track) = self.music_track(index) {
+                        track.stop(backend);
+                        track.is_playing = true;
+                    }
+                }
+                AudioCommand::Stop(index) => {
+                    if let Some(track) = self.music_track(index) {
+                        track.stop(backend);
+                    }
+                }
+                AudioCommand::SetVolume(index, volume) => {
+                    if let Some(track) = self.music_track(index) {
+                        track.volume = volume;
+                        track.fade = None;
+                        if let Some(voice) = track.voice {
+                            backend.set_volume(voice, volume);
+                        }
+                    }
+                }
+                AudioCommand::Crossfade {
+                    current,
+                    next,
+                    ticks,
+                } => {
+                    // Crossfading to the same track keeps it playing, and fades it back in if
+                    // it was fading out
+                    if current != next
+                        && let Some(current_track) = self.music_track(current)
+                    {
+                        current_track.fade = Some(Fade {
+                            from_volume: current_track.current_volume(),
+                            to_volume: 0.0,
+                            ticks_left: ticks,
+                            total_ticks: ticks,
+                            stop_when_done: true,
+                        });
+                    }
+
+                    if let Some(next_track) = self.music_track(next) {
+                        let from_volume = if next_track.is_playing {
+                            next_track.current_volume()
+                        } else {
+                            0.0
+                        };
+                        next_track.is_playing = true;
+                        next_track.fade = Some(Fade {
+                            from_volume,
+                            to_volume: next_track.volume,
+                            ticks_left: ticks,
+                            total_ticks: ticks,
+                            stop_when_done: false,
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    /// Starts the music that has been loaded, advances the fades and ticks the backend.
+    /// Called once for every simulation tick, so the fades take the same number of ticks
+    /// regardless of the frame rate.
+    pub fn tick(
+        &mut self,
+        backend: &mut dyn AudioBackend,
+        stereo_samples: Option<&LimnusAssets<StereoSample>>,
+    ) {
+        for track in &mut self.music_tracks {
+            if !track.is_playing {
+                continue;
+            }
+
+            if track.voice.is_none() {
+                // Music that is still loading starts as soon as it is available
+                if let Some(stereo_sample) = stereo_samples.and_then(|s| s.get(&track.sample)) {
+                    track.voice =
+                        Some(backend.play(stereo_sample.frames(), true, track.current_volume()));
+                }
+            }
+
+            if let Some(fade) = &mut track.fade {
+                fade.ticks_left = fade.ticks_left.saturating_sub(1);
+                let volume = fade.volume();
+                let is_done = fade.ticks_left == 0;
+                let stop_when_done = fade.stop_when_done;
+                if let Some(voice) = track.voice {
+                    backend.set_volume(voice, volume);
+                }
+                if is_done {
+                    if stop_when_done {
+                        track.stop(backend);
+                    } else {
+                        track.fade = None;
+                    }
+                }
+            }
+        }
+
+        backend.tick();
+    }
+
+    pub fn stop_all(&mut self, backend: &mut dyn AudioBackend) {
+        for track in &mut self.music_tracks {
+            track.stop(backend);
+        }
+    }
+}
+
+/// # Errors
+///
+/// # Panics
+///
+pub fn boot(
+    script_main: &ScriptMain,
+    resource_storage: &mut ResourceStorage,
+) -> Result<ScriptAudio, MangroveError> {
+    let Some(audio_new_fn) = &script_main.audio_new_fn else {
+        return Ok(ScriptAudio::default());
+    };
+
+    debug!("boot script audio");
+    let mut external_functions = ExternalFunctions::<ScriptAudioContext>::new();
+    let mangrove_audio_module_path = &["mangrove".to_string(), "audio".into()];
+    let mangrove_audio_module = script_main
+        .resolved_program
+        .modules
+        .get(mangrove_audio_module_path)
+        .ok_or_else(|| {
+            MangroveError::Other("audio function requires the mangrove::audio package".to_string())
+        })?;
+    let impls = &script_main.resolved_program.state.associated_impls;
+
+    register_print(
+        &script_main.resolved_program.modules,
+        &mut external_functions,
+    );
+
+    register_audio_assets_members(
+        &mangrove_audio_module.symbol_table,
+        impls,
+        &mut external_functions,
+    )?;
+
+    register_audio_members(
+        &mangrove_audio_module.symbol_table,
+        impls,
+        &mut external_functions,
+    )?;
+
+    let handle_named_types = get_handle_types(&mangrove_audio_module.symbol_table)?;
+
+    let mut game_assets = GameAssets::new(resource_storage, Millis::new(0));
+
+    let mut script_context = ScriptAudioContext {
+        audio_assets: Some(AudioAssetsWrapper::new(
+            &mut game_assets,
+            handle_named_types.clone(),
+            create_external_types(),
+        )),
+        music_tracks: Vec::new(),
+        commands: Vec::new(),
+    };
+
+    // AudioAssets doesn't really contain anything, the actual values are fetched from the runtime context
+    let audio_assets_value = create_empty_struct_value(handle_named_types.audio_assets.clone());
+    let audio_assets_value_mut =
+        VariableValue::Reference(Rc::new(RefCell::new(audio_assets_value)));
+
+    let audio_struct_value = util_execute_function(
+        &external_functions,
+        &script_main.constants,
+        audio_new_fn,
+        &[audio_assets_value_mut],
+        &mut script_context,
+        None,
+    )?;
+
+    let Value::NamedStruct(audio_struct_type_ref, _) = &audio_struct_value else {
+        return Err(MangroveError::Other("needs to be audio struct".to_string()));
+    };
+
+    let update_fn = get_impl_func(
+        impls,
+        audio_struct_type_ref,
+        "update",
+        &UPDATE_PARAMETER_COUNTS,
+    )?;
+
+    // Audio doesn't really contain anything, the actual values are fetched from the runtime context
+    let audio_value = create_empty_struct_value(handle_named_types.audio);
+
+    Ok(ScriptAudio {
+        state: Some(ScriptAudioState {
+            audio_value_ref: Rc::new(RefCell::new(audio_struct_value)),
+            update_fn,
+            externals: external_functions,
+            audio_struct_ref: Rc::new(RefCell::new(audio_value)),
+        }),
+        music_tracks: script_context.music_tracks,
+    })
+}
+
+/// # Panics
+///
+/// Runs the audio `update()` when the simulation has ticked, and ticks the audio once for every
+/// simulation tick in this update. Nothing is played or captured while the simulation is paused.
+#[allow(clippy::too_many_arguments)]
+pub fn audio_tick(
+    script_main: LoRe<ScriptMain>,
+    mut script_audio: LoReM<ScriptAudio>,
+    simulation: LoRe<ScriptSimulation>,
+    mut audio_output: LoReM<AudioOutput>,
+    timestep: Re<SimulationTimestep>,
+    all_resources: ReAll,
+    mut error: ReM<ErrorResource>,
+    source_map: Re<SourceMapResource>,
+) {
+    let ticks = timestep.ticks_this_update();
+    if ticks == 0 || error.has_errors() {
+        return;
+    }
+
+    match script_audio.update(&script_main, &simulation.immutable_simulation_value()) {
+        Ok(commands) => {
+            let stereo_samples = all_resources.get::<LimnusAssets<StereoSample>>();
+            let backend = audio_output.backend.as_mut();
+            script_audio.apply(commands, backend, stereo_samples);
+            for _ in 0..ticks {
+                script_audio.tick(backend, stereo_samples);
+            }
+        }
+        Err(runtime_err) => {
+            report_runtime_error(
+                &mut error,
+                DiagnosticPhase::Tick,
+                &runtime_err,
+                &source_map.source_map,
+            );
+        }
+    }
+}
+
+pub fn detect_reload_tick(
+    script_messages: Msg<ScriptMessage>,
+    mut script_audio: LoReM<ScriptAudio>,
+    mut audio_output: LoReM<AudioOutput>,
+    script_main: LoRe<ScriptMain>,
+    source_map: Re<SourceMapResource>,
+    mut all_resources: ReAll,
+    mut err: ReM<ErrorResource>,
+) {
+    if err.has_errors() {
+        return;
+    }
+    for msg in script_messages.iter_previous() {
+        match msg {
+            // Reloads are booted together with the other plugins, see `reload::reload_tick`
+            ScriptMessage::Reload(_) | ScriptMessage::Reloaded(_) => {}
+            ScriptMessage::ScreenChanged => match boot(&script_main, &mut all_resources) {
+                Ok(new_audio) => {
+                    script_audio.stop_all(audio_output.backend_mut());
+                    *script_audio = new_audio;
+                }
+                Err(mangrove_error) => {
+                    report_mangrove_error(
+                        &mut err,
+                        DiagnosticPhase::Boot,
+                        &mangrove_error,
+                        &source_map.source_map,
+                    );
+                    error!(error=?mangrove_error, "script audio failed");
+                }
+            },
+        }
+    }
+}
+
+pub struct ScriptAudioPlugin;
+
+impl Plugin for ScriptAudioPlugin {
+    fn build(&self, app: &mut App) {
+        if !app.local_resources().contains::<AudioOutput>() {
+            let device_backend = app
+                .local_resources()
+                .get::<Audio>()
+                .map(DeviceAudioBackend::new);
+            let output = match device_backend {
+                Some(Ok(device_backend)) => AudioOutput::new(device_backend),
+                Some(Err(err)) => {
+                    error!(?err, "could not start audio stream, script audio is muted");
+                    AudioOutput::new(NullAudioBackend::default())
+                }
+                None => AudioOutput::new(NullAudioBackend::default()),
+            };
+            app.insert_local_resource(output);
+        }
+
+        app.add_system(Update, detect_reload_tick);
+        app.add_system(Update, audio_tick);
+
+        // Silent until the reload message has booted the script audio
+        app.insert_local_resource(ScriptAudio::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const SAMPLE_RATE: u32 = 48_000;
+    const TICKS_PER_SECOND: u32 = 60;
+    const FRAMES_PER_TICK: usize = (SAMPLE_RATE / TICKS_PER_SECOND) as usize;
+
+    fn tone(volume: Sample) -> StereoFrames {
+        Frames::from_slice(SAMPLE_RATE, &[[volume, -volume]; FRAMES_PER_TICK * 4])
+    }
+
+    fn capture_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "mangrove-audio-test-{}-{name}.wav",
+            std::process::id()
+        ))
+    }
+
+    /// The captured samples, left and right interleaved
+    fn captured_samples(path: &Path) -> Vec<Sample> {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        assert_eq!(reader.spec().channels, 2);
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+        let samples = reader
+            .samples::<Sample>()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        std::fs::remove_file(path).unwrap();
+        samples
+    }
+
+    #[test]
+    fn null_backend_gives_every_voice_its_own_id() {
+        let mut backend = NullAudioBackend::default();
+        let frames = tone(0.5);
+        let first = backend.play(&frames, false, 1.0);
+        let second = backend.play(&frames, true, 0.5);
+        assert_ne!(first, second);
+
+        backend.set_volume(first, 0.0);
+        backend.stop(first);
+        backend.stop(first);
+        backend.tick();
+    }
+
+    #[test]
+    fn wav_capture_writes_the_same_number_of_frames_every_tick() {
+        let path = capture_path("silence");
+        let mut backend =
+            WavCaptureAudioBackend::new(&path, SAMPLE_RATE, TICKS_PER_SECOND).unwrap();
+        for _ in 0..3 {
+            backend.tick();
+        }
+        backend.finalize().unwrap();
+
+        let samples = captured_samples(&path);
+        assert_eq!(samples.len(), 3 * FRAMES_PER_TICK * 2);
+        assert!(samples.iter().all(|sample| *sample == 0.0));
+    }
+
+    #[test]
+    fn wav_capture_mixes_the_voices() {
+        let path = capture_path("voices");
+        let mut backend =
+            WavCaptureAudioBackend::new(&path, SAMPLE_RATE, TICKS_PER_SECOND).unwrap();
+        let voice = backend.play(&tone(0.5), false, 1.0);
+        backend.tick();
+        backend.stop(voice);
+        backend.tick();
+        // Is finalized when dropped
+        drop(backend);
+
+        let samples = captured_samples(&path);
+        let (played, stopped) = samples.split_at(FRAMES_PER_TICK * 2);
+        assert!(played.iter().any(|sample| *sample != 0.0));
+        assert!(stopped.iter().all(|sample| *sample == 0.0));
+    }
+}
@@ -0,0 +1,540 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Binds keyboard, mouse and gamepad input to the action sets declared in the script input struct.
+//!
+//! The bindings are read from `input_bindings.toml` in the current directory, or from the built-in
+//! default table if the file is missing. Every table is an action set (the lowercase name of the
+//! action set struct) and every entry is an action (the lowercase field name):
+//!
+//! ```toml
+//! [gameplay.jump]
+//! keys = ["Space"]
+//! buttons = ["South"]
+//!
+//! [gameplay.movement]
+//! keys = ["KeyA", "KeyD", "KeyS", "KeyW"] # left, right, down, up
+//! stick = "Left"
+//! ```
+use crate::input::{BindingKind, BindingsInSet, convert_bind_name, convert_set_name};
+use crate::script::MangroveError;
+use limnus_basic_input::prelude::{KeyCode, MouseButton};
+use limnus_gamepad::{Axis, Button, Gamepads};
+use seq_map::SeqMap;
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use swamp::prelude::{Fp, LocalResource};
+use swamp_script::prelude::Value;
+use tracing::{debug, warn};
+
+pub const BINDINGS_FILE_NAME: &str = "input_bindings.toml";
+
+const DEFAULT_BINDINGS: &str = include_str!("default_input_bindings.toml");
+
+pub const KEY_NAMES: [(&str, KeyCode); 99] = [
+    ("Backquote", KeyCode::Backquote),
+    ("Backslash", KeyCode::Backslash),
+    ("BracketLeft", KeyCode::BracketLeft),
+    ("BracketRight", KeyCode::BracketRight),
+    ("Comma", KeyCode::Comma),
+    ("Digit0", KeyCode::Digit0),
+    ("Digit1", KeyCode::Digit1),
+    ("Digit2", KeyCode::Digit2),
+    ("Digit3", KeyCode::Digit3),
+    ("Digit4", KeyCode::Digit4),
+    ("Digit5", KeyCode::Digit5),
+    ("Digit6", KeyCode::Digit6),
+    ("Digit7", KeyCode::Digit7),
+    ("Digit8", KeyCode::Digit8),
+    ("Digit9", KeyCode::Digit9),
+    ("Equal", KeyCode::Equal),
+    ("KeyA", KeyCode::KeyA),
+    ("KeyB", KeyCode::KeyB),
+    ("KeyC", KeyCode::KeyC),
+    ("KeyD", KeyCode::KeyD),
+    ("KeyE", KeyCode::KeyE),
+    ("KeyF", KeyCode::KeyF),
+    ("KeyG", KeyCode::KeyG),
+    ("KeyH", KeyCode::KeyH),
+    ("KeyI", KeyCode::KeyI),
+    ("KeyJ", KeyCode::KeyJ),
+    ("KeyK", KeyCode::KeyK),
+    ("KeyL", KeyCode::KeyL),
+    ("KeyM", KeyCode::KeyM),
+    ("KeyN", KeyCode::KeyN),
+    ("KeyO", KeyCode::KeyO),
+    ("KeyP", KeyCode::KeyP),
+    ("KeyQ", KeyCode::KeyQ),
+    ("KeyR", KeyCode::KeyR),
+    ("KeyS", KeyCode::KeyS),
+    ("KeyT", KeyCode::KeyT),
+    ("KeyU", KeyCode::KeyU),
+    ("KeyV", KeyCode::KeyV),
+    ("KeyW", KeyCode::KeyW),
+    ("KeyX", KeyCode::KeyX),
+    ("KeyY", KeyCode::KeyY),
+    ("KeyZ", KeyCode::KeyZ),
+    ("Minus", KeyCode::Minus),
+    ("Period", KeyCode::Period),
+    ("Quote", KeyCode::Quote),
+    ("Semicolon", KeyCode::Semicolon),
+    ("Slash", KeyCode::Slash),
+    ("AltLeft", KeyCode::AltLeft),
+    ("AltRight", KeyCode::AltRight),
+    ("Backspace", KeyCode::Backspace),
+    ("CapsLock", KeyCode::CapsLock),
+    ("ContextMenu", KeyCode::ContextMenu),
+    ("ControlLeft", KeyCode::ControlLeft),
+    ("ControlRight", KeyCode::ControlRight),
+    ("Enter", KeyCode::Enter),
+    ("SuperLeft", KeyCode::SuperLeft),
+    ("SuperRight", KeyCode::SuperRight),
+    ("ShiftLeft", KeyCode::ShiftLeft),
+    ("ShiftRight", KeyCode::ShiftRight),
+    ("Space", KeyCode::Space),
+    ("Tab", KeyCode::Tab),
+    ("Delete", KeyCode::Delete),
+    ("End", KeyCode::End),
+    ("Help", KeyCode::Help),
+    ("Home", KeyCode::Home),
+    ("Insert", KeyCode::Insert),
+    ("PageDown", KeyCode::PageDown),
+    ("PageUp", KeyCode::PageUp),
+    ("ArrowDown", KeyCode::ArrowDown),
+    ("ArrowLeft", KeyCode::ArrowLeft),
+    ("ArrowRight", KeyCode::ArrowRight),
+    ("ArrowUp", KeyCode::ArrowUp),
+    ("NumLock", KeyCode::NumLock),
+    ("Escape", KeyCode::Escape),
+    ("PrintScreen", KeyCode::PrintScreen),
+    ("ScrollLock", KeyCode::ScrollLock),
+    ("Pause", KeyCode::Pause),
+    ("F1", KeyCode::F1),
+    ("F2", KeyCode::F2),
+    ("F3", KeyCode::F3),
+    ("F4", KeyCode::F4),
+    ("F5", KeyCode::F5),
+    ("F6", KeyCode::F6),
+    ("F7", KeyCode::F7),
+    ("F8", KeyCode::F8),
+    ("F9", KeyCode::F9),
+    ("F10", KeyCode::F10),
+    ("F11", KeyCode::F11),
+    ("F12", KeyCode::F12),
+    // Common aliases
+    ("Up", KeyCode::ArrowUp),
+    ("Down", KeyCode::ArrowDown),
+    ("Left", KeyCode::ArrowLeft),
+    ("Right", KeyCode::ArrowRight),
+    ("Esc", KeyCode::Escape),
+    ("Return", KeyCode::Enter),
+    ("Shift", KeyCode::ShiftLeft),
+    ("Control", KeyCode::ControlLeft),
+    ("Alt", KeyCode::AltLeft),
+    ("Ctrl", KeyCode::ControlLeft),
+];
+
+pub const BUTTON_NAMES: [(&str, Button); 17] = [
+    ("South", Button::South),
+    ("East", Button::East),
+    ("North", Button::North),
+    ("West", Button::West),
+    ("LeftTrigger", Button::LeftTrigger),
+    ("LeftTrigger2", Button::LeftTrigger2),
+    ("RightTrigger", Button::RightTrigger),
+    ("RightTrigger2", Button::RightTrigger2),
+    ("Select", Button::Select),
+    ("Start", Button::Start),
+    ("Mode", Button::Mode),
+    ("LeftThumb", Button::LeftThumb),
+    ("RightThumb", Button::RightThumb),
+    ("DPadUp", Button::DPadUp),
+    ("DPadDown", Button::DPadDown),
+    ("DPadLeft", Button::DPadLeft),
+    ("DPadRight", Button::DPadRight),
+];
+
+pub const AXIS_NAMES: [(&str, Axis); 4] = [
+    ("LeftStickX", Axis::LeftStickX),
+    ("LeftStickY", Axis::LeftStickY),
+    ("RightStickX", Axis::RightStickX),
+    ("RightStickY", Axis::RightStickY),
+];
+
+const MOUSE_BUTTON_NAMES: [(&str, MouseButton); 5] = [
+    ("Left", MouseButton::Left),
+    ("Right", MouseButton::Right),
+    ("Middle", MouseButton::Middle),
+    ("Back", MouseButton::Back),
+    ("Forward", MouseButton::Forward),
+];
+
+#[must_use]
+pub fn key_code_from_name(name: &str) -> Option<KeyCode> {
+    KEY_NAMES
+        .iter()
+        .find(|(key_name, _)| *key_name == name)
+        .map(|(_, key_code)| *key_code)
+}
+
+/// Every key code with its name, without the aliases
+pub fn named_key_codes() -> impl Iterator<Item = (&'static str, KeyCode)> {
+    KEY_NAMES
+        .iter()
+        .copied()
+        .filter(|(name, key_code)| key_name(*key_code) == *name)
+}
+
+/// The name of the key, as used in the bindings file and in `mangrove::input::Key`
+///
+/// # Panics
+///
+#[must_use]
+pub fn key_name(key_code: KeyCode) -> &'static str {
+    KEY_NAMES
+        .iter()
+        .find(|(_, named_key_code)| *named_key_code == key_code)
+        .map(|(name, _)| *name)
+        .expect("all key codes have a name")
+}
+
+#[must_use]
+pub fn button_from_name(name: &str) -> Option<Button> {
+    BUTTON_NAMES
+        .iter()
+        .find(|(button_name, _)| *button_name == name)
+        .map(|(_, button)| *button)
+}
+
+fn mouse_button_from_name(name: &str) -> Option<MouseButton> {
+    MOUSE_BUTTON_NAMES
+        .iter()
+        .find(|(button_name, _)| *button_name == name)
+        .map(|(_, button)| *button)
+}
+
+/// One action entry in the bindings file. Analog actions take the keys and buttons four at a
+/// time, in the order left, right, down, up.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ActionBindingConfig {
+    #[serde(default)]
+    pub keys: Vec<String>,
+    #[serde(default)]
+    pub buttons: Vec<String>,
+    #[serde(default)]
+    pub mouse: Vec<String>,
+    pub stick: Option<String>,
+}
+
+/// Action set name -> action name -> binding, as written in the bindings file
+pub type BindingConfig = HashMap<String, HashMap<String, ActionBindingConfig>>;
+
+/// # Errors
+///
+/// If the text is not valid toml or has unknown fields.
+pub fn parse_binding_config(text: &str) -> Result<BindingConfig, String> {
+    toml::from_str(text).map_err(|err| err.to_string())
+}
+
+/// Loads `input_bindings.toml` from the directory, or the built-in default bindings if there is no such file.
+///
+/// # Errors
+///
+/// If the file can not be read or parsed.
+pub fn load_binding_config(directory: &Path) -> Result<BindingConfig, MangroveError> {
+    let path = directory.join(BINDINGS_FILE_NAME);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == ErrorKind::NotFound => {
+            debug!("no {BINDINGS_FILE_NAME}, using the default input bindings");
+            return parse_binding_config(DEFAULT_BINDINGS)
+                .map_err(|err| format!("default input bindings: {err}").into());
+        }
+        Err(err) => return Err(format!("could not read {}: {err}", path.display()).into()),
+    };
+
+    parse_binding_config(&text).map_err(|err| format!("{}: {err}", path.display()).into())
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DigitalSource {
+    Key(KeyCode),
+    Button(Button),
+    Mouse(MouseButton),
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Stick {
+    Left,
+    Right,
+}
+
+/// The four directions are in the order left, right, down, up
+#[derive(Debug, Clone, Copy)]
+pub enum AnalogSource {
+    Keys([KeyCode; 4]),
+    Buttons([Button; 4]),
+    Stick(Stick),
+}
+
+#[derive(Debug)]
+pub enum ResolvedBinding {
+    Digital(Vec<DigitalSource>),
+    Analog(Vec<AnalogSource>),
+}
+
+/// A binding together with the field index in the action set struct that it writes to
+#[derive(Debug)]
+pub struct ResolvedAction {
+    pub struct_field_index: usize,
+    pub binding: ResolvedBinding,
+}
+
+/// All actions in one action set, and the field index of the action set in the input struct
+#[derive(Debug)]
+pub struct ResolvedActionSet {
+    pub struct_field_index: usize,
+    pub actions: Vec<ResolvedAction>,
+}
+
+fn resolve_names<T: Copy>(
+    names: &[String],
+    lookup: fn(&str) -> Option<T>,
+    what: &str,
+    action: &str,
+) -> Result<Vec<T>, MangroveError> {
+    names
+        .iter()
+        .map(|name| {
+            lookup(name).ok_or_else(|| {
+                MangroveError::Other(format!("unknown {what} '{name}' in binding for '{action}'"))
+            })
+        })
+        .collect()
+}
+
+fn resolve_directions<T: Copy>(
+    items: &[T],
+    what: &str,
+    action: &str,
+) -> Result<Vec<[T; 4]>, MangroveError> {
+    if !items.len().is_multiple_of(4) {
+        return Err(format!(
+            "analog action '{action}' needs {what} in groups of four (left, right, down, up)"
+        )
+        .into());
+    }
+    Ok(items
+        .chunks_exact(4)
+        .map(|chunk| [chunk[0], chunk[1], chunk[2], chunk[3]])
+        .collect())
+}
+
+fn resolve_action(
+    action_name: &str,
+    kind: &BindingKind,
+    config: &ActionBindingConfig,
+) -> Result<ResolvedBinding, MangroveError> {
+    let keys = resolve_names(&config.keys, key_code_from_name, "key", action_name)?;
+    let buttons = resolve_names(&config.buttons, button_from_name, "button", action_name)?;
+
+    match kind {
+        BindingKind::Digital => {
+            if config.stick.is_some() {
+                return Err(
+                    format!("digital action '{action_name}' can not be bound to a stick").into(),
+                );
+            }
+            let mouse_buttons = resolve_names(
+                &config.mouse,
+                mouse_button_from_name,
+                "mouse button",
+                action_name,
+            )?;
+
+            let mut sources = Vec::new();
+            sources.extend(keys.into_iter().map(DigitalSource::Key));
+            sources.extend(buttons.into_iter().map(DigitalSource::Button));
+            sources.extend(mouse_buttons.into_iter().map(DigitalSource::Mouse));
+            Ok(ResolvedBinding::Digital(sources))
+        }
+        BindingKind::Analog => {
+            if !config.mouse.is_empty() {
+                return Err(format!(
+                    "analog action '{action_name}' can not be bound to mouse buttons"
+                )
+                .into());
+            }
+
+            let mut sources = Vec::new();
+            sources.extend(
+                resolve_directions(&keys, "keys", action_name)?
+                    .into_iter()
+                    .map(AnalogSource::Keys),
+            );
+            sources.extend(
+                resolve_directions(&buttons, "buttons", action_name)?
+                    .into_iter()
+                    .map(AnalogSource::Buttons),
+            );
+            if let Some(stick) = &config.stick {
+                let stick = match stick.as_str() {
+                    "Left" => Stick::Left,
+                    "Right" => Stick::Right,
+                    _ => {
+                        return Err(format!(
+                            "unknown stick '{stick}' in binding for '{action_name}', use Left or Right"
+                        )
+                        .into());
+                    }
+                };
+                sources.push(AnalogSource::Stick(stick));
+            }
+            Ok(ResolvedBinding::Analog(sources))
+        }
+    }
+}
+
+/// Matches the action sets found in the script input struct with the bindings in the config.
+/// Actions that are missing in the config are never set.
+///
+/// # Errors
+///
+/// If a binding refers to an unknown key or button, or doesn't fit the kind of action.
+pub fn resolve_bindings(
+    sets: &SeqMap<String, BindingsInSet>,
+    config: &BindingConfig,
+) -> Result<Vec<ResolvedActionSet>, MangroveError> {
+    let mut resolved_sets = Vec::new();
+
+    for (set_name, bindings_in_set) in sets {
+        let converted_set_name = convert_set_name(set_name);
+        let set_config = config.get(&converted_set_name);
+
+        let mut actions = Vec::new();
+        for binding in &bindings_in_set.bindings_in_source_order {
+            let converted_name = convert_bind_name(&binding.name);
+            let Some(action_config) = set_config.and_then(|set| set.get(&converted_name)) else {
+                warn!(
+                    set = converted_set_name,
+                    action = converted_name,
+                    "action has no input binding"
+                );
+                continue;
+            };
+
+            actions.push(ResolvedAction {
+                struct_field_index: binding.struct_field_index,
+                binding: resolve_action(
+                    &format!("{converted_set_name}.{converted_name}"),
+                    &binding.kind,
+                    action_config,
+                )?,
+            });
+        }
+
+        resolved_sets.push(ResolvedActionSet {
+            struct_field_index: bindings_in_set.struct_field_index,
+            actions,
+        });
+    }
+
+    Ok(resolved_sets)
+}
+
+/// Keyboard keys and mouse buttons that are currently held down
+#[derive(LocalResource, Debug, Default)]
+pub struct InputDevices {
+    pub keys: HashSet<KeyCode>,
+    pub mouse_buttons: HashSet<MouseButton>,
+}
+
+impl InputDevices {
+    fn is_pressed(&self, source: DigitalSource, gamepads: &Gamepads) -> bool {
+        match source {
+            DigitalSource::Key(key_code) => self.keys.contains(&key_code),
+            DigitalSource::Mouse(mouse_button) => self.mouse_buttons.contains(&mouse_button),
+            DigitalSource::Button(button) => gamepads
+                .iter_active()
+                .any(|gamepad| gamepad.is_pressed(button)),
+        }
+    }
+
+    fn direction(&self, source: AnalogSource, gamepads: &Gamepads) -> (f32, f32) {
+        let from_pressed = |pressed: [bool; 4]| {
+            let axis = |negative: bool, positive: bool| {
+                f32::from(u8::from(positive)) - f32::from(u8::from(negative))
+            };
+            (axis(pressed[0], pressed[1]), axis(pressed[2], pressed[3]))
+        };
+
+        match source {
+            AnalogSource::Keys(keys) => from_pressed(keys.map(|key| self.keys.contains(&key))),
+            AnalogSource::Buttons(buttons) => from_pressed(buttons.map(|button| {
+                gamepads
+                    .iter_active()
+                    .any(|gamepad| gamepad.is_pressed(button))
+            })),
+            AnalogSource::Stick(stick) => {
+                let (x_axis, y_axis) = match stick {
+                    Stick::Left => (Axis::LeftStickX, Axis::LeftStickY),
+                    Stick::Right => (Axis::RightStickX, Axis::RightStickY),
+                };
+                gamepads.iter_active().fold((0.0, 0.0), |(x, y), gamepad| {
+                    (x + gamepad.axis(x_axis), y + gamepad.axis(y_axis))
+                })
+            }
+        }
+    }
+}
+
+/// Writes the current state of all bound actions into the script input value
+///
+/// # Panics
+///
+/// If the input value doesn't have the layout that the action sets were scanned from.
+pub fn update_action_sets(
+    input_value: &Value,
+    resolved_sets: &[ResolvedActionSet],
+    devices: &InputDevices,
+    gamepads: &Gamepads,
+) {
+    let Value::NamedStruct(_, input_fields) = input_value else {
+        return;
+    };
+
+    for resolved_set in resolved_sets {
+        let set_value = input_fields[resolved_set.struct_field_index].borrow();
+        let Value::NamedStruct(_, action_fields) = &*set_value else {
+            panic!("internal error: action set must be a struct");
+        };
+
+        for action in &resolved_set.actions {
+            let mut action_value = action_fields[action.struct_field_index].borrow_mut();
+            match &action.binding {
+                ResolvedBinding::Digital(sources) => {
+                    let is_pressed = sources
+                        .iter()
+                        .any(|source| devices.is_pressed(*source, gamepads));
+                    *action_value = Value::Bool(is_pressed);
+                }
+                ResolvedBinding::Analog(sources) => {
+                    let (x, y) = sources.iter().fold((0.0, 0.0), |(x, y), source| {
+                        let (dx, dy) = devices.direction(*source, gamepads);
+                        (x + dx, y + dy)
+                    });
+                    let Value::Tuple(_, tuple_fields) = &*action_value else {
+                        panic!("internal error: analog action must be a tuple");
+                    };
+                    *tuple_fields[0].borrow_mut() = Value::Float(Fp::from(x.clamp(-1.0, 1.0)));
+                    *tuple_fields[1].borrow_mut() = Value::Float(Fp::from(y.clamp(-1.0, 1.0)));
+                }
+            }
+        }
+    }
+}
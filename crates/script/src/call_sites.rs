@@ -0,0 +1,359 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Where the scripts call the external functions.
+//!
+//! The interpreter does not tell an external function where it was called from, so the calls are
+//! found in the resolved program when it is booted. An external function can then look up the
+//! call site from its arguments, e.g. to report a missing asset at the line that requested it.
+use std::collections::HashSet;
+use swamp_script::prelude::{
+    Expression, ExpressionKind, ExternalFunctionId, Function, InternalFunctionDefinitionRef,
+    Literal, Program, Span, StringPart,
+};
+use swamp_script_semantic::{
+    ArgumentExpressionOrLocation, EnumLiteralData, LocationAccessKind, MutOrImmutableExpression,
+    Pattern, PostfixKind, SingleLocationExpression,
+};
+
+#[derive(Debug)]
+struct CallSite {
+    function_id: ExternalFunctionId,
+    /// The first argument, if it is a string literal
+    literal: Option<String>,
+    /// The first argument
+    span: Span,
+}
+
+/// The calls to some of the external functions
+#[derive(Debug, Default)]
+pub struct CallSites {
+    sites: Vec<CallSite>,
+}
+
+impl CallSites {
+    /// Finds every call to the functions in all the functions of the program
+    #[must_use]
+    pub fn find(program: &Program, function_ids: &[ExternalFunctionId]) -> Self {
+        let mut finder = Finder {
+            function_ids,
+            sites: Vec::new(),
+        };
+
+        let mut visited = HashSet::new();
+        let mut visit_function = |finder: &mut Finder, function: &InternalFunctionDefinitionRef| {
+            if visited.insert(function.program_unique_id) {
+                finder.expression(&function.body);
+            }
+        };
+
+        for module in program.modules.modules().values() {
+            if let Some(main_expression) = &module.main_expression {
+                finder.expression(&main_expression.expression);
+            }
+            for function in module.symbol_table.internal_functions() {
+                visit_function(&mut finder, &function);
+            }
+        }
+        for impl_functions in program.state.associated_impls.functions.values() {
+            for function in impl_functions.functions.values() {
+                if let Function::Internal(function) = &**function {
+                    visit_function(&mut finder, function);
+                }
+            }
+        }
+
+        Self {
+            sites: finder.sites,
+        }
+    }
+
+    /// The first argument of the call that passed `argument` to the function. If the argument
+    /// is not a literal, the function must only be called from one place to be found.
+    #[must_use]
+    pub fn span(&self, function_id: ExternalFunctionId, argument: &str) -> Option<Span> {
+        let mut calls = self
+            .sites
+            .iter()
+            .filter(|site| site.function_id == function_id);
+
+        if let Some(site) = calls
+            .clone()
+            .find(|site| site.literal.as_deref() == Some(argument))
+        {
+            return Some(site.span.clone());
+        }
+
+        match (calls.next(), calls.next()) {
+            (Some(site), None) if site.literal.is_none() => Some(site.span.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// The text of a string without any interpolations, e.g. `'player/ship'`
+fn string_literal(expression: &Expression) -> Option<String> {
+    match &expression.kind {
+        ExpressionKind::Literal(Literal::StringLiteral(text)) => Some(text.clone()),
+        ExpressionKind::InterpolatedString(parts) => parts
+            .iter()
+            .map(|part| match part {
+                StringPart::Literal(_, text) => Some(text.as_str()),
+                StringPart::Interpolation(..) => None,
+            })
+            .collect(),
+        _ => None,
+    }
+}
+
+struct Finder<'a> {
+    function_ids: &'a [ExternalFunctionId],
+    sites: Vec<CallSite>,
+}
+
+impl Finder<'_> {
+    fn call(&mut self, function: &Function, arguments: &[ArgumentExpressionOrLocation]) {
+        let Function::External(external) = function else {
+            return;
+        };
+        if !self.function_ids.contains(&external.id) {
+            return;
+        }
+        // The arguments of a member call do not include self
+        let Some(ArgumentExpressionOrLocation::Expression(first)) = arguments.first() else {
+            return;
+        };
+
+        self.sites.push(CallSite {
+            function_id: external.id,
+            literal: string_literal(first),
+            span: first.node.span.clone(),
+        });
+    }
+
+    fn arguments(&mut self, arguments: &[ArgumentExpressionOrLocation]) {
+        for argument in arguments {
+            match argument {
+                ArgumentExpressionOrLocation::Expression(expression) => self.expression(expression),
+                ArgumentExpressionOrLocation::Location(location) => self.location(location),
+            }
+        }
+    }
+
+    fn mut_or_immutable(&mut self, expression: &MutOrImmutableExpression) {
+        self.arguments(std::slice::from_ref(&expression.expression_or_location));
+    }
+
+    fn location(&mut self, location: &SingleLocationExpression) {
+        for access in &location.access_chain {
+            if let LocationAccessKind::IntrinsicCallMut(_, expressions) = &access.kind {
+                self.expressions(expressions);
+            }
+        }
+    }
+
+    fn expressions<'e>(&mut self, expressions: impl IntoIterator<Item = &'e Expression>) {
+        for expression in expressions {
+            self.expression(expression);
+        }
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::ConstantAccess(_)
+            | ExpressionKind::VariableAccess(_)
+            | ExpressionKind::IntrinsicFunctionAccess(_)
+            | ExpressionKind::InternalFunctionAccess(_)
+            | ExpressionKind::ExternalFunctionAccess(_) => {}
+            ExpressionKind::BinaryOp(operator) => {
+                self.expression(&operator.left);
+                self.expression(&operator.right);
+            }
+            ExpressionKind::UnaryOp(operator) => self.expression(&operator.left),
+            ExpressionKind::PostfixChain(start, postfixes) => {
+                self.expression(start);
+                for postfix in postfixes {
+                    match &postfix.kind {
+                        PostfixKind::MemberCall(function, arguments) => {
+                            self.call(function, arguments);
+                            self.arguments(arguments);
+                        }
+                        PostfixKind::FunctionCall(arguments) => self.arguments(arguments),
+                        PostfixKind::NoneCoalesce(expression) => self.expression(expression),
+                        PostfixKind::IntrinsicCall(_, expressions) => self.expressions(expressions),
+                        PostfixKind::StructField(..) | PostfixKind::OptionUnwrap => {}
+                    }
+                }
+            }
+            ExpressionKind::CoerceOptionToBool(expression) => self.expression(expression),
+            ExpressionKind::FunctionCall(_, function, arguments) => {
+                if let ExpressionKind::ExternalFunctionAccess(external) = &function.kind {
+                    self.call(&Function::External(external.clone()), arguments);
+                }
+                self.expression(function);
+                self.arguments(arguments);
+            }
+            ExpressionKind::InterpolatedString(parts) => {
+                for part in parts {
+                    if let StringPart::Interpolation(expression, _) = part {
+                        self.expression(expression);
+                    }
+                }
+            }
+            ExpressionKind::VariableDefinition(_, expression)
+            | ExpressionKind::VariableReassignment(_, expression) => {
+                self.mut_or_immutable(expression);
+            }
+            ExpressionKind::Assignment(location, expression) => {
+                self.location(&location.0);
+                self.expression(expression);
+            }
+            ExpressionKind::CompoundAssignment(location, _, expression) => {
+                self.location(&location.0);
+                self.expression(expression);
+            }
+            ExpressionKind::StructInstantiation(instantiation) => self.expressions(
+                instantiation
+                    .source_order_expressions
+                    .iter()
+                    .map(|(_, expression)| expression),
+            ),
+            ExpressionKind::AnonymousStructLiteral(literal) => self.expressions(
+                literal
+                    .source_order_expressions
+                    .iter()
+                    .map(|(_, expression)| expression),
+            ),
+            ExpressionKind::Literal(literal) => self.literal(literal),
+            ExpressionKind::Option(expression) => self.expressions(expression.as_deref()),
+            ExpressionKind::Range(min, max, _) => {
+                self.expression(min);
+                self.expression(max);
+            }
+            ExpressionKind::ForLoop(_, iterable, body) => {
+                self.mut_or_immutable(&iterable.resolved_expression);
+                self.expression(body);
+            }
+            ExpressionKind::WhileLoop(condition, body) => {
+                self.expression(&condition.expression);
+                self.expression(body);
+            }
+            ExpressionKind::Block(expressions) => self.expressions(expressions),
+            ExpressionKind::Match(found) => {
+                self.mut_or_immutable(&found.expression);
+                for arm in &found.arms {
+                    if let Pattern::Normal(_, Some(guard)) = &arm.pattern {
+                        self.expression(&guard.expression);
+                    }
+                    self.expression(&arm.expression);
+                }
+            }
+            ExpressionKind::Guard(guards) => {
+                for guard in guards {
+                    if let Some(condition) = &guard.condition {
+                        self.expression(&condition.expression);
+                    }
+                    self.expression(&guard.result);
+                }
+            }
+            ExpressionKind::If(condition, then, otherwise) => {
+                self.expression(&condition.expression);
+                self.expression(then);
+                self.expressions(otherwise.as_deref());
+            }
+            ExpressionKind::When(bindings, then, otherwise) => {
+                for binding in bindings {
+                    self.mut_or_immutable(&binding.expr);
+                }
+                self.expression(then);
+                self.expressions(otherwise.as_deref());
+            }
+            ExpressionKind::TupleDestructuring(_, _, expression) => self.expression(expression),
+            ExpressionKind::IntrinsicCallMut(_, location, expressions) => {
+                self.location(&location.0);
+                self.expressions(expressions);
+            }
+            ExpressionKind::IntrinsicCallEx(_, arguments) => self.arguments(arguments),
+        }
+    }
+
+    fn literal(&mut self, literal: &Literal) {
+        match literal {
+            Literal::EnumVariantLiteral(_, _, EnumLiteralData::Tuple(expressions))
+            | Literal::TupleLiteral(_, expressions)
+            | Literal::Slice(_, expressions) => self.expressions(expressions),
+            Literal::EnumVariantLiteral(_, _, EnumLiteralData::Struct(expressions)) => {
+                self.expressions(expressions.iter().map(|(_, expression)| expression));
+            }
+            Literal::SlicePair(_, pairs) => {
+                for (key, value) in pairs {
+                    self.expression(key);
+                    self.expression(value);
+                }
+            }
+            Literal::EnumVariantLiteral(_, _, EnumLiteralData::Nothing)
+            | Literal::FloatLiteral(_)
+            | Literal::NoneLiteral
+            | Literal::IntLiteral(_)
+            | Literal::StringLiteral(_)
+            | Literal::BoolLiteral(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SourceMapResource;
+    use crate::script_main::compile;
+    use crate::test_fixtures::{scripts, test_directory, with_stack};
+    use std::fs;
+
+    #[test]
+    fn finds_the_first_argument_of_the_calls() {
+        with_stack(|| {
+            let directory = test_directory("call-sites");
+            let mut source_map = SourceMapResource::with_registry(
+                &scripts("call_sites"),
+                &directory.join("registry"),
+            )
+            .unwrap();
+            let script_main = compile(&mut source_map).unwrap();
+            let program = &script_main.resolved_program;
+
+            let render_module = program
+                .modules
+                .get(&["mangrove".to_string(), "render".to_string()])
+                .unwrap();
+            let assets = render_module.symbol_table.get_type("Assets").unwrap();
+            let function_id = |name| {
+                program
+                    .state
+                    .associated_impls
+                    .api_fetch_external_function_id(assets, name)
+            };
+            let material_png = function_id("material_png");
+            let bm_font = function_id("bm_font");
+            let call_sites = CallSites::find(program, &[material_png, bm_font]);
+
+            let source = |span: Option<Span>| {
+                let span = span.expect("should find the call");
+                source_map
+                    .source_map
+                    .get_span_source(span.file_id, span.offset as usize, span.length as usize)
+                    .to_string()
+            };
+            assert_eq!(
+                source(call_sites.span(material_png, "player/ship")),
+                "'player/ship'"
+            );
+            assert_eq!(source(call_sites.span(material_png, "enemy")), "'enemy'");
+            // Not a literal, but the only call to the function
+            assert_eq!(source(call_sites.span(bm_font, "fonts/score")), "name");
+            assert!(call_sites.span(material_png, "other").is_none());
+
+            fs::remove_dir_all(&directory).unwrap();
+        });
+    }
+}
@@ -0,0 +1,110 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Compile-only checks for editors and CI.
+//!
+//! Compiles the scripts without booting them, and validates the contract with the engine,
+//! see `contract::validate`. Also warns about screens that can never be reached.
+use crate::SourceMapResource;
+use crate::contract::{module_span, screen_modules, validate};
+use crate::err::{Diagnostic, DiagnosticPhase, Severity};
+use crate::script::analyze;
+use crate::util::get_impl_func_optional;
+use swamp_script::prelude::*;
+
+/// Compiles the scripts and validates the entry points. Returns every problem that was found,
+/// an empty list means that the scripts can be booted.
+pub fn check(source_map: &mut SourceMapResource) -> Vec<Diagnostic> {
+    let entry_path = vec!["crate".to_string(), source_map.entry_module.clone()];
+
+    let program = match analyze(&entry_path, &mut source_map.source_map) {
+        Ok(program) => program,
+        Err(err) => {
+            return vec![Diagnostic::from_mangrove_error(
+                DiagnosticPhase::Compile,
+                &err,
+                &source_map.source_map,
+            )];
+        }
+    };
+
+    let mut diagnostics = validate(&program, &entry_path, &source_map.source_map)
+        .iter()
+        .map(|err| {
+            Diagnostic::from_mangrove_error(DiagnosticPhase::Compile, err, &source_map.source_map)
+        })
+        .collect::<Vec<_>>();
+
+    diagnostics.extend(unreachable_screens(
+        &program,
+        &entry_path,
+        &source_map.source_map,
+    ));
+
+    diagnostics
+}
+
+#[must_use]
+pub fn has_errors(diagnostics: &[Diagnostic]) -> bool {
+    diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+}
+
+/// Without a `flow` function, only a simulation that implements `next_screen` can change the screen
+fn unreachable_screens(
+    program: &Program,
+    entry_path: &[String],
+    source_map: &SourceMap,
+) -> Vec<Diagnostic> {
+    let Some(entry_module) = program.modules.get(entry_path) else {
+        return Vec::new();
+    };
+    if entry_module
+        .symbol_table
+        .get_internal_function("flow")
+        .is_some()
+    {
+        return Vec::new();
+    }
+
+    let has_next_screen = |module: &ModuleRef| {
+        module
+            .symbol_table
+            .get_internal_function("simulation")
+            .and_then(
+                |simulation_fn| match &*simulation_fn.signature.return_type {
+                    Type::NamedStruct(simulation_struct) => get_impl_func_optional(
+                        &program.state.associated_impls,
+                        simulation_struct,
+                        "next_screen",
+                    ),
+                    _ => None,
+                },
+            )
+            .is_some()
+    };
+
+    let screens = screen_modules(program, entry_path).collect::<Vec<_>>();
+    if has_next_screen(entry_module) || screens.iter().any(|(_, module)| has_next_screen(module)) {
+        return Vec::new();
+    }
+
+    screens
+        .iter()
+        .map(|(screen_path, _)| {
+            let mut diagnostic = Diagnostic::new(
+                DiagnosticPhase::Compile,
+                &format!(
+                    "screen '{}' can not be reached, there is no flow function and no simulation implements next_screen",
+                    screen_path[1]
+                ),
+                module_span(source_map, screen_path),
+                source_map,
+            );
+            diagnostic.severity = Severity::Warning;
+            diagnostic
+        })
+        .collect()
+}
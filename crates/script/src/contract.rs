@@ -0,0 +1,517 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! The functions, signatures and fields that mangrove expects from the scripts.
+//!
+//! The contract is validated after the scripts are compiled, so a broken contract is reported
+//! as a `MangroveError` instead of a panic when the simulation, render or input boot.
+use crate::bindings::{AXIS_NAMES, BUTTON_NAMES, named_key_codes};
+use crate::input::{scan_action_sets, scan_held_keys};
+use crate::script::MangroveError;
+use crate::util::{get_impl_func, get_impl_func_optional};
+use limnus_basic_input::prelude::KeyCode;
+use limnus_gamepad::{Axis, Button};
+use std::path::Path;
+use swamp_script::prelude::*;
+
+/// `tick(mut self)`
+pub const TICK_PARAMETER_COUNTS: [usize; 1] = [1];
+
+/// `render(mut self, simulation: Simulation, mut gfx: Gfx)`, optionally with the previous
+/// simulation and the alpha in between
+pub const RENDER_PARAMETER_COUNTS: [usize; 2] = [3, 5];
+
+/// `update(mut self, simulation: Simulation, mut audio: Audio)` and
+/// `update(mut self, input: Input, mut flow: Flow)`
+pub const UPDATE_PARAMETER_COUNTS: [usize; 1] = [3];
+
+/// The module path of the `mangrove::input` package
+pub const INPUT_MODULE_PATH: [&str; 2] = ["mangrove", "input"];
+
+/// The optional callbacks on the simulation struct that get a `mangrove::input::Key`, `Button` or `Axis`
+pub const KEYBOARD_KEY_CHANGED: &str = "keyboard_key_changed";
+pub const GAMEPAD_BUTTON_CHANGED: &str = "gamepad_button_changed";
+pub const GAMEPAD_AXIS_CHANGED: &str = "gamepad_axis_changed";
+
+/// The fields in the input struct that mangrove writes the mouse state to
+#[must_use]
+pub fn mouse_fields() -> [(&'static str, Type); 3] {
+    [
+        (
+            "mouse_cursor_position",
+            Type::Tuple(vec![Type::Int, Type::Int]),
+        ),
+        ("mouse_left_button", Type::Bool),
+        ("mouse_right_button", Type::Bool),
+    ]
+}
+
+/// Validates the entry module and every screen module in the crate.
+/// Returns every broken part of the contract, an empty list means that the scripts can be booted.
+#[must_use]
+pub fn validate(
+    program: &Program,
+    entry_module_path: &[String],
+    source_map: &SourceMap,
+) -> Vec<MangroveError> {
+    let mut validator = Validator {
+        program,
+        errors: Vec::new(),
+    };
+
+    let Some(module) = program.modules.get(entry_module_path) else {
+        return vec![MangroveError::Other(format!(
+            "could not find the entry module {}",
+            entry_module_path.join("::")
+        ))];
+    };
+    let entry_span = module_span(source_map, entry_module_path);
+
+    if let Some(input_fn) = validator.collect(required_function(module, "input", &entry_span)) {
+        validator.input(&input_fn);
+    }
+
+    validator.screen(module, &entry_span);
+
+    if let Some(flow_fn) = module.symbol_table.get_internal_function("flow") {
+        validator.constructed_struct_with_member(flow_fn, 0, "update", &UPDATE_PARAMETER_COUNTS);
+    }
+
+    for (screen_path, screen_module) in screen_modules(program, entry_module_path) {
+        validator.screen(screen_module, &module_span(source_map, screen_path));
+    }
+
+    validator.errors
+}
+
+/// The `simulation`, `render` and optional `audio` functions in the module
+///
+/// # Errors
+///
+/// If `simulation` or `render` is missing.
+pub fn screen_functions(
+    module: &ModuleRef,
+    module_span: &Span,
+) -> Result<
+    (
+        InternalFunctionDefinitionRef,
+        InternalFunctionDefinitionRef,
+        Option<InternalFunctionDefinitionRef>,
+    ),
+    MangroveError,
+> {
+    Ok((
+        required_function(module, "simulation", module_span)?,
+        required_function(module, "render", module_span)?,
+        module.symbol_table.get_internal_function("audio").cloned(),
+    ))
+}
+
+/// # Errors
+///
+/// If the module does not have the function
+pub fn required_function(
+    module: &ModuleRef,
+    name: &str,
+    module_span: &Span,
+) -> Result<InternalFunctionDefinitionRef, MangroveError> {
+    module
+        .symbol_table
+        .get_internal_function(name)
+        .cloned()
+        .ok_or_else(|| MangroveError::MissingFunction {
+            owner: module.symbol_table.module_path().join("::"),
+            expected: format!("fn {name}()"),
+            span: module_span.clone(),
+        })
+}
+
+/// Checks a function that creates a struct, e.g. `simulation()`, and returns the struct type
+///
+/// # Errors
+///
+/// If the function takes another number of parameters or does not return a struct.
+pub fn constructed_struct(
+    function: &InternalFunctionDefinitionRef,
+    parameter_count: usize,
+) -> Result<NamedStructType, MangroveError> {
+    let name = &function.assigned_name;
+    let span = &function.name.0.span;
+
+    let found_parameter_count = function.signature.parameters.len();
+    if found_parameter_count != parameter_count {
+        return Err(MangroveError::WrongSignature {
+            function: format!("{name}()"),
+            expected: format!("take {parameter_count} parameter(s)"),
+            found: format!("takes {found_parameter_count}"),
+            span: span.clone(),
+        });
+    }
+
+    let Type::NamedStruct(struct_type) = &*function.signature.return_type else {
+        return Err(MangroveError::WrongSignature {
+            function: format!("{name}()"),
+            expected: "return a struct".to_string(),
+            found: format!("returns {}", function.signature.return_type),
+            span: span.clone(),
+        });
+    };
+
+    Ok(struct_type.clone())
+}
+
+/// The index of the field in the input struct, e.g. for `mouse_left_button`
+///
+/// # Errors
+///
+/// If the field is missing or has another type.
+pub fn input_field_index(
+    input_struct: &NamedStructType,
+    field: &str,
+    expected: &Type,
+) -> Result<usize, MangroveError> {
+    let fields = &input_struct.anon_struct_type.field_name_sorted_fields;
+    let field_name = field.to_string();
+    let (Some(index), Some(found)) = (fields.get_index(&field_name), fields.get(&field_name))
+    else {
+        return Err(MangroveError::MissingField {
+            struct_name: input_struct.assigned_name.clone(),
+            field: field_name,
+            expected: expected.to_string(),
+            span: input_struct.name.span.clone(),
+        });
+    };
+
+    if found.field_type != *expected {
+        return Err(MangroveError::WrongFieldType {
+            struct_name: input_struct.assigned_name.clone(),
+            field: field_name,
+            expected: expected.to_string(),
+            found: found.field_type.to_string(),
+            span: input_struct.name.span.clone(),
+        });
+    }
+
+    Ok(index)
+}
+
+/// The indices of the `mouse_fields` in the input struct, in the same order
+///
+/// # Errors
+///
+/// If a field is missing or has another type.
+pub fn mouse_field_indices(input_struct: &NamedStructType) -> Result<[usize; 3], MangroveError> {
+    let mut indices = [0; 3];
+    for (index, (field, expected)) in indices.iter_mut().zip(mouse_fields()) {
+        *index = input_field_index(input_struct, field, &expected)?;
+    }
+
+    Ok(indices)
+}
+
+/// A struct from a mangrove package, e.g. `Gfx` from `mangrove::render`
+///
+/// # Errors
+///
+/// If the package does not have the struct.
+pub fn required_struct(
+    symbol_table: &SymbolTable,
+    name: &str,
+) -> Result<NamedStructType, MangroveError> {
+    symbol_table
+        .get_struct(name)
+        .cloned()
+        .ok_or_else(|| missing_type(symbol_table, name))
+}
+
+/// Same as `required_struct`, but as a type, for looking up the external member functions
+///
+/// # Errors
+///
+/// If the package does not have the type.
+pub fn required_type<'a>(
+    symbol_table: &'a SymbolTable,
+    name: &str,
+) -> Result<&'a Type, MangroveError> {
+    symbol_table
+        .get_type(name)
+        .ok_or_else(|| missing_type(symbol_table, name))
+}
+
+/// An enum in `mangrove::input`, e.g. `Key`
+///
+/// # Errors
+///
+/// If the package is not used or does not have the enum.
+pub fn input_enum(modules: &Modules, enum_name: &str) -> Result<EnumType, MangroveError> {
+    let module_path = INPUT_MODULE_PATH.map(str::to_string);
+    modules
+        .get(&module_path)
+        .and_then(|module| module.symbol_table.get_enum(enum_name).cloned())
+        .ok_or_else(|| MangroveError::MissingType {
+            module: module_path.join("::"),
+            name: enum_name.to_string(),
+        })
+}
+
+/// The values of the variants of an enum in `mangrove::input`, in the same order as the names.
+/// Mangrove passes them to the simulation callbacks, e.g. the `Key` to `keyboard_key_changed`.
+///
+/// # Errors
+///
+/// If the package or the enum is missing, or a variant is missing or has fields.
+pub fn input_enum_values(
+    modules: &Modules,
+    enum_name: &str,
+    variant_names: &[&str],
+) -> Result<Vec<Value>, MangroveError> {
+    let enum_type = input_enum(modules, enum_name)?;
+
+    variant_names
+        .iter()
+        .map(|variant_name| match enum_type.get_variant(variant_name) {
+            Some(EnumVariantType::Nothing(simple)) => {
+                Ok(Value::EnumVariantSimple(enum_type.clone(), simple.clone()))
+            }
+            _ => Err(MangroveError::Other(format!(
+                "{}::{enum_name} must have the variant {variant_name} without fields",
+                INPUT_MODULE_PATH.join("::")
+            ))),
+        })
+        .collect()
+}
+
+/// The script values of a `mangrove::input` enum, for the matching values in Rust
+pub type EnumValues<T> = Vec<(T, Value)>;
+
+/// The `Key` values, for every key code that mangrove knows of
+///
+/// # Errors
+///
+/// If `mangrove::input::Key` is missing or does not have all the keys.
+pub fn key_values(modules: &Modules) -> Result<EnumValues<KeyCode>, MangroveError> {
+    named_values(modules, "Key", named_key_codes())
+}
+
+/// The `Button` values, for every gamepad button
+///
+/// # Errors
+///
+/// If `mangrove::input::Button` is missing or does not have all the buttons.
+pub fn button_values(modules: &Modules) -> Result<EnumValues<Button>, MangroveError> {
+    named_values(modules, "Button", BUTTON_NAMES.iter().copied())
+}
+
+/// The `Axis` values, for every gamepad axis
+///
+/// # Errors
+///
+/// If `mangrove::input::Axis` is missing or does not have all the axes.
+pub fn axis_values(modules: &Modules) -> Result<EnumValues<Axis>, MangroveError> {
+    named_values(modules, "Axis", AXIS_NAMES.iter().copied())
+}
+
+fn named_values<T>(
+    modules: &Modules,
+    enum_name: &str,
+    named: impl Iterator<Item = (&'static str, T)>,
+) -> Result<EnumValues<T>, MangroveError> {
+    let (names, items): (Vec<_>, Vec<_>) = named.unzip();
+    let values = input_enum_values(modules, enum_name, &names)?;
+
+    Ok(items.into_iter().zip(values).collect())
+}
+
+/// Checks that a callback takes the enum at the parameter index (including self)
+///
+/// # Errors
+///
+/// If the parameter is missing or has another type.
+pub fn enum_callback_parameter(
+    function: &InternalFunctionDefinitionRef,
+    struct_name: &str,
+    parameter_index: usize,
+    expected: &EnumType,
+) -> Result<(), MangroveError> {
+    let expected_type = Type::Enum(expected.clone());
+    let is_expected_enum = function
+        .signature
+        .parameters
+        .get(parameter_index)
+        .is_some_and(|parameter| parameter.resolved_type == expected_type);
+
+    if is_expected_enum {
+        Ok(())
+    } else {
+        Err(MangroveError::WrongSignature {
+            function: format!("{struct_name}::{}()", function.assigned_name),
+            expected: format!(
+                "take a {}::{} as parameter {parameter_index} (including self)",
+                INPUT_MODULE_PATH.join("::"),
+                expected.assigned_name
+            ),
+            found: function
+                .signature
+                .parameters
+                .get(parameter_index)
+                .map_or_else(
+                    || "does not".to_string(),
+                    |parameter| format!("takes {}", parameter.resolved_type),
+                ),
+            span: function.name.0.span.clone(),
+        })
+    }
+}
+
+fn missing_type(symbol_table: &SymbolTable, name: &str) -> MangroveError {
+    MangroveError::MissingType {
+        module: symbol_table.module_path().join("::"),
+        name: name.to_string(),
+    }
+}
+
+/// Every other module in the crate that has a `simulation` function
+pub(crate) fn screen_modules<'a>(
+    program: &'a Program,
+    entry_module_path: &'a [String],
+) -> impl Iterator<Item = (&'a Vec<String>, &'a ModuleRef)> {
+    program
+        .modules
+        .modules()
+        .iter()
+        .filter(move |(module_path, module)| {
+            module_path.len() == 2
+                && module_path[0] == "crate"
+                && module_path.as_slice() != entry_module_path
+                && module
+                    .symbol_table
+                    .get_internal_function("simulation")
+                    .is_some()
+        })
+}
+
+/// The start of the module file, for problems that have no better location
+pub(crate) fn module_span(source_map: &SourceMap, module_path: &[String]) -> Span {
+    let Some((mount_name, module_names)) = module_path.split_first() else {
+        return Span::default();
+    };
+    let relative_path = Path::new(&module_names.join("/")).with_extension("swamp");
+
+    source_map
+        .cache
+        .iter()
+        .find(|(_, file_info)| {
+            file_info.mount_name == *mount_name && file_info.relative_path == relative_path
+        })
+        .map_or_else(Span::default, |(file_id, _)| Span {
+            file_id: *file_id,
+            offset: 0,
+            length: 0,
+        })
+}
+
+/// Collects every error instead of stopping at the first one
+struct Validator<'a> {
+    program: &'a Program,
+    errors: Vec<MangroveError>,
+}
+
+impl Validator<'_> {
+    fn collect<T>(&mut self, result: Result<T, MangroveError>) -> Option<T> {
+        result.map_err(|err| self.errors.push(err)).ok()
+    }
+
+    fn screen(&mut self, module: &ModuleRef, module_span: &Span) {
+        if let Some(simulation_fn) =
+            self.collect(required_function(module, "simulation", module_span))
+        {
+            self.constructed_struct_with_member(&simulation_fn, 0, "tick", &TICK_PARAMETER_COUNTS);
+            self.simulation_callbacks(&simulation_fn);
+        }
+
+        if let Some(render_fn) = self.collect(required_function(module, "render", module_span)) {
+            self.constructed_struct_with_member(&render_fn, 1, "render", &RENDER_PARAMETER_COUNTS);
+        }
+
+        if let Some(audio_fn) = module.symbol_table.get_internal_function("audio") {
+            self.constructed_struct_with_member(audio_fn, 1, "update", &UPDATE_PARAMETER_COUNTS);
+        }
+    }
+
+    fn constructed_struct_with_member(
+        &mut self,
+        function: &InternalFunctionDefinitionRef,
+        parameter_count: usize,
+        member_name: &str,
+        member_parameter_counts: &[usize],
+    ) {
+        if let Some(struct_type) = self.collect(constructed_struct(function, parameter_count)) {
+            self.collect(get_impl_func(
+                &self.program.state.associated_impls,
+                &struct_type,
+                member_name,
+                member_parameter_counts,
+            ));
+        }
+    }
+
+    /// The optional callbacks that mangrove passes `mangrove::input` values to
+    fn simulation_callbacks(&mut self, simulation_fn: &InternalFunctionDefinitionRef) {
+        let Ok(struct_type) = constructed_struct(simulation_fn, 0) else {
+            return;
+        };
+
+        self.enum_callback(&struct_type, KEYBOARD_KEY_CHANGED, 1, "Key", key_values);
+        self.enum_callback(
+            &struct_type,
+            GAMEPAD_BUTTON_CHANGED,
+            2,
+            "Button",
+            button_values,
+        );
+        self.enum_callback(&struct_type, GAMEPAD_AXIS_CHANGED, 2, "Axis", axis_values);
+    }
+
+    /// Checks the callback, if the struct has it, and that mangrove can create all the enum values
+    fn enum_callback<T>(
+        &mut self,
+        struct_type: &NamedStructType,
+        callback_name: &str,
+        parameter_index: usize,
+        enum_name: &str,
+        values: fn(&Modules) -> Result<EnumValues<T>, MangroveError>,
+    ) {
+        let Some(callback_fn) = get_impl_func_optional(
+            &self.program.state.associated_impls,
+            struct_type,
+            callback_name,
+        ) else {
+            return;
+        };
+        let Some(expected_enum) = self.collect(input_enum(&self.program.modules, enum_name)) else {
+            return;
+        };
+
+        self.collect(enum_callback_parameter(
+            &callback_fn,
+            &struct_type.assigned_name,
+            parameter_index,
+            &expected_enum,
+        ));
+        self.collect(values(&self.program.modules));
+    }
+
+    fn input(&mut self, input_fn: &InternalFunctionDefinitionRef) {
+        let Some(input_struct) = self.collect(constructed_struct(input_fn, 0)) else {
+            return;
+        };
+
+        for (field, expected) in mouse_fields() {
+            self.collect(input_field_index(&input_struct, field, &expected));
+        }
+
+        self.collect(scan_action_sets(&input_struct));
+        self.collect(scan_held_keys(&input_struct));
+    }
+}
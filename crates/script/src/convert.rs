@@ -4,10 +4,11 @@
  */
 
 use crate::ScriptMessage;
+use crate::migrate::migrate;
 use crate::simulation::ScriptSimulation;
-use swamp::prelude::{App, FixedPostUpdate, LoRe, LoReM, LocalResource, Msg, Plugin};
-use swamp_script::prelude::{NamedStructType, Type, Value, overwrite_value, quick_deserialize};
-use tracing::info;
+use swamp::prelude::{App, LoRe, LoReM, LocalResource, Msg, Plugin, PreUpdate, Update};
+use swamp_script::prelude::{Value, ValueRef};
+use tracing::{info, warn};
 
 pub fn detect_reload_tick(
     script_messages: Msg<ScriptMessage>,
@@ -15,64 +16,64 @@ pub fn detect_reload_tick(
     mut previous_logic: LoReM<PreviousSimulation>,
 ) {
     for msg in script_messages.iter_previous() {
-        match msg {
-            ScriptMessage::Reload => {
-                info!(len=%previous_logic.payload.len(), "reload detected");
-                if let Some(previous_struct_type_ref) = &previous_logic.type_ref {
-                    let (deserialized_value, deserialized_octet_size) = quick_deserialize(
-                        &Type::NamedStruct(previous_struct_type_ref.clone()),
-                        &previous_logic.payload,
-                        0,
-                    );
-                    previous_logic.type_ref = Some(previous_struct_type_ref.clone());
-                    info!(%deserialized_value, %deserialized_octet_size, "deserialized value");
-                    let overwritten_value = {
-                        let overwrite_ref = script_logic.mutable_simulation_value_ref();
-                        overwrite_value(overwrite_ref, deserialized_value);
-                        overwrite_ref.borrow().clone()
-                    };
-                    info!(%overwritten_value, "overwritten value");
-                    script_logic.debug_set_simulation_value(overwritten_value);
+        if matches!(msg, ScriptMessage::ScreenChanged) {
+            // The new screen has another simulation struct, there is nothing to convert from
+            previous_logic.value_ref = None;
+        }
+    }
+
+    // The new program was swapped in earlier in this frame, before the simulation ticks
+    for msg in script_messages.iter_current() {
+        // The simulation keeps running if only other parts were reloaded
+        let ScriptMessage::Reloaded(rebooted) = msg else {
+            continue;
+        };
+        if !rebooted.simulation {
+            continue;
+        }
+        let Some(previous_value_ref) = previous_logic.value_ref.take() else {
+            continue;
+        };
+        // Nothing else refers to the previous simulation, so the value can be moved
+        let previous_value = previous_value_ref.replace(Value::Unit);
+        let booted_value = script_logic.immutable_simulation_value();
+        match migrate(previous_value, &booted_value) {
+            Ok((migrated_value, report)) => {
+                if report.is_unchanged() {
+                    info!(%report, "migrated simulation state");
                 } else {
-                    match script_logic.immutable_simulation_value() {
-                        Value::NamedStruct(first_time_struct_ref, _) => {
-                            previous_logic.type_ref = Some(first_time_struct_ref.clone());
-                        }
-                        _ => panic!("must be struct"),
-                    }
+                    warn!(%report, "migrated simulation state to a new layout");
                 }
+                script_logic.debug_set_simulation_value(migrated_value);
+            }
+            Err(mangrove_error) => {
+                warn!(error=%mangrove_error, "could not migrate the simulation state, starting over");
             }
         }
     }
 }
 
+/// Keeps a reference to the simulation value, so it survives when the simulation is booted again
 pub fn store_tick(
     script_logic: LoRe<ScriptSimulation>,
     mut previous_logic: LoReM<PreviousSimulation>,
 ) {
-    let mut buf = [0u8; 2048];
-    let size = script_logic
-        .immutable_simulation_value()
-        .quick_serialize(&mut buf, 0);
-
-    previous_logic.payload = buf[..size].to_vec();
+    previous_logic.value_ref = Some(script_logic.simulation_value_ref().clone());
 }
 
 #[derive(Debug, LocalResource)]
 pub struct PreviousSimulation {
-    type_ref: Option<NamedStructType>,
-    payload: Vec<u8>,
+    value_ref: Option<ValueRef>,
 }
 
 pub struct ConvertPlugin;
 
 impl Plugin for ConvertPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_local_resource(PreviousSimulation {
-            type_ref: None,
-            payload: vec![],
-        });
-        app.add_system(FixedPostUpdate, detect_reload_tick);
-        app.add_system(FixedPostUpdate, store_tick);
+        app.insert_local_resource(PreviousSimulation { value_ref: None });
+        // Must be added after the ScriptMainPlugin, so the new simulation is swapped in before
+        // the previous value is migrated, and the reference is stored after the simulation ticks
+        app.add_system(PreUpdate, detect_reload_tick);
+        app.add_system(Update, store_tick);
     }
 }
@@ -4,29 +4,315 @@
  */
 use crate::ErrorResource;
 use crate::script::MangroveError;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
 use std::env::current_dir;
+use std::fmt::{Display, Formatter};
+use std::path::{Component, PathBuf};
 use swamp::prelude::{App, Plugin};
-use swamp_script::prelude::{SourceMap, show_runtime_error, show_script_resolve_error};
+use swamp_script::prelude::{RuntimeError, SourceMap, Span, show_runtime_error};
+use swamp_script_error_report::prelude::show_script_resolve_error;
+use swamp_script_error_report::runtime::build_runtime_error;
+use swamp_script_error_report::script_resolve::build_script_error;
 use tracing::error;
 
+/// Where in the script lifecycle the error happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticPhase {
+    Compile,
+    Boot,
+    Tick,
+    Render,
+}
+
+impl Display for DiagnosticPhase {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Compile => "compile",
+            Self::Boot => "boot",
+            Self::Tick => "tick",
+            Self::Render => "render",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    /// Does not stop the game, but is most likely a mistake
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        };
+        write!(f, "{name}")
+    }
+}
+
+/// Line and column are one-based, the same as the editor shows them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+    pub source_line: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub phase: DiagnosticPhase,
+    pub severity: Severity,
+    /// The source file, empty if the error is not connected to a source file
+    pub file: Option<PathBuf>,
+    /// e.g. `["crate", "main"]`. Empty if the error is not connected to a source file.
+    pub module_path: Vec<String>,
+    pub span: Span,
+    pub location: Option<SourceLocation>,
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Diagnostic {
+    #[must_use]
+    pub fn new(phase: DiagnosticPhase, message: &str, span: Span, source_map: &SourceMap) -> Self {
+        // file_id zero is used for generated code that has no source
+        let file_info = if span.file_id == 0 {
+            None
+        } else {
+            source_map.cache.get(&span.file_id)
+        };
+
+        let module_path = file_info.map_or_else(Vec::new, |file_info| {
+            let mut module_path = vec![file_info.mount_name.clone()];
+            module_path.extend(
+                file_info
+                    .relative_path
+                    .with_extension("")
+                    .components()
+                    .filter_map(|component| match component {
+                        Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+                        _ => None,
+                    }),
+            );
+            module_path
+        });
+
+        let location = file_info.map(|file_info| {
+            let (line, column) =
+                source_map.get_span_location_utf8(span.file_id, span.offset as usize);
+            // The last line has no end offset, e.g. for errors at the end of the file
+            let source_line = if line < file_info.line_offsets.len() {
+                source_map
+                    .get_source_line(span.file_id, line)
+                    .map(str::to_string)
+            } else {
+                None
+            };
+            SourceLocation {
+                line,
+                column,
+                source_line,
+            }
+        });
+
+        let file = file_info.map(|file_info| {
+            source_map
+                .base_path(&file_info.mount_name)
+                .join(&file_info.relative_path)
+        });
+
+        Self {
+            phase,
+            severity: Severity::Error,
+            file,
+            module_path,
+            span,
+            location,
+            message: message.to_string(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[must_use]
+    pub fn from_mangrove_error(
+        phase: DiagnosticPhase,
+        err: &MangroveError,
+        source_map: &SourceMap,
+    ) -> Self {
+        match err {
+            MangroveError::ScriptResolveError(script_resolve_error) => {
+                let builder = build_script_error(script_resolve_error);
+                Self::new(phase, &builder.error_name, builder.primary_span, source_map)
+            }
+            MangroveError::RuntimeError(runtime_error) => {
+                Self::from_runtime_error(phase, runtime_error, source_map)
+            }
+            MangroveError::Other(description) => {
+                Self::new(phase, description, Span::default(), source_map)
+            }
+            contract_error => Self::new(
+                phase,
+                &contract_error.to_string(),
+                contract_error.span(),
+                source_map,
+            ),
+        }
+    }
+
+    #[must_use]
+    pub fn from_runtime_error(
+        phase: DiagnosticPhase,
+        err: &RuntimeError,
+        source_map: &SourceMap,
+    ) -> Self {
+        let builder = build_runtime_error(err);
+        Self::new(phase, &builder.error_name, builder.primary_span, source_map)
+    }
+
+    /// One JSON object on a single line, for editors and CI.
+    /// `file`, `line` and `column` are `null` if the error is not connected to a source file.
+    #[must_use]
+    pub fn to_json(&self) -> String {
+        let json = DiagnosticJson {
+            file: self
+                .file
+                .as_ref()
+                .map(|file| file.to_string_lossy().to_string()),
+            line: self.location.as_ref().map(|location| location.line),
+            column: self.location.as_ref().map(|location| location.column),
+            severity: self.severity,
+            phase: self.phase,
+            message: &self.message,
+        };
+
+        serde_json::to_string(&json).expect("a diagnostic can always be serialized")
+    }
+}
+
+/// The fields of a [`Diagnostic`] that [`Diagnostic::to_json`] writes, in that order
+#[derive(Serialize)]
+struct DiagnosticJson<'a> {
+    file: Option<String>,
+    line: Option<usize>,
+    column: Option<usize>,
+    severity: Severity,
+    phase: DiagnosticPhase,
+    message: &'a str,
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.phase, self.severity)?;
+        if !self.module_path.is_empty() {
+            write!(f, " in {}", self.module_path.join("::"))?;
+        }
+        if let Some(location) = &self.location {
+            write!(f, " {}:{}", location.line, location.column)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
 /// # Panics
 ///
 pub fn show_mangrove_error(err: &MangroveError, source_map: &SourceMap) {
     let current_path = &*current_dir().unwrap();
     match err {
         MangroveError::ScriptResolveError(script_resolve_error) => {
-            //show_script_resolve_error(script_resolve_error, source_map, current_path);
-            // Should already be handled
+            show_script_resolve_error(script_resolve_error, source_map, current_path);
         }
         MangroveError::RuntimeError(err) => show_runtime_error(err, source_map, current_path),
         MangroveError::Other(description) => error!(?err, ?description, "unknown error"),
+        contract_error => error!("{contract_error}"),
     }
 }
 
+/// Shows the error in the terminal and stores it as a diagnostic
+pub fn report_mangrove_error(
+    error_resource: &mut ErrorResource,
+    phase: DiagnosticPhase,
+    err: &MangroveError,
+    source_map: &SourceMap,
+) {
+    show_mangrove_error(err, source_map);
+    error_resource.push(Diagnostic::from_mangrove_error(phase, err, source_map));
+}
+
+/// Shows the error in the terminal and stores it as the reason for the failed reload,
+/// without stopping the program that is running
+pub fn report_failed_reload(
+    error_resource: &mut ErrorResource,
+    phase: DiagnosticPhase,
+    err: &MangroveError,
+    source_map: &SourceMap,
+) {
+    show_mangrove_error(err, source_map);
+    error_resource.failed_reload = Some(Diagnostic::from_mangrove_error(phase, err, source_map));
+}
+
+/// Shows the error in the terminal and stores it as a diagnostic
+///
+/// # Panics
+///
+pub fn report_runtime_error(
+    error_resource: &mut ErrorResource,
+    phase: DiagnosticPhase,
+    err: &RuntimeError,
+    source_map: &SourceMap,
+) {
+    let current_path = &*current_dir().unwrap();
+    show_runtime_error(err, source_map, current_path);
+    error_resource.push(Diagnostic::from_runtime_error(phase, err, source_map));
+}
+
 pub struct ErrorPlugin;
 
 impl Plugin for ErrorPlugin {
     fn build(&self, app: &mut App) {
-        app.insert_resource(ErrorResource { has_errors: false });
+        app.insert_resource(ErrorResource::default());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diagnostic(file: Option<&str>, location: Option<(usize, usize)>) -> Diagnostic {
+        Diagnostic {
+            phase: DiagnosticPhase::Boot,
+            severity: Severity::Warning,
+            file: file.map(PathBuf::from),
+            module_path: Vec::new(),
+            span: Span::default(),
+            location: location.map(|(line, column)| SourceLocation {
+                line,
+                column,
+                source_line: None,
+            }),
+            message: "the \"map\" is\tmissing\n\u{1}".to_string(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn json_with_a_location() {
+        assert_eq!(
+            diagnostic(Some("scripts/logic.swamp"), Some((4, 8))).to_json(),
+            r#"{"file":"scripts/logic.swamp","line":4,"column":8,"severity":"warning","phase":"boot","message":"the \"map\" is\tmissing\n\u0001"}"#
+        );
+    }
+
+    #[test]
+    fn json_without_a_location() {
+        assert_eq!(
+            diagnostic(None, None).to_json(),
+            r#"{"file":null,"line":null,"column":null,"severity":"warning","phase":"boot","message":"the \"map\" is\tmissing\n\u0001"}"#
+        );
     }
 }
@@ -0,0 +1,320 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Fallback overlay that shows the script diagnostics on screen.
+//!
+//! Errors dim the whole screen, since the game has stopped. Warnings are shown in a strip at the
+//! top, so the game can still be seen.
+//!
+//! The diagnostics can happen before any font asset is loaded, so the text is drawn with a built-in
+//! 3x5 pixel font made out of colored quads.
+use crate::err::{Diagnostic, Severity};
+use swamp::prelude::{Color, Render, UVec2, Vec3};
+
+const GLYPH_WIDTH: u16 = 3;
+pub(crate) const GLYPH_HEIGHT: u16 = 5;
+pub(crate) const CELL_WIDTH: u16 = GLYPH_WIDTH + 1;
+const CELL_HEIGHT: u16 = GLYPH_HEIGHT + 2;
+const MARGIN: u16 = 4;
+
+/// Drawn on top of everything the scripts could have drawn
+pub(crate) const OVERLAY_Z: i16 = i16::MAX - 1;
+
+/// Only the latest diagnostics fit on the screen
+const MAX_DIAGNOSTICS_SHOWN: usize = 4;
+
+const BACKGROUND_COLOR: Color = Color::from_octet(24, 0, 0, 220);
+const HEADER_COLOR: Color = Color::from_octet(255, 90, 80, 255);
+const WARNING_HEADER_COLOR: Color = Color::from_octet(255, 200, 0, 255);
+const MESSAGE_COLOR: Color = Color::from_octet(255, 255, 255, 255);
+const SOURCE_COLOR: Color = Color::from_octet(170, 170, 190, 255);
+const MARKER_COLOR: Color = Color::from_octet(255, 200, 0, 255);
+
+/// Rows from top to bottom, the most significant bit (of the three) is the leftmost pixel
+#[allow(clippy::too_many_lines)]
+const fn glyph(ch: char) -> [u8; 5] {
+    match ch.to_ascii_uppercase() {
+        ' ' => [0b000, 0b000, 0b000, 0b000, 0b000],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ',' => [0b000, 0b000, 0b000, 0b010, 0b100],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        ';' => [0b000, 0b010, 0b000, 0b010, 0b100],
+        '\'' => [0b010, 0b010, 0b000, 0b000, 0b000],
+        '"' => [0b101, 0b101, 0b000, 0b000, 0b000],
+        '`' => [0b100, 0b010, 0b000, 0b000, 0b000],
+        '(' => [0b001, 0b010, 0b010, 0b010, 0b001],
+        ')' => [0b100, 0b010, 0b010, 0b010, 0b100],
+        '[' => [0b011, 0b010, 0b010, 0b010, 0b011],
+        ']' => [0b110, 0b010, 0b010, 0b010, 0b110],
+        '{' => [0b011, 0b010, 0b110, 0b010, 0b011],
+        '}' => [0b110, 0b010, 0b011, 0b010, 0b110],
+        '<' => [0b001, 0b010, 0b100, 0b010, 0b001],
+        '>' => [0b100, 0b010, 0b001, 0b010, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        '+' => [0b000, 0b010, 0b111, 0b010, 0b000],
+        '=' => [0b000, 0b111, 0b000, 0b111, 0b000],
+        '*' => [0b000, 0b101, 0b010, 0b101, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '\\' => [0b100, 0b100, 0b010, 0b001, 0b001],
+        '|' => [0b010, 0b010, 0b010, 0b010, 0b010],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        '#' => [0b101, 0b111, 0b101, 0b111, 0b101],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '&' => [0b010, 0b101, 0b010, 0b101, 0b011],
+        '^' => [0b010, 0b101, 0b000, 0b000, 0b000],
+        '~' => [0b000, 0b011, 0b110, 0b000, 0b000],
+        '$' => [0b011, 0b110, 0b010, 0b011, 0b110],
+        '@' => [0b010, 0b101, 0b111, 0b100, 0b011],
+        _ => [0b110, 0b001, 0b010, 0b000, 0b010], // '?'
+    }
+}
+
+struct TextCursor<'a> {
+    render: &'a mut Render,
+    columns: u16,
+    top: u16,
+    row: u16,
+    rows: u16,
+}
+
+impl TextCursor<'_> {
+    fn is_full(&self) -> bool {
+        self.row >= self.rows
+    }
+
+    const fn y_for_row(&self, row: u16) -> i16 {
+        (self.top - (row + 1) * CELL_HEIGHT) as i16
+    }
+
+    /// Writes the text, wrapping it on as many rows as needed
+    fn line(&mut self, text: &str, color: Color) {
+        let chars: Vec<char> = text
+            .chars()
+            .map(|ch| if ch == '\t' { ' ' } else { ch })
+            .collect();
+        if chars.is_empty() {
+            self.row += 1;
+            return;
+        }
+
+        for row_chars in chars.chunks(self.columns as usize) {
+            if self.is_full() {
+                return;
+            }
+            let y = self.y_for_row(self.row);
+            for (column, ch) in row_chars.iter().enumerate() {
+                let x = (MARGIN + column as u16 * CELL_WIDTH) as i16;
+                draw_glyph(self.render, x, y, glyph(*ch), color);
+            }
+            self.row += 1;
+        }
+    }
+
+    /// Underlines `length` characters starting at the zero-based `column` on the previous row
+    fn marker(&mut self, column: usize, length: usize) {
+        if self.row == 0 || column >= self.columns as usize {
+            return;
+        }
+        let length = length.clamp(1, self.columns as usize - column);
+        let x = (MARGIN + column as u16 * CELL_WIDTH) as i16;
+        let y = self.y_for_row(self.row - 1) - 1;
+        self.render.draw_quad(
+            Vec3::new(x, y, OVERLAY_Z),
+            UVec2::new(length as u16 * CELL_WIDTH - 1, 1),
+            MARKER_COLOR,
+        );
+    }
+}
+
+fn draw_glyph(render: &mut Render, x: i16, y: i16, rows: [u8; 5], color: Color) {
+    for (row_index, row_bits) in rows.iter().enumerate() {
+        let pixel_y = y + (GLYPH_HEIGHT as i16 - 1 - row_index as i16);
+        let mut column = 0;
+        while column < GLYPH_WIDTH {
+            if row_bits & (0b100 >> column) == 0 {
+                column += 1;
+                continue;
+            }
+            // Merge horizontal runs of lit pixels into one quad
+            let start = column;
+            while column < GLYPH_WIDTH && row_bits & (0b100 >> column) != 0 {
+                column += 1;
+            }
+            render.draw_quad(
+                Vec3::new(x + start as i16, pixel_y, OVERLAY_Z),
+                UVec2::new(column - start, 1),
+                color,
+            );
+        }
+    }
+}
+
+/// Draws a single line of text with the built-in font, `y` is the bottom of the glyphs
+pub(crate) fn draw_text(render: &mut Render, x: i16, y: i16, text: &str, color: Color) {
+    for (column, ch) in text.chars().enumerate() {
+        draw_glyph(
+            render,
+            x + (column as u16 * CELL_WIDTH) as i16,
+            y,
+            glyph(ch),
+            color,
+        );
+    }
+}
+
+/// Shows the latest diagnostics, with the source line and a marker under the span.
+/// The whole screen is dimmed if any of them is an error.
+pub fn draw_error_overlay(render: &mut Render, diagnostics: &[Diagnostic]) {
+    let screen_size = render.virtual_surface_size();
+    if screen_size.x <= MARGIN * 2 + CELL_WIDTH || screen_size.y <= MARGIN * 2 + CELL_HEIGHT {
+        return;
+    }
+
+    let mut cursor = TextCursor {
+        render,
+        columns: (screen_size.x - MARGIN * 2) / CELL_WIDTH,
+        top: screen_size.y - MARGIN,
+        row: 0,
+        rows: (screen_size.y - MARGIN * 2) / CELL_HEIGHT,
+    };
+
+    let first_shown = diagnostics.len().saturating_sub(MAX_DIAGNOSTICS_SHOWN);
+    for diagnostic in &diagnostics[first_shown..] {
+        if cursor.is_full() {
+            break;
+        }
+
+        let mut header = format!(
+            "{} {} {}",
+            diagnostic.timestamp.format("%H:%M:%S"),
+            diagnostic.phase,
+            diagnostic.severity
+        );
+        if !diagnostic.module_path.is_empty() {
+            header += &format!(" in {}", diagnostic.module_path.join("::"));
+        }
+        if let Some(location) = &diagnostic.location {
+            header += &format!(" {}:{}", location.line, location.column);
+        }
+        let header_color = match diagnostic.severity {
+            Severity::Error => HEADER_COLOR,
+            Severity::Warning => WARNING_HEADER_COLOR,
+        };
+        cursor.line(&header, header_color);
+        cursor.line(&diagnostic.message, MESSAGE_COLOR);
+
+        if let Some(location) = &diagnostic.location
+            && let Some(source_line) = &location.source_line
+        {
+            // Skip the indentation so more of the interesting part fits
+            let trimmed = source_line.trim_start();
+            let indentation = source_line.chars().count() - trimmed.chars().count();
+            let row_before = cursor.row;
+            cursor.line(trimmed, SOURCE_COLOR);
+            if cursor.row == row_before + 1 {
+                cursor.marker(
+                    location.column.saturating_sub(1 + indentation),
+                    diagnostic.span.length as usize,
+                );
+            }
+        }
+
+        cursor.line("", MESSAGE_COLOR);
+    }
+
+    // The last row is the empty line after the last diagnostic
+    let rows_shown = cursor.row.min(cursor.rows).saturating_sub(1);
+    let background_height = if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.severity == Severity::Error)
+    {
+        screen_size.y
+    } else {
+        (MARGIN * 2 + rows_shown * CELL_HEIGHT).min(screen_size.y)
+    };
+    render.draw_quad(
+        Vec3::new(0, (screen_size.y - background_height) as i16, OVERLAY_Z - 1),
+        UVec2::new(screen_size.x, background_height),
+        BACKGROUND_COLOR,
+    );
+}
+
+/// Rows in the failed reload banner, the header and the wrapped message
+const BANNER_ROWS: u16 = 3;
+
+const BANNER_COLOR: Color = Color::from_octet(60, 40, 0, 220);
+
+/// A strip at the top of the screen that tells that the reload failed, while the previous
+/// program keeps running underneath
+pub fn draw_failed_reload_banner(render: &mut Render, diagnostic: &Diagnostic) {
+    let screen_size = render.virtual_surface_size();
+    let banner_height = MARGIN * 2 + BANNER_ROWS * CELL_HEIGHT;
+    if screen_size.x <= MARGIN * 2 + CELL_WIDTH || screen_size.y <= banner_height {
+        return;
+    }
+
+    render.draw_quad(
+        Vec3::new(0, (screen_size.y - banner_height) as i16, OVERLAY_Z - 1),
+        UVec2::new(screen_size.x, banner_height),
+        BANNER_COLOR,
+    );
+
+    let mut cursor = TextCursor {
+        render,
+        columns: (screen_size.x - MARGIN * 2) / CELL_WIDTH,
+        top: screen_size.y - MARGIN,
+        row: 0,
+        rows: BANNER_ROWS,
+    };
+
+    let mut header = format!(
+        "reload failed ({}), still running the previous scripts",
+        diagnostic.phase
+    );
+    if !diagnostic.module_path.is_empty() {
+        header += &format!(" - {}", diagnostic.module_path.join("::"));
+    }
+    if let Some(location) = &diagnostic.location {
+        header += &format!(" {}:{}", location.line, location.column);
+    }
+    cursor.line(&header, MARKER_COLOR);
+    cursor.line(&diagnostic.message, MESSAGE_COLOR);
+}
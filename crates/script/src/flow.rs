@@ -2,254 +2,288 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
-/*
-use crate::err::show_mangrove_error;
+use crate::contract::{UPDATE_PARAMETER_COUNTS, required_struct, required_type};
+use crate::err::{DiagnosticPhase, report_mangrove_error, report_runtime_error};
 use crate::input::ScriptInput;
-use crate::script::{compile, MangroveError};
+use crate::script::{MangroveError, create_empty_struct_value, register_print};
+use crate::script_main::ScriptMain;
+use crate::simulation::ScriptSimulation;
 use crate::util::get_impl_func;
 use crate::{ErrorResource, ScriptMessage, SourceMapResource};
 use std::cell::RefCell;
 use std::rc::Rc;
-use swamp::prelude::{App, LoRe, LoReM, LocalResource, Msg, Plugin, PreUpdate, Re, ReM, Update};
+use swamp::prelude::{App, LoRe, LoReM, LocalResource, MsgM, Plugin, Re, ReM, Update};
 use swamp_script::prelude::*;
+use tracing::{debug, info};
+
+/// The screen that is used until the flow switches to another one, provided by the entry module
+pub const MAIN_SCREEN: &str = "main";
+
+#[derive(Debug)]
+pub struct ScriptFlowContext {
+    pub(crate) screen: String,
+    pub(crate) requested_screen: Option<String>,
+}
+
+/// # Errors
+///
+/// # Panics
+///
+pub fn register_flow_members(
+    symbol_table: &SymbolTable,
+    associated_impls: &AssociatedImpls,
+    externals: &mut ExternalFunctions<ScriptFlowContext>,
+) -> Result<(), MangroveError> {
+    let flow_struct = required_type(symbol_table, "Flow")?;
+
+    // goto
+    let goto_fn_id = associated_impls.api_fetch_external_function_id(flow_struct, "goto");
+    externals.register_external_function(
+        goto_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            //let _self_value = &params[0]; // the Flow struct is empty by design.
+            let screen = params[1].expect_string()?;
+
+            context.requested_screen = Some(screen);
+
+            Ok(Value::Unit)
+        },
+    )?;
 
-#[derive(Debug, LocalResource)]
-struct ScriptFlowInput {
-    value: Value,
+    // screen
+    let screen_fn_id = associated_impls.api_fetch_external_function_id(flow_struct, "screen");
+    externals.register_external_function(
+        screen_fn_id,
+        move |_mem_values: &[VariableValue], context| Ok(Value::String(context.screen.clone())),
+    )?;
+
+    Ok(())
 }
 
 #[derive(Debug)]
-pub struct ScriptFlowContext {}
+struct ScriptFlowState {
+    flow_value_ref: ValueRef,
+    update_fn: InternalFunctionDefinitionRef,
+    externals: ExternalFunctions<ScriptFlowContext>,
+    flow_struct_ref: ValueRef,
+}
 
+/// Runs the optional `flow` part of the script, that decides which screen module provides the
+/// simulation, render and audio. Scripts without a `flow` function in the entry module always stay
+/// on the main screen.
 #[derive(LocalResource, Debug)]
 pub struct ScriptFlow {
-    flow_self_value_ref: ValueRef,
-    flow_update_fn: InternalFunctionDefinitionRef,
-
-    //---
-    external_functions: ExternalFunctions<ScriptFlowContext>,
-    constants: Constants,
-    script_context: ScriptFlowContext,
-    resolved_program: Program,
-    flow_engine_module: ModuleRef,
+    state: Option<ScriptFlowState>,
+    screen: String,
 }
 
 impl Default for ScriptFlow {
     fn default() -> Self {
         Self {
-            flow_self_value_ref: Rc::new(RefCell::new(Default::default())),
-            flow_update_fn: Rc::new(InternalFunctionDefinition {
-                body: Expression {
-                    ty: Type::Int,
-                    node: Default::default(),
-                    kind: ExpressionKind::Break,
-                },
-                name: LocalIdentifier(Default::default()),
-                signature: Signature {
-                    parameters: vec![],
-                    return_type: Box::new(Type::Int),
-                },
-            }),
-            external_functions: ExternalFunctions::<ScriptFlowContext>::new(),
-            constants: Default::default(),
-            script_context: ScriptFlowContext {},
-            resolved_program: Default::default(),
-            flow_engine_module: Rc::new(RefCell::new(Module {
-                expression: None,
-                namespace: Namespace::default(),
-            })),
+            state: None,
+            screen: MAIN_SCREEN.to_string(),
         }
     }
 }
 
 impl ScriptFlow {
-    pub fn new(
-        flow_self_value_ref: ValueRef,
-        flow_update_fn: InternalFunctionDefinitionRef,
-        // -----
-        external_functions: ExternalFunctions<ScriptFlowContext>,
-        constants: Constants,
-        resolved_program: Program,
-        flow_engine_module: ModuleRef,
-    ) -> Self {
-        Self {
-            flow_self_value_ref,
-            flow_update_fn,
-            external_functions,
-            script_context: ScriptFlowContext {},
-            constants,
-            resolved_program,
-            flow_engine_module,
-        }
+    #[must_use]
+    pub fn screen(&self) -> &str {
+        &self.screen
     }
 
+    /// Calls `update()` on the flow struct and returns the screen it wants to switch to, if any.
+    ///
     /// # Errors
     ///
     pub fn update(
         &mut self,
-        flow_input_value: &Value,
-        debug_source_map: Option<&dyn SourceMapLookup>,
-    ) -> Result<(), ExecuteError> {
-        let _ = util_execute_member_function_mut(
-            &self.external_functions,
-            &self.constants,
-            &self.flow_update_fn,
-            self.flow_self_value_ref.clone(),
-            &[flow_input_value.clone()],
-            &mut self.script_context,
-            debug_source_map,
-        )?;
+        script_main: &ScriptMain,
+        input_value: &Value,
+        lookup: Option<&dyn SourceMapLookup>,
+    ) -> Result<Option<String>, RuntimeError> {
+        let Some(state) = &self.state else {
+            return Ok(None);
+        };
 
-        Ok(())
-    }
-}
+        let mut script_context = ScriptFlowContext {
+            screen: self.screen.clone(),
+            requested_screen: None,
+        };
 
-/// # Errors
-///
-pub fn create_flow_engine_module(
-    _resolve_state: &mut ProgramState,
-) -> Result<Module, Error> {
-    let module_path = ["mangrove".to_string(), "flow".to_string()];
-    let module = Module::new(&module_path);
-    Ok(module)
-}
+        util_execute_function(
+            &state.externals,
+            &script_main.constants,
+            &state.update_fn,
+            [
+                VariableValue::Reference(state.flow_value_ref.clone()),
+                VariableValue::Value(input_value.clone()),
+                VariableValue::Reference(state.flow_struct_ref.clone()),
+            ]
+            .as_ref(),
+            &mut script_context,
+            lookup,
+        )?;
 
-pub fn detect_reload_tick(
-    script_messages: Msg<ScriptMessage>,
-    mut script_flow: LoReM<ScriptFlow>,
-    script_input: LoRe<ScriptInput>,
-    mut source_map_resource: ReM<SourceMapResource>,
-    mut err: ReM<ErrorResource>,
-) {
-    for msg in script_messages.iter_previous() {
-        match msg {
-            ScriptMessage::Reload => {
-                match boot(&script_input.main_module(), &mut source_map_resource) {
-                    Ok(new_flow) => *script_flow = new_flow,
-                    Err(mangrove_error) => {
-                        show_mangrove_error(
-                            &mangrove_error,
-                            &source_map_resource.wrapper.source_map,
-                        );
-                        err.has_errors = true;
-
-                        //                    eprintln!("script logic failed: {}", mangrove_error);
-                        //                    error!(error=?mangrove_error, "script logic compile failed");
-                    }
-                }
-            }
-        }
+        Ok(script_context.requested_screen)
     }
 }
 
+/// Creates the flow struct by calling `flow()` in the entry module
+///
 /// # Errors
 ///
 /// # Panics
 ///
-pub fn boot(
-    input_main_module: &ModuleRef,
-    source_map: &mut SourceMapResource,
-) -> Result<ScriptFlow, MangroveError> {
-    let mut resolved_program = Program::new();
-    let mut external_functions = ExternalFunctions::<ScriptFlowContext>::new();
-
-    let flow_engine_module = create_flow_engine_module(&mut resolved_program.state)?;
-    let flow_engine_module_ref = Rc::new(RefCell::new(flow_engine_module));
-    resolved_program.modules.add(flow_engine_module_ref.clone());
-    resolved_program.modules.add(input_main_module.clone());
+pub fn boot(script_main: &ScriptMain) -> Result<ScriptFlow, MangroveError> {
+    let Some(flow_new_fn) = &script_main.flow_new_fn else {
+        return Ok(ScriptFlow::default());
+    };
 
-    let base_path = source_map.base_path().to_path_buf();
+    debug!("boot script flow");
+    let mut external_functions = ExternalFunctions::<ScriptFlowContext>::new();
+    let mangrove_flow_module_path = &["mangrove".to_string(), "flow".into()];
+    let mangrove_flow_module = script_main
+        .resolved_program
+        .modules
+        .get(mangrove_flow_module_path)
+        .ok_or_else(|| {
+            MangroveError::Other("flow function requires the mangrove::flow package".to_string())
+        })?;
+    let impls = &script_main.resolved_program.state.associated_impls;
 
-    let root_module_path = &["flow".to_string()];
+    register_print(
+        &script_main.resolved_program.modules,
+        &mut external_functions,
+    );
 
-    compile(
-        base_path.as_path(),
-        root_module_path,
-        &mut resolved_program,
+    register_flow_members(
+        &mangrove_flow_module.symbol_table,
+        impls,
         &mut external_functions,
-        &mut source_map.wrapper.source_map,
     )?;
 
-    let flow_module = resolved_program
-        .modules
-        .get(root_module_path)
-        .expect("could not find flow module");
+    let mut script_context = ScriptFlowContext {
+        screen: MAIN_SCREEN.to_string(),
+        requested_screen: None,
+    };
 
-    let mut script_context = ScriptFlowContext {};
-    let mut constants = Constants::new();
-    eval_constants(
+    let flow_value = util_execute_function(
         &external_functions,
-        &mut constants,
-        &resolved_program.modules,
+        &script_main.constants,
+        flow_new_fn,
+        &[],
         &mut script_context,
+        None,
     )?;
 
-    let flow_state_value = {
-        let flow_borrow = flow_module.borrow();
-        let flow_module_expression = flow_borrow
-            .expression
-            .as_ref()
-            .expect("must have code within the flow module");
-
-        util_execute_expression(
-            &external_functions,
-            &constants,
-            flow_module_expression,
-            &mut script_context,
-            None,
-        )?
-    };
-
-    let Value::Struct(logic_struct_type_ref, _) = &flow_state_value else {
-        return Err(MangroveError::Other(
-            "flow needs to return a struct".to_string(),
-        ));
+    let Value::NamedStruct(flow_struct_type_ref, _) = &flow_value else {
+        return Err(MangroveError::Other("needs to be flow struct".to_string()));
     };
 
-    let flow_update_fn = get_impl_func(logic_struct_type_ref, "update");
+    let update_fn = get_impl_func(
+        impls,
+        flow_struct_type_ref,
+        "update",
+        &UPDATE_PARAMETER_COUNTS,
+    )?;
 
-    // Convert it to a mutable (reference), so it can be mutated in update ticks
-    let flow_state_value_ref = Rc::new(RefCell::new(flow_state_value));
+    // Flow doesn't really contain anything, the actual values are fetched from the runtime context
+    let flow_struct_type = required_struct(&mangrove_flow_module.symbol_table, "Flow")?;
+    let flow_struct_value = create_empty_struct_value(flow_struct_type);
 
-    Ok(ScriptFlow::new(
-        flow_state_value_ref,
-        flow_update_fn,
-        external_functions,
-        constants,
-        resolved_program,
-        flow_engine_module_ref,
-    ))
+    Ok(ScriptFlow {
+        state: Some(ScriptFlowState {
+            flow_value_ref: Rc::new(RefCell::new(flow_value)),
+            update_fn,
+            externals: external_functions,
+            flow_struct_ref: Rc::new(RefCell::new(flow_struct_value)),
+        }),
+        screen: MAIN_SCREEN.to_string(),
+    })
 }
 
-/// # Panics
-///
+/// Lets the flow (and the simulation) decide the screen, and switches the screen functions
+/// in `ScriptMain`. The simulation, render and audio are booted again on the next update.
 pub fn flow_update(
-    mut script: LoReM<ScriptFlow>,
-    flow_input: LoRe<ScriptFlowInput>,
-    _source_map: Re<SourceMapResource>,
-    error: Re<ErrorResource>,
+    mut script_flow: LoReM<ScriptFlow>,
+    mut script_main: LoReM<ScriptMain>,
+    mut script_simulation: LoReM<ScriptSimulation>,
+    script_input: LoRe<ScriptInput>,
+    mut script_messages: MsgM<ScriptMessage>,
+    source_map: Re<SourceMapResource>,
+    mut error: ReM<ErrorResource>,
 ) {
-    //let lookup: &dyn SourceMapLookup = &source_map.wrapper;
-    if error.has_errors {
+    if error.has_errors() {
+        return;
+    }
+
+    let lookup: &dyn SourceMapLookup = &source_map.wrapper();
+
+    let from_simulation = match script_simulation.next_screen(&script_main) {
+        Ok(next_screen) => next_screen,
+        Err(runtime_err) => {
+            report_runtime_error(
+                &mut error,
+                DiagnosticPhase::Tick,
+                &runtime_err,
+                &source_map.source_map,
+            );
+            return;
+        }
+    };
+
+    let input_value = script_input.input_value.borrow().clone();
+    let from_flow = match script_flow.update(&script_main, &input_value, Some(lookup)) {
+        Ok(requested_screen) => requested_screen,
+        Err(runtime_err) => {
+            report_runtime_error(
+                &mut error,
+                DiagnosticPhase::Tick,
+                &runtime_err,
+                &source_map.source_map,
+            );
+            return;
+        }
+    };
+
+    // The flow has the final say, since it can see the input
+    let Some(requested_screen) = from_flow.or(from_simulation) else {
+        return;
+    };
+
+    if requested_screen == script_flow.screen {
+        return;
+    }
+
+    if let Err(mangrove_error) = script_main.use_screen(&requested_screen) {
+        report_mangrove_error(
+            &mut error,
+            DiagnosticPhase::Boot,
+            &mangrove_error,
+            &source_map.source_map,
+        );
         return;
     }
-    script
-        .update(&flow_input.value, None)
-        .expect("flow.update() crashed");
+
+    info!(
+        from = script_flow.screen,
+        to = requested_screen,
+        "switch screen"
+    );
+    script_flow.screen = requested_screen;
+    script_messages.send(ScriptMessage::ScreenChanged);
 }
 
 pub struct ScriptFlowPlugin;
 
 impl Plugin for ScriptFlowPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(PreUpdate, detect_reload_tick);
         app.add_system(Update, flow_update);
 
-        // HACK: Just add a completely zeroed out ScriptFlow and wait for reload message.
-        // TODO: Should not try to call updates with params that are not available yet.
+        // Stays on the main screen until the first reload has booted the script flow
         app.insert_local_resource(ScriptFlow::default());
     }
 }
-
-
- */
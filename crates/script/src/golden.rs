@@ -0,0 +1,465 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Golden-state regression tests for game logic written in Swamp.
+//!
+//! Boots the scripts in a directory, ticks the simulation with a scripted input sequence and
+//! compares the `quick_serialize`d simulation value with a stored golden file.
+//!
+//! ```ignore
+//! GoldenTest::new("scripts/", "tests/golden/jump.golden")
+//!     .ticks(120)
+//!     .input(jump_events)
+//!     .assert();
+//! ```
+//!
+//! Set `MANGROVE_BLESS=1` (or call [`GoldenTest::bless`]) to write the current state as the new golden file.
+//!
+//! Compiling the scripts needs more stack than the 2 MiB that a test thread has in debug builds,
+//! run the tests with e.g. `RUST_MIN_STACK=8388608` or on a thread with a larger stack.
+use crate::SourceMapResource;
+use crate::replay::{RecordedEvent, RecordedInput};
+use crate::save::SaveStore;
+use crate::script::MangroveError;
+use crate::script_main::compile;
+use crate::serialize::serialize_value;
+use crate::simulation::boot;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use swamp::prelude::ResourceStorage;
+use swamp_script::prelude::{SourceMapLookup, Value};
+use tracing::warn;
+
+pub const GOLDEN_VERSION: u16 = 1;
+pub const BLESS_ENV_VAR: &str = "MANGROVE_BLESS";
+
+const HEADER_PREFIX: &str = "mangrove golden state v";
+
+/// The simulation value after a run, both as serialized octets and as readable lines
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenState {
+    pub ticks: u64,
+    pub payload: Vec<u8>,
+    /// One `field.path: value` line for each field in the simulation struct
+    pub lines: Vec<String>,
+}
+
+impl GoldenState {
+    /// # Errors
+    ///
+    /// If the value can not be serialized
+    pub fn from_value(ticks: u64, value: &Value) -> Result<Self, MangroveError> {
+        let payload = serialize_value(value)?;
+
+        let mut lines = Vec::new();
+        describe_value(value, "", &mut lines);
+
+        Ok(Self {
+            ticks,
+            payload,
+            lines,
+        })
+    }
+
+    /// # Errors
+    ///
+    /// If the header, version or payload is malformed.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut text_lines = text.lines();
+
+        let version = text_lines
+            .next()
+            .and_then(|line| line.strip_prefix(HEADER_PREFIX))
+            .ok_or("not a mangrove golden file")?;
+        if version != GOLDEN_VERSION.to_string() {
+            return Err(format!("unsupported golden file version {version}"));
+        }
+
+        let ticks = text_lines
+            .next()
+            .and_then(|line| line.strip_prefix("ticks "))
+            .and_then(|count| count.parse().ok())
+            .ok_or("missing tick count")?;
+
+        let payload = text_lines
+            .next()
+            .and_then(|line| line.strip_prefix("payload "))
+            .and_then(from_hex)
+            .ok_or("missing or malformed payload")?;
+
+        Ok(Self {
+            ticks,
+            payload,
+            lines: text_lines.map(str::to_string).collect(),
+        })
+    }
+
+    #[must_use]
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "{HEADER_PREFIX}{GOLDEN_VERSION}\nticks {}\npayload {}\n",
+            self.ticks,
+            to_hex(&self.payload)
+        );
+        for line in &self.lines {
+            text.push_str(line);
+            text.push('\n');
+        }
+        text
+    }
+}
+
+/// The readable difference between the golden state and the actual state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GoldenDiff {
+    pub golden_path: PathBuf,
+    pub expected: GoldenState,
+    pub actual: GoldenState,
+}
+
+impl Display for GoldenDiff {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "simulation state differs from golden file {}",
+            self.golden_path.display()
+        )?;
+
+        if self.expected.ticks != self.actual.ticks {
+            writeln!(
+                f,
+                "  ticks: expected {}, got {}",
+                self.expected.ticks, self.actual.ticks
+            )?;
+        }
+
+        // There is one line for each field, in the order of the fields, so the lines are
+        // compared by position. A value that moved to another field is still a difference.
+        let line_count = self.expected.lines.len().max(self.actual.lines.len());
+        for index in 0..line_count {
+            let expected = self.expected.lines.get(index);
+            let actual = self.actual.lines.get(index);
+            if expected == actual {
+                continue;
+            }
+            if let Some(expected) = expected {
+                writeln!(f, "- {expected}")?;
+            }
+            if let Some(actual) = actual {
+                writeln!(f, "+ {actual}")?;
+            }
+        }
+
+        write!(f, "run with {BLESS_ENV_VAR}=1 to accept the new state")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GoldenOutcome {
+    Matched,
+    /// The golden file was (re)written with the current state
+    Blessed,
+    /// There is no golden file yet and blessing is not enabled
+    Missing(PathBuf),
+    Mismatch(Box<GoldenDiff>),
+}
+
+/// Runs the simulation in a script directory and compares the end state with a golden file
+#[derive(Debug)]
+pub struct GoldenTest {
+    scripts_path: PathBuf,
+    registry_path: Option<PathBuf>,
+    golden_path: PathBuf,
+    ticks: u64,
+    input: Vec<RecordedEvent>,
+    bless: bool,
+}
+
+impl GoldenTest {
+    /// Blessing is enabled if the `MANGROVE_BLESS` environment variable is set to something other than `0`.
+    pub fn new(scripts_path: impl AsRef<Path>, golden_path: impl AsRef<Path>) -> Self {
+        let bless = std::env::var(BLESS_ENV_VAR).is_ok_and(|value| value != "0");
+
+        Self {
+            scripts_path: scripts_path.as_ref().to_path_buf(),
+            registry_path: None,
+            golden_path: golden_path.as_ref().to_path_buf(),
+            ticks: 1,
+            input: Vec::new(),
+            bless,
+        }
+    }
+
+    /// Mounts the packages in `registry_path` instead of the swamp registry, the same as a
+    /// `registry` mount in the manifest
+    #[must_use]
+    pub fn registry(mut self, registry_path: impl AsRef<Path>) -> Self {
+        self.registry_path = Some(registry_path.as_ref().to_path_buf());
+        self
+    }
+
+    #[must_use]
+    pub const fn ticks(mut self, ticks: u64) -> Self {
+        self.ticks = ticks;
+        self
+    }
+
+    /// The gamepad and keyboard input to feed the simulation, e.g. from [`crate::replay::read_recording`].
+    /// Events are delivered after the simulation has ticked, the same as for live input.
+    #[must_use]
+    pub fn input(mut self, events: Vec<RecordedEvent>) -> Self {
+        self.input = events;
+        self
+    }
+
+    #[must_use]
+    pub const fn bless(mut self, bless: bool) -> Self {
+        self.bless = bless;
+        self
+    }
+
+    /// # Errors
+    ///
+    /// If the scripts fail to compile, report a runtime error or the golden file can not be read or written.
+    pub fn run(&self) -> Result<GoldenOutcome, MangroveError> {
+        let value = run_simulation(
+            &self.scripts_path,
+            self.registry_path.as_deref(),
+            self.ticks,
+            &self.input,
+        )?;
+        let actual = GoldenState::from_value(self.ticks, &value)?;
+
+        if self.bless {
+            if let Some(parent) = self.golden_path.parent() {
+                fs::create_dir_all(parent).map_err(|err| err.to_string())?;
+            }
+            fs::write(&self.golden_path, actual.to_text()).map_err(|err| {
+                format!(
+                    "could not write golden file {}: {err}",
+                    self.golden_path.display()
+                )
+            })?;
+            return Ok(GoldenOutcome::Blessed);
+        }
+
+        let text = match fs::read_to_string(&self.golden_path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                return Ok(GoldenOutcome::Missing(self.golden_path.clone()));
+            }
+            Err(err) => {
+                return Err(format!(
+                    "could not read golden file {}: {err}",
+                    self.golden_path.display()
+                )
+                .into());
+            }
+        };
+
+        let expected = GoldenState::parse(&text)
+            .map_err(|err| format!("{}: {err}", self.golden_path.display()))?;
+
+        if expected.ticks == actual.ticks && expected.payload == actual.payload {
+            Ok(GoldenOutcome::Matched)
+        } else {
+            Ok(GoldenOutcome::Mismatch(Box::new(GoldenDiff {
+                golden_path: self.golden_path.clone(),
+                expected,
+                actual,
+            })))
+        }
+    }
+
+    /// Runs the test and panics with a readable diff if the state does not match the golden file.
+    ///
+    /// # Panics
+    ///
+    pub fn assert(&self) {
+        match self.run() {
+            Ok(GoldenOutcome::Matched | GoldenOutcome::Blessed) => {}
+            Ok(GoldenOutcome::Missing(path)) => panic!(
+                "golden file {} is missing, run with {BLESS_ENV_VAR}=1 to create it",
+                path.display()
+            ),
+            Ok(GoldenOutcome::Mismatch(diff)) => panic!("{diff}"),
+            Err(err) => panic!("golden test failed to run: {err}"),
+        }
+    }
+}
+
+/// Boots the simulation in `scripts_path`, ticks it `ticks` times and returns the simulation value.
+/// The packages are read from `registry_path`, or from the swamp registry if it is `None`.
+///
+/// # Errors
+///
+/// If the scripts fail to compile or report a runtime error.
+pub fn run_simulation(
+    scripts_path: &Path,
+    registry_path: Option<&Path>,
+    ticks: u64,
+    input: &[RecordedEvent],
+) -> Result<Value, MangroveError> {
+    let source_map = match registry_path {
+        Some(registry_path) => SourceMapResource::with_registry(scripts_path, registry_path),
+        None => SourceMapResource::new(scripts_path),
+    };
+    let mut source_map = source_map.map_err(|err| {
+        format!(
+            "could not mount scripts directory {}: {err}",
+            scripts_path.display()
+        )
+    })?;
+
+    let script_main = compile(&mut source_map)?;
+    // Without a manifest, the maps are read from the default asset root. The saves start empty
+    // for every run, so the golden state never depends on the saves of the player.
+    let mut resources = ResourceStorage::new();
+    resources.insert(SaveStore::in_memory());
+    let mut simulation = boot(&script_main, &resources)?;
+
+    if input.iter().any(|event| {
+        matches!(
+            event.input,
+            RecordedInput::Mouse(_) | RecordedInput::MouseButton(..)
+        )
+    }) {
+        warn!(
+            "mouse input is not supported in golden tests, only gamepad and keyboard input is fed to the simulation"
+        );
+    }
+
+    let lookup: &dyn SourceMapLookup = &source_map.wrapper();
+    for tick in 0..ticks {
+        simulation.tick(&script_main, Some(lookup))?;
+
+        for event in input.iter().filter(|event| event.tick == tick) {
+            if let Some(msg) = event.input.to_gamepad_message() {
+                simulation.gamepad(&script_main, &msg)?;
+            } else if let RecordedInput::Key(key_code, pressed) = event.input {
+                simulation.key_changed(&script_main, key_code, pressed)?;
+            }
+        }
+    }
+
+    Ok(simulation.immutable_simulation_value())
+}
+
+fn describe_value(value: &Value, path: &str, lines: &mut Vec<String>) {
+    if let Value::NamedStruct(struct_type_ref, fields_in_strict_order) = value {
+        let field_names = struct_type_ref
+            .anon_struct_type
+            .field_name_sorted_fields
+            .keys();
+        for (field_name, field_value) in field_names.zip(fields_in_strict_order) {
+            let field_path = if path.is_empty() {
+                field_name.clone()
+            } else {
+                format!("{path}.{field_name}")
+            };
+            describe_value(&field_value.borrow(), &field_path, lines);
+        }
+    } else {
+        lines.push(format!("{path}: {value}"));
+    }
+}
+
+fn to_hex(octets: &[u8]) -> String {
+    octets.iter().map(|octet| format!("{octet:02x}")).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::{scripts, test_directory, with_stack};
+
+    fn golden_test(directory: &Path, ticks: u64) -> GoldenTest {
+        GoldenTest::new(scripts("golden"), directory.join("counter.golden"))
+            .registry(directory.join("registry"))
+            .bless(false)
+            .ticks(ticks)
+    }
+
+    #[test]
+    fn bless_then_match() {
+        with_stack(|| {
+            let directory = test_directory("match");
+
+            assert_eq!(
+                golden_test(&directory, 3).run().unwrap(),
+                GoldenOutcome::Missing(directory.join("counter.golden"))
+            );
+            assert_eq!(
+                golden_test(&directory, 3).bless(true).run().unwrap(),
+                GoldenOutcome::Blessed
+            );
+
+            let golden =
+                GoldenState::parse(&fs::read_to_string(directory.join("counter.golden")).unwrap())
+                    .unwrap();
+            assert_eq!(golden.ticks, 3);
+            assert_eq!(golden.lines, ["ticks: 3", "half: 1", "even: false"]);
+
+            assert_eq!(
+                golden_test(&directory, 3).run().unwrap(),
+                GoldenOutcome::Matched
+            );
+
+            fs::remove_dir_all(&directory).unwrap();
+        });
+    }
+
+    #[test]
+    fn mismatch() {
+        with_stack(|| {
+            let directory = test_directory("mismatch");
+            golden_test(&directory, 3).bless(true).assert();
+
+            let GoldenOutcome::Mismatch(diff) = golden_test(&directory, 4).run().unwrap() else {
+                panic!("four ticks should not match the golden state after three ticks");
+            };
+            assert_eq!(
+                diff.to_string(),
+                format!(
+                    "simulation state differs from golden file {}\n  ticks: expected 3, got 4\n- ticks: 3\n+ ticks: 4\n- half: 1\n+ half: 2\n- even: false\n+ even: true\nrun with MANGROVE_BLESS=1 to accept the new state",
+                    directory.join("counter.golden").display()
+                )
+            );
+
+            fs::remove_dir_all(&directory).unwrap();
+        });
+    }
+
+    #[test]
+    fn diff_compares_the_lines_by_position() {
+        let state = |lines: &[&str]| GoldenState {
+            ticks: 1,
+            payload: Vec::new(),
+            lines: lines.iter().map(ToString::to_string).collect(),
+        };
+        // The same line moved to another field is still a difference
+        let diff = GoldenDiff {
+            golden_path: PathBuf::from("a.golden"),
+            expected: state(&["a: 1", "b: 2", "c: 3"]),
+            actual: state(&["a: 2", "b: 1"]),
+        };
+
+        assert_eq!(
+            diff.to_string(),
+            "simulation state differs from golden file a.golden\n- a: 1\n+ a: 2\n- b: 2\n+ b: 1\n- c: 3\nrun with MANGROVE_BLESS=1 to accept the new state"
+        );
+    }
+}
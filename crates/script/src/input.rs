@@ -2,25 +2,31 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
-use crate::err::show_mangrove_error;
+use crate::bindings::{
+    InputDevices, ResolvedActionSet, key_code_from_name, load_binding_config, resolve_bindings,
+    update_action_sets,
+};
+use crate::contract::{constructed_struct, mouse_field_indices};
 use crate::script::MangroveError;
-use crate::{ErrorResource, ScriptMessage, SourceMapResource, util};
+use crate::{InputSource, SourceMapResource};
 use limnus_basic_input::InputMessage;
-use limnus_basic_input::prelude::{ButtonState, MouseButton};
+use limnus_basic_input::prelude::{ButtonState, KeyCode, MouseButton};
+use limnus_gamepad::Gamepads;
 use limnus_input_binding::{ActionSets, Actions, AnalogAction, DigitalAction, InputConfig};
 use limnus_screen::WindowMessage;
 use std::cell::RefCell;
 use std::cmp::{max, min};
+use std::collections::HashSet;
+use std::env::current_dir;
 use std::rc::Rc;
 use swamp::prelude::{
-    App, FixedUpdate, LoRe, LoReM, LocalResource, Msg, Plugin, PreUpdate, Re, ReM, Render, URect,
-    UVec2, Update, WgpuWindow,
+    App, LoRe, LoReM, LocalResource, Msg, Plugin, Re, Render, URect, UVec2, Update,
 };
 use swamp_script::prelude::*;
 
 use crate::script_main::ScriptMain;
-use crate::simulation::{ScriptSimulation, ScriptSimulationContext};
-use tracing::info;
+use crate::simulation::mouse_input_tick;
+use tracing::debug;
 
 #[derive(Debug)]
 pub struct ScriptInputContext {}
@@ -40,22 +46,28 @@ pub struct Binding {
 
 #[derive(Debug)]
 pub struct BindingsInSet {
+    /// The field index of the action set in the input struct
+    pub struct_field_index: usize,
     pub bindings_in_source_order: Vec<Binding>,
 }
 
 #[derive(LocalResource, Debug)]
 pub struct ScriptInput {
     pub sets: SeqMap<String, BindingsInSet>,
+    /// The bindings from the config file, for the action sets in `sets`
+    pub resolved_sets: Vec<ResolvedActionSet>,
     //    pub main_module: ModuleRef,
     pub input_value: ValueRef,
     pub mouse_cursor_position_index: usize,
     pub mouse_left_button_index: usize,
     pub mouse_right_button_index: usize,
+    /// The optional `held_keys: [Key]` field, and the `Key` enum
+    pub held_keys: Option<(usize, EnumType)>,
 }
 
 impl ScriptInput {
     pub fn new(
-        main_module: ModuleRef,
+        _main_module: ModuleRef,
         sets: SeqMap<String, BindingsInSet>,
         input_value: ValueRef,
         mouse_cursor_position_index: usize,
@@ -64,38 +76,108 @@ impl ScriptInput {
     ) -> Self {
         Self {
             sets,
+            resolved_sets: Vec::new(),
             //          main_module,
             input_value,
             mouse_cursor_position_index,
             mouse_left_button_index,
             mouse_right_button_index,
+            held_keys: None,
         }
     }
 }
 
-pub fn detect_reload_tick(
-    script_messages: Msg<ScriptMessage>,
-    mut script_input: LoReM<ScriptInput>,
-    script_game: LoRe<ScriptMain>,
-    mut source_map_resource: ReM<SourceMapResource>,
-    mut err: ReM<ErrorResource>,
-) {
-    if err.has_errors {
-        return;
+/// The mouse related fields in the script input struct
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MouseState {
+    pub cursor_position: (i32, i32),
+    pub left_button: bool,
+    pub right_button: bool,
+}
+
+impl ScriptInput {
+    /// # Panics
+    ///
+    #[must_use]
+    pub fn mouse_state(&self) -> MouseState {
+        let input_value = self.input_value.borrow();
+        let Value::NamedStruct(_, fields) = &*input_value else {
+            return MouseState::default();
+        };
+
+        let cursor_position = match &*fields[self.mouse_cursor_position_index].borrow() {
+            Value::Tuple(_, tuple_fields) => (
+                tuple_fields[0]
+                    .borrow()
+                    .expect_int()
+                    .expect("internal error"),
+                tuple_fields[1]
+                    .borrow()
+                    .expect_int()
+                    .expect("internal error"),
+            ),
+            _ => panic!("internal error"),
+        };
+
+        MouseState {
+            cursor_position,
+            left_button: fields[self.mouse_left_button_index]
+                .borrow()
+                .expect_bool()
+                .expect("internal error"),
+            right_button: fields[self.mouse_right_button_index]
+                .borrow()
+                .expect_bool()
+                .expect("internal error"),
+        }
     }
-    for msg in script_messages.iter_previous() {
-        match msg {
-            ScriptMessage::Reload => match boot(&script_game, &source_map_resource) {
-                Ok(new_script_input) => *script_input = new_script_input,
-                Err(mangrove_error) => {
-                    show_mangrove_error(&mangrove_error, &source_map_resource.source_map);
-                    err.has_errors = true;
-
-                    //                    eprintln!("script simulation failed: {}", mangrove_error);
-                    //                    error!(error=?mangrove_error, "script simulation compile failed");
-                }
-            },
+
+    /// # Panics
+    ///
+    pub fn set_mouse_state(&self, mouse_state: &MouseState) {
+        let input_value = self.input_value.borrow();
+        let Value::NamedStruct(_, fields) = &*input_value else {
+            return;
+        };
+
+        match &*fields[self.mouse_cursor_position_index].borrow() {
+            Value::Tuple(_, tuple_fields) => {
+                *tuple_fields[0].borrow_mut() = Value::Int(mouse_state.cursor_position.0);
+                *tuple_fields[1].borrow_mut() = Value::Int(mouse_state.cursor_position.1);
+            }
+            _ => panic!("internal error"),
         }
+        *fields[self.mouse_left_button_index].borrow_mut() = Value::Bool(mouse_state.left_button);
+        *fields[self.mouse_right_button_index].borrow_mut() = Value::Bool(mouse_state.right_button);
+    }
+
+    /// Sets the optional `held_keys` field to the keys, in `Key` order
+    pub fn set_held_keys(&self, keys: &HashSet<KeyCode>) {
+        let Some((held_keys_index, key_enum)) = &self.held_keys else {
+            return;
+        };
+        let Value::NamedStruct(_, fields) = &*self.input_value.borrow() else {
+            return;
+        };
+
+        let held_keys = key_enum
+            .variants
+            .iter()
+            .filter(|(name, _)| {
+                key_code_from_name(name).is_some_and(|key_code| keys.contains(&key_code))
+            })
+            .filter_map(|(_, variant)| match variant {
+                EnumVariantType::Nothing(simple) => Some(Rc::new(RefCell::new(
+                    Value::EnumVariantSimple(key_enum.clone(), simple.clone()),
+                ))),
+                _ => None,
+            })
+            .collect();
+
+        *fields[*held_keys_index].borrow_mut() = Value::Vec(
+            Type::Slice(Box::new(Type::Enum(key_enum.clone()))),
+            held_keys,
+        );
     }
 }
 
@@ -142,9 +224,13 @@ pub fn absolute_to_virtual_position(
 
 pub fn listen_cursor_moved(
     window_messages: Msg<WindowMessage>,
-    mut script_input: LoReM<ScriptInput>,
+    script_input: LoReM<ScriptInput>,
     wgpu_render: Re<Render>,
+    input_source: Re<InputSource>,
 ) {
+    if *input_source == InputSource::Replay {
+        return;
+    }
     for msg in window_messages.iter_previous() {
         if let WindowMessage::CursorMoved(absolute_position) = msg {
             match &*script_input.input_value.borrow_mut() {
@@ -176,7 +262,14 @@ pub fn listen_cursor_moved(
     }
 }
 
-pub fn listen_mouse_button(input_message: Msg<InputMessage>, mut script_input: LoReM<ScriptInput>) {
+pub fn listen_mouse_button(
+    input_message: Msg<InputMessage>,
+    script_input: LoReM<ScriptInput>,
+    input_source: Re<InputSource>,
+) {
+    if *input_source == InputSource::Replay {
+        return;
+    }
     for msg in input_message.iter_previous() {
         if let InputMessage::MouseInput(button_state, mouse_button) = msg {
             let button_index_in_struct = match *mouse_button {
@@ -200,7 +293,124 @@ pub fn listen_mouse_button(input_message: Msg<InputMessage>, mut script_input: L
     }
 }
 
-fn scan_struct(struct_type: &NamedStructType) -> Result<BindingsInSet, MangroveError> {
+/// Keeps track of the keys and mouse buttons that are held down, for the action sets
+pub fn listen_input_devices(
+    input_message: Msg<InputMessage>,
+    mut input_devices: LoReM<InputDevices>,
+    input_source: Re<InputSource>,
+) {
+    if *input_source == InputSource::Replay {
+        return;
+    }
+    for msg in input_message.iter_previous() {
+        match msg {
+            InputMessage::KeyboardInput(ButtonState::Pressed, key_code) => {
+                input_devices.keys.insert(*key_code);
+            }
+            InputMessage::KeyboardInput(ButtonState::Released, key_code) => {
+                input_devices.keys.remove(key_code);
+            }
+            InputMessage::MouseInput(ButtonState::Pressed, mouse_button) => {
+                input_devices.mouse_buttons.insert(*mouse_button);
+            }
+            InputMessage::MouseInput(ButtonState::Released, mouse_button) => {
+                input_devices.mouse_buttons.remove(mouse_button);
+            }
+            InputMessage::MouseWheel(_, _) => {}
+        }
+    }
+}
+
+/// Sets all the digital and analog actions in the action sets from the bound keys, mouse buttons and gamepads.
+/// While replaying, the replay sets them from the recorded input instead.
+pub fn action_sets_tick(
+    script_input: LoRe<ScriptInput>,
+    input_devices: LoRe<InputDevices>,
+    gamepads: Re<Gamepads>,
+    input_source: Re<InputSource>,
+) {
+    if *input_source == InputSource::Replay {
+        return;
+    }
+    update_action_sets(
+        &script_input.input_value.borrow(),
+        &script_input.resolved_sets,
+        &input_devices,
+        &gamepads,
+    );
+}
+
+/// Sets the optional `held_keys` field to all the keys that are currently held down.
+/// While replaying, the held keys come from the recorded key events.
+pub fn held_keys_tick(script_input: LoRe<ScriptInput>, input_devices: LoRe<InputDevices>) {
+    script_input.set_held_keys(&input_devices.keys);
+}
+
+/// Finds the optional `held_keys` field, which must be a slice of `mangrove::input::Key`
+pub(crate) fn scan_held_keys(
+    input_struct_type: &NamedStructType,
+) -> Result<Option<(usize, EnumType)>, MangroveError> {
+    let fields = &input_struct_type.anon_struct_type.field_name_sorted_fields;
+    let (Some(index), Some(field)) = (
+        fields.get_index(&"held_keys".to_string()),
+        fields.get(&"held_keys".to_string()),
+    ) else {
+        return Ok(None);
+    };
+
+    // `[Key]` is the core `Vec` instantiated with `Key`
+    if let Type::NamedStruct(vec_type) = &field.field_type
+        && let [Type::Enum(key_enum)] = vec_type.instantiated_type_parameters.as_slice()
+        && key_enum.assigned_name == "Key"
+    {
+        Ok(Some((index, key_enum.clone())))
+    } else {
+        Err(MangroveError::Other(
+            "held_keys in the input struct must be [Key], using mangrove::input::Key".to_string(),
+        ))
+    }
+}
+
+/// Every field in the input struct that is a struct itself, is an action set.
+/// `Bool` fields in an action set are digital actions and `(Float, Float)` fields are analog actions.
+///
+/// # Errors
+///
+/// If an action set has a field of any other type.
+pub fn scan_action_sets(
+    input_struct_type: &NamedStructType,
+) -> Result<SeqMap<String, BindingsInSet>, MangroveError> {
+    let mut sets = SeqMap::new();
+    for (index, field) in input_struct_type
+        .anon_struct_type
+        .field_name_sorted_fields
+        .values()
+        .enumerate()
+    {
+        // Generic containers, like `[Key]`, are also structs but never action sets
+        let Type::NamedStruct(set_struct_type) = &field.field_type else {
+            continue;
+        };
+        if !set_struct_type.instantiated_type_parameters.is_empty() {
+            continue;
+        }
+        let bindings_in_set = scan_struct(set_struct_type, index)?;
+        sets.insert(set_struct_type.assigned_name.clone(), bindings_in_set)
+            .map_err(|_| {
+                MangroveError::Other(format!(
+                    "action set {} is used more than once in the input struct",
+                    set_struct_type.assigned_name
+                ))
+            })?;
+    }
+
+    Ok(sets)
+}
+
+fn scan_struct(
+    struct_type: &NamedStructType,
+    struct_field_index: usize,
+) -> Result<BindingsInSet, MangroveError> {
     let mut bindings_in_source_order = Vec::new();
     for (index, (field_name, field_type)) in struct_type
         .anon_struct_type
@@ -208,21 +418,21 @@ fn scan_struct(struct_type: &NamedStructType) -> Result<BindingsInSet, MangroveE
         .iter()
         .enumerate()
     {
-        info!(ty=?field_type.field_type, "found_field");
+        debug!(ty=?field_type.field_type, "found_field");
         let binding_kind = match &field_type.field_type {
             Type::Bool => BindingKind::Digital,
-
-            Type::Tuple(tuple_type) => {
-                if tuple_type.len() != 2 {
-                    return Err(MangroveError::Other("strange field type".into()));
-                }
-                if tuple_type[0] != Type::Float && tuple_type[1] != Type::Float {
-                    return Err(MangroveError::Other("strange field type tuple".into()));
-                }
+            Type::Tuple(tuple_type) if tuple_type.as_slice() == [Type::Float, Type::Float] => {
                 BindingKind::Analog
             }
-            _ => {
-                return Err(MangroveError::Other("strange field type".into()));
+            found => {
+                return Err(MangroveError::WrongFieldType {
+                    struct_name: struct_type.assigned_name.clone(),
+                    field: field_name.clone(),
+                    expected: "Bool (a digital action) or (Float, Float) (an analog action)"
+                        .to_string(),
+                    found: found.to_string(),
+                    span: struct_type.name.span.clone(),
+                });
             }
         };
 
@@ -236,75 +446,33 @@ fn scan_struct(struct_type: &NamedStructType) -> Result<BindingsInSet, MangroveE
     }
 
     let bindings_in_set = BindingsInSet {
+        struct_field_index,
         bindings_in_source_order,
     };
     Ok(bindings_in_set)
 }
 
-/// # Errors
+/// Creates the input struct by calling `input()` in the entry module
 ///
-/// # Panics
+/// # Errors
 ///
+/// If the input struct is missing a mouse field, has an invalid action set or the bindings
+/// can not be loaded.
 pub fn boot(
     script_main: &ScriptMain,
-    source_map: &SourceMapResource,
+    _source_map: &SourceMapResource,
 ) -> Result<ScriptInput, MangroveError> {
-    /*
-    let mut mapping = SeqMap::new();
-    for (name, struct_type) in input_module.namespace.symbol_table.structs() {
-        let bindings_in_set = scan_struct(&struct_type)?;
+    let named_struct = &constructed_struct(&script_main.input_new_fn, 0)?;
 
-        mapping.insert(name.clone(), bindings_in_set)?;
-    }
-    */
-
-    let input_type = script_main.input_new_fn.signature.return_type.clone();
-
-    let Type::NamedStruct(named_struct) = &*input_type else {
-        panic!("input must be a named struct");
-    };
-
-    let anon_struct = &named_struct.anon_struct_type;
-
-    let Some(mouse_cursor_position_field) = anon_struct
-        .field_name_sorted_fields
-        .get(&"mouse_cursor_position".to_string())
-    else {
-        panic!("must have mouse_cursor_position");
-    };
-
-    let Some(mouse_cursor_position_index) = anon_struct
-        .field_name_sorted_fields
-        .get_index(&"mouse_cursor_position".to_string())
-    else {
-        panic!("must have mouse_cursor_position");
-    };
-
-    let Some(mouse_left_button_index) = anon_struct
-        .field_name_sorted_fields
-        .get_index(&"mouse_left_button".to_string())
-    else {
-        panic!("must have mouse_left_button");
-    };
-
-    let Some(mouse_right_button_index) = anon_struct
-        .field_name_sorted_fields
-        .get_index(&"mouse_right_button".to_string())
-    else {
-        panic!("must have mouse_left_button");
-    };
-
-    let Type::Tuple(found) = &mouse_cursor_position_field.field_type else {
-        panic!("must have mouse_cursor_position tuple");
-    };
-
-    assert_eq!(found.len(), 2);
-    assert_eq!(found[0], Type::Int);
-    assert_eq!(found[1], Type::Int);
+    let [
+        mouse_cursor_position_index,
+        mouse_left_button_index,
+        mouse_right_button_index,
+    ] = mouse_field_indices(named_struct)?;
 
     let mut script_context = ScriptInputContext {};
 
-    let mut input_externals = ExternalFunctions::<ScriptInputContext>::new();
+    let input_externals = ExternalFunctions::<ScriptInputContext>::new();
 
     let input_value = util_execute_function(
         &input_externals,
@@ -315,11 +483,19 @@ pub fn boot(
         None,
     )?;
 
+    let sets = scan_action_sets(named_struct)?;
+    let held_keys = scan_held_keys(named_struct)?;
+    let binding_directory =
+        current_dir().map_err(|err| format!("could not find current directory: {err}"))?;
+    let resolved_sets = resolve_bindings(&sets, &load_binding_config(&binding_directory)?)?;
+
     let script_input = ScriptInput {
-        sets: SeqMap::default(),
+        sets,
+        resolved_sets,
         mouse_cursor_position_index,
         mouse_left_button_index,
         mouse_right_button_index,
+        held_keys,
         //main_module: Default::default(),
         input_value: Rc::new(RefCell::new(input_value)),
     };
@@ -373,17 +549,22 @@ pub struct ScriptInputPlugin;
 
 impl Plugin for ScriptInputPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(PreUpdate, detect_reload_tick);
-
         app.add_system(Update, listen_cursor_moved);
         app.add_system(Update, listen_mouse_button);
+        app.add_system(Update, listen_input_devices);
+        app.add_system(Update, action_sets_tick);
+        app.add_system(Update, held_keys_tick);
+        app.add_system(Update, mouse_input_tick);
 
+        app.insert_local_resource(InputDevices::default());
         app.insert_local_resource(ScriptInput {
             sets: SeqMap::default(),
+            resolved_sets: Vec::new(),
             input_value: Rc::new(RefCell::new(Value::default())),
             mouse_cursor_position_index: 0,
             mouse_left_button_index: 0,
             mouse_right_button_index: 0,
+            held_keys: None,
         });
         /*
         let script_main = app
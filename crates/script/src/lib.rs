@@ -2,39 +2,90 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
+use crate::err::{Diagnostic, Severity};
 use limnus_message::prelude::Message;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use swamp::prelude::{App, Plugin, Resource};
 
-mod audio;
+pub mod asset_reload;
+pub mod asset_status;
+pub mod atlas;
+pub mod audio;
+pub mod bindings;
+pub mod call_sites;
+pub mod check;
+pub mod contract;
 pub mod convert;
 pub mod err;
+pub mod error_overlay;
 pub mod flow;
+pub mod golden;
 pub mod input;
-mod modules;
+pub mod manifest;
+pub mod migrate;
+pub mod reload;
 pub mod render;
+pub mod replay;
+pub mod save;
 mod script;
 pub mod script_main;
 pub mod serialize;
-mod sim_input;
 pub mod simulation;
 pub mod source_map;
+#[cfg(test)]
+mod test_fixtures;
+pub mod tilemap;
+pub mod time_machine;
+pub mod timestep;
 mod util;
+pub mod xml;
 
+pub use script::{MangroveError, ModuleGraph};
 use swamp_script::prelude::{SourceMap, SourceMapWrapper};
 
+/// The numbers of the handle types that are given to the scripts. The types are compared by the
+/// number, so every handle type must have its own.
+pub mod external_type_number {
+    pub const MATERIAL_HANDLE: u32 = 91;
+    pub const FONT_AND_MATERIAL: u32 = 92;
+    pub const FIXED_ATLAS: u32 = 93;
+    pub const SOUND_HANDLE: u32 = 94;
+    pub const MUSIC_HANDLE: u32 = 95;
+    pub const ATLAS: u32 = 96;
+    pub const TILEMAP: u32 = 97;
+}
+
 #[derive(Message, Debug)]
 pub enum ScriptMessage {
-    Reload,
+    /// The script files changed, compile and boot the parts that they affect again.
+    /// Without any files, everything is compiled and booted again.
+    Reload(Vec<PathBuf>),
+    /// Sent in the same frame as the new program is swapped in, after a successful `Reload`.
+    /// Systems that run after the reload should read it with `iter_current`.
+    Reloaded(Rebooted),
+    /// The flow switched to another screen, the simulation and render (and audio) should boot again
+    ScreenChanged,
+}
+
+/// The parts that were booted again by a reload, the other parts kept running with their state
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rebooted {
+    pub simulation: bool,
+    pub render: bool,
+    pub input: bool,
+    pub flow: bool,
+    pub audio: bool,
 }
 
 #[derive(Resource, Debug)]
 pub struct SourceMapResource {
     pub source_map: SourceMap,
+    /// The module in the `crate` mount with the `input`, `simulation`, `render` and `flow` functions
+    pub entry_module: String,
 }
 
 impl SourceMapResource {
-    pub fn wrapper(&self) -> SourceMapWrapper {
+    pub fn wrapper(&self) -> SourceMapWrapper<'_> {
         SourceMapWrapper {
             source_map: &self.source_map,
         }
@@ -48,9 +99,41 @@ impl SourceMapResource {
     }
 }
 
-#[derive(Resource, Debug)]
+/// Compile and runtime errors reported by the script plugins since the last reload.
+/// The script systems stop ticking as long as there are any errors, warnings are only reported.
+#[derive(Resource, Debug, Default)]
 pub struct ErrorResource {
-    pub has_errors: bool,
+    /// The errors stop the simulation, render and input until the scripts are reloaded
+    pub diagnostics: Vec<Diagnostic>,
+    /// Why the latest hot reload failed. Does not stop anything, the previous program keeps running.
+    pub failed_reload: Option<Diagnostic>,
+}
+
+impl ErrorResource {
+    #[must_use]
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn clear(&mut self) {
+        self.diagnostics.clear();
+        self.failed_reload = None;
+    }
+}
+
+/// Where the simulation gets its gamepad and mouse input from
+#[derive(Resource, Debug, Default, PartialEq, Eq)]
+pub enum InputSource {
+    #[default]
+    Live,
+    /// The devices are ignored, all input is fed from an input recording
+    Replay,
 }
 
 pub struct ScriptPlugin;
@@ -58,11 +141,12 @@ pub struct ScriptPlugin;
 impl Plugin for ScriptPlugin {
     fn build(&self, app: &mut App) {
         app.create_message_type::<ScriptMessage>();
+        app.insert_resource(InputSource::default());
 
         let script_messages = app
             .messages_mut()
             .get_mut::<ScriptMessage>()
             .expect("missing ScriptMessage");
-        script_messages.send(ScriptMessage::Reload);
+        script_messages.send(ScriptMessage::Reload(Vec::new()));
     }
 }
@@ -0,0 +1,330 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! The project manifest, read from `mangrove.toml` in the project directory at startup.
+//!
+//! Every field is optional, a missing manifest (or table) uses the defaults shown here:
+//!
+//! ```toml
+//! title = "mangrove"
+//!
+//! [window]
+//! virtual_resolution = [320, 180]
+//! scale = 5 # the window starts at virtual_resolution * scale
+//! # size = [1600, 900] # overrides the scale
+//! mode = "on_top" # "windowed", "on_top" or "fullscreen"
+//!
+//! [scripts]
+//! root = "scripts"
+//! entry = "main" # the module with the input, simulation, render and flow functions
+//!
+//! [scripts.mounts]
+//! # registry = "packages" # use packages shipped with the game instead of the swamp registry
+//!
+//! [assets]
+//! root = "assets"
+//!
+//! [plugins]
+//! audio = true
+//! hot_reload = true
+//! time_machine = false
+//! ```
+//!
+//! Relative paths are relative to the project directory. The asset loader reads the images and
+//! fonts from an `assets` directory, so the asset root can be moved, e.g. to `content/assets`, but
+//! the directory must be named `assets`.
+use crate::script::MangroveError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use swamp::prelude::{Resource, ResourceStorage};
+use tracing::debug;
+
+pub const MANIFEST_FILE_NAME: &str = "mangrove.toml";
+
+pub const DEFAULT_TITLE: &str = "mangrove";
+pub const DEFAULT_VIRTUAL_RESOLUTION: [u16; 2] = [320, 180];
+pub const DEFAULT_SCALE: u16 = 5;
+pub const DEFAULT_SCRIPT_ROOT: &str = "scripts";
+pub const DEFAULT_ENTRY_MODULE: &str = "main";
+
+pub const DEFAULT_ASSET_ROOT: &str = "assets";
+
+/// The asset loader reads from a directory with this name in the current directory, see
+/// [`Manifest::asset_loader_directory`]
+pub const ASSET_LOADER_DIRECTORY_NAME: &str = "assets";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WindowMode {
+    Windowed,
+    /// Windowed, but always on top of the other windows. Handy when editing the scripts next to it.
+    #[default]
+    OnTop,
+    /// A borderless window that covers the whole screen
+    Fullscreen,
+}
+
+impl FromStr for WindowMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "windowed" => Ok(Self::Windowed),
+            "on_top" => Ok(Self::OnTop),
+            "fullscreen" => Ok(Self::Fullscreen),
+            _ => Err(format!(
+                "unknown window mode '{s}', expected windowed, on_top or fullscreen"
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WindowConfig {
+    pub virtual_resolution: [u16; 2],
+    pub scale: u16,
+    /// The requested window size, `virtual_resolution * scale` if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<[u16; 2]>,
+    pub mode: WindowMode,
+}
+
+impl Default for WindowConfig {
+    fn default() -> Self {
+        Self {
+            virtual_resolution: DEFAULT_VIRTUAL_RESOLUTION,
+            scale: DEFAULT_SCALE,
+            size: None,
+            mode: WindowMode::default(),
+        }
+    }
+}
+
+impl WindowConfig {
+    #[must_use]
+    pub fn requested_size(&self) -> [u16; 2] {
+        self.size.unwrap_or([
+            self.virtual_resolution[0].saturating_mul(self.scale),
+            self.virtual_resolution[1].saturating_mul(self.scale),
+        ])
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScriptsConfig {
+    pub root: PathBuf,
+    /// The module name in the script root, without the `.swamp` extension
+    pub entry: String,
+    /// Mounted in the source map in addition to `crate` (the script root).
+    /// `registry` replaces the swamp package registry.
+    pub mounts: BTreeMap<String, PathBuf>,
+}
+
+impl Default for ScriptsConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from(DEFAULT_SCRIPT_ROOT),
+            entry: DEFAULT_ENTRY_MODULE.to_string(),
+            mounts: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AssetsConfig {
+    pub root: PathBuf,
+}
+
+impl Default for AssetsConfig {
+    fn default() -> Self {
+        Self {
+            root: PathBuf::from(DEFAULT_ASSET_ROOT),
+        }
+    }
+}
+
+/// The optional plugins. The script, input and render plugins are always enabled.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PluginsConfig {
+    pub audio: bool,
+    /// Watch the script root and reload the scripts when they are saved
+    pub hot_reload: bool,
+    pub time_machine: bool,
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        Self {
+            audio: true,
+            hot_reload: true,
+            time_machine: false,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Manifest {
+    pub title: String,
+    pub window: WindowConfig,
+    pub scripts: ScriptsConfig,
+    pub assets: AssetsConfig,
+    pub plugins: PluginsConfig,
+    /// The directory the manifest was read from, the relative paths are relative to it
+    #[serde(skip)]
+    pub project_directory: PathBuf,
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Self {
+            title: DEFAULT_TITLE.to_string(),
+            window: WindowConfig::default(),
+            scripts: ScriptsConfig::default(),
+            assets: AssetsConfig::default(),
+            plugins: PluginsConfig::default(),
+            project_directory: PathBuf::from("."),
+        }
+    }
+}
+
+impl Manifest {
+    /// Loads `mangrove.toml` from the project directory, or the defaults if there is no such file.
+    ///
+    /// # Errors
+    ///
+    /// If the file can not be read, parsed or has invalid values.
+    pub fn load(project_directory: &Path) -> Result<Self, MangroveError> {
+        let path = project_directory.join(MANIFEST_FILE_NAME);
+        let mut manifest = match fs::read_to_string(&path) {
+            Ok(text) => Self::parse(&text)
+                .map_err(|err| MangroveError::Other(format!("{}: {err}", path.display())))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => {
+                debug!("no {MANIFEST_FILE_NAME}, using the default project settings");
+                Self::default()
+            }
+            Err(err) => return Err(format!("could not read {}: {err}", path.display()).into()),
+        };
+
+        manifest.project_directory = project_directory.to_path_buf();
+        manifest
+            .validate()
+            .map_err(|err| MangroveError::Other(format!("{}: {err}", path.display())))?;
+
+        Ok(manifest)
+    }
+
+    /// # Errors
+    ///
+    /// If the text is not valid toml or has unknown fields.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        toml::from_str(text).map_err(|err| err.to_string())
+    }
+
+    /// The manifest as `mangrove.toml` text
+    ///
+    /// # Errors
+    ///
+    /// If the manifest can not be represented as toml.
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string(self).map_err(|err| err.to_string())
+    }
+
+    /// # Errors
+    ///
+    /// If a value can not be used, e.g. a zero scale or an entry module that is not a module name.
+    pub fn validate(&self) -> Result<(), String> {
+        let [width, height] = self.window.virtual_resolution;
+        if width == 0 || height == 0 {
+            return Err(format!(
+                "window.virtual_resolution must not be zero, got [{width}, {height}]"
+            ));
+        }
+
+        if self.window.scale == 0 {
+            return Err("window.scale must be at least 1".to_string());
+        }
+
+        if let Some([width, height]) = self.window.size
+            && (width < self.window.virtual_resolution[0]
+                || height < self.window.virtual_resolution[1])
+        {
+            return Err(format!(
+                "window.size [{width}, {height}] is smaller than the virtual resolution"
+            ));
+        }
+
+        if !is_module_name(&self.scripts.entry) {
+            return Err(format!(
+                "scripts.entry must be a module name (lowercase letters, digits and '_'), got '{}'",
+                self.scripts.entry
+            ));
+        }
+
+        if self.scripts.mounts.contains_key("crate") {
+            return Err(
+                "scripts.mounts can not contain 'crate', it is always the script root".to_string(),
+            );
+        }
+
+        if self.assets.root.file_name() != Some(ASSET_LOADER_DIRECTORY_NAME.as_ref()) {
+            return Err(format!(
+                "assets.root must be a directory named '{ASSET_LOADER_DIRECTORY_NAME}', got '{}'",
+                self.assets.root.display()
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn script_root(&self) -> PathBuf {
+        self.project_directory.join(&self.scripts.root)
+    }
+
+    #[must_use]
+    pub fn asset_root(&self) -> PathBuf {
+        self.project_directory.join(&self.assets.root)
+    }
+
+    /// The directory that must be the current directory when the game runs, since the asset
+    /// loader reads the images and fonts from `assets/` in the current directory
+    #[must_use]
+    pub fn asset_loader_directory(&self) -> PathBuf {
+        self.asset_root()
+            .parent()
+            .map_or_else(|| self.project_directory.clone(), Path::to_path_buf)
+    }
+
+    /// The extra mounts, with the paths relative to the project directory
+    pub fn mounts(&self) -> impl Iterator<Item = (&str, PathBuf)> {
+        self.scripts
+            .mounts
+            .iter()
+            .map(|(name, path)| (name.as_str(), self.project_directory.join(path)))
+    }
+}
+
+fn is_module_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+/// The asset root of the manifest resource, or the default asset root if there is no manifest
+#[must_use]
+pub fn resource_asset_root(resources: &ResourceStorage) -> PathBuf {
+    resources
+        .get::<Manifest>()
+        .map_or_else(|| Manifest::default().asset_root(), Manifest::asset_root)
+}
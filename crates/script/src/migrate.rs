@@ -0,0 +1,501 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Moves the simulation state from a previous version of the scripts to the newly compiled one.
+//! Struct fields (and enum variants) are matched by name, so fields can be added, removed
+//! and reordered. New fields get the value from the freshly booted simulation.
+use crate::script::MangroveError;
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+use swamp::prelude::Fp;
+use swamp_script::prelude::{
+    EnumType, EnumVariantType, NamedStructType, SeqMap, StructTypeField, Type, Value, ValueRef,
+};
+
+/// What happened to the fields during a migration. The paths are relative to the simulation
+/// struct, and all the items in a container share the same path (e.g. `enemies[].health`).
+#[derive(Debug, Default)]
+pub struct MigrationReport {
+    pub kept_count: usize,
+    /// New fields, that got the value from the booted simulation (or a default value)
+    pub added: Vec<String>,
+    /// Fields that no longer exist, their values are dropped
+    pub removed: Vec<String>,
+    /// Fields (or enum variants) that changed type, they are handled like added fields
+    pub changed: Vec<String>,
+}
+
+impl MigrationReport {
+    /// Returns true if the layout is the same as before
+    #[must_use]
+    pub fn is_unchanged(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    fn add(list: &mut Vec<String>, path: &str) {
+        if !list.iter().any(|existing| existing == path) {
+            list.push(path.to_string());
+        }
+    }
+}
+
+impl Display for MigrationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "kept {} fields", self.kept_count)?;
+        for (description, paths) in [
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("changed", &self.changed),
+        ] {
+            if !paths.is_empty() {
+                write!(f, ", {description}: {}", paths.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Migrates the `previous` simulation value into the layout of the `booted` simulation value.
+/// The previous value is consumed, so nothing (including `std::Sparse` collections) is cloned.
+///
+/// # Errors
+///
+/// If a field has changed type and there is no value to use instead
+pub fn migrate(previous: Value, booted: &Value) -> Result<(Value, MigrationReport), MangroveError> {
+    let Value::NamedStruct(booted_struct_type, _) = booted else {
+        return Err(MangroveError::Other(
+            "the simulation value must be a struct".to_string(),
+        ));
+    };
+
+    let mut report = MigrationReport::default();
+    let migrated = migrate_value(
+        previous,
+        &Type::NamedStruct(booted_struct_type.clone()),
+        Some(booted),
+        "",
+        &mut report,
+    )?;
+
+    Ok((migrated, report))
+}
+
+/// Migrates a value into `new_type`, e.g. a value that was loaded with a previous layout.
+/// There is no booted value, so the new fields get a default value.
+///
+/// # Errors
+///
+/// If a field has changed type and there is no default value for the new type
+pub fn migrate_to_type(
+    previous: Value,
+    new_type: &Type,
+) -> Result<(Value, MigrationReport), MangroveError> {
+    let mut report = MigrationReport::default();
+    let migrated = migrate_value(previous, new_type, None, "", &mut report)?;
+
+    Ok((migrated, report))
+}
+
+/// The name of the generic struct without the type parameters, e.g. `Vec` for `[Int]`
+#[must_use]
+pub fn container_name(struct_type: &NamedStructType) -> &str {
+    struct_type
+        .assigned_name
+        .split('<')
+        .next()
+        .unwrap_or_default()
+}
+
+fn join_path(path: &str, name: &str) -> String {
+    if path.is_empty() {
+        name.to_string()
+    } else {
+        format!("{path}.{name}")
+    }
+}
+
+fn to_ref(value: Value) -> ValueRef {
+    Rc::new(RefCell::new(value))
+}
+
+/// Takes the value out of the reference, if it is not shared, otherwise a copy is made
+fn take_ref(value_ref: ValueRef) -> Value {
+    Rc::try_unwrap(value_ref).map_or_else(|shared| shared.borrow().clone(), RefCell::into_inner)
+}
+
+/// The fields of the template if it is the same kind of value, used for the nested new fields
+fn template_fields(template: Option<&Value>) -> Option<&[ValueRef]> {
+    match template? {
+        Value::NamedStruct(_, fields)
+        | Value::Tuple(_, fields)
+        | Value::EnumVariantTuple(_, _, fields)
+        | Value::EnumVariantStruct(_, _, fields) => Some(fields),
+        _ => None,
+    }
+}
+
+fn migrate_value(
+    previous: Value,
+    new_type: &Type,
+    template: Option<&Value>,
+    path: &str,
+    report: &mut MigrationReport,
+) -> Result<Value, MangroveError> {
+    let migrated = match (new_type, previous) {
+        (Type::Int, previous @ Value::Int(_))
+        | (Type::Float, previous @ Value::Float(_))
+        | (Type::String, previous @ Value::String(_))
+        | (Type::Bool, previous @ Value::Bool(_))
+        | (Type::Unit, previous @ Value::Unit) => previous,
+
+        (Type::Optional(inner_type), Value::Option(maybe_value)) => {
+            let inner_template = match template {
+                Some(Value::Option(Some(template_ref))) => Some(template_ref.borrow().clone()),
+                _ => None,
+            };
+            let migrated_inner = maybe_value
+                .map(|value_ref| {
+                    migrate_value(
+                        take_ref(value_ref),
+                        inner_type,
+                        inner_template.as_ref(),
+                        path,
+                        report,
+                    )
+                })
+                .transpose()?;
+            Value::Option(migrated_inner.map(to_ref))
+        }
+
+        (Type::Tuple(new_types), Value::Tuple(_, values)) if new_types.len() == values.len() => {
+            let fields = migrate_in_order(values, new_types, template, path, report)?;
+            Value::Tuple(new_types.clone(), fields)
+        }
+
+        (Type::NamedStruct(new_struct_type), Value::NamedStruct(previous_struct_type, values))
+            if new_struct_type.instantiated_type_parameters.is_empty() =>
+        {
+            let fields = migrate_fields(
+                &previous_struct_type
+                    .anon_struct_type
+                    .field_name_sorted_fields,
+                values,
+                &new_struct_type.anon_struct_type.field_name_sorted_fields,
+                template,
+                path,
+                report,
+            )?;
+            Value::NamedStruct(new_struct_type.clone(), fields)
+        }
+
+        // `[T]`, `[K: V]` and `Sparse<T>` are structs with the item types as type parameters.
+        // The container type is created from the new item types, the same as in `default_value`.
+        (Type::NamedStruct(new_struct_type), Value::Vec(_, items)) => {
+            let item_type = type_parameter(new_struct_type, 0, path)?;
+            let item_path = format!("{path}[]");
+            let migrated_items = items
+                .into_iter()
+                .map(|item| {
+                    migrate_value(take_ref(item), item_type, None, &item_path, report).map(to_ref)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Value::Vec(Type::Slice(Box::new(item_type.clone())), migrated_items)
+        }
+
+        (Type::NamedStruct(new_struct_type), Value::Map(_, entries)) => {
+            let key_type = type_parameter(new_struct_type, 0, path)?;
+            let value_type = type_parameter(new_struct_type, 1, path)?;
+            let item_path = format!("{path}[]");
+            let mut migrated_entries = SeqMap::new();
+            for (key, value) in entries {
+                let migrated_key = migrate_value(key, key_type, None, &item_path, report)?;
+                let migrated_value =
+                    migrate_value(take_ref(value), value_type, None, &item_path, report)?;
+                // Keys that became equal when migrated, keep the first one
+                let _ = migrated_entries.insert(migrated_key, to_ref(migrated_value));
+            }
+            Value::Map(
+                Type::SlicePair(Box::new(key_type.clone()), Box::new(value_type.clone())),
+                migrated_entries,
+            )
+        }
+
+        (Type::NamedStruct(new_struct_type), Value::Sparse(sparse_type, mut sparse_map)) => {
+            let item_type = type_parameter(new_struct_type, 0, path)?;
+            let item_path = format!("{path}[]");
+            // Migrated in place, so the ids that the simulation holds are still valid
+            for (_id, item_ref) in sparse_map.iter_mut() {
+                let item = item_ref.replace(Value::Unit);
+                *item_ref.borrow_mut() = migrate_value(item, item_type, None, &item_path, report)?;
+            }
+            // The sparse type is the external `Sparse` type for all item types, the item type
+            // is only stored in the map
+            sparse_map.value_item_type = item_type.clone();
+            Value::Sparse(sparse_type, sparse_map)
+        }
+
+        (Type::Enum(new_enum_type), previous @ Value::EnumVariantSimple(..))
+        | (Type::Enum(new_enum_type), previous @ Value::EnumVariantTuple(..))
+        | (Type::Enum(new_enum_type), previous @ Value::EnumVariantStruct(..)) => {
+            migrate_enum(previous, new_enum_type, template, path, report)?
+        }
+
+        (_, _) => {
+            MigrationReport::add(&mut report.changed, path);
+            replacement(new_type, template, path)?
+        }
+    };
+
+    Ok(migrated)
+}
+
+fn type_parameter<'a>(
+    struct_type: &'a NamedStructType,
+    index: usize,
+    path: &str,
+) -> Result<&'a Type, MangroveError> {
+    struct_type
+        .instantiated_type_parameters
+        .get(index)
+        .ok_or_else(|| {
+            MangroveError::Other(format!(
+                "{path}: {} is missing a type parameter",
+                struct_type.assigned_name
+            ))
+        })
+}
+
+/// Migrates the values by position, e.g. tuples and the fields of tuple enum variants
+fn migrate_in_order(
+    values: Vec<ValueRef>,
+    new_types: &[Type],
+    template: Option<&Value>,
+    path: &str,
+    report: &mut MigrationReport,
+) -> Result<Vec<ValueRef>, MangroveError> {
+    let template_fields = template_fields(template);
+    values
+        .into_iter()
+        .zip(new_types)
+        .enumerate()
+        .map(|(index, (value, new_type))| {
+            let field_template = template_fields
+                .and_then(|fields| fields.get(index))
+                .map(|field| field.borrow());
+            migrate_value(
+                take_ref(value),
+                new_type,
+                field_template.as_deref(),
+                &join_path(path, &index.to_string()),
+                report,
+            )
+            .map(to_ref)
+        })
+        .collect()
+}
+
+/// Migrates the values by field name, e.g. structs and the fields of struct enum variants
+fn migrate_fields(
+    previous_fields: &SeqMap<String, StructTypeField>,
+    previous_values: Vec<ValueRef>,
+    new_fields: &SeqMap<String, StructTypeField>,
+    template: Option<&Value>,
+    path: &str,
+    report: &mut MigrationReport,
+) -> Result<Vec<ValueRef>, MangroveError> {
+    let mut previous_by_name: SeqMap<String, ValueRef> = SeqMap::new();
+    for (name, value) in previous_fields.keys().zip(previous_values) {
+        previous_by_name
+            .insert(name.clone(), value)
+            .expect("struct field names are unique");
+    }
+
+    let template_fields = template_fields(template);
+    let mut migrated_fields = Vec::with_capacity(new_fields.len());
+
+    for (index, (name, new_field)) in new_fields.iter().enumerate() {
+        let field_path = join_path(path, name);
+        let field_template = template_fields
+            .and_then(|fields| fields.get(index))
+            .map(|field| field.borrow());
+
+        let migrated = if let Some(previous_value) = previous_by_name.remove(name) {
+            report.kept_count += 1;
+            migrate_value(
+                take_ref(previous_value),
+                &new_field.field_type,
+                field_template.as_deref(),
+                &field_path,
+                report,
+            )?
+        } else {
+            MigrationReport::add(&mut report.added, &field_path);
+            replacement(
+                &new_field.field_type,
+                field_template.as_deref(),
+                &field_path,
+            )?
+        };
+        migrated_fields.push(to_ref(migrated));
+    }
+
+    for removed_name in previous_by_name.keys() {
+        MigrationReport::add(&mut report.removed, &join_path(path, removed_name));
+    }
+
+    Ok(migrated_fields)
+}
+
+fn migrate_enum(
+    previous: Value,
+    new_enum_type: &EnumType,
+    template: Option<&Value>,
+    path: &str,
+    report: &mut MigrationReport,
+) -> Result<Value, MangroveError> {
+    let previous_variant_name = match &previous {
+        Value::EnumVariantSimple(_, variant) => variant.common.assigned_name.clone(),
+        Value::EnumVariantTuple(_, variant, _) => variant.common.assigned_name.clone(),
+        Value::EnumVariantStruct(_, variant, _) => variant.common.assigned_name.clone(),
+        _ => unreachable!("only called for enum values"),
+    };
+    let variant_path = join_path(path, &previous_variant_name);
+
+    // The template is only useful if the booted simulation has the same variant
+    let variant_template = template.filter(|template_value| match template_value {
+        Value::EnumVariantTuple(_, variant, _) => {
+            variant.common.assigned_name == previous_variant_name
+        }
+        Value::EnumVariantStruct(_, variant, _) => {
+            variant.common.assigned_name == previous_variant_name
+        }
+        _ => false,
+    });
+
+    let migrated = match (new_enum_type.get_variant(&previous_variant_name), previous) {
+        (Some(EnumVariantType::Nothing(new_variant)), Value::EnumVariantSimple(..)) => {
+            Value::EnumVariantSimple(new_enum_type.clone(), new_variant.clone())
+        }
+        (Some(EnumVariantType::Tuple(new_variant)), Value::EnumVariantTuple(_, _, values))
+            if new_variant.fields_in_order.len() == values.len() =>
+        {
+            let fields = migrate_in_order(
+                values,
+                &new_variant.fields_in_order,
+                variant_template,
+                &variant_path,
+                report,
+            )?;
+            Value::EnumVariantTuple(new_enum_type.clone(), new_variant.clone(), fields)
+        }
+        (
+            Some(EnumVariantType::Struct(new_variant)),
+            Value::EnumVariantStruct(_, previous_variant, values),
+        ) => {
+            let fields = migrate_fields(
+                &previous_variant.anon_struct.field_name_sorted_fields,
+                values,
+                &new_variant.anon_struct.field_name_sorted_fields,
+                variant_template,
+                &variant_path,
+                report,
+            )?;
+            Value::EnumVariantStruct(new_enum_type.clone(), new_variant.clone(), fields)
+        }
+        (_, _) => {
+            // The variant was removed or changed kind
+            MigrationReport::add(&mut report.changed, &variant_path);
+            replacement(&Type::Enum(new_enum_type.clone()), template, path)?
+        }
+    };
+
+    Ok(migrated)
+}
+
+/// The value to use when there is nothing to migrate from
+fn replacement(
+    new_type: &Type,
+    template: Option<&Value>,
+    path: &str,
+) -> Result<Value, MangroveError> {
+    if let Some(template_value) = template {
+        return Ok(template_value.clone());
+    }
+
+    default_value(new_type).ok_or_else(|| {
+        MangroveError::Other(format!(
+            "{path}: there is no default value for type {new_type}"
+        ))
+    })
+}
+
+/// Zero, empty or the first enum variant. Only used when there is no booted value to take the
+/// new field from, e.g. for the items in a container.
+fn default_value(value_type: &Type) -> Option<Value> {
+    let value = match value_type {
+        Type::Int => Value::Int(0),
+        Type::Float => Value::Float(Fp::zero()),
+        Type::String => Value::String(String::new()),
+        Type::Bool => Value::Bool(false),
+        Type::Unit => Value::Unit,
+        Type::Optional(_) => Value::Option(None),
+        Type::Tuple(types) => Value::Tuple(types.clone(), default_values(types)?),
+        Type::NamedStruct(struct_type) if struct_type.instantiated_type_parameters.is_empty() => {
+            let field_types: Vec<Type> = struct_type
+                .anon_struct_type
+                .field_name_sorted_fields
+                .values()
+                .map(|field| field.field_type.clone())
+                .collect();
+            Value::NamedStruct(struct_type.clone(), default_values(&field_types)?)
+        }
+        Type::Enum(enum_type) => match enum_type.variants.values().next()? {
+            EnumVariantType::Nothing(variant) => {
+                Value::EnumVariantSimple(enum_type.clone(), variant.clone())
+            }
+            EnumVariantType::Tuple(variant) => Value::EnumVariantTuple(
+                enum_type.clone(),
+                variant.clone(),
+                default_values(&variant.fields_in_order)?,
+            ),
+            EnumVariantType::Struct(variant) => {
+                let field_types: Vec<Type> = variant
+                    .anon_struct
+                    .field_name_sorted_fields
+                    .values()
+                    .map(|field| field.field_type.clone())
+                    .collect();
+                Value::EnumVariantStruct(
+                    enum_type.clone(),
+                    variant.clone(),
+                    default_values(&field_types)?,
+                )
+            }
+        },
+        Type::NamedStruct(struct_type) => match (
+            container_name(struct_type),
+            struct_type.instantiated_type_parameters.as_slice(),
+        ) {
+            ("Vec", [item_type]) => Value::Vec(Type::Slice(Box::new(item_type.clone())), vec![]),
+            ("Map", [key_type, value_type]) => Value::Map(
+                Type::SlicePair(Box::new(key_type.clone()), Box::new(value_type.clone())),
+                SeqMap::new(),
+            ),
+            // e.g. `Sparse<T>`, that needs an id type from the runtime
+            _ => return None,
+        },
+        // The external types can not be created from the type alone
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn default_values(types: &[Type]) -> Option<Vec<ValueRef>> {
+    types
+        .iter()
+        .map(|value_type| default_value(value_type).map(to_ref))
+        .collect()
+}
@@ -0,0 +1,637 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Transactional and selective hot reload.
+//!
+//! Only the changed modules, and the modules that depend on them, are compiled again. The parts
+//! (simulation, render, input, flow and audio) whose struct is defined in one of those modules are
+//! booted into staging, the other parts keep running with their state.
+//! The staged parts are only swapped in if all of them succeeded. Otherwise the previous program
+//! keeps running, and the error is shown without stopping it.
+use crate::audio::{AudioOutput, ScriptAudio};
+use crate::err::{DiagnosticPhase, report_failed_reload, report_mangrove_error};
+use crate::flow::ScriptFlow;
+use crate::input::{ScriptInput, convert_to_input_bindings};
+use crate::render::ScriptRender;
+use crate::script::MangroveError;
+use crate::script_main::{ScriptMain, compile, compile_changed};
+use crate::simulation::ScriptSimulation;
+use crate::{
+    ErrorResource, Rebooted, ScriptMessage, SourceMapResource, audio, flow, input, render,
+    simulation,
+};
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+use swamp::prelude::{
+    LoReAll, LocalResource, LocalResourceStorage, MsgM, ReAll, ReM, ResourceStorage,
+};
+use swamp_script::prelude::{InternalFunctionDefinitionRef, SourceMap, Type};
+use tracing::{debug, info, warn};
+
+/// The constants of the replaced modules stay in the program state, and the evaluated constants
+/// have a fixed capacity, so every now and then everything is compiled from scratch
+const MAX_CONSTANTS_BEFORE_FULL_COMPILE: usize = 512;
+
+#[derive(LocalResource, Debug, Default)]
+pub struct ReloadState {
+    /// Whether a program has been swapped in. A failed reload can only fall back to a previous program.
+    has_program: bool,
+    /// The script files that changed since the running program was compiled.
+    /// Kept until a reload succeeds, since a failed reload does not replace anything.
+    changed_files: Vec<PathBuf>,
+    /// A reload without changed files, e.g. the first one, compiles and boots everything
+    everything_changed: bool,
+}
+
+impl ReloadState {
+    fn add_changes(&mut self, changed_files: &[PathBuf]) {
+        if changed_files.is_empty() {
+            self.everything_changed = true;
+        }
+        for changed_file in changed_files {
+            if !self.changed_files.contains(changed_file) {
+                self.changed_files.push(changed_file.clone());
+            }
+        }
+    }
+}
+
+/// Only the plugins that were added to the app are booted, e.g. headless has no render and input
+struct Staged {
+    main: ScriptMain,
+    simulation: Option<ScriptSimulation>,
+    render: Option<ScriptRender>,
+    input: Option<ScriptInput>,
+    flow: Option<ScriptFlow>,
+    audio: Option<ScriptAudio>,
+}
+
+impl Staged {
+    const fn rebooted(&self) -> Rebooted {
+        Rebooted {
+            simulation: self.simulation.is_some(),
+            render: self.render.is_some(),
+            input: self.input.is_some(),
+            flow: self.flow.is_some(),
+            audio: self.audio.is_some(),
+        }
+    }
+}
+
+/// The `crate` module paths of the changed `.swamp` files.
+/// `None` if a file is not in the scripts directory, then it is not known what depends on it.
+fn changed_modules(source_map: &SourceMap, changed_files: &[PathBuf]) -> Option<Vec<Vec<String>>> {
+    let scripts_path = canonical(source_map.base_path("crate"));
+
+    let mut module_paths = Vec::new();
+    for changed_file in changed_files {
+        if changed_file
+            .extension()
+            .is_none_or(|extension| extension != "swamp")
+        {
+            continue;
+        }
+        let relative_path = canonical(changed_file)
+            .strip_prefix(&scripts_path)
+            .ok()?
+            .with_extension("");
+
+        let mut module_path = vec!["crate".to_string()];
+        for component in relative_path.components() {
+            let Component::Normal(name) = component else {
+                return None;
+            };
+            module_path.push(name.to_string_lossy().to_string());
+        }
+        module_paths.push(module_path);
+    }
+
+    Some(module_paths)
+}
+
+/// The files reported by the watcher can be relative or go through symlinks
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| {
+            // A removed file can not be canonicalized, but the directory it was in can
+            match (path.parent(), path.file_name()) {
+                (Some(parent), Some(file_name)) => {
+                    parent.canonicalize().map(|parent| parent.join(file_name))
+                }
+                _ => Ok(path.to_path_buf()),
+            }
+        })
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The module and name of the struct that the function creates, e.g. `simulation()`
+fn constructed_struct_name(function: &InternalFunctionDefinitionRef) -> Option<(&[String], &str)> {
+    match &*function.signature.return_type {
+        Type::NamedStruct(struct_type) => Some((
+            struct_type.module_path.as_slice(),
+            struct_type.assigned_name.as_str(),
+        )),
+        _ => None,
+    }
+}
+
+/// A part must be booted again if its struct was analyzed again, or if the function now creates
+/// another struct. Otherwise the running part keeps its state and functions.
+fn needs_boot(
+    running: Option<&InternalFunctionDefinitionRef>,
+    compiled: Option<&InternalFunctionDefinitionRef>,
+    affected: &HashSet<Vec<String>>,
+) -> bool {
+    match (running, compiled) {
+        (None, None) => false,
+        (Some(running), Some(compiled)) => {
+            match (
+                constructed_struct_name(running),
+                constructed_struct_name(compiled),
+            ) {
+                (Some(running_struct), Some(compiled_struct)) => {
+                    running_struct != compiled_struct || affected.contains(compiled_struct.0)
+                }
+                _ => true,
+            }
+        }
+        _ => true,
+    }
+}
+
+/// See `MAX_CONSTANTS_BEFORE_FULL_COMPILE`
+fn needs_full_compile(running: &ScriptMain) -> bool {
+    running
+        .resolved_program
+        .state
+        .constants_in_dependency_order
+        .len()
+        > MAX_CONSTANTS_BEFORE_FULL_COMPILE
+}
+
+/// Compiles the changed modules and boots the parts that they affect. Returns `None` if it is
+/// not known what the changes affect, then everything must be compiled and booted again.
+fn stage_changed(
+    changed_modules: &[Vec<String>],
+    source_map: &mut SourceMapResource,
+    local_resources: &LocalResourceStorage,
+    resources: &mut ResourceStorage,
+) -> Option<Result<Staged, (DiagnosticPhase, MangroveError)>> {
+    let running = local_resources.fetch::<ScriptMain>();
+    if needs_full_compile(running) {
+        return None;
+    }
+
+    let affected = running.module_graph.with_dependents(changed_modules);
+    debug!(?affected, "modules affected by the change");
+
+    // Analyzing only some of the modules can fail for reasons that a full compile does not
+    // have, so the full compile decides if the scripts are broken
+    let mut main = compile_changed(running, &affected, source_map).ok()?;
+
+    let running_flow = local_resources.get::<ScriptFlow>();
+    if let Some(running_flow) = running_flow {
+        if needs_boot(
+            running.flow_new_fn.as_ref(),
+            main.flow_new_fn.as_ref(),
+            &affected,
+        ) {
+            // A new flow starts over from the main screen, so everything is booted again
+            return None;
+        }
+        // Stay on the screen that the flow has switched to
+        main.use_screen(running_flow.screen()).ok()?;
+    }
+
+    Some(boot_affected(
+        main,
+        running,
+        &affected,
+        source_map,
+        local_resources,
+        resources,
+    ))
+}
+
+/// Boots the parts whose struct was affected by the change, see `needs_boot`
+fn boot_affected(
+    main: ScriptMain,
+    running: &ScriptMain,
+    affected: &HashSet<Vec<String>>,
+    source_map: &SourceMapResource,
+    local_resources: &LocalResourceStorage,
+    resources: &mut ResourceStorage,
+) -> Result<Staged, (DiagnosticPhase, MangroveError)> {
+    let boot_error = |err| (DiagnosticPhase::Boot, err);
+
+    let simulation = (local_resources.contains::<ScriptSimulation>()
+        && needs_boot(
+            Some(&running.simulation_new_fn),
+            Some(&main.simulation_new_fn),
+            affected,
+        ))
+    .then(|| simulation::boot(&main, resources))
+    .transpose()
+    .map_err(boot_error)?;
+
+    let render = (local_resources.contains::<ScriptRender>()
+        && needs_boot(
+            Some(&running.render_new_fn),
+            Some(&main.render_new_fn),
+            affected,
+        ))
+    .then(|| render::boot(&main, resources))
+    .transpose()
+    .map_err(boot_error)?;
+
+    let input = (local_resources.contains::<ScriptInput>()
+        && needs_boot(
+            Some(&running.input_new_fn),
+            Some(&main.input_new_fn),
+            affected,
+        ))
+    .then(|| input::boot(&main, source_map))
+    .transpose()
+    .map_err(boot_error)?;
+
+    let audio = (local_resources.contains::<ScriptAudio>()
+        && needs_boot(
+            running.audio_new_fn.as_ref(),
+            main.audio_new_fn.as_ref(),
+            affected,
+        ))
+    .then(|| audio::boot(&main, resources))
+    .transpose()
+    .map_err(boot_error)?;
+
+    Ok(Staged {
+        main,
+        simulation,
+        render,
+        input,
+        flow: None,
+        audio,
+    })
+}
+
+/// Compiles all modules and boots every part
+fn stage_everything(
+    source_map: &mut SourceMapResource,
+    local_resources: &LocalResourceStorage,
+    resources: &mut ResourceStorage,
+) -> Result<Staged, (DiagnosticPhase, MangroveError)> {
+    let main = compile(source_map).map_err(|err| (DiagnosticPhase::Compile, err))?;
+    let boot_error = |err| (DiagnosticPhase::Boot, err);
+
+    let simulation = local_resources
+        .contains::<ScriptSimulation>()
+        .then(|| simulation::boot(&main, resources))
+        .transpose()
+        .map_err(boot_error)?;
+
+    let render = local_resources
+        .contains::<ScriptRender>()
+        .then(|| render::boot(&main, resources))
+        .transpose()
+        .map_err(boot_error)?;
+
+    let input = local_resources
+        .contains::<ScriptInput>()
+        .then(|| input::boot(&main, source_map))
+        .transpose()
+        .map_err(boot_error)?;
+
+    let flow = local_resources
+        .contains::<ScriptFlow>()
+        .then(|| flow::boot(&main))
+        .transpose()
+        .map_err(boot_error)?;
+
+    let audio = local_resources
+        .contains::<ScriptAudio>()
+        .then(|| audio::boot(&main, resources))
+        .transpose()
+        .map_err(boot_error)?;
+
+    Ok(Staged {
+        main,
+        simulation,
+        render,
+        input,
+        flow,
+        audio,
+    })
+}
+
+fn commit(
+    staged: Staged,
+    local_resources: &mut LocalResourceStorage,
+    resources: &mut ResourceStorage,
+) {
+    *local_resources.fetch_mut::<ScriptMain>() = staged.main;
+
+    if let Some(simulation) = staged.simulation {
+        local_resources
+            .fetch_mut::<ScriptSimulation>()
+            .replace(simulation);
+    }
+
+    if let Some(render) = staged.render {
+        *local_resources.fetch_mut::<ScriptRender>() = render;
+    }
+
+    if let Some(input) = staged.input {
+        // The Steam input reads the action sets from the config
+        resources.insert(convert_to_input_bindings(&input.sets));
+        *local_resources.fetch_mut::<ScriptInput>() = input;
+    }
+
+    // A new flow always starts over from the main screen
+    if let Some(flow) = staged.flow {
+        *local_resources.fetch_mut::<ScriptFlow>() = flow;
+    }
+
+    if let Some(audio) = staged.audio {
+        let mut previous_audio =
+            std::mem::replace(local_resources.fetch_mut::<ScriptAudio>(), audio);
+        if let Some(audio_output) = local_resources.get_mut::<AudioOutput>() {
+            previous_audio.stop_all(audio_output.backend_mut());
+        }
+    }
+}
+
+/// Compiles and boots the changed parts on `ScriptMessage::Reload`, and sends
+/// `ScriptMessage::Reloaded` if they were swapped in
+pub fn reload_tick(
+    mut script_messages: MsgM<ScriptMessage>,
+    mut local_resources: LoReAll,
+    mut source_map: ReM<SourceMapResource>,
+    mut all_resources: ReAll,
+    mut err: ReM<ErrorResource>,
+) {
+    let mut reload_requested = false;
+    {
+        let reload_state = local_resources.fetch_mut::<ReloadState>();
+        for msg in script_messages.iter_previous() {
+            if let ScriptMessage::Reload(changed_files) = msg {
+                reload_requested = true;
+                reload_state.add_changes(changed_files);
+            }
+        }
+    }
+    if !reload_requested {
+        return;
+    }
+
+    let reload_state = local_resources.fetch::<ReloadState>();
+    // After an error, the program is stopped and starts over with everything booted again
+    let only_changes =
+        reload_state.has_program && !reload_state.everything_changed && !err.has_errors();
+    let changed_modules = only_changes
+        .then(|| changed_modules(&source_map.source_map, &reload_state.changed_files))
+        .flatten();
+
+    if changed_modules.as_ref().is_some_and(Vec::is_empty) {
+        debug!("no scripts changed");
+        return;
+    }
+
+    let staged = changed_modules
+        .and_then(|changed_modules| {
+            stage_changed(
+                &changed_modules,
+                &mut source_map,
+                &local_resources,
+                &mut all_resources,
+            )
+        })
+        .unwrap_or_else(|| stage_everything(&mut source_map, &local_resources, &mut all_resources));
+
+    match staged {
+        Ok(staged) => {
+            let rebooted = staged.rebooted();
+            commit(staged, &mut local_resources, &mut all_resources);
+            let reload_state = local_resources.fetch_mut::<ReloadState>();
+            *reload_state = ReloadState {
+                has_program: true,
+                ..ReloadState::default()
+            };
+            err.clear();
+            script_messages.send(ScriptMessage::Reloaded(rebooted));
+            info!(?rebooted, "reloaded scripts");
+        }
+        Err((phase, mangrove_error)) => {
+            let previous_is_running =
+                local_resources.fetch::<ReloadState>().has_program && !err.has_errors();
+            if previous_is_running {
+                warn!("reload failed, the previous program keeps running");
+                report_failed_reload(&mut err, phase, &mangrove_error, &source_map.source_map);
+            } else {
+                // Nothing to fall back to, e.g. on the first load or after the program stopped
+                err.clear();
+                report_mangrove_error(&mut err, phase, &mangrove_error, &source_map.source_map);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_fixtures::with_stack;
+    use seq_map::SeqMap;
+    use std::fs;
+    use std::rc::Rc;
+    use swamp_script::prelude::{
+        AnonymousStructType, Constant, Expression, ExpressionKind, InternalFunctionDefinition,
+        NamedStructType, Node,
+    };
+
+    fn scripts_directory(name: &str) -> PathBuf {
+        let directory =
+            std::env::temp_dir().join(format!("mangrove-reload-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(directory.join("ui")).unwrap();
+        fs::write(directory.join("main.swamp"), "").unwrap();
+        fs::write(directory.join("ui/menu.swamp"), "").unwrap();
+        directory
+    }
+
+    fn source_map(scripts_path: &Path) -> SourceMap {
+        let mut mounts = SeqMap::new();
+        mounts
+            .insert("crate".to_string(), scripts_path.to_path_buf())
+            .unwrap();
+        SourceMap::new(&mounts).unwrap()
+    }
+
+    fn crate_module(path: &[&str]) -> Vec<String> {
+        let mut module_path = vec!["crate".to_string()];
+        module_path.extend(path.iter().map(ToString::to_string));
+        module_path
+    }
+
+    #[test]
+    fn changed_modules_are_the_crate_module_paths() {
+        let directory = scripts_directory("modules");
+        let source_map = source_map(&directory);
+
+        let changed = changed_modules(
+            &source_map,
+            &[
+                directory.join("main.swamp"),
+                directory.join("ui/menu.swamp"),
+                directory.join("ui/../main.swamp"),
+                // Removed files can not be canonicalized, but are still changes
+                directory.join("ui/removed.swamp"),
+            ],
+        );
+
+        assert_eq!(
+            changed,
+            Some(vec![
+                crate_module(&["main"]),
+                crate_module(&["ui", "menu"]),
+                crate_module(&["main"]),
+                crate_module(&["ui", "removed"]),
+            ])
+        );
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn changed_modules_skip_other_files() {
+        let directory = scripts_directory("other");
+        let source_map = source_map(&directory);
+
+        let changed = changed_modules(
+            &source_map,
+            &[directory.join("notes.txt"), directory.join("main")],
+        );
+
+        assert_eq!(changed, Some(vec![]));
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn changed_modules_outside_of_the_scripts_are_unknown() {
+        let directory = scripts_directory("outside");
+        let source_map = source_map(&directory);
+
+        let changed = changed_modules(
+            &source_map,
+            &[
+                directory.join("main.swamp"),
+                directory.join("../elsewhere.swamp"),
+            ],
+        );
+
+        assert_eq!(changed, None);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    fn creates(module: &str, struct_name: &str) -> InternalFunctionDefinitionRef {
+        let mut function = InternalFunctionDefinition::default();
+        function.signature.return_type = Box::new(Type::NamedStruct(NamedStructType {
+            name: Node::default(),
+            module_path: crate_module(&[module]),
+            assigned_name: struct_name.to_string(),
+            anon_struct_type: AnonymousStructType {
+                field_name_sorted_fields: SeqMap::new(),
+            },
+            instantiated_type_parameters: vec![],
+        }));
+        InternalFunctionDefinitionRef::new(function)
+    }
+
+    fn affected(modules: &[&str]) -> HashSet<Vec<String>> {
+        modules
+            .iter()
+            .map(|module| crate_module(&[module]))
+            .collect()
+    }
+
+    #[test]
+    fn boots_a_part_whose_struct_was_affected() {
+        let running = creates("logic", "Logic");
+        let compiled = creates("logic", "Logic");
+
+        assert!(needs_boot(
+            Some(&running),
+            Some(&compiled),
+            &affected(&["logic"])
+        ));
+        assert!(!needs_boot(
+            Some(&running),
+            Some(&compiled),
+            &affected(&["render"])
+        ));
+    }
+
+    #[test]
+    fn boots_a_part_that_creates_another_struct() {
+        let running = creates("logic", "Logic");
+
+        assert!(needs_boot(
+            Some(&running),
+            Some(&creates("logic", "Game")),
+            &affected(&[])
+        ));
+        assert!(needs_boot(
+            Some(&running),
+            Some(&creates("game", "Logic")),
+            &affected(&[])
+        ));
+    }
+
+    #[test]
+    fn boots_a_part_that_was_added_or_removed() {
+        let part = creates("audio", "Audio");
+
+        assert!(needs_boot(None, Some(&part), &affected(&[])));
+        assert!(needs_boot(Some(&part), None, &affected(&[])));
+        assert!(!needs_boot(None, None, &affected(&["audio"])));
+    }
+
+    #[test]
+    fn boots_a_part_that_does_not_create_a_struct() {
+        let running = InternalFunctionDefinitionRef::new(InternalFunctionDefinition::default());
+
+        assert!(needs_boot(
+            Some(&running),
+            Some(&creates("logic", "Logic")),
+            &affected(&[])
+        ));
+    }
+
+    fn with_constants(count: usize) -> ScriptMain {
+        let constant = Rc::new(Constant {
+            name: Node::default(),
+            assigned_name: "SPEED".to_string(),
+            id: 0,
+            expr: Expression {
+                ty: Type::Int,
+                node: Node::default(),
+                kind: ExpressionKind::Block(vec![]),
+            },
+            resolved_type: Type::Int,
+        });
+        let mut main = ScriptMain::default();
+        main.resolved_program.state.constants_in_dependency_order = vec![constant; count];
+        main
+    }
+
+    #[test]
+    fn compiles_everything_when_the_constants_have_piled_up() {
+        with_stack(|| {
+            assert!(!needs_full_compile(&with_constants(0)));
+            assert!(!needs_full_compile(&with_constants(
+                MAX_CONSTANTS_BEFORE_FULL_COMPILE
+            )));
+            assert!(needs_full_compile(&with_constants(
+                MAX_CONSTANTS_BEFORE_FULL_COMPILE + 1
+            )));
+        });
+    }
+}
@@ -2,24 +2,32 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
-use crate::err::show_mangrove_error;
+use crate::asset_status::{AssetLoadState, LoadedAssets, asset_status_tick};
+use crate::atlas::{AtlasFrame, AtlasSheet, NamedAtlas};
+use crate::call_sites::CallSites;
+use crate::contract::{RENDER_PARAMETER_COUNTS, required_struct, required_type};
+use crate::err::{DiagnosticPhase, report_mangrove_error, report_runtime_error};
+use crate::error_overlay::{draw_error_overlay, draw_failed_reload_banner};
+use crate::manifest::resource_asset_root;
 use crate::script::{
-    MangroveError, color_like, create_empty_struct_value, register_print, sprite_params,
-    uvec2_like, vec3_like,
+    MangroveError, animation_frame_like, color_like, create_empty_struct_value, register_print,
+    sprite_params, uvec2_like, vec3_like,
 };
 use crate::script_main::ScriptMain;
 use crate::simulation::ScriptSimulation;
+use crate::tilemap::{Tilemap, find_map_file};
+use crate::timestep::SimulationTimestep;
 use crate::util::get_impl_func;
-use crate::{ErrorResource, ScriptMessage, SourceMapResource};
+use crate::{ErrorResource, ScriptMessage, SourceMapResource, external_type_number};
 use monotonic_time_rs::Millis;
 use std::cell::RefCell;
-use std::env::current_dir;
 use std::fmt::{Debug, Display, Formatter};
+use std::path::Path;
 use std::rc::Rc;
 use swamp::prelude::{
-    App, Assets, Color, FixedAtlas, FontAndMaterial, FrameLookup, GameAssets, Gfx, LoRe, LoReM,
+    App, Assets, Color, FixedAtlas, FontAndMaterial, Fp, FrameLookup, GameAssets, Gfx, LoRe, LoReM,
     LocalResource, MaterialRef, Msg, Plugin, Re, ReAll, ReM, Render, RenderUpdate, ResourceStorage,
-    SpriteParams, UVec2, Update, Vec3, ViewportStrategy,
+    SpriteParams, URect, UVec2, Update, Vec3, ViewportStrategy,
 };
 use swamp_script::prelude::*;
 use tracing::{debug, error};
@@ -29,8 +37,15 @@ pub struct DisplaySettings {
     pub scale: u16,
 }
 
+impl Default for DisplaySettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl DisplaySettings {
-    pub fn new() -> Self {
+    #[must_use]
+    pub const fn new() -> Self {
         Self { scale: 0 }
     }
 
@@ -44,6 +59,8 @@ pub struct ScriptRenderContext {
     pub(crate) game_assets: Option<GameAssetsWrapper>,
     pub(crate) render: Option<RenderWrapper>,
     pub display: DisplaySettings,
+    /// The assets that the render struct has requested, for the load status functions in `Assets`
+    pub(crate) loaded_assets: LoadedAssets,
 }
 
 // I didn't want to change the implementation of GameAssets.
@@ -56,9 +73,8 @@ pub struct RenderWrapper {
 
 impl RenderWrapper {
     pub fn new(render: &mut Render) -> Self {
-        let ptr = render as *mut Render;
         Self {
-            render: ptr as *mut Render, // Coerce. is there a better way?
+            render: std::ptr::from_mut(render),
         }
     }
 
@@ -92,6 +108,7 @@ impl RenderWrapper {
         render.draw_quad(pos, size, color);
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub(crate) fn push_nine_slice(
         &self,
         pos: Vec3,
@@ -145,6 +162,16 @@ impl RenderWrapper {
         render.sprite_atlas_frame_ex(position, frame, atlas, params);
     }
 
+    pub fn sprite_atlas(&self, position: Vec3, atlas_rect: URect, material_ref: &MaterialRef) {
+        // Safety: We assume the Render pointer is still valid, since the RenderWrapper is short-lived (only alive during a render call)
+        let render: &mut Render;
+        unsafe {
+            render = &mut *self.render;
+        }
+
+        render.sprite_atlas(position, atlas_rect, material_ref);
+    }
+
     pub fn text_draw(&self, pos: Vec3, str: &str, material_ref: &FontAndMaterial, color: &Color) {
         // Safety: We assume the Render pointer is still valid, since the RenderWrapper is short-lived (only alive during a render call)
         let render: &mut Render;
@@ -194,6 +221,41 @@ impl Display for FixedAtlasWrapper {
     }
 }
 
+#[derive(Debug, PartialEq)]
+pub struct AtlasWrapper {
+    pub atlas: NamedAtlas,
+}
+
+impl QuickSerialize for AtlasWrapper {}
+
+impl Display for AtlasWrapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "atlas with {} frames", self.atlas.frames.len())
+    }
+}
+
+/// A Tiled map and the materials of its tilesets, in the same order as the tilesets.
+/// The material is missing for a tileset that could not be loaded, then its tiles are not drawn.
+#[derive(Debug, PartialEq)]
+pub struct TilemapWrapper {
+    pub tilemap: Tilemap,
+    pub materials: Vec<Option<MaterialRef>>,
+}
+
+impl QuickSerialize for TilemapWrapper {}
+
+impl Display for TilemapWrapper {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "tilemap {}x{} with {} tile layers",
+            self.tilemap.width,
+            self.tilemap.height,
+            self.tilemap.tile_layers.len()
+        )
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct FontAndMaterialWrapper {
     pub font_and_material: FontAndMaterial,
@@ -245,7 +307,7 @@ impl GameAssetsWrapper {
     ) -> Self {
         let ptr = game_assets as *mut GameAssets;
         Self {
-            game_assets: ptr as *mut GameAssets<'static>, // Coerce to 'static. is there a better way?
+            game_assets: ptr.cast::<GameAssets<'static>>(), // Coerce to 'static. is there a better way?
             struct_types,
             external_types,
         }
@@ -276,6 +338,25 @@ impl GameAssetsWrapper {
         )
     }
 
+    fn atlas_handle(&self, atlas: NamedAtlas) -> Value {
+        let wrapper = AtlasWrapper { atlas };
+        let atlas_ref = Rc::new(RefCell::new(Value::RustValue(
+            self.external_types.atlas.clone(),
+            Rc::new(RefCell::new(Box::new(wrapper))),
+        )));
+
+        Value::NamedStruct(self.struct_types.atlas.clone(), [atlas_ref].to_vec())
+    }
+
+    fn tilemap_handle(&self, tilemap: TilemapWrapper) -> Value {
+        let tilemap_ref = Rc::new(RefCell::new(Value::RustValue(
+            self.external_types.tilemap.clone(),
+            Rc::new(RefCell::new(Box::new(tilemap))),
+        )));
+
+        Value::NamedStruct(self.struct_types.tilemap.clone(), [tilemap_ref].to_vec())
+    }
+
     fn font_and_material_handle(&self, font_and_material: FontAndMaterial) -> Value {
         let wrapper = FontAndMaterialWrapper { font_and_material };
         let font_and_material_ref = Rc::new(RefCell::new(Value::RustValue(
@@ -290,13 +371,9 @@ impl GameAssetsWrapper {
     }
 
     #[must_use]
-    pub fn material_png(&self, name: &str) -> Value {
-        // Safety: We assume the GameAssets pointer is still valid, since the GameAssetsWrapper is short-lived (only alive during a tick)
-        let assets: &mut GameAssets;
-        unsafe {
-            assets = &mut *self.game_assets;
-        }
-        let material_ref = assets.material_png(name);
+    pub fn material_png(&self, name: &str, loaded_assets: &mut LoadedAssets) -> Value {
+        let material_ref = self.material_png_ref(name);
+        loaded_assets.add_material(&material_ref);
 
         self.material_handle(material_ref)
     }
@@ -307,6 +384,7 @@ impl GameAssetsWrapper {
         name: &str,
         grid_size: UVec2,
         texture_size: UVec2,
+        loaded_assets: &mut LoadedAssets,
     ) -> Value {
         // Safety: We assume the GameAssets pointer is still valid, since the GameAssetsWrapper is short-lived (only alive during a tick)
         let assets: &mut GameAssets;
@@ -314,12 +392,13 @@ impl GameAssetsWrapper {
             assets = &mut *self.game_assets;
         }
         let material_ref = assets.frame_fixed_grid_material_png(name, grid_size, texture_size);
+        loaded_assets.add_material(&material_ref.material);
 
         self.fixed_atlas_handle(material_ref)
     }
 
     #[must_use]
-    pub fn bm_font(&self, name: &str) -> Value {
+    pub fn bm_font(&self, name: &str, loaded_assets: &mut LoadedAssets) -> Value {
         let assets: &mut GameAssets;
         unsafe {
             // Safety: We assume the GameAssets pointer is still valid, since the GameAssetsWrapper is short-lived (only alive during a tick)
@@ -327,21 +406,179 @@ impl GameAssetsWrapper {
             assets = &mut *self.game_assets;
         }
         let font_and_material_ref = assets.bm_font(name);
+        loaded_assets.add_font(&font_and_material_ref.font_ref);
+        loaded_assets.add_material(&font_and_material_ref.material_ref);
 
         self.font_and_material_handle(font_and_material_ref)
     }
+
+    /// Reads the Aseprite or TexturePacker sheet `{name}.json` and starts loading its image.
+    /// A sheet that can not be read gives an atlas without frames, and is reported as a failed asset.
+    #[must_use]
+    pub fn atlas_json(&self, name: &str, loaded_assets: &mut LoadedAssets) -> Value {
+        let sheet_name = format!("{name}.json");
+        let sheet = std::fs::read_to_string(loaded_assets.asset_root().join(&sheet_name))
+            .map_err(|err| format!("could not read the atlas '{sheet_name}': {err}"))
+            .and_then(|text| {
+                AtlasSheet::from_json(&text)
+                    .map_err(|err| format!("could not parse the atlas '{sheet_name}': {err}"))
+            });
+
+        let atlas = match sheet {
+            Ok(sheet) => {
+                // The image is relative to the sheet, and material_png adds the extension
+                let image_path = Path::new(name)
+                    .parent()
+                    .unwrap_or_else(|| Path::new(""))
+                    .join(&sheet.image);
+                let material = if image_path.extension().is_some_and(|ext| ext == "png") {
+                    let material_ref = self.material_png_ref(
+                        &image_path
+                            .with_extension("")
+                            .to_string_lossy()
+                            .replace('\\', "/"),
+                    );
+                    loaded_assets.add_material(&material_ref);
+                    Some(material_ref)
+                } else {
+                    loaded_assets.add_failed_file(
+                        &sheet_name,
+                        &format!(
+                            "the atlas '{sheet_name}' uses the image '{}', only PNG images are supported",
+                            sheet.image
+                        ),
+                    );
+                    None
+                };
+
+                NamedAtlas {
+                    material,
+                    frames: sheet.frames,
+                    tags: sheet.tags,
+                }
+            }
+            Err(reason) => {
+                loaded_assets.add_failed_file(&sheet_name, &reason);
+                NamedAtlas {
+                    material: None,
+                    frames: Vec::new(),
+                    tags: Vec::new(),
+                }
+            }
+        };
+
+        self.atlas_handle(atlas)
+    }
+
+    /// Reads the Tiled map `{name}.tmx` or `{name}.tmj` and starts loading its tileset images.
+    /// A map that can not be read gives a map without layers, and is reported as a failed asset.
+    #[must_use]
+    pub fn tilemap(&self, name: &str, loaded_assets: &mut LoadedAssets) -> Value {
+        let map_name = find_map_file(loaded_assets.asset_root(), name)
+            .unwrap_or_else(|| format!("{name}.tmx"));
+        let tilemap = match Tilemap::load(loaded_assets.asset_root(), name) {
+            Ok(tilemap) => {
+                let materials = tilemap
+                    .tilesets
+                    .iter()
+                    .map(|tileset| match &tileset.image {
+                        Some(image) if image.ends_with(".png") => {
+                            let material_ref = self.material_png_ref(&image[..image.len() - 4]);
+                            loaded_assets.add_material(&material_ref);
+                            Some(material_ref)
+                        }
+                        Some(image) => {
+                            loaded_assets.add_failed_file(
+                                &map_name,
+                                &format!(
+                                    "the map '{map_name}' uses the image '{image}', only PNG images are supported"
+                                ),
+                            );
+                            None
+                        }
+                        None => {
+                            loaded_assets.add_failed_file(
+                                &map_name,
+                                &format!(
+                                    "the tileset '{}' in the map '{map_name}' is a collection of images, only tilesets with one image are supported",
+                                    tileset.name
+                                ),
+                            );
+                            None
+                        }
+                    })
+                    .collect();
+
+                TilemapWrapper { tilemap, materials }
+            }
+            Err(reason) => {
+                loaded_assets.add_failed_file(&map_name, &reason);
+                TilemapWrapper {
+                    tilemap: Tilemap {
+                        width: 0,
+                        height: 0,
+                        tile_width: 0,
+                        tile_height: 0,
+                        tilesets: Vec::new(),
+                        tile_layers: Vec::new(),
+                        object_layers: Vec::new(),
+                        properties: Vec::new(),
+                    },
+                    materials: Vec::new(),
+                }
+            }
+        };
+
+        self.tilemap_handle(tilemap)
+    }
+
+    fn material_png_ref(&self, name: &str) -> MaterialRef {
+        // Safety: We assume the GameAssets pointer is still valid, since the GameAssetsWrapper is short-lived (only alive during a tick)
+        let assets: &mut GameAssets;
+        unsafe {
+            assets = &mut *self.game_assets;
+        }
+        assets.material_png(name)
+    }
 }
 
 /// # Errors
 ///
 /// # Panics
 ///
+/// The `Assets` functions that load assets. A missing asset is reported where one of them is called.
+const ASSET_LOADING_FUNCTIONS: [&str; 5] = [
+    "material_png",
+    "bm_font",
+    "frame_fixed_grid_material_png",
+    "atlas_json",
+    "tilemap",
+];
+
+fn asset_call_sites(
+    symbol_table: &SymbolTable,
+    program: &Program,
+) -> Result<CallSites, MangroveError> {
+    let assets_struct = required_type(symbol_table, "Assets")?;
+    let function_ids: Vec<ExternalFunctionId> = ASSET_LOADING_FUNCTIONS
+        .iter()
+        .map(|name| {
+            program
+                .state
+                .associated_impls
+                .api_fetch_external_function_id(assets_struct, name)
+        })
+        .collect();
+
+    Ok(CallSites::find(program, &function_ids))
+}
+
 pub fn register_assets_members(
     symbol_table: &SymbolTable,
     associated_impls: &AssociatedImpls,
     externals: &mut ExternalFunctions<ScriptRenderContext>,
-) -> Result<(), String> {
-    let assets_struct = symbol_table.get_type("Assets").unwrap();
+) -> Result<(), MangroveError> {
+    let assets_struct = required_type(symbol_table, "Assets")?;
 
     // material_png
     let material_png_function_id =
@@ -353,12 +590,15 @@ pub fn register_assets_members(
             let params = convert_to_values(mem_values)
                 .expect("should only be passed values to material png function");
             let asset_name = &params[1].expect_string()?;
+            context
+                .loaded_assets
+                .set_requested_by(material_png_function_id, asset_name);
 
             Ok(context
                 .game_assets
                 .as_mut()
                 .unwrap()
-                .material_png(asset_name))
+                .material_png(asset_name, &mut context.loaded_assets))
         },
     )?;
 
@@ -372,8 +612,15 @@ pub fn register_assets_members(
             let params = convert_to_values(mem_values)
                 .expect("should only be passed values to material png function");
             let asset_name = &params[1].expect_string()?;
+            context
+                .loaded_assets
+                .set_requested_by(bm_font_function_id, asset_name);
 
-            Ok(context.game_assets.as_mut().unwrap().bm_font(asset_name))
+            Ok(context
+                .game_assets
+                .as_mut()
+                .unwrap()
+                .bm_font(asset_name, &mut context.loaded_assets))
         },
     )?;
 
@@ -387,6 +634,9 @@ pub fn register_assets_members(
                 .expect("should work to get only values to gfx functions");
             //let self_value = &params[0]; // Assets is, by design, an empty struct
             let asset_name = &params[1].expect_string()?;
+            context
+                .loaded_assets
+                .set_requested_by(frame_fixed_grid_material_png_function_id, asset_name);
             let grid_size = uvec2_like(&params[2])?;
             let texture_size = uvec2_like(&params[3])?; // TODO: Remove this parameter
 
@@ -394,7 +644,279 @@ pub fn register_assets_members(
                 .game_assets
                 .as_mut()
                 .unwrap()
-                .frame_fixed_grid_material_png(asset_name, grid_size, texture_size))
+                .frame_fixed_grid_material_png(
+                    asset_name,
+                    grid_size,
+                    texture_size,
+                    &mut context.loaded_assets,
+                ))
+        },
+    )?;
+
+    // atlas_json
+    let atlas_json_function_id =
+        associated_impls.api_fetch_external_function_id(assets_struct, "atlas_json");
+    externals.register_external_function(
+        atlas_json_function_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to atlas_json function");
+            let asset_name = &params[1].expect_string()?;
+            context
+                .loaded_assets
+                .set_requested_by(atlas_json_function_id, asset_name);
+
+            Ok(context
+                .game_assets
+                .as_mut()
+                .ok_or_else(assets_not_available)?
+                .atlas_json(asset_name, &mut context.loaded_assets))
+        },
+    )?;
+
+    // tilemap
+    let tilemap_function_id =
+        associated_impls.api_fetch_external_function_id(assets_struct, "tilemap");
+    externals.register_external_function(
+        tilemap_function_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to tilemap function");
+            let asset_name = &params[1].expect_string()?;
+            context
+                .loaded_assets
+                .set_requested_by(tilemap_function_id, asset_name);
+
+            Ok(context
+                .game_assets
+                .as_mut()
+                .ok_or_else(assets_not_available)?
+                .tilemap(asset_name, &mut context.loaded_assets))
+        },
+    )?;
+
+    register_asset_status_members(assets_struct, associated_impls, externals)?;
+
+    Ok(())
+}
+
+/// The assets can only be loaded while `render()` creates the render struct. The load status can be
+/// checked later, but nothing can be loaded.
+fn assets_not_available() -> ValueError {
+    ValueError::TypeError("Assets can only load assets in render()".to_string())
+}
+
+/// Draws every tile in the layer. The position is the lower left corner of the map.
+/// Nothing is drawn for an unknown layer, the same as for a material that is not loaded.
+fn draw_tilemap_layer(
+    render: &RenderWrapper,
+    position: Vec3,
+    tilemap: &TilemapWrapper,
+    layer_name: &str,
+) {
+    let Some(layer) = tilemap.tilemap.tile_layer(layer_name) else {
+        return;
+    };
+    let color = Color::from_f32(1.0, 1.0, 1.0, layer.opacity);
+    let tile_width = i32::from(tilemap.tilemap.tile_width);
+    let tile_height = i32::from(tilemap.tilemap.tile_height);
+
+    for (row, row_tiles) in layer.tiles.chunks(layer.width.max(1) as usize).enumerate() {
+        // The first row is the top of the map
+        let y = i32::from(position.y) + (layer.height as i32 - 1 - row as i32) * tile_height;
+        for (column, gid) in row_tiles.iter().enumerate() {
+            let Some(tile) = tilemap.tilemap.tile(*gid) else {
+                continue;
+            };
+            let Some(Some(material)) = tilemap.materials.get(tile.tileset) else {
+                continue;
+            };
+            let x = i32::from(position.x) + column as i32 * tile_width;
+            let (Ok(x), Ok(y)) = (i16::try_from(x), i16::try_from(y)) else {
+                continue;
+            };
+
+            render.push_sprite_ex(
+                Vec3::new(x, y, position.z),
+                material,
+                SpriteParams {
+                    texture_pos: tile.texture_pos,
+                    texture_size: tile.size,
+                    flip_x: tile.flip_x,
+                    flip_y: tile.flip_y,
+                    color,
+                    ..Default::default()
+                },
+            );
+        }
+    }
+}
+
+/// A trimmed frame is drawn where it was in the untrimmed frame
+fn trimmed_position(position: Vec3, frame: &AtlasFrame) -> Vec3 {
+    Vec3::new(
+        position.x + frame.offset.x as i16,
+        position.y + frame.offset.y as i16,
+        position.z,
+    )
+}
+
+fn string_vec(items: Vec<String>) -> Value {
+    Value::Vec(
+        Type::Slice(Box::new(Type::String)),
+        items
+            .into_iter()
+            .map(|item| Rc::new(RefCell::new(Value::String(item))))
+            .collect(),
+    )
+}
+
+/// The functions in `AtlasHandle`, to find the frames by name
+///
+/// # Errors
+///
+pub fn register_atlas_members(
+    symbol_table: &SymbolTable,
+    associated_impls: &AssociatedImpls,
+    externals: &mut ExternalFunctions<ScriptRenderContext>,
+) -> Result<(), MangroveError> {
+    let atlas_struct = required_type(symbol_table, "AtlasHandle")?;
+
+    // frame_names
+    let frame_names_function_id =
+        associated_impls.api_fetch_external_function_id(atlas_struct, "frame_names");
+    externals.register_external_function(
+        frame_names_function_id,
+        move |mem_values: &[VariableValue], _context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to frame_names function");
+            let atlas_ref = params[0].downcast_hidden_rust::<AtlasWrapper>().unwrap();
+            let names = atlas_ref
+                .borrow()
+                .atlas
+                .frames
+                .iter()
+                .map(|frame| frame.name.clone())
+                .collect();
+
+            Ok(string_vec(names))
+        },
+    )?;
+
+    // tag_frames
+    let tag_frames_function_id =
+        associated_impls.api_fetch_external_function_id(atlas_struct, "tag_frames");
+    externals.register_external_function(
+        tag_frames_function_id,
+        move |mem_values: &[VariableValue], _context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to tag_frames function");
+            let atlas_ref = params[0].downcast_hidden_rust::<AtlasWrapper>().unwrap();
+            let tag_name = params[1].expect_string()?;
+            let atlas = &atlas_ref.borrow().atlas;
+            let names = atlas.tag(&tag_name).map_or_else(Vec::new, |tag| {
+                atlas
+                    .tag_frames(tag)
+                    .iter()
+                    .map(|frame| frame.name.clone())
+                    .collect()
+            });
+
+            Ok(string_vec(names))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// The load status functions in `Assets`. They also work in `render()`, if the render struct has
+/// kept the `Assets` value, e.g. for a loading screen.
+fn register_asset_status_members(
+    assets_struct: &Type,
+    associated_impls: &AssociatedImpls,
+    externals: &mut ExternalFunctions<ScriptRenderContext>,
+) -> Result<(), MangroveError> {
+    // is_loaded
+    let is_loaded_function_id =
+        associated_impls.api_fetch_external_function_id(assets_struct, "is_loaded");
+    externals.register_external_function(
+        is_loaded_function_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to is_loaded function");
+            let material_ref = params[1].downcast_hidden_rust::<MaterialWrapper>().unwrap();
+            let state = context
+                .loaded_assets
+                .material_state(&material_ref.borrow().0);
+
+            Ok(Value::Bool(state == Some(AssetLoadState::Loaded)))
+        },
+    )?;
+
+    // is_atlas_loaded
+    let is_atlas_loaded_function_id =
+        associated_impls.api_fetch_external_function_id(assets_struct, "is_atlas_loaded");
+    externals.register_external_function(
+        is_atlas_loaded_function_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to is_atlas_loaded function");
+            let fixed_atlas_ref = params[1]
+                .downcast_hidden_rust::<FixedAtlasWrapper>()
+                .unwrap();
+            let state = context
+                .loaded_assets
+                .material_state(&fixed_atlas_ref.borrow().fixed_atlas.material);
+
+            Ok(Value::Bool(state == Some(AssetLoadState::Loaded)))
+        },
+    )?;
+
+    // is_font_loaded
+    let is_font_loaded_function_id =
+        associated_impls.api_fetch_external_function_id(assets_struct, "is_font_loaded");
+    externals.register_external_function(
+        is_font_loaded_function_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("should only be passed values to is_font_loaded function");
+            let font_and_material_ref = params[1]
+                .downcast_hidden_rust::<FontAndMaterialWrapper>()
+                .unwrap();
+            let font_and_material = &font_and_material_ref.borrow().font_and_material;
+            let font_state = context
+                .loaded_assets
+                .font_state(&font_and_material.font_ref);
+            let material_state = context
+                .loaded_assets
+                .material_state(&font_and_material.material_ref);
+
+            Ok(Value::Bool(
+                font_state == Some(AssetLoadState::Loaded)
+                    && material_state == Some(AssetLoadState::Loaded),
+            ))
+        },
+    )?;
+
+    // load_progress
+    let load_progress_function_id =
+        associated_impls.api_fetch_external_function_id(assets_struct, "load_progress");
+    externals.register_external_function(
+        load_progress_function_id,
+        move |_mem_values: &[VariableValue], context| {
+            Ok(Value::Float(Fp::from(
+                context.loaded_assets.load_progress(),
+            )))
+        },
+    )?;
+
+    // failed_assets
+    let failed_assets_function_id =
+        associated_impls.api_fetch_external_function_id(assets_struct, "failed_assets");
+    externals.register_external_function(
+        failed_assets_function_id,
+        move |_mem_values: &[VariableValue], context| {
+            Ok(string_vec(context.loaded_assets.failed_assets()))
         },
     )?;
 
@@ -410,8 +932,8 @@ pub fn register_gfx_members(
     symbol_table: &SymbolTable,
     associated_impls: &AssociatedImpls,
     externals: &mut ExternalFunctions<ScriptRenderContext>,
-) -> Result<(), String> {
-    let gfx_struct = symbol_table.get_type("Gfx").unwrap();
+) -> Result<(), MangroveError> {
+    let gfx_struct = required_type(symbol_table, "Gfx")?;
 
     // sprite
     let sprite_fn_id = associated_impls.api_fetch_external_function_id(gfx_struct, "sprite");
@@ -591,6 +1113,106 @@ pub fn register_gfx_members(
         },
     )?;
 
+    // animation
+    let animation_external_fn_id =
+        associated_impls.api_fetch_external_function_id(gfx_struct, "animation");
+    externals.register_external_function(
+        animation_external_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let position = vec3_like(&params[1])?;
+            let atlas_ref = params[2]
+                .downcast_hidden_rust::<FixedAtlasWrapper>()
+                .unwrap();
+
+            if let Some(frame) = animation_frame_like(&params[3])? {
+                context.render.as_mut().unwrap().sprite_atlas_frame(
+                    position,
+                    frame,
+                    &atlas_ref.as_ref().borrow().fixed_atlas,
+                );
+            }
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    // animation_named
+    let animation_named_external_fn_id =
+        associated_impls.api_fetch_external_function_id(gfx_struct, "animation_named");
+    externals.register_external_function(
+        animation_named_external_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let position = vec3_like(&params[1])?;
+            let atlas_ref = params[2].downcast_hidden_rust::<AtlasWrapper>().unwrap();
+
+            let atlas = &atlas_ref.borrow().atlas;
+            let frame = animation_frame_like(&params[3])?
+                .and_then(|frame| atlas.frames.get(usize::from(frame)));
+            if let (Some(material), Some(frame)) = (&atlas.material, frame) {
+                context.render.as_mut().unwrap().sprite_atlas(
+                    trimmed_position(position, frame),
+                    frame.rect,
+                    material,
+                );
+            }
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    // sprite_atlas_named
+    let sprite_atlas_named_external_fn_id =
+        associated_impls.api_fetch_external_function_id(gfx_struct, "sprite_atlas_named");
+    externals.register_external_function(
+        sprite_atlas_named_external_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let position = vec3_like(&params[1])?;
+            let atlas_ref = params[2].downcast_hidden_rust::<AtlasWrapper>().unwrap();
+            let frame_name = params[3].expect_string()?;
+
+            let atlas = &atlas_ref.borrow().atlas;
+            // Nothing is drawn for an unknown frame, the same as for a material that is not loaded
+            if let (Some(material), Some(frame)) = (&atlas.material, atlas.frame(&frame_name)) {
+                context.render.as_mut().unwrap().sprite_atlas(
+                    trimmed_position(position, frame),
+                    frame.rect,
+                    material,
+                );
+            }
+
+            Ok(Value::Unit)
+        },
+    )?;
+
+    // tilemap_layer
+    let tilemap_layer_external_fn_id =
+        associated_impls.api_fetch_external_function_id(gfx_struct, "tilemap_layer");
+    externals.register_external_function(
+        tilemap_layer_external_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let position = vec3_like(&params[1])?;
+            let tilemap_ref = params[2].downcast_hidden_rust::<TilemapWrapper>().unwrap();
+            let layer_name = params[3].expect_string()?;
+
+            draw_tilemap_layer(
+                context.render.as_ref().unwrap(),
+                position,
+                &tilemap_ref.borrow(),
+                &layer_name,
+            );
+
+            Ok(Value::Unit)
+        },
+    )?;
+
     // set_virtual_resolution
     let set_virtual_resolution_external_fn_id =
         associated_impls.api_fetch_external_function_id(gfx_struct, "set_virtual_resolution");
@@ -634,11 +1256,16 @@ pub fn register_gfx_members(
 pub struct ScriptRender {
     render_value_ref: ValueRef,
     render_fn: InternalFunctionDefinitionRef,
+    interpolated: bool,
     externals: ExternalFunctions<ScriptRenderContext>,
     display_settings: DisplaySettings,
     gfx_struct_ref: ValueRef,
+    loaded_assets: LoadedAssets,
 }
 
+/// `render(mut self, previous: Simulation, current: Simulation, alpha: Float, mut gfx: Gfx)`
+const INTERPOLATED_RENDER_PARAMETER_COUNT: usize = 5;
+
 impl ScriptRender {
     /// # Errors
     ///
@@ -648,48 +1275,87 @@ impl ScriptRender {
         externals: ExternalFunctions<ScriptRenderContext>,
         impls: &AssociatedImpls,
         gfx_struct_ref: ValueRef,
+        loaded_assets: LoadedAssets,
     ) -> Result<Self, MangroveError> {
-        let render_fn = get_impl_func(impls, render_struct_type_ref, "render");
+        let render_fn = get_impl_func(
+            impls,
+            render_struct_type_ref,
+            "render",
+            &RENDER_PARAMETER_COUNTS,
+        )?;
+        let interpolated =
+            render_fn.signature.parameters.len() == INTERPOLATED_RENDER_PARAMETER_COUNT;
 
         Ok(Self {
             render_value_ref,
             render_fn,
+            interpolated,
             externals,
             display_settings: DisplaySettings::new(),
             gfx_struct_ref,
+            loaded_assets,
         })
     }
 
+    /// The assets that the render struct loaded when it was created
+    #[must_use]
+    pub const fn loaded_assets(&self) -> &LoadedAssets {
+        &self.loaded_assets
+    }
+
+    pub const fn loaded_assets_mut(&mut self) -> &mut LoadedAssets {
+        &mut self.loaded_assets
+    }
+
+    /// Calls `render()` on the render struct. If it takes both the previous and the current
+    /// simulation value, it also gets the `alpha` to interpolate between them.
+    ///
     /// # Errors
     ///
     pub fn render(
         &mut self,
         script_main: &ScriptMain,
         wgpu_render: &mut Render,
+        previous_simulation_value: &Value,
         simulation_value_ref: &Value,
+        alpha: f32,
         _source_map_wrapper: &SourceMapWrapper,
     ) -> Result<(), RuntimeError> {
         let mut script_context = ScriptRenderContext {
             game_assets: None,
             render: Some(RenderWrapper::new(wgpu_render)),
             display: DisplaySettings::new(),
+            loaded_assets: std::mem::take(&mut self.loaded_assets),
         };
 
         let self_mut_ref = VariableValue::Reference(self.render_value_ref.clone());
 
-        util_execute_function(
-            &self.externals,
-            &script_main.constants,
-            &self.render_fn,
-            [
+        let arguments = if self.interpolated {
+            vec![
+                self_mut_ref,
+                VariableValue::Value(previous_simulation_value.clone()),
+                VariableValue::Value(simulation_value_ref.clone()),
+                VariableValue::Value(Value::Float(Fp::from(alpha))),
+                VariableValue::Reference(self.gfx_struct_ref.clone()),
+            ]
+        } else {
+            vec![
                 self_mut_ref, //   self.render_value_ref.clone()
                 VariableValue::Value(simulation_value_ref.clone()),
                 VariableValue::Reference(self.gfx_struct_ref.clone()),
             ]
-            .as_ref(),
+        };
+
+        let result = util_execute_function(
+            &self.externals,
+            &script_main.constants,
+            &self.render_fn,
+            &arguments,
             &mut script_context,
             None,
-        )?;
+        );
+        self.loaded_assets = script_context.loaded_assets;
+        result?;
 
         self.display_settings = script_context.display;
 
@@ -701,6 +1367,8 @@ impl ScriptRender {
 pub struct HandleNamedStructTypes {
     pub material: NamedStructType,
     pub fixed_atlas: NamedStructType,
+    pub atlas: NamedStructType,
+    pub tilemap: NamedStructType,
     pub font_and_material: NamedStructType,
     pub assets: NamedStructType,
     pub gfx: NamedStructType,
@@ -722,7 +1390,11 @@ pub fn boot(
         .resolved_program
         .modules
         .get(mangrove_render_module_path)
-        .unwrap();
+        .ok_or_else(|| {
+            MangroveError::Other(
+                "render function requires the mangrove::render package".to_string(),
+            )
+        })?;
     let impls = &script_main.resolved_program.state.associated_impls;
 
     register_print(
@@ -742,9 +1414,20 @@ pub fn boot(
         &mut external_functions,
     )?;
 
-    let handle_named_types = get_handle_types(&mangrove_render_module.symbol_table);
+    register_atlas_members(
+        &mangrove_render_module.symbol_table,
+        impls,
+        &mut external_functions,
+    )?;
+
+    let handle_named_types = get_handle_types(&mangrove_render_module.symbol_table)?;
     let handle_external_types = create_external_types();
 
+    let asset_root = resource_asset_root(resource_storage);
+    let call_sites = asset_call_sites(
+        &mangrove_render_module.symbol_table,
+        &script_main.resolved_program,
+    )?;
     let mut game_assets = GameAssets::new(resource_storage, Millis::new(0));
 
     let mut script_context = ScriptRenderContext {
@@ -755,6 +1438,7 @@ pub fn boot(
         )),
         render: None,
         display: DisplaySettings::new(),
+        loaded_assets: LoadedAssets::new(asset_root, call_sites),
     };
 
     let mut constants = Constants::new();
@@ -799,6 +1483,7 @@ pub fn boot(
         external_functions,
         impls,
         gfx_value_mut,
+        script_context.loaded_assets,
     )
 }
 
@@ -807,69 +1492,64 @@ pub struct ExternalTypes {
     pub material: ExternalType,
     pub font_and_material: ExternalType,
     pub fixed_atlas: ExternalType,
+    pub atlas: ExternalType,
+    pub tilemap: ExternalType,
 }
 
 fn create_external_types() -> ExternalTypes {
     let material = ExternalType {
         type_name: "MaterialHandle".to_string(),
-        number: 91,
+        number: external_type_number::MATERIAL_HANDLE,
     };
 
     let font_and_material = ExternalType {
         type_name: "FontAndMaterial".to_string(),
-        number: 92,
+        number: external_type_number::FONT_AND_MATERIAL,
     };
 
     let fixed_atlas = ExternalType {
         type_name: "FixedAtlas".to_string(),
-        number: 93,
+        number: external_type_number::FIXED_ATLAS,
+    };
+
+    let atlas = ExternalType {
+        type_name: "Atlas".to_string(),
+        number: external_type_number::ATLAS,
+    };
+
+    let tilemap = ExternalType {
+        type_name: "Tilemap".to_string(),
+        number: external_type_number::TILEMAP,
     };
 
     ExternalTypes {
         material,
         font_and_material,
         fixed_atlas,
+        atlas,
+        tilemap,
     }
 }
 
-fn get_handle_types(mangrove_render_symbol_table: &SymbolTable) -> HandleNamedStructTypes {
-    let font_and_material = mangrove_render_symbol_table
-        .get_struct("FontAndMaterialHandle")
-        .unwrap()
-        .clone();
-    let fixed_atlas = mangrove_render_symbol_table
-        .get_struct("FixedAtlasHandle")
-        .unwrap()
-        .clone();
-    let material = mangrove_render_symbol_table
-        .get_struct("MaterialHandle")
-        .unwrap()
-        .clone();
-
-    let assets = mangrove_render_symbol_table
-        .get_struct("Assets")
-        .unwrap()
-        .clone();
-
-    let gfx = mangrove_render_symbol_table
-        .get_struct("Gfx")
-        .unwrap()
-        .clone();
-
-    HandleNamedStructTypes {
-        material,
-        fixed_atlas,
-        font_and_material,
-        assets,
-        gfx,
-    }
+fn get_handle_types(
+    mangrove_render_symbol_table: &SymbolTable,
+) -> Result<HandleNamedStructTypes, MangroveError> {
+    Ok(HandleNamedStructTypes {
+        material: required_struct(mangrove_render_symbol_table, "MaterialHandle")?,
+        fixed_atlas: required_struct(mangrove_render_symbol_table, "FixedAtlasHandle")?,
+        atlas: required_struct(mangrove_render_symbol_table, "AtlasHandle")?,
+        tilemap: required_struct(mangrove_render_symbol_table, "TilemapHandle")?,
+        font_and_material: required_struct(mangrove_render_symbol_table, "FontAndMaterialHandle")?,
+        assets: required_struct(mangrove_render_symbol_table, "Assets")?,
+        gfx: required_struct(mangrove_render_symbol_table, "Gfx")?,
+    })
 }
 
 /// # Panics
 ///
 pub fn update_screen_resolution_tick(
-    mut script: LoReM<ScriptRender>,
-    mut wgpu_render: ReM<Render>,
+    script: LoRe<ScriptRender>,
+    wgpu_render: Re<Render>,
     mut window_settings: ReM<limnus_screen::Window>,
 ) {
     if script.display_settings.scale != 0 {
@@ -884,26 +1564,40 @@ pub fn render_tick(
     script_main: LoRe<ScriptMain>,
     mut script: LoReM<ScriptRender>,
     simulation: LoRe<ScriptSimulation>,
+    timestep: Re<SimulationTimestep>,
     mut wgpu_render: ReM<Render>,
 
     mut error: ReM<ErrorResource>,
     source_map: Re<SourceMapResource>,
 ) {
-    if error.has_errors {
-        return;
+    if !error.has_errors() {
+        let _ = script
+            .render(
+                &script_main,
+                &mut wgpu_render,
+                simulation.previous_simulation_value(),
+                &simulation.immutable_simulation_value(),
+                timestep.alpha(),
+                &source_map.wrapper(),
+            )
+            .inspect_err(|runtime_err| {
+                report_runtime_error(
+                    &mut error,
+                    DiagnosticPhase::Render,
+                    runtime_err,
+                    &source_map.source_map,
+                );
+            });
+    }
+
+    if error.has_errors() {
+        draw_error_overlay(&mut wgpu_render, &error.diagnostics);
+    } else if let Some(failed_reload) = &error.failed_reload {
+        draw_failed_reload_banner(&mut wgpu_render, failed_reload);
+    } else if !error.diagnostics.is_empty() {
+        // Only warnings, the game keeps running underneath
+        draw_error_overlay(&mut wgpu_render, &error.diagnostics);
     }
-    script
-        .render(
-            &script_main,
-            &mut wgpu_render,
-            &simulation.immutable_simulation_value(),
-            &source_map.wrapper(),
-        )
-        .inspect_err(|runtime_err| {
-            error.has_errors = true;
-            let current_path = &*current_dir().unwrap();
-            show_runtime_error(runtime_err, &source_map.source_map, &current_path)
-        });
 }
 
 pub fn detect_reload_tick(
@@ -914,17 +1608,22 @@ pub fn detect_reload_tick(
     mut all_resources: ReAll,
     mut err: ReM<ErrorResource>,
 ) {
-    if err.has_errors {
+    if err.has_errors() {
         return;
     }
     for msg in script_messages.iter_previous() {
         match msg {
-            ScriptMessage::Reload => match boot(&script_main, &mut all_resources) {
+            // Reloads are booted together with the other plugins, see `reload::reload_tick`
+            ScriptMessage::Reload(_) | ScriptMessage::Reloaded(_) => {}
+            ScriptMessage::ScreenChanged => match boot(&script_main, &mut all_resources) {
                 Ok(new_render) => *script_render = new_render,
                 Err(mangrove_error) => {
-                    err.has_errors = true;
-                    show_mangrove_error(&mangrove_error, &source_map.source_map);
-                    eprintln!("script render failed: {mangrove_error}");
+                    report_mangrove_error(
+                        &mut err,
+                        DiagnosticPhase::Boot,
+                        &mangrove_error,
+                        &source_map.source_map,
+                    );
                     error!(error=?mangrove_error, "script render failed");
                 }
             },
@@ -939,6 +1638,7 @@ impl Plugin for ScriptRenderPlugin {
         app.add_system(Update, detect_reload_tick);
         app.add_system(Update, update_screen_resolution_tick);
         app.add_system(RenderUpdate, render_tick);
+        app.add_system(Update, asset_status_tick);
         // HACK: Just add a completely zeroed out ScriptRender and wait for reload message.
         // TODO: Should not try to call updates with params that are not available yet.
         app.insert_local_resource(ScriptRender {
@@ -959,9 +1659,11 @@ impl Plugin for ScriptRenderPlugin {
                 function_scope_state: Vec::default(),
                 program_unique_id: 0,
             }),
+            interpolated: false,
             externals: ExternalFunctions::new(),
             display_settings: DisplaySettings::new(),
             gfx_struct_ref: Rc::new(RefCell::new(Value::default())),
+            loaded_assets: LoadedAssets::default(),
         });
     }
 }
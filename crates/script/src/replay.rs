@@ -0,0 +1,724 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Records the gamepad, keyboard and mouse input that reaches the simulation, together with the simulation
+//! tick it arrived on, so it can be fed back in the exact same order later.
+//! The replay ticks the simulation in lockstep, once per update, so it does not depend on the frame rate.
+//! The action sets are not recorded, they are set from the recorded keys, mouse buttons and gamepads.
+//!
+//! File format (little endian):
+//! - magic `MGIR` and a `u16` version
+//! - events until end of file: `u64` tick, `u8` kind, followed by the payload for that kind
+use crate::bindings::{InputDevices, key_code_from_name, key_name, update_action_sets};
+use crate::input::{MouseState, ScriptInput};
+use crate::script_main::ScriptMain;
+use crate::simulation::{ScriptSimulation, report_callback_error};
+use crate::timestep::SimulationTimestep;
+use crate::{ErrorResource, InputSource, SourceMapResource};
+use limnus_basic_input::InputMessage;
+use limnus_basic_input::prelude::{ButtonState, KeyCode, MouseButton};
+use limnus_gamepad::{
+    Axis, AxisValueType, Button, ButtonValueType, GamePadId, GamepadMessage, Gamepads,
+};
+use limnus_message::Messages;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+use swamp::prelude::{
+    App, LoRe, LoReM, LocalResource, Msg, Plugin, PostUpdate, PreUpdate, Re, ReM, Update,
+};
+use tracing::{error, info, warn};
+
+pub const INPUT_RECORDING_VERSION: u16 = 2;
+const MAGIC: [u8; 4] = *b"MGIR";
+
+const KIND_GAMEPAD_CONNECTED: u8 = 0;
+const KIND_GAMEPAD_DISCONNECTED: u8 = 1;
+const KIND_GAMEPAD_ACTIVATED: u8 = 2;
+const KIND_GAMEPAD_BUTTON_CHANGED: u8 = 3;
+const KIND_GAMEPAD_AXIS_CHANGED: u8 = 4;
+const KIND_MOUSE: u8 = 5;
+const KIND_KEY: u8 = 6;
+const KIND_MOUSE_BUTTON: u8 = 7;
+
+const BUTTONS: [Button; 17] = [
+    Button::South,
+    Button::East,
+    Button::North,
+    Button::West,
+    Button::LeftTrigger,
+    Button::LeftTrigger2,
+    Button::RightTrigger,
+    Button::RightTrigger2,
+    Button::Select,
+    Button::Start,
+    Button::Mode,
+    Button::LeftThumb,
+    Button::RightThumb,
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+];
+
+const MOUSE_BUTTONS: [MouseButton; 5] = [
+    MouseButton::Left,
+    MouseButton::Right,
+    MouseButton::Middle,
+    MouseButton::Back,
+    MouseButton::Forward,
+];
+
+const AXES: [Axis; 4] = [
+    Axis::LeftStickX,
+    Axis::LeftStickY,
+    Axis::RightStickX,
+    Axis::RightStickY,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordedInput {
+    GamepadConnected(GamePadId, String),
+    GamepadDisconnected(GamePadId),
+    GamepadActivated(GamePadId),
+    GamepadButtonChanged(GamePadId, Button, ButtonValueType),
+    GamepadAxisChanged(GamePadId, Axis, AxisValueType),
+    /// The complete mouse state, only recorded when it has changed
+    Mouse(MouseState),
+    /// A keyboard key was pressed (`true`) or released
+    Key(KeyCode, bool),
+    /// A mouse button was pressed (`true`) or released, for the action sets
+    MouseButton(MouseButton, bool),
+}
+
+impl RecordedInput {
+    #[must_use]
+    pub fn from_gamepad_message(msg: &GamepadMessage) -> Self {
+        match msg {
+            GamepadMessage::Connected(id, name) => Self::GamepadConnected(*id, name.clone()),
+            GamepadMessage::Disconnected(id) => Self::GamepadDisconnected(*id),
+            GamepadMessage::Activated(id) => Self::GamepadActivated(*id),
+            GamepadMessage::ButtonChanged(id, button, value) => {
+                Self::GamepadButtonChanged(*id, *button, *value)
+            }
+            GamepadMessage::AxisChanged(id, axis, value) => {
+                Self::GamepadAxisChanged(*id, *axis, *value)
+            }
+        }
+    }
+
+    #[must_use]
+    pub fn to_gamepad_message(&self) -> Option<GamepadMessage> {
+        match self {
+            Self::GamepadConnected(id, name) => Some(GamepadMessage::Connected(*id, name.clone())),
+            Self::GamepadDisconnected(id) => Some(GamepadMessage::Disconnected(*id)),
+            Self::GamepadActivated(id) => Some(GamepadMessage::Activated(*id)),
+            Self::GamepadButtonChanged(id, button, value) => {
+                Some(GamepadMessage::ButtonChanged(*id, *button, *value))
+            }
+            Self::GamepadAxisChanged(id, axis, value) => {
+                Some(GamepadMessage::AxisChanged(*id, *axis, *value))
+            }
+            Self::Mouse(_) | Self::Key(..) | Self::MouseButton(..) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedEvent {
+    pub tick: u64,
+    pub input: RecordedInput,
+}
+
+fn invalid_data(description: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, description)
+}
+
+fn gamepad_id_to_u32(id: GamePadId) -> io::Result<u32> {
+    u32::try_from(id).map_err(|_| invalid_data("gamepad id is too large"))
+}
+
+/// # Errors
+///
+pub fn write_header(writer: &mut impl Write) -> io::Result<()> {
+    writer.write_all(&MAGIC)?;
+    writer.write_all(&INPUT_RECORDING_VERSION.to_le_bytes())
+}
+
+/// The buttons and axes are stored as the position in the tables above, so the recording does not
+/// depend on how the enums are numbered
+fn position_in<T: PartialEq>(known: &[T], value: &T, unknown: &str) -> io::Result<u8> {
+    known
+        .iter()
+        .position(|candidate| candidate == value)
+        .map(|index| index as u8)
+        .ok_or_else(|| invalid_data(unknown))
+}
+
+/// # Errors
+///
+pub fn write_event(writer: &mut impl Write, event: &RecordedEvent) -> io::Result<()> {
+    writer.write_all(&event.tick.to_le_bytes())?;
+    match &event.input {
+        RecordedInput::GamepadConnected(id, name) => {
+            writer.write_all(&[KIND_GAMEPAD_CONNECTED])?;
+            writer.write_all(&gamepad_id_to_u32(*id)?.to_le_bytes())?;
+            let name_len =
+                u16::try_from(name.len()).map_err(|_| invalid_data("gamepad name is too long"))?;
+            writer.write_all(&name_len.to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+        }
+        RecordedInput::GamepadDisconnected(id) => {
+            writer.write_all(&[KIND_GAMEPAD_DISCONNECTED])?;
+            writer.write_all(&gamepad_id_to_u32(*id)?.to_le_bytes())?;
+        }
+        RecordedInput::GamepadActivated(id) => {
+            writer.write_all(&[KIND_GAMEPAD_ACTIVATED])?;
+            writer.write_all(&gamepad_id_to_u32(*id)?.to_le_bytes())?;
+        }
+        RecordedInput::GamepadButtonChanged(id, button, value) => {
+            writer.write_all(&[KIND_GAMEPAD_BUTTON_CHANGED])?;
+            writer.write_all(&gamepad_id_to_u32(*id)?.to_le_bytes())?;
+            writer.write_all(&[position_in(&BUTTONS, button, "unknown gamepad button")?])?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        RecordedInput::GamepadAxisChanged(id, axis, value) => {
+            writer.write_all(&[KIND_GAMEPAD_AXIS_CHANGED])?;
+            writer.write_all(&gamepad_id_to_u32(*id)?.to_le_bytes())?;
+            writer.write_all(&[position_in(&AXES, axis, "unknown gamepad axis")?])?;
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        RecordedInput::Mouse(mouse_state) => {
+            writer.write_all(&[KIND_MOUSE])?;
+            writer.write_all(&mouse_state.cursor_position.0.to_le_bytes())?;
+            writer.write_all(&mouse_state.cursor_position.1.to_le_bytes())?;
+            writer.write_all(&[
+                u8::from(mouse_state.left_button),
+                u8::from(mouse_state.right_button),
+            ])?;
+        }
+        RecordedInput::Key(key_code, pressed) => {
+            // The name is stored, so the recording does not depend on the order of the key codes
+            let name = key_name(*key_code);
+            writer.write_all(&[KIND_KEY, u8::from(*pressed)])?;
+            let name_len =
+                u8::try_from(name.len()).map_err(|_| invalid_data("key name is too long"))?;
+            writer.write_all(&[name_len])?;
+            writer.write_all(name.as_bytes())?;
+        }
+        RecordedInput::MouseButton(mouse_button, pressed) => {
+            let index = position_in(&MOUSE_BUTTONS, mouse_button, "unknown mouse button")?;
+            writer.write_all(&[KIND_MOUSE_BUTTON, index, u8::from(*pressed)])?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut octets = [0u8; N];
+    reader.read_exact(&mut octets)?;
+    Ok(octets)
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    Ok(read_array::<1>(reader)?[0])
+}
+
+fn read_gamepad_id(reader: &mut impl Read) -> io::Result<GamePadId> {
+    Ok(u32::from_le_bytes(read_array(reader)?) as GamePadId)
+}
+
+fn read_event(reader: &mut impl Read) -> io::Result<Option<RecordedEvent>> {
+    let mut tick_octets = [0u8; 8];
+    match reader.read_exact(&mut tick_octets) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(err) => return Err(err),
+    }
+    let tick = u64::from_le_bytes(tick_octets);
+
+    let input = match read_u8(reader)? {
+        KIND_GAMEPAD_CONNECTED => {
+            let id = read_gamepad_id(reader)?;
+            let name_len = u16::from_le_bytes(read_array(reader)?);
+            let mut name_octets = vec![0u8; name_len as usize];
+            reader.read_exact(&mut name_octets)?;
+            let name = String::from_utf8(name_octets)
+                .map_err(|_| invalid_data("gamepad name is not utf8"))?;
+            RecordedInput::GamepadConnected(id, name)
+        }
+        KIND_GAMEPAD_DISCONNECTED => RecordedInput::GamepadDisconnected(read_gamepad_id(reader)?),
+        KIND_GAMEPAD_ACTIVATED => RecordedInput::GamepadActivated(read_gamepad_id(reader)?),
+        KIND_GAMEPAD_BUTTON_CHANGED => {
+            let id = read_gamepad_id(reader)?;
+            let button = *BUTTONS
+                .get(read_u8(reader)? as usize)
+                .ok_or_else(|| invalid_data("unknown gamepad button"))?;
+            let value = f32::from_le_bytes(read_array(reader)?);
+            RecordedInput::GamepadButtonChanged(id, button, value)
+        }
+        KIND_GAMEPAD_AXIS_CHANGED => {
+            let id = read_gamepad_id(reader)?;
+            let axis = *AXES
+                .get(read_u8(reader)? as usize)
+                .ok_or_else(|| invalid_data("unknown gamepad axis"))?;
+            let value = f32::from_le_bytes(read_array(reader)?);
+            RecordedInput::GamepadAxisChanged(id, axis, value)
+        }
+        KIND_MOUSE => {
+            let x = i32::from_le_bytes(read_array(reader)?);
+            let y = i32::from_le_bytes(read_array(reader)?);
+            let [left_button, right_button] = read_array(reader)?;
+            RecordedInput::Mouse(MouseState {
+                cursor_position: (x, y),
+                left_button: left_button != 0,
+                right_button: right_button != 0,
+            })
+        }
+        KIND_KEY => {
+            let pressed = read_u8(reader)? != 0;
+            let mut name_octets = vec![0u8; read_u8(reader)? as usize];
+            reader.read_exact(&mut name_octets)?;
+            let key_code = std::str::from_utf8(&name_octets)
+                .ok()
+                .and_then(key_code_from_name)
+                .ok_or_else(|| invalid_data("unknown key"))?;
+            RecordedInput::Key(key_code, pressed)
+        }
+        KIND_MOUSE_BUTTON => {
+            let mouse_button = *MOUSE_BUTTONS
+                .get(read_u8(reader)? as usize)
+                .ok_or_else(|| invalid_data("unknown mouse button"))?;
+            RecordedInput::MouseButton(mouse_button, read_u8(reader)? != 0)
+        }
+        _ => return Err(invalid_data("unknown input event kind")),
+    };
+
+    Ok(Some(RecordedEvent { tick, input }))
+}
+
+/// # Errors
+///
+/// If the header is wrong, the version is not supported or if the events can not be read.
+pub fn read_recording(reader: &mut impl Read) -> io::Result<Vec<RecordedEvent>> {
+    if read_array::<4>(reader)? != MAGIC {
+        return Err(invalid_data("not a mangrove input recording"));
+    }
+
+    let version = u16::from_le_bytes(read_array(reader)?);
+    if version != INPUT_RECORDING_VERSION {
+        return Err(invalid_data(&format!(
+            "unsupported input recording version {version}, expected {INPUT_RECORDING_VERSION}"
+        )));
+    }
+
+    let mut events = Vec::new();
+    while let Some(event) = read_event(reader)? {
+        events.push(event);
+    }
+
+    Ok(events)
+}
+
+/// Writes all input to the file as it arrives, so the recording survives a crash.
+#[derive(LocalResource, Debug)]
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    tick: u64,
+    last_mouse_state: Option<MouseState>,
+}
+
+impl InputRecorder {
+    /// # Errors
+    ///
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_header(&mut writer)?;
+
+        Ok(Self {
+            writer,
+            tick: 0,
+            last_mouse_state: None,
+        })
+    }
+
+    fn record(&mut self, input: RecordedInput) {
+        let event = RecordedEvent {
+            tick: self.tick,
+            input,
+        };
+        if let Err(err) = write_event(&mut self.writer, &event) {
+            error!(?err, "could not record input event");
+        }
+    }
+}
+
+/// Feeds the recorded input back to the simulation, on the same ticks it was recorded on.
+#[derive(LocalResource, Debug)]
+pub struct InputReplayer {
+    events: Vec<RecordedEvent>,
+    next_event_index: usize,
+    tick: u64,
+    /// The recorded gamepads, that the action sets are set from instead of the live gamepads
+    gamepads: Gamepads,
+}
+
+impl InputReplayer {
+    /// # Errors
+    ///
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let events = read_recording(&mut BufReader::new(File::open(path)?))?;
+        Ok(Self::new(events))
+    }
+
+    #[must_use]
+    pub fn new(events: Vec<RecordedEvent>) -> Self {
+        Self {
+            events,
+            next_event_index: 0,
+            tick: 0,
+            gamepads: Gamepads::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn is_done(&self) -> bool {
+        self.next_event_index >= self.events.len()
+    }
+
+    fn current_tick_range(&self) -> Range<usize> {
+        let start = self.next_event_index;
+        let count = self.events[start..]
+            .iter()
+            .take_while(|event| event.tick <= self.tick)
+            .count();
+        start..start + count
+    }
+
+    fn events_for_current_tick(&self) -> &[RecordedEvent] {
+        &self.events[self.current_tick_range()]
+    }
+
+    /// Keeps the recorded gamepads up to date, the same way as the live gamepads are
+    fn apply_to_gamepads(&mut self) {
+        // Only the gamepad state is needed, the simulation gets the messages from the recording
+        let mut unused_messages = Messages::new();
+        for event in &self.events[self.current_tick_range()] {
+            match &event.input {
+                RecordedInput::GamepadConnected(id, name) => {
+                    self.gamepads.connected(*id, name, &mut unused_messages);
+                }
+                RecordedInput::GamepadDisconnected(id) => {
+                    self.gamepads.disconnected(*id, &mut unused_messages);
+                }
+                RecordedInput::GamepadButtonChanged(id, button, value) => {
+                    self.gamepads
+                        .set_button(*id, *button, *value, &mut unused_messages);
+                }
+                RecordedInput::GamepadAxisChanged(id, axis, value) => {
+                    self.gamepads
+                        .set_axis(*id, *axis, *value, &mut unused_messages);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The input arrives after the simulation ticks of this update, which is where the replay
+/// feeds it in, after its tick with the same (zero based) index
+fn recorder_sync_tick(mut recorder: LoReM<InputRecorder>, timestep: Re<SimulationTimestep>) {
+    recorder.tick = timestep.tick_count().saturating_sub(1);
+}
+
+fn record_gamepad_tick(mut recorder: LoReM<InputRecorder>, gamepad_messages: Msg<GamepadMessage>) {
+    for gamepad_message in gamepad_messages.iter_current() {
+        recorder.record(RecordedInput::from_gamepad_message(gamepad_message));
+    }
+}
+
+fn record_keys_and_mouse_buttons_tick(
+    mut recorder: LoReM<InputRecorder>,
+    input_messages: Msg<InputMessage>,
+) {
+    for input_message in input_messages.iter_previous() {
+        match input_message {
+            InputMessage::KeyboardInput(button_state, key_code) => recorder.record(
+                RecordedInput::Key(*key_code, *button_state == ButtonState::Pressed),
+            ),
+            InputMessage::MouseInput(button_state, mouse_button) => {
+                recorder.record(RecordedInput::MouseButton(
+                    *mouse_button,
+                    *button_state == ButtonState::Pressed,
+                ));
+            }
+            InputMessage::MouseWheel(_, _) => {}
+        }
+    }
+}
+
+fn record_mouse_tick(mut recorder: LoReM<InputRecorder>, script_input: LoRe<ScriptInput>) {
+    let mouse_state = script_input.mouse_state();
+    if recorder.last_mouse_state != Some(mouse_state) {
+        recorder.last_mouse_state = Some(mouse_state);
+        recorder.record(RecordedInput::Mouse(mouse_state));
+    }
+}
+
+fn recorder_end_of_tick(mut recorder: LoReM<InputRecorder>) {
+    if let Err(err) = recorder.writer.flush() {
+        error!(?err, "could not flush input recording");
+    }
+}
+
+/// Must run before the input is passed on to the simulation in `Update`
+fn replay_mouse_tick(replayer: LoRe<InputReplayer>, script_input: LoRe<ScriptInput>) {
+    for event in replayer.events_for_current_tick() {
+        if let RecordedInput::Mouse(mouse_state) = &event.input {
+            script_input.set_mouse_state(mouse_state);
+        }
+    }
+}
+
+/// Must run before the held keys and the action sets are passed on to the simulation in `Update`
+fn replay_input_devices_tick(
+    mut replayer: LoReM<InputReplayer>,
+    mut input_devices: LoReM<InputDevices>,
+    script_input: LoRe<ScriptInput>,
+) {
+    for event in replayer.events_for_current_tick() {
+        match event.input {
+            RecordedInput::Key(key_code, true) => {
+                input_devices.keys.insert(key_code);
+            }
+            RecordedInput::Key(key_code, false) => {
+                input_devices.keys.remove(&key_code);
+            }
+            RecordedInput::MouseButton(mouse_button, true) => {
+                input_devices.mouse_buttons.insert(mouse_button);
+            }
+            RecordedInput::MouseButton(mouse_button, false) => {
+                input_devices.mouse_buttons.remove(&mouse_button);
+            }
+            _ => {}
+        }
+    }
+    replayer.apply_to_gamepads();
+
+    // The live `action_sets_tick` is skipped while replaying
+    update_action_sets(
+        &script_input.input_value.borrow(),
+        &script_input.resolved_sets,
+        &input_devices,
+        &replayer.gamepads,
+    );
+}
+
+/// Runs at the same point as the live `gamepad_input_tick`, after the simulation tick
+fn replay_gamepad_tick(
+    replayer: LoRe<InputReplayer>,
+    mut script: LoReM<ScriptSimulation>,
+    main: LoRe<ScriptMain>,
+    source_map: Re<SourceMapResource>,
+    mut error: ReM<ErrorResource>,
+) {
+    if error.has_errors() {
+        return;
+    }
+    for event in replayer.events_for_current_tick() {
+        if let Some(gamepad_message) = event.input.to_gamepad_message() {
+            let result = script.gamepad(&main, &gamepad_message);
+            if !report_callback_error(result, &mut error, &source_map) {
+                return;
+            }
+        }
+    }
+}
+
+/// Runs at the same point as the live `keyboard_input_tick`, after the simulation tick
+fn replay_keyboard_tick(
+    replayer: LoRe<InputReplayer>,
+    mut script: LoReM<ScriptSimulation>,
+    main: LoRe<ScriptMain>,
+    source_map: Re<SourceMapResource>,
+    mut error: ReM<ErrorResource>,
+) {
+    if error.has_errors() {
+        return;
+    }
+    for event in replayer.events_for_current_tick() {
+        if let RecordedInput::Key(key_code, pressed) = event.input {
+            let result = script.key_changed(&main, key_code, pressed);
+            if !report_callback_error(result, &mut error, &source_map) {
+                return;
+            }
+        }
+    }
+}
+
+fn replayer_end_of_tick(mut replayer: LoReM<InputReplayer>) {
+    let count = replayer.events_for_current_tick().len();
+    replayer.next_event_index += count;
+    replayer.tick += 1;
+
+    if count > 0 && replayer.is_done() {
+        info!(tick = replayer.tick, "input replay is done");
+    }
+}
+
+/// Records all input to the `InputRecorder`, that must be inserted before adding the plugin.
+/// Must be added after the `ScriptSimulationPlugin` and the `ScriptInputPlugin` (if used).
+pub struct InputRecordPlugin;
+
+impl Plugin for InputRecordPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(Update, recorder_sync_tick);
+        app.add_system(Update, record_gamepad_tick);
+        app.add_system(Update, record_keys_and_mouse_buttons_tick);
+        if app.local_resources().contains::<ScriptInput>() {
+            app.add_system(Update, record_mouse_tick);
+        }
+        app.add_system(PostUpdate, recorder_end_of_tick);
+    }
+}
+
+/// Replays the input from the `InputReplayer`, that must be inserted before adding the plugin.
+/// The live gamepad, keyboard and mouse input is ignored while replaying.
+/// Must be added after the `ScriptSimulationPlugin` and the `ScriptInputPlugin` (if used).
+pub struct InputReplayPlugin;
+
+impl Plugin for InputReplayPlugin {
+    fn build(&self, app: &mut App) {
+        *app.resource_mut::<InputSource>() = InputSource::Replay;
+        app.resource_mut::<SimulationTimestep>().set_lockstep(true);
+
+        let has_mouse_events = app
+            .local_resources()
+            .fetch::<InputReplayer>()
+            .events
+            .iter()
+            .any(|event| matches!(event.input, RecordedInput::Mouse(_)));
+
+        if app.local_resources().contains::<ScriptInput>() {
+            app.add_system(PreUpdate, replay_mouse_tick);
+            app.add_system(PreUpdate, replay_input_devices_tick);
+        } else if has_mouse_events {
+            warn!(
+                "the input recording has mouse input, but there is no script input to replay it to"
+            );
+        }
+        app.add_system(Update, replay_gamepad_tick);
+        app.add_system(Update, replay_keyboard_tick);
+        app.add_system(PostUpdate, replayer_end_of_tick);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn all_kinds() -> Vec<RecordedEvent> {
+        let inputs = [
+            RecordedInput::GamepadConnected(1, "Pad".to_string()),
+            RecordedInput::GamepadActivated(1),
+            RecordedInput::GamepadButtonChanged(1, Button::DPadLeft, 0.5),
+            RecordedInput::GamepadAxisChanged(1, Axis::RightStickY, -0.25),
+            RecordedInput::Mouse(MouseState {
+                cursor_position: (-3, 200),
+                left_button: true,
+                right_button: false,
+            }),
+            RecordedInput::Key(KeyCode::Comma, true),
+            RecordedInput::MouseButton(MouseButton::Forward, false),
+            RecordedInput::GamepadDisconnected(1),
+        ];
+        inputs
+            .into_iter()
+            .enumerate()
+            .map(|(tick, input)| RecordedEvent {
+                tick: tick as u64 * 3,
+                input,
+            })
+            .collect()
+    }
+
+    fn write_recording(events: &[RecordedEvent]) -> Vec<u8> {
+        let mut octets = Vec::new();
+        write_header(&mut octets).unwrap();
+        for event in events {
+            write_event(&mut octets, event).unwrap();
+        }
+        octets
+    }
+
+    #[test]
+    fn round_trip() {
+        let events = all_kinds();
+        let octets = write_recording(&events);
+
+        let read_events = read_recording(&mut octets.as_slice()).unwrap();
+
+        assert_eq!(read_events, events);
+    }
+
+    #[test]
+    fn every_button_and_axis_round_trips() {
+        let buttons = BUTTONS
+            .iter()
+            .map(|button| RecordedInput::GamepadButtonChanged(0, *button, 1.0));
+        let axes = AXES
+            .iter()
+            .map(|axis| RecordedInput::GamepadAxisChanged(0, *axis, 1.0));
+        let events: Vec<RecordedEvent> = buttons
+            .chain(axes)
+            .map(|input| RecordedEvent { tick: 0, input })
+            .collect();
+        let octets = write_recording(&events);
+
+        assert_eq!(read_recording(&mut octets.as_slice()).unwrap(), events);
+    }
+
+    #[test]
+    fn rejects_other_version() {
+        let mut octets = write_recording(&all_kinds());
+        octets[4..6].copy_from_slice(&(INPUT_RECORDING_VERSION + 1).to_le_bytes());
+
+        let err = read_recording(&mut octets.as_slice()).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("version"));
+    }
+
+    #[test]
+    fn rejects_other_magic() {
+        let mut octets = write_recording(&[]);
+        octets[0] = b'X';
+
+        let err = read_recording(&mut octets.as_slice()).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn truncated_event_is_an_error() {
+        let octets = write_recording(&all_kinds());
+        let truncated = &octets[..octets.len() - 2];
+
+        let err = read_recording(&mut &truncated[..]).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn truncated_header_is_an_error() {
+        let octets = write_recording(&[]);
+
+        let err = read_recording(&mut &octets[..5]).unwrap_err();
+
+        assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,835 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! The `mangrove::save` package. Each slot is a file (or an entry in a store in memory) with a
+//! header and the saved value.
+//! The value is self-describing, every struct field and enum variant is stored with its name,
+//! so a slot that was saved with a previous version of the type can be migrated by field name
+//! when it is loaded. The header has the `version` of the `Saves<T>`, so the scripts can do the
+//! migrations that can not be done by name in a `migrate` function on `T`.
+use crate::migrate::migrate_to_type;
+use crate::script::{MangroveError, register_print};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use swamp::prelude::{Fp, Resource};
+use swamp_script::prelude::*;
+use tracing::{debug, error, info, warn};
+
+/// Overrides the directory that the slots are saved in
+pub const SAVE_DIR_ENV: &str = "MANGROVE_SAVE_DIR";
+
+/// The optional function on `T` that is called when a slot was saved with an older `version`
+pub const MIGRATE_FUNCTION: &str = "migrate";
+
+const MAGIC: &[u8; 4] = b"MSAV";
+const FORMAT_VERSION: u16 = 2;
+const SLOT_EXTENSION: &str = "sav";
+
+/// Structs can not contain themselves, but stop before the stack does if something is wrong
+const MAX_DEPTH: usize = 64;
+
+const TAG_INT: u8 = 1;
+const TAG_FLOAT: u8 = 2;
+const TAG_STRING: u8 = 3;
+const TAG_BOOL: u8 = 4;
+const TAG_UNIT: u8 = 5;
+const TAG_OPTIONAL: u8 = 6;
+const TAG_TUPLE: u8 = 7;
+const TAG_STRUCT: u8 = 8;
+const TAG_ENUM: u8 = 9;
+const TAG_VEC: u8 = 10;
+const TAG_MAP: u8 = 11;
+
+const VARIANT_NOTHING: u8 = 0;
+const VARIANT_TUPLE: u8 = 1;
+const VARIANT_STRUCT: u8 = 2;
+
+/// A value that was read from a slot, before it is migrated to the current type
+#[derive(Debug)]
+pub struct SavedSlot {
+    /// The `version` of the `Saves<T>` that saved the slot
+    pub version: i32,
+    pub value: Value,
+}
+
+#[derive(Debug, Clone)]
+enum SlotStorage {
+    Directory(PathBuf),
+    /// Shared by the clones of the store, so a slot saved by one boot can be loaded by the next
+    Memory(Arc<Mutex<HashMap<String, Vec<u8>>>>),
+}
+
+/// Reads and writes the save slots, in a directory or in memory.
+/// The simulation uses the `SaveStore` resource when it boots, or an empty store in memory if
+/// there is none, so runs that must be repeatable never see the saves of the player.
+#[derive(Resource, Debug, Clone)]
+pub struct SaveStore {
+    storage: SlotStorage,
+}
+
+impl SaveStore {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            storage: SlotStorage::Directory(directory.into()),
+        }
+    }
+
+    /// Uses `MANGROVE_SAVE_DIR` if it is set, otherwise a directory for the game in the user
+    /// data directory
+    #[must_use]
+    pub fn for_game(game_name: &str) -> Self {
+        Self::new(default_save_directory(game_name))
+    }
+
+    /// The slots are lost when the store is dropped, e.g. for headless runs and golden tests
+    #[must_use]
+    pub fn in_memory() -> Self {
+        Self {
+            storage: SlotStorage::Memory(Arc::default()),
+        }
+    }
+
+    /// `None` for a store in memory
+    #[must_use]
+    pub fn directory(&self) -> Option<&Path> {
+        match &self.storage {
+            SlotStorage::Directory(directory) => Some(directory),
+            SlotStorage::Memory(_) => None,
+        }
+    }
+
+    /// # Errors
+    ///
+    /// If the slot name is empty or has other characters than `a-z`, `A-Z`, `0-9`, `-` and `_`
+    fn check_slot_name(slot: &str) -> Result<(), MangroveError> {
+        let is_valid = !slot.is_empty()
+            && slot
+                .chars()
+                .all(|ch| ch.is_ascii_alphanumeric() || ch == '-' || ch == '_');
+        if is_valid {
+            Ok(())
+        } else {
+            Err(MangroveError::Other(format!(
+                "save slot '{slot}' must only contain a-z, A-Z, 0-9, '-' and '_'"
+            )))
+        }
+    }
+
+    /// Writes the value to a temporary file that replaces the slot file when it is complete,
+    /// so a crash while saving never leaves a broken slot.
+    ///
+    /// # Errors
+    ///
+    /// If the value can not be saved (e.g. `Sparse<T>`) or the file could not be written
+    pub fn save(&self, slot: &str, value: &Value, version: i32) -> Result<(), MangroveError> {
+        Self::check_slot_name(slot)?;
+
+        let mut octets = Vec::new();
+        octets.extend_from_slice(MAGIC);
+        octets.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        octets.extend_from_slice(&version.to_le_bytes());
+        write_value(value, &mut octets, 0)?;
+
+        match &self.storage {
+            SlotStorage::Directory(directory) => write_slot_file(directory, slot, &octets)?,
+            SlotStorage::Memory(slots) => {
+                slots
+                    .lock()
+                    .map_err(|_| "the save slots are poisoned".to_string())?
+                    .insert(slot.to_string(), octets.clone());
+            }
+        }
+
+        debug!(slot, version, octets = octets.len(), "saved");
+
+        Ok(())
+    }
+
+    /// The octets in the slot, `None` if nothing has been saved in it
+    fn read_slot(&self, slot: &str) -> Result<Option<Vec<u8>>, MangroveError> {
+        match &self.storage {
+            SlotStorage::Directory(directory) => {
+                let path = slot_path(directory, slot);
+                match fs::read(&path) {
+                    Ok(octets) => Ok(Some(octets)),
+                    Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                    Err(err) => Err(MangroveError::Other(format!(
+                        "could not load {}: {err}",
+                        path.display()
+                    ))),
+                }
+            }
+            SlotStorage::Memory(slots) => Ok(slots
+                .lock()
+                .map_err(|_| "the save slots are poisoned".to_string())?
+                .get(slot)
+                .cloned()),
+        }
+    }
+
+    /// Returns `None` if nothing has been saved in the slot.
+    /// The value is migrated by field name to `value_type`, but it is up to the caller to
+    /// handle the version.
+    ///
+    /// # Errors
+    ///
+    /// If the file could not be read, is not a save file or could not be migrated
+    pub fn load(&self, slot: &str, value_type: &Type) -> Result<Option<SavedSlot>, MangroveError> {
+        Self::check_slot_name(slot)?;
+        let Some(octets) = self.read_slot(slot)? else {
+            return Ok(None);
+        };
+
+        let mut reader = OctetReader::new(&octets);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(MangroveError::Other(format!(
+                "save slot '{slot}' is not a save file"
+            )));
+        }
+        let format_version = reader.u16()?;
+        if format_version != FORMAT_VERSION {
+            return Err(MangroveError::Other(format!(
+                "save slot '{slot}' has save format version {format_version}, expected {FORMAT_VERSION}"
+            )));
+        }
+        let version = reader.i32()?;
+        let (saved_value, _) = read_value(&mut reader, 0)?;
+
+        // The saved value has the types that were described in the file, it is always migrated
+        // so the loaded value has the types of the current scripts
+        let (value, report) = migrate_to_type(saved_value, value_type)?;
+        if !report.is_unchanged() {
+            info!(slot, %report, "migrated save slot to a new layout");
+        }
+
+        Ok(Some(SavedSlot { version, value }))
+    }
+}
+
+/// Registers `save()` and `load()` for `Saves<T>`, if the scripts use the `mangrove::save` package.
+///
+/// The `migrate` function on `T` is run with only `print` available, since the other
+/// external functions need the engine.
+///
+/// # Errors
+///
+pub fn register_save_members<C: 'static>(
+    modules: &Modules,
+    associated_impls: &AssociatedImpls,
+    constants: &Constants,
+    externals: &mut ExternalFunctions<C>,
+    store: &SaveStore,
+) -> Result<(), String> {
+    let Some(save_module) = modules.get(&["mangrove".to_string(), "save".to_string()]) else {
+        return Ok(());
+    };
+    let saves_blueprint = save_module
+        .symbol_table
+        .get_blueprint("Saves")
+        .ok_or("mangrove::save must have the Saves struct")?;
+    let saves_type = Type::Blueprint(saves_blueprint.clone());
+
+    let save_fn_id = associated_impls.api_fetch_external_function_id(&saves_type, "save");
+    let save_store = store.clone();
+    externals.register_external_function(
+        save_fn_id,
+        move |mem_values: &[VariableValue], _context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let slot = params[1].expect_string()?;
+            let was_saved = saves_version(&params[0])
+                .and_then(|version| save_store.save(&slot, &params[2], version))
+                .inspect_err(|mangrove_error| {
+                    error!(slot, error=%mangrove_error, "could not save");
+                })
+                .is_ok();
+
+            Ok(Value::Bool(was_saved))
+        },
+    )?;
+
+    let mut migrate_externals = ExternalFunctions::<C>::new();
+    register_print(modules, &mut migrate_externals);
+    let migrator = Migrator {
+        associated_impls: associated_impls.clone(),
+        constants: Constants {
+            values: constants.values.clone(),
+        },
+        externals: migrate_externals,
+    };
+
+    let load_fn_id = associated_impls.api_fetch_external_function_id(&saves_type, "load");
+    let load_store = store.clone();
+    externals.register_external_function(
+        load_fn_id,
+        move |mem_values: &[VariableValue], context| {
+            let params = convert_to_values(mem_values)
+                .expect("external function should be given values and no references");
+            let slot = params[1].expect_string()?;
+            let loaded = saved_type(&params[0])
+                .and_then(|value_type| {
+                    let version = saves_version(&params[0])?;
+                    load_store
+                        .load(&slot, &value_type)?
+                        .map(|saved| migrator.migrate(saved, &value_type, version, context))
+                        .transpose()
+                })
+                .unwrap_or_else(|mangrove_error| {
+                    warn!(slot, error=%mangrove_error, "could not load");
+                    None
+                });
+
+            Ok(Value::Option(
+                loaded.map(|value| Rc::new(RefCell::new(value))),
+            ))
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Calls the `migrate` function on `T` for slots that were saved with an older version
+struct Migrator<C> {
+    associated_impls: AssociatedImpls,
+    constants: Constants,
+    externals: ExternalFunctions<C>,
+}
+
+impl<C> Migrator<C> {
+    fn migrate(
+        &self,
+        saved: SavedSlot,
+        value_type: &Type,
+        version: i32,
+        context: &mut C,
+    ) -> Result<Value, MangroveError> {
+        if saved.version > version {
+            return Err(MangroveError::Other(format!(
+                "the slot was saved with version {}, that is newer than {version}",
+                saved.version
+            )));
+        }
+        if saved.version == version {
+            return Ok(saved.value);
+        }
+
+        let Some(migrate_fn) = self
+            .associated_impls
+            .get_internal_member_function(value_type, MIGRATE_FUNCTION)
+        else {
+            debug!(from = saved.version, to = version, "no migrate function");
+            return Ok(saved.value);
+        };
+
+        let signature = &migrate_fn.signature;
+        let has_signature = matches!(
+            signature.parameters.as_slice(),
+            [self_parameter, version_parameter]
+                if self_parameter.resolved_type == *value_type
+                    && !self_parameter.is_mutable
+                    && version_parameter.resolved_type == Type::Int
+        ) && *signature.return_type == *value_type;
+        if !has_signature {
+            return Err(MangroveError::Other(format!(
+                "{value_type}::{MIGRATE_FUNCTION}() must be fn {MIGRATE_FUNCTION}(self, saved_version: Int) -> {value_type}, but is {signature}"
+            )));
+        }
+
+        let migrated = util_execute_function(
+            &self.externals,
+            &self.constants,
+            migrate_fn,
+            &[
+                VariableValue::Value(saved.value),
+                VariableValue::Value(Value::Int(saved.version)),
+            ],
+            context,
+            None,
+        )?;
+        info!(from = saved.version, to = version, "migrated save slot");
+
+        Ok(migrated)
+    }
+}
+
+/// The `T` in the `Saves<T>` value
+fn saved_type(saves_value: &Value) -> Result<Type, MangroveError> {
+    match saves_value {
+        Value::NamedStruct(saves_struct_type, _) => saves_struct_type
+            .instantiated_type_parameters
+            .first()
+            .cloned()
+            .ok_or_else(|| MangroveError::Other("Saves must have a type parameter".to_string())),
+        _ => Err(MangroveError::Other("expected a Saves value".to_string())),
+    }
+}
+
+/// The `version` field in the `Saves<T>` value
+fn saves_version(saves_value: &Value) -> Result<i32, MangroveError> {
+    let Value::NamedStruct(saves_struct_type, fields) = saves_value else {
+        return Err(MangroveError::Other("expected a Saves value".to_string()));
+    };
+    let version_index = saves_struct_type
+        .anon_struct_type
+        .field_name_sorted_fields
+        .keys()
+        .position(|name| name == "version")
+        .ok_or_else(|| MangroveError::Other("Saves must have a version field".to_string()))?;
+
+    match &*fields[version_index].borrow() {
+        Value::Int(version) => Ok(*version),
+        _ => Err(MangroveError::Other(
+            "Saves.version must be an Int".to_string(),
+        )),
+    }
+}
+
+fn slot_path(directory: &Path, slot: &str) -> PathBuf {
+    directory.join(slot).with_extension(SLOT_EXTENSION)
+}
+
+fn write_slot_file(directory: &Path, slot: &str, octets: &[u8]) -> Result<(), MangroveError> {
+    let path = slot_path(directory, slot);
+    let io_err = |err: std::io::Error| {
+        MangroveError::Other(format!("could not save to {}: {err}", path.display()))
+    };
+
+    fs::create_dir_all(directory).map_err(io_err)?;
+    let temp_path = path.with_extension(format!("{SLOT_EXTENSION}.tmp"));
+    {
+        let mut file = fs::File::create(&temp_path).map_err(io_err)?;
+        file.write_all(octets).map_err(io_err)?;
+        file.sync_all().map_err(io_err)?;
+    }
+    fs::rename(&temp_path, &path).map_err(io_err)
+}
+
+fn default_save_directory(game_name: &str) -> PathBuf {
+    if let Some(directory) = env::var_os(SAVE_DIR_ENV) {
+        return PathBuf::from(directory);
+    }
+
+    user_data_directory().map_or_else(
+        || PathBuf::from("saves"),
+        |data_directory| {
+            data_directory
+                .join("mangrove")
+                .join(game_name)
+                .join("saves")
+        },
+    )
+}
+
+fn user_data_directory() -> Option<PathBuf> {
+    if cfg!(target_os = "windows") {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else if cfg!(target_os = "macos") {
+        env::var_os("HOME").map(|home| {
+            PathBuf::from(home)
+                .join("Library")
+                .join("Application Support")
+        })
+    } else {
+        env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+    }
+}
+
+fn write_count(count: usize, out: &mut Vec<u8>) -> Result<(), MangroveError> {
+    let count = u32::try_from(count)
+        .map_err(|_| MangroveError::Other(format!("{count} is too many to save")))?;
+    out.extend_from_slice(&count.to_le_bytes());
+    Ok(())
+}
+
+fn write_name(name: &str, out: &mut Vec<u8>) -> Result<(), MangroveError> {
+    write_count(name.len(), out)?;
+    out.extend_from_slice(name.as_bytes());
+    Ok(())
+}
+
+fn write_values(values: &[ValueRef], out: &mut Vec<u8>, depth: usize) -> Result<(), MangroveError> {
+    write_count(values.len(), out)?;
+    for value in values {
+        write_value(&value.borrow(), out, depth + 1)?;
+    }
+    Ok(())
+}
+
+/// Each field is written with its name, so the order of the fields does not matter
+fn write_fields(
+    fields: &SeqMap<String, StructTypeField>,
+    values: &[ValueRef],
+    out: &mut Vec<u8>,
+    depth: usize,
+) -> Result<(), MangroveError> {
+    write_count(values.len(), out)?;
+    for (field_name, value) in fields.keys().zip(values) {
+        write_name(field_name, out)?;
+        write_value(&value.borrow(), out, depth + 1)?;
+    }
+    Ok(())
+}
+
+fn write_value(value: &Value, out: &mut Vec<u8>, depth: usize) -> Result<(), MangroveError> {
+    if depth > MAX_DEPTH {
+        return Err(MangroveError::Other(
+            "value is nested too deep to be saved".to_string(),
+        ));
+    }
+
+    match value {
+        Value::Int(x) => {
+            out.push(TAG_INT);
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        Value::Float(fp) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&fp.inner().to_le_bytes());
+        }
+        Value::String(s) => {
+            out.push(TAG_STRING);
+            write_name(s, out)?;
+        }
+        Value::Bool(b) => {
+            out.push(TAG_BOOL);
+            out.push(u8::from(*b));
+        }
+        Value::Unit => out.push(TAG_UNIT),
+        Value::Option(maybe_value) => {
+            out.push(TAG_OPTIONAL);
+            match maybe_value {
+                None => out.push(0),
+                Some(inner_value) => {
+                    out.push(1);
+                    write_value(&inner_value.borrow(), out, depth + 1)?;
+                }
+            }
+        }
+        Value::Tuple(_, values) => {
+            out.push(TAG_TUPLE);
+            write_values(values, out, depth)?;
+        }
+        Value::NamedStruct(struct_type, values) => {
+            out.push(TAG_STRUCT);
+            write_name(&struct_type.assigned_name, out)?;
+            write_fields(
+                &struct_type.anon_struct_type.field_name_sorted_fields,
+                values,
+                out,
+                depth,
+            )?;
+        }
+        Value::EnumVariantSimple(enum_type, variant) => {
+            out.push(TAG_ENUM);
+            write_name(&enum_type.assigned_name, out)?;
+            write_name(&variant.common.assigned_name, out)?;
+            out.push(VARIANT_NOTHING);
+        }
+        Value::EnumVariantTuple(enum_type, variant, values) => {
+            out.push(TAG_ENUM);
+            write_name(&enum_type.assigned_name, out)?;
+            write_name(&variant.common.assigned_name, out)?;
+            out.push(VARIANT_TUPLE);
+            write_values(values, out, depth)?;
+        }
+        Value::EnumVariantStruct(enum_type, variant, values) => {
+            out.push(TAG_ENUM);
+            write_name(&enum_type.assigned_name, out)?;
+            write_name(&variant.common.assigned_name, out)?;
+            out.push(VARIANT_STRUCT);
+            write_fields(
+                &variant.anon_struct.field_name_sorted_fields,
+                values,
+                out,
+                depth,
+            )?;
+        }
+        Value::Vec(_, values) => {
+            out.push(TAG_VEC);
+            write_values(values, out, depth)?;
+        }
+        Value::Map(_, entries) => {
+            out.push(TAG_MAP);
+            write_count(entries.len(), out)?;
+            for (key, value) in entries {
+                write_value(key, out, depth + 1)?;
+                write_value(&value.borrow(), out, depth + 1)?;
+            }
+        }
+        _ => {
+            return Err(MangroveError::Other(format!("{value} can not be saved")));
+        }
+    }
+
+    Ok(())
+}
+
+struct OctetReader<'a> {
+    octets: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> OctetReader<'a> {
+    const fn new(octets: &'a [u8]) -> Self {
+        Self { octets, offset: 0 }
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], MangroveError> {
+        let end = self
+            .offset
+            .checked_add(count)
+            .filter(|end| *end <= self.octets.len())
+            .ok_or_else(|| MangroveError::Other("save file is truncated".to_string()))?;
+        let taken = &self.octets[self.offset..end];
+        self.offset = end;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, MangroveError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, MangroveError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, MangroveError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, MangroveError> {
+        Ok(i32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn count(&mut self) -> Result<usize, MangroveError> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> Result<String, MangroveError> {
+        let len = self.count()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| MangroveError::Other("save file has an invalid string".to_string()))
+    }
+}
+
+fn read_values(
+    reader: &mut OctetReader,
+    depth: usize,
+) -> Result<(Vec<ValueRef>, Vec<Type>), MangroveError> {
+    let count = reader.count()?;
+    let mut values = Vec::new();
+    let mut types = Vec::new();
+    for _ in 0..count {
+        let (value, value_type) = read_value(reader, depth + 1)?;
+        values.push(Rc::new(RefCell::new(value)));
+        types.push(value_type);
+    }
+    Ok((values, types))
+}
+
+fn read_fields(
+    reader: &mut OctetReader,
+    depth: usize,
+) -> Result<(Vec<ValueRef>, SeqMap<String, StructTypeField>), MangroveError> {
+    let count = reader.count()?;
+    let mut values = Vec::new();
+    let mut fields = SeqMap::new();
+    for _ in 0..count {
+        let field_name = reader.string()?;
+        let (value, field_type) = read_value(reader, depth + 1)?;
+        let field = StructTypeField {
+            identifier: None,
+            field_type,
+        };
+        fields.insert(field_name, field).map_err(|_| {
+            MangroveError::Other("save file has a duplicate field name".to_string())
+        })?;
+        values.push(Rc::new(RefCell::new(value)));
+    }
+    Ok((values, fields))
+}
+
+/// Reads a value and creates the types that are described in the save file. The types only
+/// live until the value has been migrated to the types of the scripts, so they only have to
+/// describe the names of the fields and variants.
+fn read_value(reader: &mut OctetReader, depth: usize) -> Result<(Value, Type), MangroveError> {
+    if depth > MAX_DEPTH {
+        return Err(MangroveError::Other(
+            "save file has a value that is nested too deep".to_string(),
+        ));
+    }
+
+    let value_and_type = match reader.u8()? {
+        TAG_INT => (Value::Int(reader.i32()?), Type::Int),
+        TAG_FLOAT => (Value::Float(Fp::from_raw(reader.i32()?)), Type::Float),
+        TAG_STRING => (Value::String(reader.string()?), Type::String),
+        TAG_BOOL => (Value::Bool(reader.u8()? != 0), Type::Bool),
+        TAG_UNIT => (Value::Unit, Type::Unit),
+        TAG_OPTIONAL => {
+            if reader.u8()? == 0 {
+                (Value::Option(None), Type::Optional(Box::new(Type::Unit)))
+            } else {
+                let (inner_value, inner_type) = read_value(reader, depth + 1)?;
+                (
+                    Value::Option(Some(Rc::new(RefCell::new(inner_value)))),
+                    Type::Optional(Box::new(inner_type)),
+                )
+            }
+        }
+        TAG_TUPLE => {
+            let (values, types) = read_values(reader, depth)?;
+            (Value::Tuple(types.clone(), values), Type::Tuple(types))
+        }
+        TAG_STRUCT => {
+            let struct_name = reader.string()?;
+            let (values, fields) = read_fields(reader, depth)?;
+            let struct_type = NamedStructType::new(
+                Node::default(),
+                &struct_name,
+                AnonymousStructType::new(fields),
+                &[],
+            );
+            (
+                Value::NamedStruct(struct_type.clone(), values),
+                Type::NamedStruct(struct_type),
+            )
+        }
+        TAG_ENUM => {
+            let enum_name = reader.string()?;
+            let variant_name = reader.string()?;
+            let common = EnumVariantCommon {
+                name: Node::default(),
+                assigned_name: variant_name.clone(),
+                container_index: 0,
+            };
+            let mut enum_type = EnumType::new(Node::default(), &enum_name, vec![]);
+            let (value_variant, variant) = match reader.u8()? {
+                VARIANT_NOTHING => {
+                    let variant = EnumVariantSimpleType { common };
+                    (
+                        Value::EnumVariantSimple(enum_type.clone(), variant.clone()),
+                        EnumVariantType::Nothing(variant),
+                    )
+                }
+                VARIANT_TUPLE => {
+                    let (values, types) = read_values(reader, depth)?;
+                    let variant = EnumVariantTupleType {
+                        common,
+                        fields_in_order: types,
+                    };
+                    (
+                        Value::EnumVariantTuple(enum_type.clone(), variant.clone(), values),
+                        EnumVariantType::Tuple(variant),
+                    )
+                }
+                VARIANT_STRUCT => {
+                    let (values, fields) = read_fields(reader, depth)?;
+                    let variant = EnumVariantStructType {
+                        common,
+                        anon_struct: AnonymousStructType::new(fields),
+                    };
+                    (
+                        Value::EnumVariantStruct(enum_type.clone(), variant.clone(), values),
+                        EnumVariantType::Struct(variant),
+                    )
+                }
+                unknown => {
+                    return Err(MangroveError::Other(format!(
+                        "save file has an unknown enum variant kind {unknown}"
+                    )));
+                }
+            };
+            // Only the saved variant is known, that is all the migration needs
+            let _ = enum_type.variants.insert(variant_name, variant);
+            (value_variant, Type::Enum(enum_type))
+        }
+        // The item types are not known for empty containers, the migration creates the real types
+        TAG_VEC => {
+            let (items, types) = read_values(reader, depth)?;
+            let item_type = types.into_iter().next().unwrap_or(Type::Unit);
+            let vec_type = Type::Slice(Box::new(item_type));
+            (Value::Vec(vec_type.clone(), items), vec_type)
+        }
+        TAG_MAP => {
+            let count = reader.count()?;
+            let mut entries = SeqMap::new();
+            let mut entry_types = None;
+            for _ in 0..count {
+                let (key, key_type) = read_value(reader, depth + 1)?;
+                let (item, item_type) = read_value(reader, depth + 1)?;
+                entry_types.get_or_insert((key_type, item_type));
+                entries
+                    .insert(key, Rc::new(RefCell::new(item)))
+                    .map_err(|_| {
+                        MangroveError::Other("save file has a duplicate key".to_string())
+                    })?;
+            }
+            let (key_type, item_type) = entry_types.unwrap_or((Type::Unit, Type::Unit));
+            let map_type = Type::SlicePair(Box::new(key_type), Box::new(item_type));
+            (Value::Map(map_type.clone(), entries), map_type)
+        }
+        unknown => {
+            return Err(MangroveError::Other(format!(
+                "save file has an unknown value tag {unknown}"
+            )));
+        }
+    };
+
+    Ok(value_and_type)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn memory_slots_are_shared_by_clones() {
+        let store = SaveStore::in_memory();
+        assert!(store.directory().is_none());
+        store.save("slot-1", &Value::Int(42), 3).unwrap();
+
+        let saved = store.clone().load("slot-1", &Type::Int).unwrap().unwrap();
+        assert_eq!(saved.version, 3);
+        assert!(matches!(saved.value, Value::Int(42)));
+
+        assert!(
+            SaveStore::in_memory()
+                .load("slot-1", &Type::Int)
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn directory_slots_are_files() {
+        let directory = env::temp_dir().join(format!("mangrove-save-test-{}", std::process::id()));
+        let store = SaveStore::new(&directory);
+        store.save("slot", &Value::Bool(true), 1).unwrap();
+        assert!(directory.join("slot.sav").is_file());
+        assert!(!directory.join("slot.sav.tmp").exists());
+
+        let saved = store.load("slot", &Type::Bool).unwrap().unwrap();
+        assert!(matches!(saved.value, Value::Bool(true)));
+        assert!(store.load("other", &Type::Bool).unwrap().is_none());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn rejects_slot_names() {
+        let store = SaveStore::in_memory();
+        for slot in ["", "../slot", "slot.sav", "a b"] {
+            assert!(store.save(slot, &Value::Unit, 0).is_err(), "{slot}");
+            assert!(store.load(slot, &Type::Unit).is_err(), "{slot}");
+        }
+    }
+}
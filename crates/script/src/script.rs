@@ -2,31 +2,109 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
-use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
-use std::rc::Rc;
 use std::time::Instant;
 use swamp::prelude::{Color, Rotation, SpriteParams, UVec2, Vec2, Vec3};
-use swamp_script::compile_and_analyze;
 use swamp_script::prelude::*;
+use swamp_script_compile::bootstrap_modules;
+use swamp_script_dep_loader::get_all_local_paths;
+use swamp_script_eval_loader::analyze_modules_in_order;
 use yansi::Paint;
 
 #[derive(Debug)]
 pub enum MangroveError {
     RuntimeError(RuntimeError),
     Other(String),
-    ScriptResolveError(ScriptResolveError),
+    ScriptResolveError(Box<ScriptResolveError>),
+    /// A function that mangrove calls is missing, e.g. `simulation()` in the entry module or
+    /// `tick()` on the simulation struct
+    MissingFunction {
+        /// The module or struct that should have the function
+        owner: String,
+        expected: String,
+        span: Span,
+    },
+    /// The function exists, but takes the wrong number of parameters or returns the wrong type
+    WrongSignature {
+        function: String,
+        expected: String,
+        found: String,
+        span: Span,
+    },
+    /// A field that mangrove writes to is missing, e.g. `mouse_left_button` in the input struct
+    MissingField {
+        struct_name: String,
+        field: String,
+        expected: String,
+        span: Span,
+    },
+    WrongFieldType {
+        struct_name: String,
+        field: String,
+        expected: String,
+        found: String,
+        span: Span,
+    },
+    /// A type that mangrove registers functions on is missing, e.g. `Gfx` in `mangrove::render`
+    MissingType {
+        module: String,
+        name: String,
+    },
+}
+
+impl MangroveError {
+    /// Where in the scripts the contract is broken, the default span if it is not known
+    #[must_use]
+    pub fn span(&self) -> Span {
+        match self {
+            Self::MissingFunction { span, .. }
+            | Self::WrongSignature { span, .. }
+            | Self::MissingField { span, .. }
+            | Self::WrongFieldType { span, .. } => span.clone(),
+            _ => Span::default(),
+        }
+    }
 }
 
 impl Display for MangroveError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{self:?}")
+        match self {
+            Self::Other(description) => write!(f, "{description}"),
+            Self::MissingFunction {
+                owner, expected, ..
+            } => write!(f, "{owner} must have {expected}"),
+            Self::WrongSignature {
+                function,
+                expected,
+                found,
+                ..
+            } => write!(f, "{function} must {expected}, but {found}"),
+            Self::MissingField {
+                struct_name,
+                field,
+                expected,
+                ..
+            } => write!(f, "{struct_name} must have the field {field}: {expected}"),
+            Self::WrongFieldType {
+                struct_name,
+                field,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "{struct_name}.{field} must be {expected}, but is {found}"
+            ),
+            Self::MissingType { module, name } => write!(f, "{module} is missing the type {name}"),
+            _ => write!(f, "{self:?}"),
+        }
     }
 }
 
 impl From<ScriptResolveError> for MangroveError {
     fn from(value: ScriptResolveError) -> Self {
-        Self::ScriptResolveError(value)
+        Self::ScriptResolveError(Box::new(value))
     }
 }
 impl From<RuntimeError> for MangroveError {
@@ -41,25 +119,10 @@ impl From<String> for MangroveError {
     }
 }
 
-pub fn create_empty_struct_type(
-    symbol_table: &mut SymbolTable,
-    name: &str,
-) -> Result<NamedStructType, Error> {
-    Ok(symbol_table.add_generated_struct(name, &[("hidden", Type::Unit)])?)
-}
-
 pub fn create_empty_struct_value(struct_type: NamedStructType) -> Value {
     Value::NamedStruct(struct_type, [].to_vec())
 }
 
-pub fn create_empty_struct_value_util(
-    symbol_table: &mut SymbolTable,
-    name: &str,
-) -> Result<(Value, NamedStructType), Error> {
-    let struct_type = create_empty_struct_type(symbol_table, name)?;
-    Ok((create_empty_struct_value(struct_type.clone()), struct_type))
-}
-
 pub fn sprite_params(sprite_params_struct: &Value) -> Result<SpriteParams, ValueError> {
     if let Value::NamedStruct(_struct_type_ref, fields) = sprite_params_struct {
         Ok(SpriteParams {
@@ -83,14 +146,6 @@ pub fn sprite_params(sprite_params_struct: &Value) -> Result<SpriteParams, Value
     }
 }
 
-pub fn value_to_value_ref(fields: &[Value]) -> Vec<ValueRef> {
-    fields
-        .iter()
-        .map(|v| Rc::new(RefCell::new(v.clone())))
-        .clone()
-        .collect()
-}
-
 pub fn vec3_like(v: &Value) -> Result<Vec3, ValueError> {
     match v {
         Value::Tuple(_, fields) => {
@@ -118,6 +173,33 @@ pub fn color_like(v: &Value) -> Result<Color, ValueError> {
     }
 }
 
+/// The atlas frame that a `mangrove::anim::AnimationPlayer` shows, `None` if the animation has no frames
+pub fn animation_frame_like(v: &Value) -> Result<Option<u16>, ValueError> {
+    let Value::NamedStruct(_, player_fields) = v else {
+        return Err(ValueError::TypeError("not an animation player".to_string()));
+    };
+    let index = player_fields[1].borrow().expect_int()?;
+
+    let animation = player_fields[0].borrow();
+    let Value::NamedStruct(_, animation_fields) = &*animation else {
+        return Err(ValueError::TypeError("not an animation".to_string()));
+    };
+    let frames = animation_fields[0].borrow();
+    let Value::Vec(_, frames) = &*frames else {
+        return Err(ValueError::TypeError("not a frame list".to_string()));
+    };
+
+    let Some(frame) = usize::try_from(index)
+        .ok()
+        .and_then(|index| frames.get(index))
+    else {
+        return Ok(None);
+    };
+    let frame = frame.borrow().expect_int()?;
+
+    Ok(u16::try_from(frame).ok())
+}
+
 pub fn uvec2_like(v: &Value) -> Result<UVec2, ValueError> {
     match v {
         Value::Tuple(_, fields) => {
@@ -136,7 +218,7 @@ pub fn register_print<C>(modules: &Modules, externals: &mut ExternalFunctions<C>
         .unwrap()
         .symbol_table;
 
-    register_print_internal(&mangrove_std_symbol_table, externals);
+    register_print_internal(mangrove_std_symbol_table, externals);
 }
 
 fn register_print_internal<C>(std_module: &SymbolTable, externals: &mut ExternalFunctions<C>) {
@@ -161,38 +243,210 @@ fn register_print_internal<C>(std_module: &SymbolTable, externals: &mut External
         .expect("should work to register");
 }
 
-#[derive(Debug)]
-pub struct DecoratedParseErr {
-    pub span: Span,
-    pub specific: SpecificError,
-}
 use chrono::{DateTime, Utc};
 
-pub fn compile(
+/// The local modules that each module imports or uses, from the latest compile.
+/// Used for finding the modules that must be analyzed again when a module changes.
+#[derive(Debug, Default, Clone)]
+pub struct ModuleGraph {
+    core_module_path: Vec<String>,
+    dependencies: HashMap<Vec<String>, Vec<Vec<String>>>,
+}
+
+impl ModuleGraph {
+    /// The modules and every module that imports or uses them, directly or indirectly
+    #[must_use]
+    pub fn with_dependents(&self, modules: &[Vec<String>]) -> HashSet<Vec<String>> {
+        let mut affected: HashSet<Vec<String>> = modules.iter().cloned().collect();
+        let mut to_visit = modules.to_vec();
+
+        while let Some(module_path) = to_visit.pop() {
+            for (dependent, dependencies) in &self.dependencies {
+                if dependencies.contains(&module_path) && affected.insert(dependent.clone()) {
+                    to_visit.push(dependent.clone());
+                }
+            }
+        }
+
+        affected
+    }
+}
+
+/// Parses and analyzes the module and everything it uses, without printing anything.
+/// The errors are reported by the caller, see `err::report_mangrove_error`.
+///
+/// # Errors
+///
+/// The first parse or analyze error.
+pub fn analyze(
     module_path: &[String],
     source_map: &mut SourceMap,
 ) -> Result<Program, MangroveError> {
+    analyze_with_graph(module_path, source_map).map(|(program, _)| program)
+}
+
+/// Same as `analyze`, but also returns the module graph, for analyzing only the changed modules
+/// the next time, see `analyze_changed`.
+///
+/// # Errors
+///
+/// The first parse or analyze error.
+///
+/// # Panics
+///
+pub fn analyze_with_graph(
+    module_path: &[String],
+    source_map: &mut SourceMap,
+) -> Result<(Program, ModuleGraph), MangroveError> {
+    let bootstrap_result = bootstrap_modules(source_map)?;
+    let mut program = bootstrap_result.program;
+
+    let mut graph = ModuleGraph {
+        core_module_path: bootstrap_result.core_module_path,
+        dependencies: HashMap::new(),
+    };
+
+    analyze_local_modules(module_path, &mut program, &mut graph, source_map)?;
+
+    Ok((program, graph))
+}
+
+/// Analyzes only the `affected` modules again, they must include every module that depends on a
+/// changed module, see `ModuleGraph::with_dependents`. The other modules, and the types in them,
+/// are shared with the `previous` program.
+///
+/// # Errors
+///
+/// The first parse or analyze error.
+pub fn analyze_changed(
+    module_path: &[String],
+    previous: &Program,
+    previous_graph: &ModuleGraph,
+    affected: &HashSet<Vec<String>>,
+    source_map: &mut SourceMap,
+) -> Result<(Program, ModuleGraph), MangroveError> {
+    let mut modules = Modules::new();
+    for (path, module) in previous.modules.modules() {
+        if !affected.contains(path) {
+            modules.link_module(path, module.clone());
+        }
+    }
+
+    // The state is kept, so the constant and function ids in the unchanged modules stay valid
+    let mut program = Program::new(
+        previous.state.clone(),
+        modules,
+        previous.default_symbol_table.clone(),
+    );
+
+    let mut graph = previous_graph.clone();
+    graph
+        .dependencies
+        .retain(|path, _| !affected.contains(path));
+
+    analyze_local_modules(module_path, &mut program, &mut graph, source_map)?;
+
+    Ok((program, graph))
+}
+
+/// Parses and analyzes the modules that are not in the program yet, in dependency order
+fn analyze_local_modules(
+    module_path: &[String],
+    program: &mut Program,
+    graph: &mut ModuleGraph,
+    source_map: &mut SourceMap,
+) -> Result<(), MangroveError> {
+    let core_symbol_table = program
+        .modules
+        .get(&graph.core_module_path)
+        .ok_or_else(|| MangroveError::Other("core module is not bootstrapped".to_string()))?
+        .symbol_table
+        .clone();
+
+    let mut dependency_parser = DependencyParser::new();
+    for resolved_path in program.modules.modules().keys() {
+        dependency_parser.add_resolved_module(resolved_path.clone());
+    }
+
+    let module_paths_in_order =
+        parse_local_modules_and_get_order(module_path.to_vec(), &mut dependency_parser, source_map)
+            .map_err(ScriptResolveError::from)?;
+
+    for path in &module_paths_in_order {
+        if let Some(parsed_module) = dependency_parser.get_parsed_module(path) {
+            let (imports, uses) = get_all_local_paths(source_map, parsed_module);
+            graph
+                .dependencies
+                .insert(path.clone(), imports.into_iter().chain(uses).collect());
+        }
+    }
+
+    analyze_modules_in_order(
+        &mut program.state,
+        &program.default_symbol_table,
+        &mut program.modules,
+        core_symbol_table.into(),
+        source_map,
+        &module_paths_in_order,
+        &dependency_parser,
+    )
+    .map_err(ScriptResolveError::from)?;
+
+    Ok(())
+}
+
+/// # Errors
+///
+/// The first parse or analyze error.
+pub fn compile(
+    module_path: &[String],
+    source_map: &mut SourceMap,
+) -> Result<(Program, ModuleGraph), MangroveError> {
     let start = Instant::now();
 
     //let mut root_versions = SeqMap::new();
     //root_versions.insert("mangrove".to_string(), "0.0.0".parse().unwrap())?;
 
-    let program = compile_and_analyze(module_path, source_map)?;
+    let compiled = analyze_with_graph(module_path, source_map)?;
 
-    let end = Instant::now();
-    let duration = end.duration_since(start);
+    log_compiled(module_path, start, "compiled");
+
+    Ok(compiled)
+}
+
+/// Same as `compile`, but only for the `affected` modules, see `analyze_changed`
+///
+/// # Errors
+///
+/// The first parse or analyze error.
+pub fn compile_changed(
+    module_path: &[String],
+    previous: &Program,
+    previous_graph: &ModuleGraph,
+    affected: &HashSet<Vec<String>>,
+    source_map: &mut SourceMap,
+) -> Result<(Program, ModuleGraph), MangroveError> {
+    let start = Instant::now();
+
+    let compiled = analyze_changed(module_path, previous, previous_graph, affected, source_map)?;
+
+    log_compiled(module_path, start, "compiled changes");
+
+    Ok(compiled)
+}
+
+fn log_compiled(module_path: &[String], start: Instant, what: &str) {
+    let duration = Instant::now().duration_since(start);
 
     let now: DateTime<Utc> = Utc::now();
     eprintln!(
         "{} {}: {} {} {:?}",
         now.format("%Y-%m-%d %H:%M:%S").white(),
-        "compiled".bright_cyan(),
+        what.bright_cyan(),
         module_path.join("::").green(),
         "took".bright_cyan(),
         duration.blue(),
     );
-
-    Ok(program)
 }
 
 /*
@@ -1,13 +1,16 @@
-use crate::err::show_mangrove_error;
-use crate::script::MangroveError;
-use crate::{ErrorResource, ScriptMessage, SourceMapResource};
-use std::rc::Rc;
-use swamp::prelude::{App, LoReM, LocalResource, Msg, Plugin, PreUpdate, ReM, Update};
+use crate::SourceMapResource;
+use crate::contract::{required_function, screen_functions, validate};
+use crate::flow::MAIN_SCREEN;
+use crate::manifest::DEFAULT_ENTRY_MODULE;
+use crate::reload::{ReloadState, reload_tick};
+use crate::script::{MangroveError, ModuleGraph};
+use std::collections::HashSet;
+use swamp::prelude::{App, LocalResource, Plugin, PreUpdate};
 use swamp_script::prelude::{
     Constants, ExternalFunctions, InternalFunctionDefinition, InternalFunctionDefinitionRef,
-    Program, eval_constants,
+    Program, Span, eval_constants,
 };
-use tracing::{debug, error};
+use tracing::debug;
 
 #[derive(LocalResource, Debug)]
 pub struct ScriptMain {
@@ -16,6 +19,12 @@ pub struct ScriptMain {
     pub simulation_new_fn: InternalFunctionDefinitionRef,
     pub render_new_fn: InternalFunctionDefinitionRef,
     pub input_new_fn: InternalFunctionDefinitionRef,
+    pub audio_new_fn: Option<InternalFunctionDefinitionRef>,
+    pub flow_new_fn: Option<InternalFunctionDefinitionRef>,
+    /// The module that provides the main screen
+    pub entry_module: String,
+    /// For compiling only the changed modules on hot reload
+    pub module_graph: ModuleGraph,
 }
 
 impl Default for ScriptMain {
@@ -30,6 +39,10 @@ impl Default for ScriptMain {
                 InternalFunctionDefinition::default(),
             ),
             input_new_fn: InternalFunctionDefinitionRef::from(InternalFunctionDefinition::default()),
+            audio_new_fn: None,
+            flow_new_fn: None,
+            entry_module: DEFAULT_ENTRY_MODULE.to_string(),
+            module_graph: ModuleGraph::default(),
         }
     }
 }
@@ -39,54 +52,84 @@ pub struct ScriptMainContext {}
 #[derive(LocalResource, Debug)]
 pub struct ScriptRender {}
 
+/// Compiles the scripts and validates the contract with the engine, see `contract::validate`.
+///
+/// # Errors
+///
+/// If the scripts fail to compile, or the first broken part of the contract.
 pub fn compile(source_map: &mut SourceMapResource) -> Result<ScriptMain, MangroveError> {
     debug!("start compiling");
 
-    let crate_main_path = &["crate".to_string(), "main".to_string()];
+    let crate_main_path = &["crate".to_string(), source_map.entry_module.clone()];
 
-    let resolved_program = crate::script::compile(crate_main_path, &mut source_map.source_map)?;
+    let (resolved_program, module_graph) =
+        crate::script::compile(crate_main_path, &mut source_map.source_map)?;
 
-    let simulation_new_fn = {
-        let main_module = resolved_program
-            .modules
-            .get(crate_main_path)
-            .expect("could not find main module");
+    create_script_main(resolved_program, module_graph, source_map)
+}
 
-        if let Some(function_ref) = main_module.symbol_table.get_internal_function("simulation") {
-            Rc::clone(function_ref)
-        } else {
-            error!(?main_module.symbol_table, "empty? main module");
-            return Err(MangroveError::Other("no simulation function".to_string()));
-        }
-    };
+/// Same as `compile`, but only analyzes the `affected` modules again, the other modules are shared
+/// with the `previous` program. `affected` must include the modules that depend on the changed
+/// modules, see `ModuleGraph::with_dependents`.
+///
+/// # Errors
+///
+/// If the scripts fail to compile, or the first broken part of the contract.
+pub fn compile_changed(
+    previous: &ScriptMain,
+    affected: &HashSet<Vec<String>>,
+    source_map: &mut SourceMapResource,
+) -> Result<ScriptMain, MangroveError> {
+    debug!(?affected, "start compiling changed modules");
+
+    let crate_main_path = &["crate".to_string(), source_map.entry_module.clone()];
+
+    let (resolved_program, module_graph) = crate::script::compile_changed(
+        crate_main_path,
+        &previous.resolved_program,
+        &previous.module_graph,
+        affected,
+        &mut source_map.source_map,
+    )?;
 
-    let render_new_fn = {
-        let main_module = resolved_program
-            .modules
-            .get(crate_main_path)
-            .expect("could not find main module");
+    create_script_main(resolved_program, module_graph, source_map)
+}
 
-        if let Some(function_ref) = main_module.symbol_table.get_internal_function("render") {
-            Rc::clone(function_ref)
-        } else {
-            error!(?main_module.symbol_table, "empty? main module");
-            return Err(MangroveError::Other("no render function".to_string()));
-        }
-    };
+fn create_script_main(
+    resolved_program: Program,
+    module_graph: ModuleGraph,
+    source_map: &SourceMapResource,
+) -> Result<ScriptMain, MangroveError> {
+    let crate_main_path = &["crate".to_string(), source_map.entry_module.clone()];
+
+    if let Some(contract_error) =
+        validate(&resolved_program, crate_main_path, &source_map.source_map)
+            .into_iter()
+            .next()
+    {
+        return Err(contract_error);
+    }
 
-    let input_new_fn = {
-        let main_module = resolved_program
-            .modules
-            .get(crate_main_path)
-            .expect("could not find main module");
+    let main_module = resolved_program
+        .modules
+        .get(crate_main_path)
+        .ok_or_else(|| {
+            MangroveError::Other(format!(
+                "could not find the entry module {}",
+                crate_main_path.join("::")
+            ))
+        })?;
 
-        if let Some(function_ref) = main_module.symbol_table.get_internal_function("input") {
-            Rc::clone(function_ref)
-        } else {
-            error!(?main_module.symbol_table, "empty? main module");
-            return Err(MangroveError::Other("no input function".to_string()));
-        }
-    };
+    let (simulation_new_fn, render_new_fn, audio_new_fn) =
+        screen_functions(main_module, &Span::default())?;
+
+    let input_new_fn = required_function(main_module, "input", &Span::default())?;
+
+    // Flow is optional, scripts without a `flow` function always stay on the main screen
+    let flow_new_fn = main_module
+        .symbol_table
+        .get_internal_function("flow")
+        .cloned();
 
     let external_functions = ExternalFunctions::<ScriptMainContext>::new();
 
@@ -106,27 +149,44 @@ pub fn compile(source_map: &mut SourceMapResource) -> Result<ScriptMain, Mangrov
         simulation_new_fn,
         render_new_fn,
         input_new_fn,
+        audio_new_fn,
+        flow_new_fn,
+        entry_module: source_map.entry_module.clone(),
+        module_graph,
     };
 
     Ok(script_game)
 }
 
-pub fn detect_reload_tick(
-    script_messages: Msg<ScriptMessage>,
-    mut script_game: LoReM<ScriptMain>,
-    mut source_map_resource: ReM<SourceMapResource>,
-    mut err: ReM<ErrorResource>,
-) {
-    for msg in script_messages.iter_previous() {
-        match msg {
-            ScriptMessage::Reload => match compile(&mut source_map_resource) {
-                Ok(new_simulation) => *script_game = new_simulation,
-                Err(mangrove_error) => {
-                    err.has_errors = true;
-                    show_mangrove_error(&mangrove_error, &source_map_resource.source_map);
-                }
-            },
-        }
+impl ScriptMain {
+    /// Uses the `simulation`, `render` and `audio` functions from the screen module from now on.
+    /// `main` is the entry module, otherwise the module must be imported by the entry module.
+    ///
+    /// # Errors
+    ///
+    /// If the module is missing or does not have a simulation or render function.
+    pub fn use_screen(&mut self, screen: &str) -> Result<(), MangroveError> {
+        let module_name = if screen == MAIN_SCREEN {
+            &self.entry_module
+        } else {
+            screen
+        };
+        let module_path = ["crate".to_string(), module_name.to_string()];
+        let module = self
+            .resolved_program
+            .modules
+            .get(&module_path)
+            .ok_or_else(|| {
+                MangroveError::Other(format!("could not find module {}", module_path.join("::")))
+            })?;
+        let (simulation_new_fn, render_new_fn, audio_new_fn) =
+            screen_functions(module, &Span::default())?;
+
+        self.simulation_new_fn = simulation_new_fn;
+        self.render_new_fn = render_new_fn;
+        self.audio_new_fn = audio_new_fn;
+
+        Ok(())
     }
 }
 
@@ -134,7 +194,10 @@ pub struct ScriptMainPlugin;
 
 impl Plugin for ScriptMainPlugin {
     fn build(&self, app: &mut App) {
-        app.add_system(PreUpdate, detect_reload_tick);
+        // Must be added before the other script plugins, so they see the new program in the
+        // same frame as it is swapped in
+        app.add_system(PreUpdate, reload_tick);
+        app.insert_local_resource(ReloadState::default());
 
         // HACK: Just add a completely zeroed out ScriptGame and wait for reload message.
         // TODO: Should not try to call updates with params that are not available yet.
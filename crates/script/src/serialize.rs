@@ -2,132 +2,140 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
+//! Writes the same layout as `Value::quick_serialize`, so it can be read back with
+//! `quick_deserialize`. `quick_serialize` writes into a fixed octet slice and panics
+//! when the value does not fit or has a type it does not support, so the value is walked
+//! here instead and written into a buffer that grows as needed.
+use crate::script::MangroveError;
+use swamp_script::prelude::{Value, ValueRef};
+use swamp_script_core_extra::extra::SparseValueId;
+
+/// No simulation state should ever get close to this
+pub const MAX_SERIALIZED_SIZE: usize = 64 * 1024 * 1024;
+
+/// Reuses the buffer between the serializations, so it only grows a few times at start
+#[derive(Debug, Default)]
+pub struct SerializeBuffer {
+    buf: Vec<u8>,
+}
 
-use crate::simulation::ScriptSimulation;
-use limnus_gamepad::{Button, GamepadMessage};
-use swamp::prelude::{App, FixedPostUpdate, LoReM, LocalResource, Msg, Plugin};
-use swamp_script::prelude::{Type, Value, quick_deserialize};
-
-fn serialize(mut logic: LoReM<ScriptSimulation>, mut rewind: LoReM<Rewind>) {
-    let mut buf = [0u8; 2048];
-    let logic_val = logic.immutable_simulation_value();
-    if let Value::NamedStruct(found_struct_type, _values) = &logic_val {
-        if rewind.tick_to_show.is_none() {
-            let serialized_octet_size = logic_val.quick_serialize(&mut buf, 0);
-            rewind.snapshots.push(Snapshot {
-                payload: buf[0..serialized_octet_size].to_vec(),
-            });
-
-            // Just for verification
-            {
-                let (_deserialized_value, deserialized_octet_size) =
-                    quick_deserialize(&Type::NamedStruct(found_struct_type.clone()), &buf, 0);
+impl SerializeBuffer {
+    #[must_use]
+    pub const fn new() -> Self {
+        Self { buf: Vec::new() }
+    }
 
-                assert_eq!(serialized_octet_size, deserialized_octet_size);
-            }
+    /// # Errors
+    ///
+    /// If the value is larger than `MAX_SERIALIZED_SIZE` or has a type that can not be serialized
+    pub fn serialize(&mut self, value: &Value) -> Result<&[u8], MangroveError> {
+        self.buf.clear();
+        write_value(value, &mut self.buf).map_err(|message| {
+            MangroveError::Other(format!("simulation value can not be serialized: {message}"))
+        })?;
+
+        if self.buf.len() > MAX_SERIALIZED_SIZE {
+            return Err(MangroveError::Other(format!(
+                "simulation value is larger than {MAX_SERIALIZED_SIZE} octets"
+            )));
         }
 
-        let index_to_show = {
-            match rewind.tick_to_show {
-                None => rewind.snapshots.len() - 1,
-                Some(index) => index.clamp(0, rewind.snapshots.len() - 1),
-            }
-        };
-
-        let payload = &rewind.snapshots[index_to_show].payload;
-
-        let (deserialized_payload_value, _deserialized_octet_size) =
-            quick_deserialize(&Type::NamedStruct(found_struct_type.clone()), payload, 0);
-
-        logic.debug_set_simulation_value(deserialized_payload_value);
-    } else {
-        panic!("logic has wrong type")
+        Ok(&self.buf)
     }
 }
 
-fn set_velocity(rewind: &mut Rewind, new_velocity: f32) {
-    if let Some(ref mut velocity) = rewind.tick_velocity {
-        *velocity = (new_velocity * new_velocity) * 5.0 * new_velocity.signum();
-    } else {
-        rewind.tick_velocity = Some(new_velocity);
-        rewind.tick_float = (rewind.snapshots.len() - 1) as f32;
-    };
+/// Serializes the value into a new vector, that is exactly the size of the serialized value
+///
+/// # Errors
+///
+/// If the value is larger than `MAX_SERIALIZED_SIZE` or has a type that can not be serialized
+pub fn serialize_value(value: &Value) -> Result<Vec<u8>, MangroveError> {
+    SerializeBuffer::new().serialize(value).map(<[u8]>::to_vec)
 }
 
-fn change_tick(mut rewind: LoReM<Rewind>) {
-    if let Some(found_velocity) = rewind.tick_velocity {
-        rewind.tick_float += found_velocity;
-        rewind.tick_float = rewind
-            .tick_float
-            .clamp(0.0, (rewind.snapshots.len() - 1) as f32);
-        rewind.tick_to_show = Some(rewind.tick_float as usize);
-    }
+/// Strings and containers are prefixed with an u16 length
+fn write_len(len: usize, what: &str, out: &mut Vec<u8>) -> Result<(), String> {
+    let len = u16::try_from(len).map_err(|_| {
+        format!(
+            "{what} length {len} is larger than the supported {}",
+            u16::MAX
+        )
+    })?;
+    out.extend_from_slice(&len.to_le_bytes());
+    Ok(())
 }
 
-fn rewind(messages: Msg<GamepadMessage>, mut rewind: LoReM<Rewind>) {
-    for gamepad in messages.iter_current() {
-        if let GamepadMessage::ButtonChanged(_gamepad_id, button, button_value) = gamepad {
-            match button {
-                Button::LeftTrigger2 => {
-                    set_velocity(&mut rewind, -*button_value);
-                }
-                Button::RightTrigger2 => {
-                    set_velocity(&mut rewind, *button_value);
-                }
-                Button::Select => {
-                    let currently_pushed_down = *button_value > 0.5;
-                    let was_pushed_now =
-                        !rewind.select_button_previous_state && currently_pushed_down;
+fn write_value(value: &Value, out: &mut Vec<u8>) -> Result<(), String> {
+    if out.len() > MAX_SERIALIZED_SIZE {
+        return Err(format!("larger than {MAX_SERIALIZED_SIZE} octets"));
+    }
 
-                    rewind.select_button_previous_state = currently_pushed_down;
-                    if was_pushed_now {
-                        match rewind.tick_to_show {
-                            None => {
-                                // Pause from the current tick index
-                                set_velocity(&mut rewind, 0.0);
-                            }
-                            Some(tick_index) => {
-                                // Continue from this tick, forget everything past this point
-                                rewind.snapshots.truncate(tick_index);
-                                rewind.tick_to_show = None;
-                                rewind.tick_velocity = None;
-                            }
-                        }
-                    }
-                }
-                _ => {}
+    match value {
+        Value::Int(x) => out.extend_from_slice(&x.to_le_bytes()),
+        Value::Float(fp) => out.extend_from_slice(&fp.inner().to_le_bytes()),
+        Value::String(s) => {
+            write_len(s.len(), "string", out)?;
+            out.extend_from_slice(s.as_bytes());
+        }
+        Value::Bool(b) => out.push(u8::from(*b)),
+        Value::Unit => {}
+        Value::Option(maybe_value) => match maybe_value {
+            None => out.push(0),
+            Some(inner_value) => {
+                out.push(1);
+                write_value(&inner_value.borrow(), out)?;
             }
+        },
+        Value::Vec(_, values) => {
+            write_len(values.len(), "vector", out)?;
+            write_values(values, out)?;
+        }
+        Value::Map(_, values) => {
+            write_len(values.len(), "map", out)?;
+            for (key, value) in values {
+                write_value(key, out)?;
+                write_value(&value.borrow(), out)?;
+            }
+        }
+        Value::Sparse(_, sparse) => {
+            write_len(sparse.sparse_slot.len(), "sparse", out)?;
+            for (id, value) in sparse.sparse_slot.iter() {
+                write_len(id.index, "sparse index", out)?;
+                out.push(id.generation);
+                write_value(&value.borrow(), out)?;
+            }
+        }
+        Value::Tuple(_, values) | Value::NamedStruct(_, values) => write_values(values, out)?,
+        Value::EnumVariantSimple(_, variant) => out.push(variant.common.container_index),
+        Value::EnumVariantTuple(_, variant, values) => {
+            out.push(variant.common.container_index);
+            write_values(values, out)?;
+        }
+        Value::EnumVariantStruct(_, variant, values) => {
+            out.push(variant.common.container_index);
+            write_values(values, out)?;
+        }
+        Value::RustValue(_, rust_value) => {
+            // Handles to engine resources (e.g. materials and sounds) are not part of the state
+            if let Some(sparse_id) = rust_value.borrow().as_any().downcast_ref::<SparseValueId>() {
+                write_len(sparse_id.0.index, "sparse index", out)?;
+                out.push(sparse_id.0.generation);
+            }
+        }
+        Value::AnonymousStruct(..) => return Err("anonymous structs are not supported".into()),
+        Value::Range(..) => return Err("ranges are not supported".into()),
+        Value::Slice(..) | Value::SlicePair(..) => return Err("slices are not supported".into()),
+        Value::InternalFunction(_) | Value::ExternalFunction(_) => {
+            return Err("functions are not supported".into());
         }
     }
-}
 
-#[derive(Debug)]
-pub struct Snapshot {
-    pub payload: Vec<u8>,
+    Ok(())
 }
 
-#[derive(Debug, LocalResource)]
-pub struct Rewind {
-    pub tick_velocity: Option<f32>,
-    pub tick_float: f32,
-    pub snapshots: Vec<Snapshot>,
-    pub tick_to_show: Option<usize>,
-    pub select_button_previous_state: bool,
-}
-
-pub struct SerializePlugin;
-
-impl Plugin for SerializePlugin {
-    fn build(&self, app: &mut App) {
-        app.insert_local_resource(Rewind {
-            tick_velocity: None,
-            tick_to_show: None,
-            tick_float: 0.0,
-            snapshots: vec![],
-            select_button_previous_state: false,
-        });
-        app.add_system(FixedPostUpdate, serialize);
-        app.add_system(FixedPostUpdate, rewind);
-        app.add_system(FixedPostUpdate, change_tick);
+fn write_values(values: &[ValueRef], out: &mut Vec<u8>) -> Result<(), String> {
+    for value in values {
+        write_value(&value.borrow(), out)?;
     }
+    Ok(())
 }
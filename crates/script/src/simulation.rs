@@ -2,68 +2,79 @@
  * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
-use crate::err::show_mangrove_error;
+use crate::contract::{
+    GAMEPAD_AXIS_CHANGED, GAMEPAD_BUTTON_CHANGED, KEYBOARD_KEY_CHANGED, TICK_PARAMETER_COUNTS,
+    axis_values, button_values, key_values,
+};
+use crate::err::{DiagnosticPhase, report_mangrove_error, report_runtime_error};
 use crate::input::ScriptInput;
+use crate::manifest::resource_asset_root;
+use crate::save::{SaveStore, register_save_members};
 use crate::script::{MangroveError, register_print};
 use crate::script_main::ScriptMain;
+use crate::tilemap::register_tilemap_members;
+use crate::timestep::SimulationTimestep;
 use crate::util::{get_impl_func, get_impl_func_optional};
-use crate::{ErrorResource, ScriptMessage, SourceMapResource};
+use crate::{ErrorResource, InputSource, ScriptMessage, SourceMapResource};
+use limnus_basic_input::InputMessage;
+use limnus_basic_input::prelude::{ButtonState, KeyCode};
+use limnus_clock::MonotonicTime;
 use limnus_gamepad::{Axis, AxisValueType, Button, ButtonValueType, GamePadId, GamepadMessage};
 use std::cell::RefCell;
-use std::env::current_dir;
 use std::rc::Rc;
 use swamp::prelude::{
-    App, Fp, LoRe, LoReM, LocalResource, Msg, Plugin, PreUpdate, Re, ReM, Update,
+    App, Fp, LoRe, LoReM, LocalResource, Msg, Plugin, PreUpdate, Re, ReAll, ReM, ResourceStorage,
+    Update,
 };
 use swamp_script::prelude::*;
 use tracing::debug;
 
-/// # Panics
-///
+/// Ticks the simulation as many times as the `SimulationTimestep` allows for the current time
 pub fn simulation_tick(
-    mut main: LoReM<ScriptMain>,
+    main: LoReM<ScriptMain>,
     mut script_simulation: LoReM<ScriptSimulation>,
+    mut timestep: ReM<SimulationTimestep>,
+    time: Re<MonotonicTime>,
     source_map: Re<SourceMapResource>,
     mut error: ReM<ErrorResource>,
 ) {
     let lookup: &dyn SourceMapLookup = &source_map.wrapper();
-    if error.has_errors {
+    if error.has_errors() {
         return;
     }
 
-    let variable_value_ref =
-        VariableValue::Reference(script_simulation.simulation_value_ref.clone());
-
-    let mut script_context = ScriptSimulationContext {};
-
-    let _ = util_execute_function(
-        &script_simulation.external_functions,
-        &main.constants,
-        &script_simulation.simulation_tick_fn,
-        &[variable_value_ref],
-        &mut script_context,
-        Some(lookup),
-    )
-    .inspect_err(|runtime_err| {
-        error.has_errors = true;
-        let current_path = &*current_dir().unwrap();
-        show_runtime_error(runtime_err, &source_map.source_map, &current_path)
-    });
+    let tick_count = timestep.advance(time.time);
+    for _ in 0..tick_count {
+        if let Err(runtime_err) = script_simulation.tick(&main, Some(lookup)) {
+            report_runtime_error(
+                &mut error,
+                DiagnosticPhase::Tick,
+                &runtime_err,
+                &source_map.source_map,
+            );
+            return;
+        }
+        timestep.ticked();
+    }
 }
 
 pub fn mouse_input_tick(
     mut script: LoReM<ScriptSimulation>,
     script_main: LoRe<ScriptMain>,
     script_input: LoRe<ScriptInput>,
+    source_map: Re<SourceMapResource>,
+    mut error: ReM<ErrorResource>,
 ) {
+    if error.has_errors() {
+        return;
+    }
     if let Some(found_fn) = &script.input_changed_fn.clone() {
-        script
-            .execute(
-                &script_main,
-                found_fn,
-                &[script_input.input_value.borrow().clone()],
-            )
-            .unwrap();
+        let result = script.execute(
+            &script_main,
+            found_fn,
+            &[script_input.input_value.borrow().clone()],
+        );
+        report_callback_error(result, &mut error, &source_map);
     }
 }
 
@@ -71,46 +82,205 @@ pub fn gamepad_input_tick(
     mut script: LoReM<ScriptSimulation>,
     main: LoRe<ScriptMain>,
     gamepad_messages: Msg<GamepadMessage>,
+    input_source: Re<InputSource>,
+    timestep: Re<SimulationTimestep>,
+    source_map: Re<SourceMapResource>,
+    mut error: ReM<ErrorResource>,
 ) {
+    // While paused, the input would change a simulation value that is not ticking
+    if *input_source == InputSource::Replay || timestep.is_paused() || error.has_errors() {
+        return;
+    }
     for gamepad_message in gamepad_messages.iter_current() {
-        script.gamepad(&main, gamepad_message);
+        let result = script.gamepad(&main, gamepad_message);
+        if !report_callback_error(result, &mut error, &source_map) {
+            return;
+        }
     }
 }
 
-#[derive(Debug)]
-pub struct ScriptSimulationContext {}
+pub fn keyboard_input_tick(
+    mut script: LoReM<ScriptSimulation>,
+    main: LoRe<ScriptMain>,
+    input_messages: Msg<InputMessage>,
+    input_source: Re<InputSource>,
+    timestep: Re<SimulationTimestep>,
+    source_map: Re<SourceMapResource>,
+    mut error: ReM<ErrorResource>,
+) {
+    if *input_source == InputSource::Replay || timestep.is_paused() || error.has_errors() {
+        return;
+    }
+    for input_message in input_messages.iter_previous() {
+        if let InputMessage::KeyboardInput(button_state, key_code) = input_message {
+            let result =
+                script.key_changed(&main, *key_code, *button_state == ButtonState::Pressed);
+            if !report_callback_error(result, &mut error, &source_map) {
+                return;
+            }
+        }
+    }
+}
+
+/// Reports a runtime error in a simulation callback, which stops the simulation the same way as
+/// an error in `tick()` does. Returns false if there was an error.
+pub fn report_callback_error(
+    result: Result<(), RuntimeError>,
+    error: &mut ErrorResource,
+    source_map: &SourceMapResource,
+) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(runtime_err) => {
+            report_runtime_error(
+                error,
+                DiagnosticPhase::Tick,
+                &runtime_err,
+                &source_map.source_map,
+            );
+            false
+        }
+    }
+}
+
+/// A gamepad as seen by the simulation, kept up to date from the gamepad messages
+#[derive(Debug, Clone)]
+pub struct ConnectedGamepad {
+    pub id: GamePadId,
+    pub name: String,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct ScriptSimulationContext {
+    pub connected_gamepads: Vec<ConnectedGamepad>,
+}
+
+/// # Errors
+///
+pub fn register_simulation_members(
+    input_symbol_table: &SymbolTable,
+    externals: &mut ExternalFunctions<ScriptSimulationContext>,
+) -> Result<(), String> {
+    // The mangrove::input package is only available if the scripts use it
+    let Some(connected_gamepads_fn) =
+        input_symbol_table.get_external_function_declaration("connected_gamepads")
+    else {
+        return Ok(());
+    };
+    let gamepad_struct_type = input_symbol_table
+        .get_struct("Gamepad")
+        .ok_or("connected_gamepads requires the Gamepad struct")?
+        .clone();
+
+    externals.register_external_function(
+        connected_gamepads_fn.id,
+        move |_mem_values: &[VariableValue], context| {
+            let gamepads = context
+                .connected_gamepads
+                .iter()
+                .map(|gamepad| {
+                    let fields = gamepad_struct_type
+                        .anon_struct_type
+                        .field_name_sorted_fields
+                        .keys()
+                        .map(|field_name| {
+                            let value = match field_name.as_str() {
+                                "id" => Value::Int(gamepad.id as i32),
+                                "name" => Value::String(gamepad.name.clone()),
+                                "active" => Value::Bool(gamepad.is_active),
+                                _ => Value::Unit,
+                            };
+                            Rc::new(RefCell::new(value))
+                        })
+                        .collect();
+                    Rc::new(RefCell::new(Value::NamedStruct(
+                        gamepad_struct_type.clone(),
+                        fields,
+                    )))
+                })
+                .collect();
+
+            Ok(Value::Vec(
+                Type::Slice(Box::new(Type::NamedStruct(gamepad_struct_type.clone()))),
+                gamepads,
+            ))
+        },
+    )?;
+
+    Ok(())
+}
 
 #[derive(LocalResource, Debug)]
 pub struct ScriptSimulation {
     simulation_value_ref: ValueRef,
+    previous_simulation_value: Value,
     simulation_tick_fn: InternalFunctionDefinitionRef,
     gamepad_axis_changed_fn: Option<InternalFunctionDefinitionRef>,
     gamepad_button_changed_fn: Option<InternalFunctionDefinitionRef>,
+    gamepad_connected_fn: Option<InternalFunctionDefinitionRef>,
+    gamepad_disconnected_fn: Option<InternalFunctionDefinitionRef>,
+    gamepad_activated_fn: Option<InternalFunctionDefinitionRef>,
+    keyboard_key_changed_fn: Option<InternalFunctionDefinitionRef>,
+    /// The `mangrove::input` values for the callbacks, only set if the simulation has the callback
+    key_values: Vec<(KeyCode, Value)>,
+    button_values: Vec<(Button, Value)>,
+    axis_values: Vec<(Axis, Value)>,
     input_changed_fn: Option<InternalFunctionDefinitionRef>,
+    next_screen_fn: Option<InternalFunctionDefinitionRef>,
     external_functions: ExternalFunctions<ScriptSimulationContext>,
-    script_context: ScriptSimulationContext, // It is empty, but stored for convenience
-    input_module: ModuleRef,
+    script_context: ScriptSimulationContext,
+    /// The simulation value after each tick, only kept if `keep_ticked_values` has been called
+    ticked_values: Option<Vec<Value>>,
 }
 
 impl ScriptSimulation {
-    pub const fn new(
+    /// Switches to a newly booted simulation.
+    /// The gamepads are only connected once, so they must survive the reboot.
+    pub fn replace(&mut self, mut booted: Self) {
+        booted.script_context.connected_gamepads =
+            std::mem::take(&mut self.script_context.connected_gamepads);
+        booted.ticked_values = self.ticked_values.take().map(|_| Vec::new());
+        *self = booted;
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
         simulation_value_ref: ValueRef,
         simulation_fn: InternalFunctionDefinitionRef,
         gamepad_axis_changed_fn: Option<InternalFunctionDefinitionRef>,
         gamepad_button_changed_fn: Option<InternalFunctionDefinitionRef>,
+        gamepad_connected_fn: Option<InternalFunctionDefinitionRef>,
+        gamepad_disconnected_fn: Option<InternalFunctionDefinitionRef>,
+        gamepad_activated_fn: Option<InternalFunctionDefinitionRef>,
+        keyboard_key_changed_fn: Option<InternalFunctionDefinitionRef>,
+        key_values: Vec<(KeyCode, Value)>,
+        button_values: Vec<(Button, Value)>,
+        axis_values: Vec<(Axis, Value)>,
         input_changed_fn: Option<InternalFunctionDefinitionRef>,
+        next_screen_fn: Option<InternalFunctionDefinitionRef>,
         external_functions: ExternalFunctions<ScriptSimulationContext>,
-        input_module: ModuleRef,
+        script_context: ScriptSimulationContext,
     ) -> Self {
+        let previous_simulation_value = simulation_value_ref.borrow().clone();
         Self {
             simulation_value_ref,
+            previous_simulation_value,
             simulation_tick_fn: simulation_fn,
             gamepad_axis_changed_fn,
             gamepad_button_changed_fn,
+            gamepad_connected_fn,
+            gamepad_disconnected_fn,
+            gamepad_activated_fn,
+            keyboard_key_changed_fn,
+            key_values,
+            button_values,
+            axis_values,
             input_changed_fn,
+            next_screen_fn,
             external_functions,
-            script_context: ScriptSimulationContext {},
-            input_module,
+            script_context,
+            ticked_values: None,
         }
     }
 
@@ -119,14 +289,107 @@ impl ScriptSimulation {
         self.simulation_value_ref.borrow().clone()
     }
 
+    /// The simulation value as it was before the latest tick, used for interpolation
+    #[must_use]
+    pub const fn previous_simulation_value(&self) -> &Value {
+        &self.previous_simulation_value
+    }
+
+    /// The shared reference to the simulation value, it is replaced when the simulation boots
+    #[must_use]
+    pub const fn simulation_value_ref(&self) -> &ValueRef {
+        &self.simulation_value_ref
+    }
+
     pub fn mutable_simulation_value_ref(&mut self) -> &ValueRef {
         &self.simulation_value_ref
     }
 
+    /// Replaces the simulation value, e.g. with a snapshot. There is nothing to interpolate from.
     pub fn debug_set_simulation_value(&mut self, value: Value) {
+        self.previous_simulation_value = value.clone();
         self.simulation_value_ref = Rc::new(RefCell::new(value));
     }
 
+    /// The gamepads that are connected, in the order they were connected
+    #[must_use]
+    pub fn connected_gamepads(&self) -> &[ConnectedGamepad] {
+        &self.script_context.connected_gamepads
+    }
+
+    /// Calls `tick()` on the simulation struct once
+    ///
+    /// # Errors
+    ///
+    pub fn tick(
+        &mut self,
+        script_main: &ScriptMain,
+        lookup: Option<&dyn SourceMapLookup>,
+    ) -> Result<(), RuntimeError> {
+        self.previous_simulation_value = self.immutable_simulation_value();
+        let variable_value_ref = VariableValue::Reference(self.simulation_value_ref.clone());
+
+        util_execute_function(
+            &self.external_functions,
+            &script_main.constants,
+            &self.simulation_tick_fn,
+            &[variable_value_ref],
+            &mut self.script_context,
+            lookup,
+        )?;
+
+        if let Some(ticked_values) = &mut self.ticked_values {
+            ticked_values.push(self.simulation_value_ref.borrow().clone());
+        }
+
+        Ok(())
+    }
+
+    /// Keeps a copy of the simulation value after every tick, e.g. for the time machine,
+    /// until they are taken with `take_ticked_values`
+    pub fn keep_ticked_values(&mut self) {
+        self.ticked_values.get_or_insert_with(Vec::new);
+    }
+
+    /// The simulation values for the ticks since the previous call, the oldest first
+    pub fn take_ticked_values(&mut self) -> Vec<Value> {
+        self.ticked_values
+            .as_mut()
+            .map(std::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// Calls the optional `next_screen()` on the simulation struct. An empty string means that
+    /// the simulation wants to stay on the current screen.
+    ///
+    /// # Errors
+    ///
+    pub fn next_screen(
+        &mut self,
+        script_main: &ScriptMain,
+    ) -> Result<Option<String>, RuntimeError> {
+        let Some(next_screen_fn) = &self.next_screen_fn else {
+            return Ok(None);
+        };
+
+        let screen_value = util_execute_function(
+            &self.external_functions,
+            &script_main.constants,
+            next_screen_fn,
+            &[VariableValue::Value(self.immutable_simulation_value())],
+            &mut self.script_context,
+            None,
+        )?;
+
+        let screen = screen_value.expect_string()?;
+
+        Ok(if screen.is_empty() {
+            None
+        } else {
+            Some(screen)
+        })
+    }
+
     fn execute(
         &mut self,
         script_main: &ScriptMain,
@@ -151,252 +414,200 @@ impl ScriptSimulation {
         Ok(())
     }
 
-    pub fn gamepad(&mut self, script_main: &ScriptMain, msg: &GamepadMessage) {
+    /// Calls the optional gamepad callbacks on the simulation struct
+    ///
+    /// # Errors
+    ///
+    /// If the script reports a runtime error.
+    pub fn gamepad(
+        &mut self,
+        script_main: &ScriptMain,
+        msg: &GamepadMessage,
+    ) -> Result<(), RuntimeError> {
         match msg {
-            GamepadMessage::Connected(_, _) => {}
-            GamepadMessage::Disconnected(_) => {}
-            GamepadMessage::Activated(_) => {}
+            GamepadMessage::Connected(gamepad_id, name) => {
+                self.connected(script_main, *gamepad_id, name)
+            }
+            GamepadMessage::Disconnected(gamepad_id) => self.disconnected(script_main, *gamepad_id),
+            GamepadMessage::Activated(gamepad_id) => self.activated(script_main, *gamepad_id),
             GamepadMessage::ButtonChanged(gamepad_id, button, value) => {
-                self.button_changed(script_main, *gamepad_id, *button, *value);
+                self.button_changed(script_main, *gamepad_id, *button, *value)
             }
             GamepadMessage::AxisChanged(gamepad_id, axis, value) => {
-                self.axis_changed(script_main, *gamepad_id, *axis, *value);
+                self.axis_changed(script_main, *gamepad_id, *axis, *value)
             }
         }
     }
 
-    fn axis_changed(
+    fn connected(
         &mut self,
         script_main: &ScriptMain,
         gamepad_id: GamePadId,
-        axis: Axis,
-        value: AxisValueType,
-    ) {
-        let script_axis_value = {
-            let input_module_ref = &self.input_module;
-            let axis_str = match axis {
-                Axis::LeftStickX => "LeftStickX",
-                Axis::LeftStickY => "LeftStickY",
-                Axis::RightStickX => "RightStickX",
-                Axis::RightStickY => "RightStickY",
-            };
-
-            let axis_enum = input_module_ref
-                .symbol_table
-                .get_enum("Axis")
-                .expect("axis")
-                .clone();
-
-            let variant = axis_enum
-                .get_variant(axis_str)
-                .expect("should be there")
-                .clone();
-
-            if let EnumVariantType::Nothing(simple) = variant {
-                Value::EnumVariantSimple(axis_enum, simple.clone())
-            } else {
-                panic!("variant axis problem");
-            }
-        };
-
-        if let Some(found_fn) = &self.gamepad_axis_changed_fn {
-            let gamepad_id_value = Value::Int(gamepad_id as i32);
-            let axis_value = Value::Float(Fp::from(value));
-
-            let fn_ref = found_fn.clone();
+        name: &str,
+    ) -> Result<(), RuntimeError> {
+        let connected_gamepads = &mut self.script_context.connected_gamepads;
+        connected_gamepads.retain(|gamepad| gamepad.id != gamepad_id);
+        connected_gamepads.push(ConnectedGamepad {
+            id: gamepad_id,
+            name: name.to_string(),
+            is_active: false,
+        });
 
-            self.execute(
-                script_main,
-                &fn_ref,
-                &[gamepad_id_value, script_axis_value, axis_value],
-            )
-            .expect("gamepad_axis_changed");
-        }
+        let Some(found_fn) = self.gamepad_connected_fn.clone() else {
+            return Ok(());
+        };
+        self.execute(
+            script_main,
+            &found_fn,
+            &[
+                Value::Int(gamepad_id as i32),
+                Value::String(name.to_string()),
+            ],
+        )
     }
 
-    fn button_changed(
+    fn disconnected(
         &mut self,
         script_main: &ScriptMain,
         gamepad_id: GamePadId,
-        button: Button,
-        value: ButtonValueType,
-    ) {
-        let script_button_value = {
-            let input_module_ref = &self.input_module;
-            let button_str = match button {
-                Button::South => "South",
-                Button::East => "East",
-                Button::North => "North",
-                Button::West => "West",
-                Button::LeftTrigger => "LeftTrigger",
-                Button::LeftTrigger2 => "LeftTrigger2",
-                Button::RightTrigger => "RightTrigger",
-                Button::RightTrigger2 => "RightTrigger2",
-                Button::Select => "Select",
-                Button::Start => "Start",
-                Button::Mode => "Mode",
-                Button::LeftThumb => "LeftThumb",
-                Button::RightThumb => "RightThumb",
-                Button::DPadUp => "DPadUp",
-                Button::DPadDown => "DPadDown",
-                Button::DPadLeft => "DPadLeft",
-                Button::DPadRight => "DPadRight",
-            };
-
-            let button_enum = input_module_ref
-                .symbol_table
-                .get_enum("Button")
-                .expect("button name failed")
-                .clone();
-
-            let variant = button_enum
-                .get_variant(button_str)
-                .expect("should exist")
-                .clone();
-
-            if let EnumVariantType::Nothing(simple) = variant {
-                Value::EnumVariantSimple(button_enum, simple.clone())
-            } else {
-                panic!("variant axis problem");
-            }
-        };
-
-        if let Some(found_fn) = &self.gamepad_button_changed_fn {
-            let gamepad_id_value = Value::Int(
-                i32::try_from(gamepad_id).expect("could not convert gamepad button to i32"),
-            );
-            let button_value = Value::Float(Fp::from(value));
-
-            let fn_ref = found_fn.clone();
-
-            self.execute(
-                script_main,
-                &fn_ref,
-                &[gamepad_id_value, script_button_value, button_value],
-            )
-            .expect("gamepad_button_changed");
-        }
-    }
-}
-
-/// # Errors
-///
-pub fn input_module() -> Result<(SymbolTable, EnumType, EnumType), Error> {
-    let mut symbol_table = SymbolTable::new(&["mangrove".to_string(), "input".to_string()]);
+    ) -> Result<(), RuntimeError> {
+        self.script_context
+            .connected_gamepads
+            .retain(|gamepad| gamepad.id != gamepad_id);
 
-    let axis_enum_type_ref = {
-        let mut parent = EnumType {
-            name: Node {
-                span: Span::default(),
-            },
-            assigned_name: "Axis".to_string(),
-            module_path: Vec::default(),
-            variants: SeqMap::default(),
-            instantiated_type_parameters: vec![],
+        let Some(found_fn) = self.gamepad_disconnected_fn.clone() else {
+            return Ok(());
         };
+        self.execute(script_main, &found_fn, &[Value::Int(gamepad_id as i32)])
+    }
 
-        let variant_names = ["LeftStickX", "LeftStickY", "RightStickX", "RightStickY"];
-        let mut resolved_variants = SeqMap::new();
-        for (container_index, variant_name) in variant_names.iter().enumerate() {
-            let variant = EnumVariantSimpleType {
-                common: EnumVariantCommon {
-                    name: Node {
-                        span: Span::default(),
-                    },
-                    assigned_name: variant_name.to_string(),
-                    container_index: container_index as u8,
-                },
-            };
-
-            let complete_variant = EnumVariantType::Nothing(variant.into());
-
-            resolved_variants
-                .insert(variant_name.to_string(), complete_variant)
-                .expect("works");
+    fn activated(
+        &mut self,
+        script_main: &ScriptMain,
+        gamepad_id: GamePadId,
+    ) -> Result<(), RuntimeError> {
+        if let Some(gamepad) = self
+            .script_context
+            .connected_gamepads
+            .iter_mut()
+            .find(|gamepad| gamepad.id == gamepad_id)
+        {
+            gamepad.is_active = true;
         }
 
-        parent.variants = resolved_variants;
+        let Some(found_fn) = self.gamepad_activated_fn.clone() else {
+            return Ok(());
+        };
+        self.execute(script_main, &found_fn, &[Value::Int(gamepad_id as i32)])
+    }
 
-        symbol_table.add_enum_type(parent.clone())?;
+    /// Calls the optional `keyboard_key_changed()` with the `mangrove::input::Key` and if it was pressed or released
+    ///
+    /// # Errors
+    ///
+    /// If the script reports a runtime error.
+    pub fn key_changed(
+        &mut self,
+        script_main: &ScriptMain,
+        key_code: KeyCode,
+        pressed: bool,
+    ) -> Result<(), RuntimeError> {
+        let Some(found_fn) = self.keyboard_key_changed_fn.clone() else {
+            return Ok(());
+        };
+        // The values are checked for all key codes when the simulation boots
+        let Some((_, script_key_value)) = self
+            .key_values
+            .iter()
+            .find(|(known_key_code, _)| *known_key_code == key_code)
+        else {
+            return Ok(());
+        };
 
-        parent
-    };
+        self.execute(
+            script_main,
+            &found_fn,
+            &[script_key_value.clone(), Value::Bool(pressed)],
+        )
+    }
 
-    let button_enum_type_ref = {
-        let mut parent = EnumType {
-            name: Node {
-                span: Span::default(),
-            },
-            assigned_name: "Button".to_string(),
-            module_path: Vec::default(),
-            variants: Default::default(),
-            instantiated_type_parameters: vec![],
+    fn axis_changed(
+        &mut self,
+        script_main: &ScriptMain,
+        gamepad_id: GamePadId,
+        axis: Axis,
+        value: AxisValueType,
+    ) -> Result<(), RuntimeError> {
+        let Some(found_fn) = self.gamepad_axis_changed_fn.clone() else {
+            return Ok(());
+        };
+        // The values are checked for all axes when the simulation boots
+        let Some((_, script_axis_value)) =
+            self.axis_values.iter().find(|(known, _)| *known == axis)
+        else {
+            return Ok(());
         };
 
-        let button_names = [
-            "South",
-            "East",
-            "North",
-            "West",
-            "LeftTrigger",
-            "LeftTrigger2",
-            "RightTrigger",
-            "RightTrigger2",
-            "Select",
-            "Start",
-            "Mode",
-            "LeftThumb",
-            "RightThumb",
-            "DPadUp",
-            "DPadDown",
-            "DPadLeft",
-            "DPadRight",
+        let arguments = [
+            Value::Int(gamepad_id as i32),
+            script_axis_value.clone(),
+            Value::Float(Fp::from(value)),
         ];
+        self.execute(script_main, &found_fn, &arguments)
+    }
 
-        for (container_index, button_variant_name) in button_names.iter().enumerate() {
-            let variant = EnumVariantSimpleType {
-                common: EnumVariantCommon {
-                    name: Node {
-                        span: Span::default(),
-                    },
-                    assigned_name: button_variant_name.to_string(),
-                    container_index: container_index as u8,
-                },
-            };
-
-            let complete_variant = EnumVariantType::Nothing(variant);
-            // symbol_table.add_enum_variant(&mut button_enum_type_ref, complete_variant)?;
-            parent
-                .variants
-                .insert(button_variant_name.to_string(), complete_variant)
-                .unwrap();
-        }
-
-        symbol_table.add_enum_type(parent.clone())?;
-
-        parent.clone()
-    };
+    fn button_changed(
+        &mut self,
+        script_main: &ScriptMain,
+        gamepad_id: GamePadId,
+        button: Button,
+        value: ButtonValueType,
+    ) -> Result<(), RuntimeError> {
+        let Some(found_fn) = self.gamepad_button_changed_fn.clone() else {
+            return Ok(());
+        };
+        // The values are checked for all buttons when the simulation boots
+        let Some((_, script_button_value)) = self
+            .button_values
+            .iter()
+            .find(|(known, _)| *known == button)
+        else {
+            return Ok(());
+        };
 
-    Ok((symbol_table, axis_enum_type_ref, button_enum_type_ref))
+        let arguments = [
+            Value::Int(gamepad_id as i32),
+            script_button_value.clone(),
+            Value::Float(Fp::from(value)),
+        ];
+        self.execute(script_main, &found_fn, &arguments)
+    }
 }
 
 pub fn detect_reload_tick(
     script_messages: Msg<ScriptMessage>,
     mut script_simulation: LoReM<ScriptSimulation>,
     script_game: LoRe<ScriptMain>,
-    mut source_map_resource: ReM<SourceMapResource>,
+    source_map_resource: ReM<SourceMapResource>,
+    all_resources: ReAll,
     mut err: ReM<ErrorResource>,
 ) {
-    if err.has_errors {
+    if err.has_errors() {
         return;
     }
     for msg in script_messages.iter_previous() {
         match msg {
-            ScriptMessage::Reload => match boot(&script_game) {
-                Ok(new_simulation) => *script_simulation = new_simulation,
+            // Reloads are booted together with the other plugins, see `reload::reload_tick`
+            ScriptMessage::Reload(_) | ScriptMessage::Reloaded(_) => {}
+            ScriptMessage::ScreenChanged => match boot(&script_game, &all_resources) {
+                Ok(new_simulation) => script_simulation.replace(new_simulation),
                 Err(mangrove_error) => {
-                    show_mangrove_error(&mangrove_error, &source_map_resource.source_map);
-                    err.has_errors = true;
+                    report_mangrove_error(
+                        &mut err,
+                        DiagnosticPhase::Boot,
+                        &mangrove_error,
+                        &source_map_resource.source_map,
+                    );
 
                     //                    eprintln!("script simulation failed: {}", mangrove_error);
                     //                    error!(error=?mangrove_error, "script simulation compile failed");
@@ -406,9 +617,16 @@ pub fn detect_reload_tick(
     }
 }
 
-fn boot(script_main: &ScriptMain) -> Result<ScriptSimulation, MangroveError> {
+/// Creates the simulation struct by calling `simulation()` in the main module
+///
+/// # Errors
+///
+pub fn boot(
+    script_main: &ScriptMain,
+    resources: &ResourceStorage,
+) -> Result<ScriptSimulation, MangroveError> {
     debug!("boot simulation");
-    let mut script_context = ScriptSimulationContext {};
+    let mut script_context = ScriptSimulationContext::default();
 
     let mut simulation_externals = ExternalFunctions::<ScriptSimulationContext>::new();
 
@@ -417,6 +635,34 @@ fn boot(script_main: &ScriptMain) -> Result<ScriptSimulation, MangroveError> {
         &mut simulation_externals,
     );
 
+    // The mangrove::input package is only available if the scripts use it
+    let input_module = script_main
+        .resolved_program
+        .modules
+        .get(&["mangrove".to_string(), "input".to_string()])
+        .cloned()
+        .unwrap_or_else(|| ModuleRef::new(Module::new(SymbolTable::new(&[]), None)));
+
+    register_simulation_members(&input_module.symbol_table, &mut simulation_externals)?;
+
+    register_save_members(
+        &script_main.resolved_program.modules,
+        &script_main.resolved_program.state.associated_impls,
+        &script_main.constants,
+        &mut simulation_externals,
+        &resources
+            .get::<SaveStore>()
+            .cloned()
+            .unwrap_or_else(SaveStore::in_memory),
+    )?;
+
+    register_tilemap_members(
+        &script_main.resolved_program.modules,
+        &script_main.resolved_program.state.associated_impls,
+        &mut simulation_externals,
+        &resource_asset_root(resources),
+    )?;
+
     let simulation_value = util_execute_function(
         &simulation_externals,
         &script_main.constants,
@@ -436,17 +682,39 @@ fn boot(script_main: &ScriptMain) -> Result<ScriptSimulation, MangroveError> {
         &script_main.resolved_program.state.associated_impls,
         simulation_struct_type_ref,
         "tick",
-    );
+        &TICK_PARAMETER_COUNTS,
+    )?;
     let gamepad_axis_changed_fn = get_impl_func_optional(
         &script_main.resolved_program.state.associated_impls,
         simulation_struct_type_ref,
-        "gamepad_axis_changed",
+        GAMEPAD_AXIS_CHANGED,
     );
+    let axis_values = if gamepad_axis_changed_fn.is_some() {
+        axis_values(&script_main.resolved_program.modules)?
+    } else {
+        Vec::new()
+    };
     let gamepad_button_changed_fn = get_impl_func_optional(
         &script_main.resolved_program.state.associated_impls,
         simulation_struct_type_ref,
-        "gamepad_button_changed",
+        GAMEPAD_BUTTON_CHANGED,
     );
+    let button_values = if gamepad_button_changed_fn.is_some() {
+        button_values(&script_main.resolved_program.modules)?
+    } else {
+        Vec::new()
+    };
+
+    let keyboard_key_changed_fn = get_impl_func_optional(
+        &script_main.resolved_program.state.associated_impls,
+        simulation_struct_type_ref,
+        KEYBOARD_KEY_CHANGED,
+    );
+    let key_values = if keyboard_key_changed_fn.is_some() {
+        key_values(&script_main.resolved_program.modules)?
+    } else {
+        Vec::new()
+    };
 
     let input_changed_fn = get_impl_func_optional(
         &script_main.resolved_program.state.associated_impls,
@@ -454,19 +722,49 @@ fn boot(script_main: &ScriptMain) -> Result<ScriptSimulation, MangroveError> {
         "input_changed",
     );
 
+    // Optional, lets the simulation ask the flow for another screen, e.g. when the game is over
+    let next_screen_fn = get_impl_func_optional(
+        &script_main.resolved_program.state.associated_impls,
+        simulation_struct_type_ref,
+        "next_screen",
+    );
+
+    // Optional, lets local multiplayer games know when a player plugs in or unplugs a gamepad
+    let gamepad_connected_fn = get_impl_func_optional(
+        &script_main.resolved_program.state.associated_impls,
+        simulation_struct_type_ref,
+        "gamepad_connected",
+    );
+    let gamepad_disconnected_fn = get_impl_func_optional(
+        &script_main.resolved_program.state.associated_impls,
+        simulation_struct_type_ref,
+        "gamepad_disconnected",
+    );
+    let gamepad_activated_fn = get_impl_func_optional(
+        &script_main.resolved_program.state.associated_impls,
+        simulation_struct_type_ref,
+        "gamepad_activated",
+    );
+
     // Convert it to a mutable (reference), so it can be mutated in update ticks
     let simulation_value_ref = Rc::new(RefCell::new(simulation_value));
 
-    //let input_module = input_module()?;
-
     Ok(ScriptSimulation::new(
         simulation_value_ref,
         simulation_tick_fn,
         gamepad_axis_changed_fn,
         gamepad_button_changed_fn,
+        gamepad_connected_fn,
+        gamepad_disconnected_fn,
+        gamepad_activated_fn,
+        keyboard_key_changed_fn,
+        key_values,
+        button_values,
+        axis_values,
         input_changed_fn,
+        next_screen_fn,
         simulation_externals,
-        ModuleRef::new(Module::new(SymbolTable::new(&[]), None)),
+        script_context,
     ))
 }
 
@@ -474,15 +772,17 @@ pub struct ScriptSimulationPlugin;
 
 impl Plugin for ScriptSimulationPlugin {
     fn build(&self, app: &mut App) {
+        app.insert_resource(SimulationTimestep::default());
         app.add_system(PreUpdate, detect_reload_tick);
         app.add_system(Update, simulation_tick);
         app.add_system(Update, gamepad_input_tick);
-        app.add_system(Update, mouse_input_tick);
+        app.add_system(Update, keyboard_input_tick);
 
         // HACK: Just add a completely zeroed out ScriptSimulation and wait for reload message.
         // TODO: Should not try to call updates with params that are not available yet.
         app.insert_local_resource(ScriptSimulation {
             simulation_value_ref: Rc::new(RefCell::new(Value::default())),
+            previous_simulation_value: Value::default(),
             simulation_tick_fn: Rc::new(InternalFunctionDefinition {
                 body: Expression {
                     ty: Type::Int,
@@ -501,13 +801,18 @@ impl Plugin for ScriptSimulationPlugin {
             }),
             gamepad_axis_changed_fn: None,
             gamepad_button_changed_fn: None,
+            gamepad_connected_fn: None,
+            gamepad_disconnected_fn: None,
+            gamepad_activated_fn: None,
+            keyboard_key_changed_fn: None,
+            key_values: Vec::new(),
+            button_values: Vec::new(),
+            axis_values: Vec::new(),
             input_changed_fn: None,
+            next_screen_fn: None,
             external_functions: ExternalFunctions::new(),
-            script_context: ScriptSimulationContext {},
-            input_module: Rc::new(Module {
-                main_expression: None,
-                symbol_table: SymbolTable::new(&[]),
-            }),
+            script_context: ScriptSimulationContext::default(),
+            ticked_values: None,
         });
     }
 }
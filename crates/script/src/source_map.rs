@@ -3,27 +3,93 @@
  * Licensed under the MIT License. See LICENSE in the project root for license information.
  */
 use crate::SourceMapResource;
+use crate::manifest::{DEFAULT_ENTRY_MODULE, Manifest};
 use seq_map::SeqMap;
+use std::io;
 use std::path::Path;
 use swamp::prelude::{App, Plugin};
 use swamp_script::prelude::{SourceMap, swamp_registry_path};
 
-pub struct SourceMapPlugin;
+impl SourceMapResource {
+    /// Mounts `scripts_path` as the crate and the swamp registry as the registry
+    ///
+    /// # Errors
+    ///
+    /// # Panics
+    ///
+    pub fn new(scripts_path: &Path) -> io::Result<Self> {
+        Self::with_registry(scripts_path, &swamp_registry_path()?)
+    }
 
-impl Plugin for SourceMapPlugin {
-    fn build(&self, app: &mut App) {
-        //app.add_system(UpdatePhase::Update, detect_reload_tick);
+    /// Mounts `scripts_path` as the crate and `registry_path` as the registry
+    ///
+    /// # Errors
+    ///
+    /// If a mounted directory can not be found.
+    ///
+    /// # Panics
+    ///
+    pub fn with_registry(scripts_path: &Path, registry_path: &Path) -> io::Result<Self> {
         let mut mounts = SeqMap::new();
-        let path_buf = Path::new("scripts/").to_path_buf();
-        mounts.insert("crate".to_string(), path_buf).unwrap();
+        mounts
+            .insert("crate".to_string(), scripts_path.to_path_buf())
+            .unwrap();
+        mounts
+            .insert("registry".to_string(), registry_path.to_path_buf())
+            .unwrap();
+
+        Ok(Self {
+            source_map: SourceMap::new(&mounts)?,
+            entry_module: DEFAULT_ENTRY_MODULE.to_string(),
+        })
+    }
 
-        let registry_path = swamp_registry_path().unwrap();
+    /// Mounts the script root and the extra mounts of the manifest.
+    /// A `registry` mount replaces the swamp registry.
+    ///
+    /// # Errors
+    ///
+    /// If a mounted directory can not be found.
+    ///
+    /// # Panics
+    ///
+    pub fn from_manifest(manifest: &Manifest) -> io::Result<Self> {
+        let mut mounts = SeqMap::new();
         mounts
-            .insert("registry".to_string(), registry_path)
+            .insert("crate".to_string(), manifest.script_root())
             .unwrap();
 
-        app.insert_resource(SourceMapResource {
-            source_map: SourceMap::new(&mounts).unwrap(),
+        if !manifest.scripts.mounts.contains_key("registry") {
+            mounts
+                .insert("registry".to_string(), swamp_registry_path()?)
+                .unwrap();
+        }
+
+        for (name, path) in manifest.mounts() {
+            mounts.insert(name.to_string(), path).unwrap();
+        }
+
+        Ok(Self {
+            source_map: SourceMap::new(&mounts)?,
+            entry_module: manifest.scripts.entry.clone(),
+        })
+    }
+}
+
+pub struct SourceMapPlugin;
+
+impl Plugin for SourceMapPlugin {
+    fn build(&self, app: &mut App) {
+        let manifest = app
+            .get_resource_ref::<Manifest>()
+            .cloned()
+            .unwrap_or_default();
+        let source_map = SourceMapResource::from_manifest(&manifest).unwrap_or_else(|err| {
+            panic!(
+                "could not mount the scripts in {}: {err}",
+                manifest.script_root().display()
+            )
         });
+        app.insert_resource(source_map);
     }
 }
@@ -0,0 +1,57 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Helpers for the tests that compile the scripts in `tests/fixtures`, or need a whole program
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The core package of the swamp-script version that mangrove uses
+const CORE_PACKAGE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/tests/fixtures/registry/core-0.0.0"
+);
+const MANGROVE_PACKAGE: &str = concat!(
+    env!("CARGO_MANIFEST_DIR"),
+    "/../../assets/packages/mangrove-0.0.0"
+);
+
+/// The scripts directory of a fixture, e.g. `golden`
+pub fn scripts(fixture: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(fixture)
+        .join("scripts")
+}
+
+fn copy_package(from: &str, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let path = entry.unwrap().path();
+        fs::copy(&path, to.join(path.file_name().unwrap())).unwrap();
+    }
+}
+
+/// An empty temporary directory with a `registry` that has the core package and the mangrove
+/// package of this repository, so the tests do not need an installed swamp registry
+pub fn test_directory(name: &str) -> PathBuf {
+    let directory =
+        std::env::temp_dir().join(format!("mangrove-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    copy_package(CORE_PACKAGE, &directory.join("registry/core-0.0.0"));
+    copy_package(MANGROVE_PACKAGE, &directory.join("registry/mangrove"));
+    directory
+}
+
+/// Compiling the scripts, and even an empty program, needs more stack than the 2 MiB that a test
+/// thread has in debug builds
+pub fn with_stack<T: Send>(test: impl FnOnce() -> T + Send) -> T {
+    std::thread::scope(|scope| {
+        std::thread::Builder::new()
+            .stack_size(8 * 1024 * 1024)
+            .spawn_scoped(scope, test)
+            .unwrap()
+            .join()
+            .unwrap()
+    })
+}
@@ -0,0 +1,1261 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Maps made in Tiled, saved as `.tmx` (XML) or `.tmj` (JSON).
+//!
+//! The tile layers are drawn by `Gfx::tilemap_layer`, and the object layers and custom properties
+//! are given to the simulation by the `mangrove::tilemap` package. Only orthogonal, finite maps
+//! are supported. The tilesets can be embedded or in `.tsx` / `.tsj` files, and must use a single
+//! PNG image.
+use crate::xml::{self, XmlElement};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use serde_json::Value as JsonValue;
+use std::cell::RefCell;
+use std::io::Read;
+use std::path::{Component, Path};
+use std::rc::Rc;
+use swamp::prelude::{Fp, UVec2};
+use swamp_script::prelude::*;
+use tracing::warn;
+
+/// The map file extensions, in the order they are looked for
+pub const MAP_EXTENSIONS: &[&str] = &["tmx", "tmj"];
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+/// The value of a custom property. Colors and files are text, e.g. `#ff00ff00`, and an object
+/// property is the id of the object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PropertyValue {
+    Text(String),
+    Int(i32),
+    Float(f64),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Property {
+    pub name: String,
+    pub value: PropertyValue,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tileset {
+    pub first_gid: u32,
+    pub name: String,
+    pub tile_width: u16,
+    pub tile_height: u16,
+    pub columns: u32,
+    pub tile_count: u32,
+    pub spacing: u16,
+    pub margin: u16,
+    /// The image relative to the asset root, e.g. `tiles/forest.png`.
+    /// `None` for a collection of images, which is not supported.
+    pub image: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileLayer {
+    pub name: String,
+    pub width: u32,
+    pub height: u32,
+    /// The global tile ids with the flip flags, row by row from the top. Zero is an empty cell.
+    pub tiles: Vec<u32>,
+    pub opacity: f32,
+    pub properties: Vec<Property>,
+}
+
+/// An object in an object layer. The position is the lower left corner of the object, in pixels
+/// from the lower left corner of the map, with y going up like everywhere else in mangrove.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MapObject {
+    pub id: u32,
+    pub name: String,
+    /// Called type in older versions of Tiled
+    pub class: String,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+    pub properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectLayer {
+    pub name: String,
+    pub objects: Vec<MapObject>,
+    pub properties: Vec<Property>,
+}
+
+/// Where a tile is in its tileset image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TileFrame {
+    /// The index in `Tilemap::tilesets`
+    pub tileset: usize,
+    /// From the upper left corner of the image
+    pub texture_pos: UVec2,
+    pub size: UVec2,
+    pub flip_x: bool,
+    pub flip_y: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tilemap {
+    /// In tiles
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u16,
+    pub tile_height: u16,
+    /// Sorted by the first global tile id
+    pub tilesets: Vec<Tileset>,
+    /// The tile layers and object layers in group layers are included, in the order of the map
+    pub tile_layers: Vec<TileLayer>,
+    pub object_layers: Vec<ObjectLayer>,
+    pub properties: Vec<Property>,
+}
+
+/// The map file for the asset name, e.g. `levels/one.tmx` for `levels/one`
+#[must_use]
+pub fn find_map_file(asset_root: &Path, name: &str) -> Option<String> {
+    MAP_EXTENSIONS
+        .iter()
+        .map(|extension| format!("{name}.{extension}"))
+        .find(|file_name| asset_root.join(file_name).is_file())
+}
+
+impl Tilemap {
+    /// Reads `{name}.tmx` or `{name}.tmj` from the asset root
+    ///
+    /// # Errors
+    ///
+    /// If the map can not be found, read or understood.
+    pub fn load(asset_root: &Path, name: &str) -> Result<Self, String> {
+        let file_name = find_map_file(asset_root, name).ok_or_else(|| {
+            format!(
+                "could not find the map '{name}.tmx' or '{name}.tmj' in {}",
+                asset_root.display()
+            )
+        })?;
+
+        Self::load_file(asset_root, &file_name)
+    }
+
+    /// # Errors
+    ///
+    /// If the map can not be read or understood.
+    pub fn load_file(asset_root: &Path, file_name: &str) -> Result<Self, String> {
+        let text = read_asset(asset_root, file_name)?;
+        let mut tilemap = if file_name.ends_with(".tmx") {
+            xml::parse(&text).and_then(|root| tilemap_from_xml(&root, asset_root, file_name))
+        } else {
+            parse_json(&text).and_then(|root| tilemap_from_json(&root, asset_root, file_name))
+        }
+        .map_err(|err| format!("could not load the map '{file_name}': {err}"))?;
+
+        tilemap.tilesets.sort_by_key(|tileset| tileset.first_gid);
+
+        let rotated_count = tilemap
+            .tile_layers
+            .iter()
+            .flat_map(|layer| &layer.tiles)
+            .filter(|gid| *gid & FLIPPED_DIAGONALLY != 0)
+            .count();
+        if rotated_count > 0 {
+            warn!(
+                map = file_name,
+                rotated_count, "rotated tiles are drawn without the rotation"
+            );
+        }
+
+        Ok(tilemap)
+    }
+
+    #[must_use]
+    pub fn tile_layer(&self, name: &str) -> Option<&TileLayer> {
+        self.tile_layers.iter().find(|layer| layer.name == name)
+    }
+
+    /// Where the tile is in its tileset. `None` for an empty cell, or a tile that is not in any
+    /// tileset with an image.
+    #[must_use]
+    pub fn tile(&self, gid_and_flags: u32) -> Option<TileFrame> {
+        let gid = gid_and_flags & GID_MASK;
+        if gid == 0 {
+            return None;
+        }
+
+        let tileset_index = self
+            .tilesets
+            .iter()
+            .rposition(|tileset| tileset.first_gid <= gid)?;
+        let tileset = &self.tilesets[tileset_index];
+        let local_id = gid - tileset.first_gid;
+        if local_id >= tileset.tile_count || tileset.columns == 0 || tileset.image.is_none() {
+            return None;
+        }
+
+        let column = local_id % tileset.columns;
+        let row = local_id / tileset.columns;
+        let x =
+            u32::from(tileset.margin) + column * u32::from(tileset.tile_width + tileset.spacing);
+        let y = u32::from(tileset.margin) + row * u32::from(tileset.tile_height + tileset.spacing);
+
+        Some(TileFrame {
+            tileset: tileset_index,
+            texture_pos: UVec2::new(u16::try_from(x).ok()?, u16::try_from(y).ok()?),
+            size: UVec2::new(tileset.tile_width, tileset.tile_height),
+            flip_x: gid_and_flags & FLIPPED_HORIZONTALLY != 0,
+            flip_y: gid_and_flags & FLIPPED_VERTICALLY != 0,
+        })
+    }
+}
+
+fn read_asset(asset_root: &Path, file_name: &str) -> Result<String, String> {
+    std::fs::read_to_string(asset_root.join(file_name))
+        .map_err(|err| format!("could not read '{file_name}': {err}"))
+}
+
+/// A path in a map or tileset file is relative to that file. The result is relative to the asset
+/// root, with forward slashes.
+fn relative_asset_path(base_file: &str, relative: &str) -> Result<String, String> {
+    let mut parts: Vec<String> = Vec::new();
+    let joined = Path::new(base_file)
+        .parent()
+        .unwrap_or_else(|| Path::new(""))
+        .join(relative);
+    for component in joined.components() {
+        match component {
+            Component::Normal(name) => parts.push(name.to_string_lossy().into_owned()),
+            Component::ParentDir => {
+                parts
+                    .pop()
+                    .ok_or_else(|| format!("'{relative}' is outside of the asset root"))?;
+            }
+            Component::CurDir => {}
+            Component::RootDir | Component::Prefix(_) => {
+                return Err(format!("'{relative}' must be relative to the map"));
+            }
+        }
+    }
+
+    Ok(parts.join("/"))
+}
+
+fn property_value(type_name: &str, text: &str, owner: &str) -> Result<PropertyValue, String> {
+    let invalid = || format!("the {type_name} property {owner} has the value '{text}'");
+    Ok(match type_name {
+        "int" | "object" => PropertyValue::Int(text.parse().map_err(|_| invalid())?),
+        "float" => PropertyValue::Float(text.parse().map_err(|_| invalid())?),
+        "bool" => PropertyValue::Bool(text.parse().map_err(|_| invalid())?),
+        _ => PropertyValue::Text(text.to_string()),
+    })
+}
+
+fn decode_base64(text: &str) -> Result<Vec<u8>, String> {
+    let mut octets = Vec::new();
+    let mut buffer = 0u32;
+    let mut bit_count = 0;
+    for ch in text.bytes().filter(|ch| !ch.is_ascii_whitespace()) {
+        let value = match ch {
+            b'A'..=b'Z' => ch - b'A',
+            b'a'..=b'z' => ch - b'a' + 26,
+            b'0'..=b'9' => ch - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            _ => return Err(format!("'{}' is not valid in base64", char::from(ch))),
+        };
+        buffer = (buffer << 6) | u32::from(value);
+        bit_count += 6;
+        if bit_count >= 8 {
+            bit_count -= 8;
+            octets.push((buffer >> bit_count) as u8);
+            buffer &= (1 << bit_count) - 1;
+        }
+    }
+
+    Ok(octets)
+}
+
+/// The global tile ids in a layer, from the `data` in the map
+fn decode_tiles(
+    encoding: Option<&str>,
+    compression: Option<&str>,
+    data: &str,
+) -> Result<Vec<u32>, String> {
+    match encoding {
+        Some("csv") => data
+            .split(',')
+            .map(str::trim)
+            .filter(|gid| !gid.is_empty())
+            .map(|gid| gid.parse().map_err(|_| format!("'{gid}' is not a tile id")))
+            .collect(),
+        Some("base64") => {
+            let encoded = decode_base64(data)?;
+            let mut octets = Vec::new();
+            match compression {
+                None | Some("") => octets = encoded,
+                Some("zlib") => {
+                    ZlibDecoder::new(encoded.as_slice())
+                        .read_to_end(&mut octets)
+                        .map_err(|err| format!("could not uncompress the tiles: {err}"))?;
+                }
+                Some("gzip") => {
+                    GzDecoder::new(encoded.as_slice())
+                        .read_to_end(&mut octets)
+                        .map_err(|err| format!("could not uncompress the tiles: {err}"))?;
+                }
+                Some(other) => {
+                    return Err(format!(
+                        "the compression '{other}' is not supported, use zlib, gzip or none"
+                    ));
+                }
+            }
+
+            Ok(octets
+                .chunks_exact(4)
+                .map(|gid| u32::from_le_bytes([gid[0], gid[1], gid[2], gid[3]]))
+                .collect())
+        }
+        Some(other) => Err(format!("the tile layer format '{other}' is not supported")),
+        None => Err("expected the tiles to be encoded".to_string()),
+    }
+}
+
+fn check_tile_count(layer: &TileLayer) -> Result<(), String> {
+    let expected = layer.width as usize * layer.height as usize;
+    if layer.tiles.len() == expected {
+        Ok(())
+    } else {
+        Err(format!(
+            "the layer '{}' has {} tiles, expected {expected}",
+            layer.name,
+            layer.tiles.len()
+        ))
+    }
+}
+
+/// Tiled uses y going down, from the upper left corner of the map. Tile objects are positioned
+/// at their lower left corner, and the other objects at their upper left corner.
+const fn flip_object_y(y: f64, height: f64, is_tile_object: bool, map_height: f64) -> f64 {
+    if is_tile_object {
+        map_height - y
+    } else {
+        map_height - y - height
+    }
+}
+
+const fn map_pixel_height(height: u32, tile_height: u16) -> f64 {
+    height as f64 * tile_height as f64
+}
+
+// The XML format (.tmx and .tsx)
+
+fn xml_u32(element: &XmlElement, name: &str) -> Result<u32, String> {
+    element
+        .attribute(name)
+        .ok_or_else(|| format!("<{}> is missing '{name}'", element.name))?
+        .parse()
+        .map_err(|_| {
+            format!(
+                "expected '{name}' in <{}> to be a whole number",
+                element.name
+            )
+        })
+}
+
+fn xml_u32_or(element: &XmlElement, name: &str, default: u32) -> Result<u32, String> {
+    element
+        .attribute(name)
+        .map_or(Ok(default), |_| xml_u32(element, name))
+}
+
+fn xml_u16_or(element: &XmlElement, name: &str, default: u16) -> Result<u16, String> {
+    u16::try_from(xml_u32_or(element, name, u32::from(default))?).map_err(|_| {
+        format!(
+            "expected '{name}' in <{}> to be at most {}",
+            element.name,
+            u16::MAX
+        )
+    })
+}
+
+fn xml_f64_or(element: &XmlElement, name: &str, default: f64) -> Result<f64, String> {
+    element.attribute(name).map_or(Ok(default), |value| {
+        value
+            .parse()
+            .map_err(|_| format!("expected '{name}' in <{}> to be a number", element.name))
+    })
+}
+
+fn xml_properties(element: &XmlElement) -> Result<Vec<Property>, String> {
+    let Some(properties) = element.child("properties") else {
+        return Ok(Vec::new());
+    };
+
+    properties
+        .children_named("property")
+        // Class properties have nested members, which are not supported
+        .filter(|property| property.attribute("type") != Some("class"))
+        .map(|property| {
+            let name = property
+                .attribute("name")
+                .ok_or("a property is missing its 'name'")?;
+            // A string with several lines is written as the text of the element
+            let text = property.attribute("value").unwrap_or(&property.text);
+            let type_name = property.attribute("type").unwrap_or("string");
+            Ok(Property {
+                name: name.to_string(),
+                value: property_value(type_name, text, &format!("'{name}'"))?,
+            })
+        })
+        .collect()
+}
+
+fn tileset_from_xml(
+    element: &XmlElement,
+    first_gid: u32,
+    file_name: &str,
+) -> Result<Tileset, String> {
+    let image = element
+        .child("image")
+        .and_then(|image| image.attribute("source"))
+        .map(|source| relative_asset_path(file_name, source))
+        .transpose()?;
+
+    Ok(Tileset {
+        first_gid,
+        name: element.attribute("name").unwrap_or_default().to_string(),
+        tile_width: xml_u16_or(element, "tilewidth", 0)?,
+        tile_height: xml_u16_or(element, "tileheight", 0)?,
+        columns: xml_u32_or(element, "columns", 0)?,
+        tile_count: xml_u32_or(element, "tilecount", 0)?,
+        spacing: xml_u16_or(element, "spacing", 0)?,
+        margin: xml_u16_or(element, "margin", 0)?,
+        image,
+    })
+}
+
+fn tile_layer_from_xml(element: &XmlElement) -> Result<TileLayer, String> {
+    let name = element.attribute("name").unwrap_or_default().to_string();
+    let data = element
+        .child("data")
+        .ok_or_else(|| format!("the layer '{name}' is missing <data>"))?;
+    if data.child("chunk").is_some() {
+        return Err("infinite maps are not supported".to_string());
+    }
+
+    // Without an encoding, every tile is a <tile> element
+    let tiles = if data.attribute("encoding").is_some() {
+        decode_tiles(
+            data.attribute("encoding"),
+            data.attribute("compression"),
+            &data.text,
+        )?
+    } else {
+        data.children_named("tile")
+            .map(|tile| xml_u32_or(tile, "gid", 0))
+            .collect::<Result<_, _>>()?
+    };
+
+    let layer = TileLayer {
+        name,
+        width: xml_u32(element, "width")?,
+        height: xml_u32(element, "height")?,
+        tiles,
+        opacity: xml_f64_or(element, "opacity", 1.0)? as f32,
+        properties: xml_properties(element)?,
+    };
+    check_tile_count(&layer)?;
+
+    Ok(layer)
+}
+
+fn object_layer_from_xml(element: &XmlElement, map_height: f64) -> Result<ObjectLayer, String> {
+    let objects = element
+        .children_named("object")
+        .map(|object| {
+            let y = xml_f64_or(object, "y", 0.0)?;
+            let height = xml_f64_or(object, "height", 0.0)?;
+            Ok(MapObject {
+                id: xml_u32_or(object, "id", 0)?,
+                name: object.attribute("name").unwrap_or_default().to_string(),
+                class: object
+                    .attribute("class")
+                    .or_else(|| object.attribute("type"))
+                    .unwrap_or_default()
+                    .to_string(),
+                x: xml_f64_or(object, "x", 0.0)?,
+                y: flip_object_y(y, height, object.attribute("gid").is_some(), map_height),
+                width: xml_f64_or(object, "width", 0.0)?,
+                height,
+                properties: xml_properties(object)?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(ObjectLayer {
+        name: element.attribute("name").unwrap_or_default().to_string(),
+        objects,
+        properties: xml_properties(element)?,
+    })
+}
+
+/// Adds the layers, and the layers in the group layers
+fn layers_from_xml(
+    element: &XmlElement,
+    map_height: f64,
+    tilemap: &mut Tilemap,
+) -> Result<(), String> {
+    for child in &element.children {
+        match child.name.as_str() {
+            "layer" => tilemap.tile_layers.push(tile_layer_from_xml(child)?),
+            "objectgroup" => tilemap
+                .object_layers
+                .push(object_layer_from_xml(child, map_height)?),
+            "group" => layers_from_xml(child, map_height, tilemap)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn tilemap_from_xml(
+    root: &XmlElement,
+    asset_root: &Path,
+    file_name: &str,
+) -> Result<Tilemap, String> {
+    if root.name != "map" {
+        return Err(format!("expected <map>, found <{}>", root.name));
+    }
+    check_orthogonal(
+        root.attribute("orientation"),
+        root.attribute("infinite") == Some("1"),
+    )?;
+
+    let tilesets = root
+        .children_named("tileset")
+        .map(|tileset| {
+            let first_gid = xml_u32(tileset, "firstgid")?;
+            match tileset.attribute("source") {
+                Some(source) => load_external_tileset(asset_root, file_name, source, first_gid),
+                None => tileset_from_xml(tileset, first_gid, file_name),
+            }
+        })
+        .collect::<Result<_, String>>()?;
+
+    let mut tilemap = Tilemap {
+        width: xml_u32(root, "width")?,
+        height: xml_u32(root, "height")?,
+        tile_width: xml_u16_or(root, "tilewidth", 0)?,
+        tile_height: xml_u16_or(root, "tileheight", 0)?,
+        tilesets,
+        tile_layers: Vec::new(),
+        object_layers: Vec::new(),
+        properties: xml_properties(root)?,
+    };
+    let map_height = map_pixel_height(tilemap.height, tilemap.tile_height);
+    layers_from_xml(root, map_height, &mut tilemap)?;
+
+    Ok(tilemap)
+}
+
+fn check_orthogonal(orientation: Option<&str>, infinite: bool) -> Result<(), String> {
+    match orientation {
+        None | Some("orthogonal") => {}
+        Some(other) => {
+            return Err(format!(
+                "the orientation '{other}' is not supported, only orthogonal maps are"
+            ));
+        }
+    }
+    if infinite {
+        return Err("infinite maps are not supported".to_string());
+    }
+    Ok(())
+}
+
+fn load_external_tileset(
+    asset_root: &Path,
+    map_file: &str,
+    source: &str,
+    first_gid: u32,
+) -> Result<Tileset, String> {
+    let tileset_file = relative_asset_path(map_file, source)?;
+    let text = read_asset(asset_root, &tileset_file)?;
+    if tileset_file.ends_with(".tsx") {
+        let root = xml::parse(&text)?;
+        tileset_from_xml(&root, first_gid, &tileset_file)
+    } else {
+        let root = parse_json(&text)?;
+        tileset_from_json(&root, first_gid, &tileset_file)
+    }
+    .map_err(|err| format!("could not load the tileset '{tileset_file}': {err}"))
+}
+
+// The JSON format (.tmj and .tsj)
+
+fn parse_json(text: &str) -> Result<JsonValue, String> {
+    serde_json::from_str(text).map_err(|err| err.to_string())
+}
+
+/// A whole number that is zero or positive and fits in an `u32`
+fn json_u32(value: &JsonValue) -> Option<u32> {
+    value.as_u64().and_then(|number| u32::try_from(number).ok())
+}
+
+fn json_u32_or(value: &JsonValue, name: &str, default: u32) -> Result<u32, String> {
+    value.get(name).map_or(Ok(default), |field| {
+        json_u32(field).ok_or_else(|| format!("expected '{name}' to be a whole number"))
+    })
+}
+
+fn json_u16_or(value: &JsonValue, name: &str, default: u16) -> Result<u16, String> {
+    u16::try_from(json_u32_or(value, name, u32::from(default))?)
+        .map_err(|_| format!("expected '{name}' to be at most {}", u16::MAX))
+}
+
+fn json_f64_or(value: &JsonValue, name: &str, default: f64) -> Result<f64, String> {
+    value.get(name).map_or(Ok(default), |field| {
+        field
+            .as_f64()
+            .ok_or_else(|| format!("expected '{name}' to be a number"))
+    })
+}
+
+fn json_str<'a>(value: &'a JsonValue, name: &str) -> &'a str {
+    value
+        .get(name)
+        .and_then(JsonValue::as_str)
+        .unwrap_or_default()
+}
+
+fn json_properties(value: &JsonValue) -> Result<Vec<Property>, String> {
+    value
+        .get("properties")
+        .and_then(JsonValue::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        // Class properties have nested members, which are not supported
+        .filter(|property| json_str(property, "type") != "class")
+        .map(|property| {
+            let name = property
+                .get("name")
+                .and_then(JsonValue::as_str)
+                .ok_or("a property is missing its 'name'")?;
+            let text = match property.get("value") {
+                Some(JsonValue::String(text)) => text.clone(),
+                Some(JsonValue::Bool(value)) => value.to_string(),
+                Some(JsonValue::Number(number)) => number.to_string(),
+                _ => String::new(),
+            };
+            let type_name = property
+                .get("type")
+                .and_then(JsonValue::as_str)
+                .unwrap_or("string");
+            Ok(Property {
+                name: name.to_string(),
+                value: property_value(type_name, &text, &format!("'{name}'"))?,
+            })
+        })
+        .collect()
+}
+
+fn tileset_from_json(
+    value: &JsonValue,
+    first_gid: u32,
+    file_name: &str,
+) -> Result<Tileset, String> {
+    let image = value
+        .get("image")
+        .and_then(JsonValue::as_str)
+        .map(|source| relative_asset_path(file_name, source))
+        .transpose()?;
+
+    Ok(Tileset {
+        first_gid,
+        name: json_str(value, "name").to_string(),
+        tile_width: json_u16_or(value, "tilewidth", 0)?,
+        tile_height: json_u16_or(value, "tileheight", 0)?,
+        columns: json_u32_or(value, "columns", 0)?,
+        tile_count: json_u32_or(value, "tilecount", 0)?,
+        spacing: json_u16_or(value, "spacing", 0)?,
+        margin: json_u16_or(value, "margin", 0)?,
+        image,
+    })
+}
+
+fn tile_layer_from_json(value: &JsonValue) -> Result<TileLayer, String> {
+    let name = json_str(value, "name").to_string();
+    if value.get("chunks").is_some() {
+        return Err("infinite maps are not supported".to_string());
+    }
+
+    let tiles = match value.get("data") {
+        Some(JsonValue::Array(gids)) => gids
+            .iter()
+            .map(|gid| {
+                json_u32(gid)
+                    .ok_or_else(|| format!("the layer '{name}' has a tile id that is not valid"))
+            })
+            .collect::<Result<_, _>>()?,
+        Some(JsonValue::String(data)) => decode_tiles(
+            value.get("encoding").and_then(JsonValue::as_str),
+            value.get("compression").and_then(JsonValue::as_str),
+            data,
+        )?,
+        _ => return Err(format!("the layer '{name}' is missing 'data'")),
+    };
+
+    let layer = TileLayer {
+        width: json_u32_or(value, "width", 0)?,
+        height: json_u32_or(value, "height", 0)?,
+        tiles,
+        opacity: json_f64_or(value, "opacity", 1.0)? as f32,
+        properties: json_properties(value)?,
+        name,
+    };
+    check_tile_count(&layer)?;
+
+    Ok(layer)
+}
+
+fn object_layer_from_json(value: &JsonValue, map_height: f64) -> Result<ObjectLayer, String> {
+    let objects = value
+        .get("objects")
+        .and_then(JsonValue::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|object| {
+            let y = json_f64_or(object, "y", 0.0)?;
+            let height = json_f64_or(object, "height", 0.0)?;
+            let class = match json_str(object, "class") {
+                "" => json_str(object, "type"),
+                class => class,
+            };
+            Ok(MapObject {
+                id: json_u32_or(object, "id", 0)?,
+                name: json_str(object, "name").to_string(),
+                class: class.to_string(),
+                x: json_f64_or(object, "x", 0.0)?,
+                y: flip_object_y(y, height, object.get("gid").is_some(), map_height),
+                width: json_f64_or(object, "width", 0.0)?,
+                height,
+                properties: json_properties(object)?,
+            })
+        })
+        .collect::<Result<_, String>>()?;
+
+    Ok(ObjectLayer {
+        name: json_str(value, "name").to_string(),
+        objects,
+        properties: json_properties(value)?,
+    })
+}
+
+/// Adds the layers, and the layers in the group layers
+fn layers_from_json(
+    value: &JsonValue,
+    map_height: f64,
+    tilemap: &mut Tilemap,
+) -> Result<(), String> {
+    for layer in value
+        .get("layers")
+        .and_then(JsonValue::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+    {
+        match json_str(layer, "type") {
+            "tilelayer" => tilemap.tile_layers.push(tile_layer_from_json(layer)?),
+            "objectgroup" => tilemap
+                .object_layers
+                .push(object_layer_from_json(layer, map_height)?),
+            "group" => layers_from_json(layer, map_height, tilemap)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn tilemap_from_json(
+    root: &JsonValue,
+    asset_root: &Path,
+    file_name: &str,
+) -> Result<Tilemap, String> {
+    check_orthogonal(
+        root.get("orientation").and_then(JsonValue::as_str),
+        root.get("infinite").and_then(JsonValue::as_bool) == Some(true),
+    )?;
+
+    let tilesets = root
+        .get("tilesets")
+        .and_then(JsonValue::as_array)
+        .map(Vec::as_slice)
+        .unwrap_or_default()
+        .iter()
+        .map(|tileset| {
+            let first_gid = tileset
+                .get("firstgid")
+                .and_then(json_u32)
+                .ok_or("a tileset is missing 'firstgid'")?;
+            match tileset.get("source").and_then(JsonValue::as_str) {
+                Some(source) => load_external_tileset(asset_root, file_name, source, first_gid),
+                None => tileset_from_json(tileset, first_gid, file_name),
+            }
+        })
+        .collect::<Result<_, String>>()?;
+
+    let mut tilemap = Tilemap {
+        width: json_u32_or(root, "width", 0)?,
+        height: json_u32_or(root, "height", 0)?,
+        tile_width: json_u16_or(root, "tilewidth", 0)?,
+        tile_height: json_u16_or(root, "tileheight", 0)?,
+        tilesets,
+        tile_layers: Vec::new(),
+        object_layers: Vec::new(),
+        properties: json_properties(root)?,
+    };
+    let map_height = map_pixel_height(tilemap.height, tilemap.tile_height);
+    layers_from_json(root, map_height, &mut tilemap)?;
+
+    Ok(tilemap)
+}
+
+// The mangrove::tilemap package
+
+/// The types in the `mangrove::tilemap` package, for creating the values for the script
+struct TilemapTypes {
+    property: NamedStructType,
+    property_value: EnumType,
+    map_object: NamedStructType,
+    object_layer: NamedStructType,
+}
+
+impl TilemapTypes {
+    fn property_value(&self, value: &PropertyValue) -> Value {
+        let (variant_name, field) = match value {
+            PropertyValue::Text(text) => ("Text", Value::String(text.clone())),
+            PropertyValue::Int(value) => ("Int", Value::Int(*value)),
+            PropertyValue::Float(value) => ("Float", Value::Float(Fp::from(*value as f32))),
+            PropertyValue::Bool(value) => ("Bool", Value::Bool(*value)),
+        };
+        match self.property_value.variants.get(&variant_name.to_string()) {
+            Some(EnumVariantType::Tuple(variant)) => Value::EnumVariantTuple(
+                self.property_value.clone(),
+                variant.clone(),
+                vec![Rc::new(RefCell::new(field))],
+            ),
+            _ => Value::Unit,
+        }
+    }
+
+    fn properties(&self, properties: &[Property]) -> Value {
+        let items = properties
+            .iter()
+            .map(|property| {
+                struct_value(&self.property, |field_name| match field_name {
+                    "name" => Value::String(property.name.clone()),
+                    "value" => self.property_value(&property.value),
+                    _ => Value::Unit,
+                })
+            })
+            .collect();
+
+        Value::Vec(
+            Type::Slice(Box::new(Type::NamedStruct(self.property.clone()))),
+            items,
+        )
+    }
+
+    fn map_object(&self, object: &MapObject) -> ValueRef {
+        struct_value(&self.map_object, |field_name| match field_name {
+            "id" => Value::Int(object.id as i32),
+            "name" => Value::String(object.name.clone()),
+            "class" => Value::String(object.class.clone()),
+            "x" => Value::Float(Fp::from(object.x as f32)),
+            "y" => Value::Float(Fp::from(object.y as f32)),
+            "width" => Value::Float(Fp::from(object.width as f32)),
+            "height" => Value::Float(Fp::from(object.height as f32)),
+            "properties" => self.properties(&object.properties),
+            _ => Value::Unit,
+        })
+    }
+
+    fn object_layers(&self, object_layers: &[ObjectLayer]) -> Value {
+        let items = object_layers
+            .iter()
+            .map(|layer| {
+                struct_value(&self.object_layer, |field_name| match field_name {
+                    "name" => Value::String(layer.name.clone()),
+                    "objects" => Value::Vec(
+                        Type::Slice(Box::new(Type::NamedStruct(self.map_object.clone()))),
+                        layer
+                            .objects
+                            .iter()
+                            .map(|object| self.map_object(object))
+                            .collect(),
+                    ),
+                    "properties" => self.properties(&layer.properties),
+                    _ => Value::Unit,
+                })
+            })
+            .collect();
+
+        Value::Vec(
+            Type::Slice(Box::new(Type::NamedStruct(self.object_layer.clone()))),
+            items,
+        )
+    }
+}
+
+/// The fields must be in the order of the struct type
+fn struct_value(struct_type: &NamedStructType, field: impl Fn(&str) -> Value) -> ValueRef {
+    let fields = struct_type
+        .anon_struct_type
+        .field_name_sorted_fields
+        .keys()
+        .map(|field_name| Rc::new(RefCell::new(field(field_name))))
+        .collect();
+
+    Rc::new(RefCell::new(Value::NamedStruct(
+        struct_type.clone(),
+        fields,
+    )))
+}
+
+fn required_struct(symbol_table: &SymbolTable, name: &str) -> Result<NamedStructType, String> {
+    symbol_table
+        .get_struct(name)
+        .cloned()
+        .ok_or_else(|| format!("mangrove::tilemap must have the {name} struct"))
+}
+
+/// The map for the simulation. A map that can not be loaded is logged, and gives no layers.
+fn load_for_simulation(asset_root: &Path, name: &str) -> Option<Tilemap> {
+    Tilemap::load(asset_root, name)
+        .inspect_err(|err| warn!(map = name, err, "could not load the map"))
+        .ok()
+}
+
+/// The map name that is passed to the `Tilemaps` functions, after `self`
+fn map_name_param(mem_values: &[VariableValue]) -> Result<String, ValueError> {
+    convert_to_values(mem_values)
+        .and_then(|params| params.get(1).cloned())
+        .ok_or_else(|| ValueError::TypeError("expected a map name".to_string()))?
+        .expect_string()
+}
+
+/// Registers the functions in `Tilemaps`, if the scripts use the `mangrove::tilemap` package
+///
+/// # Errors
+///
+pub fn register_tilemap_members<C>(
+    modules: &Modules,
+    associated_impls: &AssociatedImpls,
+    externals: &mut ExternalFunctions<C>,
+    asset_root: &Path,
+) -> Result<(), String> {
+    let Some(tilemap_module) = modules.get(&["mangrove".to_string(), "tilemap".to_string()]) else {
+        return Ok(());
+    };
+    let symbol_table = &tilemap_module.symbol_table;
+    let types = Rc::new(TilemapTypes {
+        property: required_struct(symbol_table, "Property")?,
+        property_value: symbol_table
+            .get_enum("PropertyValue")
+            .cloned()
+            .ok_or("mangrove::tilemap must have the PropertyValue enum")?,
+        map_object: required_struct(symbol_table, "MapObject")?,
+        object_layer: required_struct(symbol_table, "ObjectLayer")?,
+    });
+    let tilemaps_type = Type::NamedStruct(required_struct(symbol_table, "Tilemaps")?);
+
+    // object_layers
+    let object_layers_fn_id =
+        associated_impls.api_fetch_external_function_id(&tilemaps_type, "object_layers");
+    let object_layers_types = types.clone();
+    let object_layers_asset_root = asset_root.to_path_buf();
+    externals.register_external_function(
+        object_layers_fn_id,
+        move |mem_values: &[VariableValue], _context| {
+            let map_name = map_name_param(mem_values)?;
+            let object_layers = load_for_simulation(&object_layers_asset_root, &map_name)
+                .map(|tilemap| tilemap.object_layers)
+                .unwrap_or_default();
+
+            Ok(object_layers_types.object_layers(&object_layers))
+        },
+    )?;
+
+    // properties
+    let properties_fn_id =
+        associated_impls.api_fetch_external_function_id(&tilemaps_type, "properties");
+    let properties_asset_root = asset_root.to_path_buf();
+    externals.register_external_function(
+        properties_fn_id,
+        move |mem_values: &[VariableValue], _context| {
+            let map_name = map_name_param(mem_values)?;
+            let properties = load_for_simulation(&properties_asset_root, &map_name)
+                .map(|tilemap| tilemap.properties)
+                .unwrap_or_default();
+
+            Ok(types.properties(&properties))
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::manifest::DEFAULT_ASSET_ROOT;
+
+    const MAP_FILE: &str = "levels/one.tmx";
+
+    /// The maps in the tests have embedded tilesets, so nothing is read from the asset root
+    fn asset_root() -> &'static Path {
+        Path::new(DEFAULT_ASSET_ROOT)
+    }
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" renderorder="right-down" width="3" height="2" tilewidth="16" tileheight="16" infinite="0">
+ <properties>
+  <property name="music" value="forest"/>
+  <property name="gravity" type="float" value="-9.5"/>
+  <property name="dark" type="bool" value="true"/>
+ </properties>
+ <tileset firstgid="1" name="forest" tilewidth="16" tileheight="16" spacing="1" margin="2" tilecount="8" columns="4">
+  <image source="../tiles/forest.png" width="70" height="36"/>
+ </tileset>
+ <layer id="1" name="ground" width="3" height="2" opacity="0.5">
+  <data encoding="csv">
+1,2147483650,0,
+1073741831,0,3
+</data>
+ </layer>
+ <group id="2" name="details">
+  <layer id="3" name="base64" width="3" height="2">
+   <data encoding="base64">AQAAAAIAAAADAAAAAAAAAAAAAAABAACA</data>
+  </layer>
+  <layer id="4" name="zlib" width="3" height="2">
+   <data encoding="base64" compression="zlib">eJxjZGBgYAJiZgYEYGRgaAAAAQwAiA==</data>
+  </layer>
+  <objectgroup id="5" name="spawns">
+   <properties>
+    <property name="enemies" type="int" value="4"/>
+   </properties>
+   <object id="1" name="player" type="Player" x="8" y="4" width="16" height="8">
+    <properties>
+     <property name="health" type="int" value="3"/>
+     <property name="target" type="object" value="2"/>
+     <property name="intro">Hello
+there</property>
+    </properties>
+   </object>
+   <object id="2" class="Chest" gid="5" x="0" y="32" width="16" height="16"/>
+  </objectgroup>
+ </group>
+ <layer id="6" name="plain" width="1" height="1">
+  <data>
+   <tile gid="4"/>
+  </data>
+ </layer>
+</map>
+"#;
+
+    fn tilemap() -> Tilemap {
+        tilemap_from_xml(&xml::parse(TMX).unwrap(), asset_root(), MAP_FILE).unwrap()
+    }
+
+    fn map_with_layer(layer: &str) -> String {
+        format!(
+            r#"<map orientation="orthogonal" width="2" height="1" tilewidth="8" tileheight="8">{layer}</map>"#
+        )
+    }
+
+    #[test]
+    fn map_and_tileset() {
+        let tilemap = tilemap();
+
+        assert_eq!((tilemap.width, tilemap.height), (3, 2));
+        assert_eq!((tilemap.tile_width, tilemap.tile_height), (16, 16));
+        assert_eq!(
+            tilemap.tilesets,
+            [Tileset {
+                first_gid: 1,
+                name: "forest".to_string(),
+                tile_width: 16,
+                tile_height: 16,
+                columns: 4,
+                tile_count: 8,
+                spacing: 1,
+                margin: 2,
+                image: Some("tiles/forest.png".to_string()),
+            }]
+        );
+        assert_eq!(
+            tilemap.properties,
+            [
+                Property {
+                    name: "music".to_string(),
+                    value: PropertyValue::Text("forest".to_string()),
+                },
+                Property {
+                    name: "gravity".to_string(),
+                    value: PropertyValue::Float(-9.5),
+                },
+                Property {
+                    name: "dark".to_string(),
+                    value: PropertyValue::Bool(true),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tile_layers_in_all_encodings() {
+        let tilemap = tilemap();
+
+        let names: Vec<_> = tilemap
+            .tile_layers
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect();
+        assert_eq!(names, ["ground", "base64", "zlib", "plain"]);
+
+        let ground = tilemap.tile_layer("ground").unwrap();
+        assert_eq!(ground.tiles, [1, 0x8000_0002, 0, 0x4000_0007, 0, 3]);
+        assert!((ground.opacity - 0.5).abs() < f32::EPSILON);
+
+        let expected = [1, 2, 3, 0, 0, 0x8000_0001];
+        assert_eq!(tilemap.tile_layer("base64").unwrap().tiles, expected);
+        assert_eq!(tilemap.tile_layer("zlib").unwrap().tiles, expected);
+        assert_eq!(tilemap.tile_layer("plain").unwrap().tiles, [4]);
+    }
+
+    #[test]
+    fn flip_flags() {
+        let tilemap = tilemap();
+
+        assert_eq!(tilemap.tile(0), None);
+        assert_eq!(
+            tilemap.tile(1),
+            Some(TileFrame {
+                tileset: 0,
+                texture_pos: UVec2::new(2, 2),
+                size: UVec2::new(16, 16),
+                flip_x: false,
+                flip_y: false,
+            })
+        );
+
+        let flipped_x = tilemap.tile(0x8000_0002).unwrap();
+        assert_eq!(flipped_x.texture_pos, UVec2::new(19, 2));
+        assert!(flipped_x.flip_x && !flipped_x.flip_y);
+
+        let flipped_y = tilemap.tile(0x4000_0007).unwrap();
+        assert_eq!(flipped_y.texture_pos, UVec2::new(36, 19));
+        assert!(!flipped_y.flip_x && flipped_y.flip_y);
+
+        // The diagonal flag is ignored
+        let rotated = tilemap.tile(FLIPPED_DIAGONALLY | 1).unwrap();
+        assert!(!rotated.flip_x && !rotated.flip_y);
+
+        // Outside of the tileset
+        assert_eq!(tilemap.tile(9), None);
+    }
+
+    #[test]
+    fn object_layers() {
+        let tilemap = tilemap();
+
+        assert_eq!(tilemap.object_layers.len(), 1);
+        let layer = &tilemap.object_layers[0];
+        assert_eq!(layer.name, "spawns");
+        assert_eq!(
+            layer.properties,
+            [Property {
+                name: "enemies".to_string(),
+                value: PropertyValue::Int(4),
+            }]
+        );
+
+        let player = &layer.objects[0];
+        assert_eq!(player.id, 1);
+        assert_eq!(player.name, "player");
+        assert_eq!(player.class, "Player");
+        // The map is 32 pixels high, and the lower left corner is at y 4 + 8 from the top
+        assert_eq!((player.x, player.y), (8.0, 20.0));
+        assert_eq!((player.width, player.height), (16.0, 8.0));
+        assert_eq!(
+            player.properties,
+            [
+                Property {
+                    name: "health".to_string(),
+                    value: PropertyValue::Int(3),
+                },
+                Property {
+                    name: "target".to_string(),
+                    value: PropertyValue::Int(2),
+                },
+                Property {
+                    name: "intro".to_string(),
+                    value: PropertyValue::Text("Hello\nthere".to_string()),
+                },
+            ]
+        );
+
+        // A tile object is already positioned at its lower left corner
+        let chest = &layer.objects[1];
+        assert_eq!(chest.class, "Chest");
+        assert_eq!((chest.x, chest.y), (0.0, 0.0));
+    }
+
+    #[test]
+    fn unsupported_maps() {
+        for text in [
+            r#"<tileset name="forest"/>"#.to_string(),
+            r#"<map orientation="isometric" width="1" height="1"/>"#.to_string(),
+            r#"<map orientation="orthogonal" infinite="1" width="1" height="1"/>"#.to_string(),
+            map_with_layer(
+                r#"<layer name="a" width="2" height="1"><data encoding="csv">1</data></layer>"#,
+            ),
+            map_with_layer(
+                r#"<layer name="a" width="2" height="1"><data encoding="csv">1,x</data></layer>"#,
+            ),
+            map_with_layer(
+                r#"<layer name="a" width="2" height="1"><data encoding="base64" compression="zstd">AQAAAAIAAAA=</data></layer>"#,
+            ),
+            map_with_layer(
+                r#"<layer name="a" width="2" height="1"><data encoding="base64">AQ*A</data></layer>"#,
+            ),
+            map_with_layer(r#"<layer name="a" width="2" height="1"/>"#),
+            map_with_layer(
+                r#"<objectgroup><object><properties><property name="a" type="int" value="b"/></properties></object></objectgroup>"#,
+            ),
+        ] {
+            let result =
+                xml::parse(&text).and_then(|root| tilemap_from_xml(&root, asset_root(), MAP_FILE));
+            assert!(result.is_err(), "'{text}' should not load");
+        }
+    }
+
+    #[test]
+    fn tileset_image_outside_of_the_assets() {
+        let text = map_with_layer(
+            r#"<tileset firstgid="1" tilewidth="8" tileheight="8"><image source="../../forest.png"/></tileset>"#,
+        );
+
+        let err =
+            tilemap_from_xml(&xml::parse(&text).unwrap(), asset_root(), MAP_FILE).unwrap_err();
+
+        assert!(err.contains("outside"), "{err}");
+    }
+}
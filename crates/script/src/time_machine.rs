@@ -0,0 +1,440 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Keeps a snapshot of the simulation value for the latest ticks, so the simulation can be paused,
+//! stepped and scrubbed back and forth, and continued from an earlier tick.
+//!
+//! Keyboard: `F5` pause/resume, `F6`/`F7` step one tick back/forward, hold `Shift` with `F6`/`F7`
+//! to scrub and `F8` to branch from the shown tick.
+//! Gamepad: the triggers scrub and `Select` pauses or branches.
+use crate::ScriptMessage;
+use crate::error_overlay::{GLYPH_HEIGHT, OVERLAY_Z, draw_text};
+use crate::serialize::SerializeBuffer;
+use crate::simulation::ScriptSimulation;
+use crate::timestep::SimulationTimestep;
+use limnus_basic_input::InputMessage;
+use limnus_basic_input::prelude::{ButtonState, KeyCode};
+use limnus_gamepad::{Button, GamepadMessage};
+use std::collections::{HashSet, VecDeque};
+use swamp::prelude::{
+    App, Color, LoRe, LoReM, LocalResource, Msg, Plugin, PreUpdate, Re, ReM, Render, RenderUpdate,
+    UVec2, Update, Vec3,
+};
+use swamp_script::prelude::{NamedStructType, Type, Value, quick_deserialize};
+use tracing::{error, info};
+
+/// Two minutes of history at the default tick rate
+pub const DEFAULT_CAPACITY: usize = 60 * 60 * 2;
+
+const SCRUB_TICKS_PER_UPDATE: f32 = 2.0;
+
+const TIMELINE_MARGIN: u16 = 4;
+const TIMELINE_HEIGHT: u16 = 3;
+const TIMELINE_Z: i16 = OVERLAY_Z - 2;
+const TIMELINE_BACKGROUND_COLOR: Color = Color::from_octet(0, 0, 0, 160);
+const TIMELINE_HISTORY_COLOR: Color = Color::from_octet(80, 110, 160, 255);
+const TIMELINE_SHOWN_COLOR: Color = Color::from_octet(160, 200, 255, 255);
+const TIMELINE_CURSOR_COLOR: Color = Color::from_octet(255, 200, 0, 255);
+const TIMELINE_TEXT_COLOR: Color = Color::from_octet(255, 255, 255, 255);
+
+#[derive(Debug)]
+struct Snapshot {
+    tick: u64,
+    payload: Vec<u8>,
+}
+
+#[derive(Debug, LocalResource)]
+pub struct TimeMachine {
+    snapshots: VecDeque<Snapshot>,
+    capacity: usize,
+    struct_type: Option<NamedStructType>,
+    /// The snapshot that is shown while paused. `None` while the simulation is running.
+    cursor: Option<usize>,
+    cursor_float: f32,
+    gamepad_velocity: f32,
+    keyboard_velocity: f32,
+    held_keys: HashSet<KeyCode>,
+    select_button_previous_state: bool,
+    buffer: SerializeBuffer,
+    serialize_failed: bool,
+}
+
+impl TimeMachine {
+    /// # Panics
+    ///
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "time machine must hold at least one snapshot");
+        Self {
+            snapshots: VecDeque::with_capacity(capacity),
+            capacity,
+            struct_type: None,
+            cursor: None,
+            cursor_float: 0.0,
+            gamepad_velocity: 0.0,
+            keyboard_velocity: 0.0,
+            held_keys: HashSet::new(),
+            select_button_previous_state: false,
+            buffer: SerializeBuffer::new(),
+            serialize_failed: false,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_paused(&self) -> bool {
+        self.cursor.is_some()
+    }
+
+    /// The oldest tick that is still in the history
+    #[must_use]
+    pub fn first_tick(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.tick)
+    }
+
+    /// The newest tick in the history
+    #[must_use]
+    pub fn max_tick(&self) -> Option<u64> {
+        self.snapshots.back().map(|snapshot| snapshot.tick)
+    }
+
+    /// The tick that is shown, the newest one while the simulation is running
+    #[must_use]
+    pub fn current_tick(&self) -> Option<u64> {
+        match self.cursor {
+            None => self.max_tick(),
+            Some(index) => self.snapshots.get(index).map(|snapshot| snapshot.tick),
+        }
+    }
+
+    /// Stores the simulation value for the tick, the oldest snapshot is dropped when it is full
+    pub fn record(&mut self, tick: u64, value: &Value) {
+        let Value::NamedStruct(struct_type, _) = value else {
+            return;
+        };
+        self.struct_type = Some(struct_type.clone());
+
+        let payload = match self.buffer.serialize(value) {
+            Ok(octets) => octets.to_vec(),
+            Err(mangrove_error) => {
+                if !self.serialize_failed {
+                    error!(error=%mangrove_error, "time machine can not take a snapshot");
+                }
+                self.serialize_failed = true;
+                return;
+            }
+        };
+        self.serialize_failed = false;
+
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+        }
+        self.snapshots.push_back(Snapshot { tick, payload });
+    }
+
+    /// Forgets the history, e.g. when the simulation struct changes
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.struct_type = None;
+        self.cursor = None;
+        self.gamepad_velocity = 0.0;
+        self.keyboard_velocity = 0.0;
+    }
+
+    /// Stops at the newest tick
+    pub fn pause(&mut self) {
+        if self.cursor.is_none() && !self.snapshots.is_empty() {
+            self.move_cursor_to(self.snapshots.len() - 1);
+        }
+    }
+
+    /// Continues the simulation from the newest tick, the history is kept
+    pub const fn resume(&mut self) {
+        self.cursor = None;
+    }
+
+    /// Moves the shown tick, pausing the simulation first if needed
+    pub fn step(&mut self, delta: i64) {
+        self.pause();
+        if let Some(index) = self.cursor {
+            let last_index = self.snapshots.len().saturating_sub(1) as i64;
+            self.move_cursor_to((index as i64 + delta).clamp(0, last_index) as usize);
+        }
+    }
+
+    /// Forgets all ticks after the shown one and continues the simulation from it
+    pub fn branch(&mut self) {
+        if let Some(index) = self.cursor {
+            self.snapshots.truncate(index + 1);
+            info!(tick = self.current_tick(), "branch from here");
+            self.cursor = None;
+            self.gamepad_velocity = 0.0;
+            self.keyboard_velocity = 0.0;
+        }
+    }
+
+    const fn move_cursor_to(&mut self, index: usize) {
+        self.cursor = Some(index);
+        self.cursor_float = index as f32;
+    }
+
+    fn scrub(&mut self) {
+        let velocity = self.gamepad_velocity + self.keyboard_velocity;
+        if velocity.abs() < f32::EPSILON {
+            return;
+        }
+        self.pause();
+        if self.cursor.is_some() {
+            let last_index = self.snapshots.len().saturating_sub(1) as f32;
+            self.cursor_float = (self.cursor_float + velocity).clamp(0.0, last_index);
+            self.cursor = Some(self.cursor_float as usize);
+        }
+    }
+
+    /// The simulation value for the shown tick, if paused
+    #[must_use]
+    pub fn shown_value(&self) -> Option<Value> {
+        self.value_at(self.cursor?)
+    }
+
+    /// The simulation value for the newest tick
+    #[must_use]
+    pub fn newest_value(&self) -> Option<Value> {
+        self.value_at(self.snapshots.len().checked_sub(1)?)
+    }
+
+    fn value_at(&self, index: usize) -> Option<Value> {
+        let snapshot = self.snapshots.get(index)?;
+        let struct_type = self.struct_type.as_ref()?;
+
+        let (deserialized_value, _deserialized_octet_size) = quick_deserialize(
+            &Type::NamedStruct(struct_type.clone()),
+            &snapshot.payload,
+            0,
+        );
+
+        Some(deserialized_value)
+    }
+
+    fn key_changed(&mut self, key_code: KeyCode, pressed: bool) {
+        if !pressed {
+            self.held_keys.remove(&key_code);
+            return;
+        }
+        self.held_keys.insert(key_code);
+
+        let shift_held = self.held_keys.contains(&KeyCode::ShiftLeft)
+            || self.held_keys.contains(&KeyCode::ShiftRight);
+
+        match key_code {
+            KeyCode::F5 => {
+                if self.is_paused() {
+                    self.resume();
+                } else {
+                    self.pause();
+                }
+            }
+            KeyCode::F6 if !shift_held => self.step(-1),
+            KeyCode::F7 if !shift_held => self.step(1),
+            KeyCode::F8 => self.branch(),
+            _ => {}
+        }
+    }
+
+    fn update_keyboard_velocity(&mut self) {
+        let shift_held = self.held_keys.contains(&KeyCode::ShiftLeft)
+            || self.held_keys.contains(&KeyCode::ShiftRight);
+
+        self.keyboard_velocity = if !shift_held {
+            0.0
+        } else if self.held_keys.contains(&KeyCode::F6) {
+            -SCRUB_TICKS_PER_UPDATE
+        } else if self.held_keys.contains(&KeyCode::F7) {
+            SCRUB_TICKS_PER_UPDATE
+        } else {
+            0.0
+        };
+    }
+
+    fn button_changed(&mut self, button: Button, value: f32) {
+        match button {
+            Button::LeftTrigger2 => self.gamepad_velocity = -trigger_velocity(value),
+            Button::RightTrigger2 => self.gamepad_velocity = trigger_velocity(value),
+            Button::Select => {
+                let currently_pushed_down = value > 0.5;
+                let was_pushed_now = !self.select_button_previous_state && currently_pushed_down;
+                self.select_button_previous_state = currently_pushed_down;
+                if was_pushed_now {
+                    if self.is_paused() {
+                        self.branch();
+                    } else {
+                        self.pause();
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Pushing the trigger further scrubs a lot faster, so both single ticks and long jumps are easy
+fn trigger_velocity(value: f32) -> f32 {
+    value * value * 5.0
+}
+
+/// Records a snapshot for every simulation tick in this update, the simulation can tick
+/// more than once per update
+fn record_tick(
+    mut simulation: LoReM<ScriptSimulation>,
+    mut time_machine: LoReM<TimeMachine>,
+    timestep: Re<SimulationTimestep>,
+) {
+    simulation.keep_ticked_values();
+    let ticked_values = simulation.take_ticked_values();
+    if time_machine.is_paused() || ticked_values.is_empty() {
+        return;
+    }
+
+    // Counted from the history, so the ticks continue from where it was branched
+    let first_tick = time_machine.max_tick().map_or_else(
+        || (timestep.tick_count() + 1).saturating_sub(ticked_values.len() as u64),
+        |max_tick| max_tick + 1,
+    );
+    for (tick, value) in (first_tick..).zip(&ticked_values) {
+        time_machine.record(tick, value);
+    }
+}
+
+fn controls_tick(
+    input_messages: Msg<InputMessage>,
+    gamepad_messages: Msg<GamepadMessage>,
+    mut time_machine: LoReM<TimeMachine>,
+) {
+    for input_message in input_messages.iter_previous() {
+        if let InputMessage::KeyboardInput(button_state, key_code) = input_message {
+            time_machine.key_changed(*key_code, *button_state == ButtonState::Pressed);
+        }
+    }
+    time_machine.update_keyboard_velocity();
+
+    for gamepad_message in gamepad_messages.iter_current() {
+        if let GamepadMessage::ButtonChanged(_gamepad_id, button, value) = gamepad_message {
+            time_machine.button_changed(*button, *value);
+        }
+    }
+
+    time_machine.scrub();
+}
+
+/// Shows the shown tick in the simulation and stops the simulation from ticking while paused
+fn apply_tick(
+    mut simulation: LoReM<ScriptSimulation>,
+    time_machine: LoRe<TimeMachine>,
+    mut timestep: ReM<SimulationTimestep>,
+) {
+    let was_paused = timestep.is_paused();
+    timestep.set_paused(time_machine.is_paused());
+
+    if let Some(shown_value) = time_machine.shown_value() {
+        simulation.debug_set_simulation_value(shown_value);
+    } else if was_paused {
+        // Resumed or branched, both continue from the newest tick in the history
+        if let Some(newest_value) = time_machine.newest_value() {
+            simulation.debug_set_simulation_value(newest_value);
+        }
+        info!(tick = time_machine.current_tick(), "time machine resumed");
+    }
+}
+
+/// The snapshots can not be read with the new simulation struct. A failed reload, or a reload that
+/// does not boot the simulation again, keeps the simulation running, so its history is kept as well.
+fn detect_reload_tick(script_messages: Msg<ScriptMessage>, mut time_machine: LoReM<TimeMachine>) {
+    let screen_changed = script_messages
+        .iter_previous()
+        .any(|msg| matches!(msg, ScriptMessage::ScreenChanged));
+    let reloaded = script_messages
+        .iter_current()
+        .any(|msg| matches!(msg, ScriptMessage::Reloaded(rebooted) if rebooted.simulation));
+
+    if screen_changed || reloaded {
+        time_machine.clear();
+    }
+}
+
+/// Draws the timeline bar at the bottom of the screen, with the history, the shown tick and
+/// the tick numbers
+fn draw_timeline_tick(time_machine: LoRe<TimeMachine>, mut render: ReM<Render>) {
+    let (Some(first_tick), Some(current_tick), Some(max_tick)) = (
+        time_machine.first_tick(),
+        time_machine.current_tick(),
+        time_machine.max_tick(),
+    ) else {
+        return;
+    };
+
+    let screen_size = render.virtual_surface_size();
+    if screen_size.x <= TIMELINE_MARGIN * 2 {
+        return;
+    }
+    let bar_width = screen_size.x - TIMELINE_MARGIN * 2;
+    let bar_x = TIMELINE_MARGIN as i16;
+    let bar_y = TIMELINE_MARGIN as i16;
+
+    render.draw_quad(
+        Vec3::new(0, 0, TIMELINE_Z - 1),
+        UVec2::new(
+            screen_size.x,
+            TIMELINE_MARGIN * 3 + TIMELINE_HEIGHT + GLYPH_HEIGHT,
+        ),
+        TIMELINE_BACKGROUND_COLOR,
+    );
+
+    // The history only fills the bar when the time machine is full
+    let x_for_tick = |tick: u64| -> u16 {
+        let ticks_since_first = tick.saturating_sub(first_tick) as f32;
+        let span = (time_machine.capacity.max(1) - 1).max(1) as f32;
+        ((ticks_since_first / span).min(1.0) * f32::from(bar_width - 1)) as u16
+    };
+
+    let history_width = x_for_tick(max_tick) + 1;
+    let shown_width = x_for_tick(current_tick) + 1;
+    render.draw_quad(
+        Vec3::new(bar_x, bar_y, TIMELINE_Z),
+        UVec2::new(history_width, TIMELINE_HEIGHT),
+        TIMELINE_HISTORY_COLOR,
+    );
+    render.draw_quad(
+        Vec3::new(bar_x, bar_y, TIMELINE_Z),
+        UVec2::new(shown_width, TIMELINE_HEIGHT),
+        TIMELINE_SHOWN_COLOR,
+    );
+    render.draw_quad(
+        Vec3::new(bar_x + shown_width as i16 - 1, bar_y - 1, TIMELINE_Z),
+        UVec2::new(1, TIMELINE_HEIGHT + 2),
+        TIMELINE_CURSOR_COLOR,
+    );
+
+    let state = if time_machine.is_paused() {
+        "paused"
+    } else {
+        "live"
+    };
+    let label = format!("{state} {current_tick}/{max_tick}");
+    let label_y = bar_y + (TIMELINE_HEIGHT + TIMELINE_MARGIN) as i16;
+    draw_text(&mut render, bar_x, label_y, &label, TIMELINE_TEXT_COLOR);
+}
+
+/// Must be added after the `ScriptSimulationPlugin`, so the snapshots are taken after the
+/// simulation ticks, and after the `ScriptRenderPlugin` so the timeline is drawn on top
+pub struct TimeMachinePlugin;
+
+impl Plugin for TimeMachinePlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_local_resource(TimeMachine::new(DEFAULT_CAPACITY));
+        app.add_system(PreUpdate, detect_reload_tick);
+        app.add_system(PreUpdate, controls_tick);
+        app.add_system(PreUpdate, apply_tick);
+        app.add_system(Update, record_tick);
+        app.add_system(RenderUpdate, draw_timeline_tick);
+    }
+}
@@ -0,0 +1,265 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+use monotonic_time_rs::Millis;
+use swamp::prelude::Resource;
+use tracing::debug;
+
+pub const DEFAULT_TICK_RATE: u32 = 60;
+pub const DEFAULT_MAX_TICKS_PER_UPDATE: u32 = 5;
+
+/// Decides how many times the simulation should tick each update, so the simulation speed is
+/// the same regardless of the frame rate. The time left over is exposed as `alpha`, so
+/// the render can interpolate between the previous and the current simulation value.
+#[derive(Resource, Debug)]
+pub struct SimulationTimestep {
+    tick_rate: u32,
+    max_ticks_per_update: u32,
+    lockstep: bool,
+    paused: bool,
+    accumulated_ms: f64,
+    last_time: Option<Millis>,
+    tick_count: u64,
+    ticks_this_update: u32,
+    alpha: f32,
+}
+
+impl Default for SimulationTimestep {
+    fn default() -> Self {
+        Self::new(DEFAULT_TICK_RATE, DEFAULT_MAX_TICKS_PER_UPDATE)
+    }
+}
+
+impl SimulationTimestep {
+    /// # Panics
+    ///
+    #[must_use]
+    pub fn new(tick_rate: u32, max_ticks_per_update: u32) -> Self {
+        assert!(
+            tick_rate > 0,
+            "tick rate must be at least one tick per second"
+        );
+        assert!(
+            max_ticks_per_update > 0,
+            "must be allowed to tick at least once per update"
+        );
+        Self {
+            tick_rate,
+            max_ticks_per_update,
+            lockstep: false,
+            paused: false,
+            accumulated_ms: 0.0,
+            last_time: None,
+            tick_count: 0,
+            ticks_this_update: 0,
+            alpha: 1.0,
+        }
+    }
+
+    /// Ticks exactly once every update, regardless of the time. Used when running headless and
+    /// when replaying input, where the result must not depend on how fast the host is.
+    #[must_use]
+    pub fn lockstep() -> Self {
+        Self {
+            lockstep: true,
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub const fn tick_rate(&self) -> u32 {
+        self.tick_rate
+    }
+
+    #[must_use]
+    pub const fn is_lockstep(&self) -> bool {
+        self.lockstep
+    }
+
+    pub const fn set_lockstep(&mut self, lockstep: bool) {
+        self.lockstep = lockstep;
+    }
+
+    #[must_use]
+    pub const fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// While paused, the simulation does not tick and the time does not accumulate
+    pub const fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// The number of simulation ticks since start
+    #[must_use]
+    pub const fn tick_count(&self) -> u64 {
+        self.tick_count
+    }
+
+    /// The number of simulation ticks in the current update, it can be zero on high frame rates
+    #[must_use]
+    pub const fn ticks_this_update(&self) -> u32 {
+        self.ticks_this_update
+    }
+
+    /// How far (0.0 to 1.0) the time has passed from the latest tick towards the next one
+    #[must_use]
+    pub const fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    #[must_use]
+    pub fn tick_duration_ms(&self) -> f64 {
+        1000.0 / f64::from(self.tick_rate)
+    }
+
+    /// Returns how many times the simulation should tick for the time `now`.
+    /// The first update always ticks once, so the simulation has a tick to render.
+    pub fn advance(&mut self, now: Millis) -> u32 {
+        if self.paused {
+            self.last_time = Some(now);
+            self.alpha = 1.0;
+            self.ticks_this_update = 0;
+            return 0;
+        }
+
+        if self.lockstep {
+            self.alpha = 1.0;
+            self.ticks_this_update = 1;
+            return 1;
+        }
+
+        let tick_duration_ms = self.tick_duration_ms();
+
+        self.accumulated_ms += match self.last_time {
+            None => tick_duration_ms,
+            Some(last_time) => now.duration_since_ms(last_time).as_millis() as f64,
+        };
+        self.last_time = Some(now);
+
+        let mut ticks = 0;
+        while self.accumulated_ms >= tick_duration_ms && ticks < self.max_ticks_per_update {
+            self.accumulated_ms -= tick_duration_ms;
+            ticks += 1;
+        }
+
+        if self.accumulated_ms >= tick_duration_ms {
+            // Too far behind to catch up (e.g. a breakpoint or a long reload), let the time go
+            debug!(
+                skipped_ms = self.accumulated_ms,
+                "simulation could not keep up"
+            );
+            self.accumulated_ms %= tick_duration_ms;
+        }
+
+        self.alpha = (self.accumulated_ms / tick_duration_ms) as f32;
+        self.ticks_this_update = ticks;
+
+        ticks
+    }
+
+    /// Must be called after each completed simulation tick
+    pub const fn ticked(&mut self) {
+        self.tick_count += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 20 ms per tick, so the times in the tests add up exactly
+    const TICK_RATE: u32 = 50;
+
+    fn timestep() -> SimulationTimestep {
+        SimulationTimestep::new(TICK_RATE, DEFAULT_MAX_TICKS_PER_UPDATE)
+    }
+
+    #[test]
+    fn first_update_ticks_once() {
+        let mut timestep = timestep();
+        assert_eq!(timestep.advance(Millis::new(1000)), 1);
+        assert_eq!(timestep.ticks_this_update(), 1);
+        assert!(timestep.alpha().abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn accumulates_time_between_ticks() {
+        let mut timestep = timestep();
+        timestep.advance(Millis::new(1000));
+
+        assert_eq!(timestep.advance(Millis::new(1010)), 0);
+        assert_eq!(timestep.ticks_this_update(), 0);
+        assert!((timestep.alpha() - 0.5).abs() < f32::EPSILON);
+
+        assert_eq!(timestep.advance(Millis::new(1045)), 2);
+        assert!((timestep.alpha() - 0.25).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn catches_up_at_most_five_ticks() {
+        let mut timestep = timestep();
+        timestep.advance(Millis::new(1000));
+
+        // 50 ticks behind, the time that can not be caught up is dropped
+        assert_eq!(
+            timestep.advance(Millis::new(2010)),
+            DEFAULT_MAX_TICKS_PER_UPDATE
+        );
+        assert_eq!(timestep.ticks_this_update(), DEFAULT_MAX_TICKS_PER_UPDATE);
+        assert!((timestep.alpha() - 0.5).abs() < f32::EPSILON);
+
+        assert_eq!(timestep.advance(Millis::new(2020)), 1);
+    }
+
+    #[test]
+    fn lockstep_ticks_once_every_update() {
+        let mut timestep = SimulationTimestep::lockstep();
+        assert!(timestep.is_lockstep());
+        for now in [1000, 1000, 1001, 5000] {
+            assert_eq!(timestep.advance(Millis::new(now)), 1);
+            assert!((timestep.alpha() - 1.0).abs() < f32::EPSILON);
+        }
+
+        timestep.set_lockstep(false);
+        assert_eq!(timestep.advance(Millis::new(5001)), 1);
+        assert_eq!(timestep.advance(Millis::new(5002)), 0);
+    }
+
+    #[test]
+    fn paused_does_not_tick_or_accumulate_time() {
+        let mut timestep = timestep();
+        timestep.advance(Millis::new(1000));
+
+        timestep.set_paused(true);
+        assert!(timestep.is_paused());
+        assert_eq!(timestep.advance(Millis::new(3000)), 0);
+        assert_eq!(timestep.ticks_this_update(), 0);
+        assert!((timestep.alpha() - 1.0).abs() < f32::EPSILON);
+
+        // Continues from the time it was resumed, the paused time is not caught up
+        timestep.set_paused(false);
+        assert_eq!(timestep.advance(Millis::new(3010)), 0);
+        assert!((timestep.alpha() - 0.5).abs() < f32::EPSILON);
+        assert_eq!(timestep.advance(Millis::new(3020)), 1);
+    }
+
+    #[test]
+    fn counts_the_ticks() {
+        let mut timestep = SimulationTimestep::lockstep();
+        for _ in 0..3 {
+            let ticks = timestep.advance(Millis::new(0));
+            for _ in 0..ticks {
+                timestep.ticked();
+            }
+        }
+        assert_eq!(timestep.tick_count(), 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "tick rate must be at least one tick per second")]
+    fn zero_tick_rate_panics() {
+        let _ = SimulationTimestep::new(0, DEFAULT_MAX_TICKS_PER_UPDATE);
+    }
+}
@@ -0,0 +1,203 @@
+/*
+ * Copyright (c) Peter Bjorklund. All rights reserved. https://github.com/swamp/mangrove
+ * Licensed under the MIT License. See LICENSE in the project root for license information.
+ */
+//! Reads small XML files that tools export, e.g. Tiled maps, into a tree of elements.
+use quick_xml::Reader;
+use quick_xml::events::{BytesStart, Event};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct XmlElement {
+    pub name: String,
+    /// The attributes are kept in the same order as in the file
+    pub attributes: Vec<(String, String)>,
+    pub children: Vec<XmlElement>,
+    /// The text directly inside the element, with the whitespace around it trimmed
+    pub text: String,
+}
+
+impl XmlElement {
+    #[must_use]
+    pub fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(attribute_name, _)| attribute_name == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The first child element with the name
+    #[must_use]
+    pub fn child(&self, name: &str) -> Option<&Self> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    pub fn children_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Self> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+}
+
+/// # Errors
+///
+/// If the text is not valid XML. The error tells the line where the problem was found.
+pub fn parse(text: &str) -> Result<XmlElement, String> {
+    let mut reader = Reader::from_str(text);
+    reader.config_mut().trim_text(true);
+
+    let line_at = |position: u64| {
+        let position = usize::try_from(position)
+            .unwrap_or(text.len())
+            .min(text.len());
+        text[..position].matches('\n').count() + 1
+    };
+
+    // The elements that have been started but not ended, the root is first
+    let mut open: Vec<XmlElement> = Vec::new();
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|err| format!("{err} at line {}", line_at(reader.error_position())))?;
+        match event {
+            Event::Start(start) => open.push(element(&start)?),
+            Event::Empty(start) => {
+                let empty = element(&start)?;
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(empty),
+                    None => return Ok(empty),
+                }
+            }
+            Event::End(_) => {
+                let ended = open.pop().ok_or("unexpected end tag")?;
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(ended),
+                    None => return Ok(ended),
+                }
+            }
+            Event::Text(text_event) => {
+                if let Some(current) = open.last_mut() {
+                    let unescaped = text_event.unescape().map_err(|err| {
+                        format!("{err} at line {}", line_at(reader.buffer_position()))
+                    })?;
+                    current.text.push_str(&unescaped);
+                }
+            }
+            Event::CData(cdata) => {
+                if let Some(current) = open.last_mut() {
+                    current
+                        .text
+                        .push_str(&String::from_utf8_lossy(&cdata.into_inner()));
+                }
+            }
+            Event::Eof => return Err("expected an element".to_string()),
+            Event::Comment(_) | Event::Decl(_) | Event::PI(_) | Event::DocType(_) => {}
+        }
+    }
+}
+
+fn element(start: &BytesStart) -> Result<XmlElement, String> {
+    let attributes = start
+        .attributes()
+        .map(|attribute| {
+            let attribute = attribute.map_err(|err| err.to_string())?;
+            let value = attribute
+                .unescape_value()
+                .map_err(|err| err.to_string())?
+                .into_owned();
+            Ok((
+                String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                value,
+            ))
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    Ok(XmlElement {
+        name: String::from_utf8_lossy(start.name().as_ref()).into_owned(),
+        attributes,
+        children: Vec::new(),
+        text: String::new(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements() {
+        let root = parse(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <!-- exported -->
+            <map b="2" a="1">
+                <layer name="ground"/>
+                <layer name="top">
+                    <data> 1,2 </data>
+                </layer>
+                <objectgroup/>
+            </map>"#,
+        )
+        .unwrap();
+
+        assert_eq!(root.name, "map");
+        assert_eq!(
+            root.attributes,
+            [
+                ("b".to_string(), "2".to_string()),
+                ("a".to_string(), "1".to_string())
+            ]
+        );
+        assert_eq!(root.attribute("a"), Some("1"));
+        assert_eq!(root.attribute("c"), None);
+        let names: Vec<_> = root
+            .children_named("layer")
+            .map(|layer| layer.attribute("name").unwrap())
+            .collect();
+        assert_eq!(names, ["ground", "top"]);
+        assert_eq!(
+            root.child("layer").unwrap().attribute("name"),
+            Some("ground")
+        );
+        assert_eq!(root.children.len(), 3);
+        assert_eq!(root.children[1].child("data").unwrap().text, "1,2");
+    }
+
+    #[test]
+    fn escapes_and_cdata() {
+        let root = parse(
+            r#"<property name="a &amp; b" value="&lt;&quot;&#65;&gt;">x &amp; y<![CDATA[ <z> ]]></property>"#,
+        )
+        .unwrap();
+
+        assert_eq!(root.attribute("name"), Some("a & b"));
+        assert_eq!(root.attribute("value"), Some("<\"A>"));
+        assert_eq!(root.text, "x & y <z> ");
+    }
+
+    #[test]
+    fn empty_root() {
+        let root = parse("<map/>").unwrap();
+
+        assert_eq!(root.name, "map");
+        assert!(root.children.is_empty());
+    }
+
+    #[test]
+    fn malformed() {
+        for text in [
+            "",
+            "just text",
+            "<map>",
+            "<map></layer>",
+            "<map a=1/>",
+            r#"<map a="1" a="2"/>"#,
+            "<map>&unknown;</map>",
+        ] {
+            assert!(parse(text).is_err(), "'{text}' should not parse");
+        }
+    }
+
+    #[test]
+    fn error_tells_the_line() {
+        let err = parse("<map>\n<layer>\n</map>").unwrap_err();
+
+        assert!(err.ends_with("at line 3"), "{err}");
+    }
+}